@@ -23,7 +23,7 @@ use vampirc_uci::{
 };
 
 use crate::{
-    db::{is_position_in_db, GameQuery, PositionQuery},
+    db::{is_position_in_db, GameQuery, PositionMode, PositionQuery},
     error::Error,
     AppState,
 };
@@ -709,7 +709,7 @@ pub async fn analyze_game(
 
     for (i, analysis) in analysis.iter_mut().enumerate() {
         let fen = &fens[i].0;
-        let query = PositionQuery::exact_from_fen(&fen.to_string())?;
+        let query = PositionQuery::exact_from_fen(&fen.to_string(), PositionMode::Exact)?;
 
         analysis.is_sacrifice = fens[i].2;
         if options.annotate_novelties && !novelty_found {
@@ -0,0 +1,289 @@
+//! Polyglot opening books built from the games of a database.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufWriter, Write},
+    path::PathBuf,
+};
+
+use diesel::{connection::DefaultLoadingMode, prelude::*};
+use serde::Deserialize;
+use shakmaty::{Chess, Color, Move, Position, Role};
+use tauri_specta::Event as _;
+
+use crate::{
+    db::{
+        encoding::{decode_move, initial_position, mainline_moves, zobrist_key},
+        get_db_or_create,
+        schema::*,
+        ConnectionOptions, DatabaseProgress, GameResult,
+    },
+    error::Error,
+    AppState,
+};
+
+/// How the weight of a book move is computed.
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BookWeight {
+    /// Number of games the move was played in.
+    #[default]
+    Frequency,
+    /// Two points per win and one per draw for the side that played the move.
+    Score,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct MoveStats {
+    games: u32,
+    wins: u32,
+    draws: u32,
+}
+
+impl MoveStats {
+    /// Score of the side that played the move, games without a result
+    /// counting as losses.
+    fn score(&self) -> f64 {
+        (self.wins as f64 + self.draws as f64 / 2.0) / self.games as f64
+    }
+
+    fn weight(&self, weight: BookWeight) -> u64 {
+        match weight {
+            BookWeight::Frequency => self.games as u64,
+            BookWeight::Score => 2 * self.wins as u64 + self.draws as u64,
+        }
+    }
+}
+
+/// An entry of a Polyglot book, 16 big-endian bytes on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BookEntry {
+    pub key: u64,
+    pub mv: u16,
+    pub weight: u16,
+}
+
+impl BookEntry {
+    fn write(&self, writer: &mut impl Write) -> Result<(), Error> {
+        writer.write_all(&self.key.to_be_bytes())?;
+        writer.write_all(&self.mv.to_be_bytes())?;
+        writer.write_all(&self.weight.to_be_bytes())?;
+        // Learn field, unused
+        writer.write_all(&0u32.to_be_bytes())?;
+        Ok(())
+    }
+}
+
+/// Encodes a move the Polyglot way. Castling is written as the king taking
+/// its own rook.
+fn polyglot_move(m: &Move) -> u16 {
+    let (from, to) = match *m {
+        Move::Castle { king, rook } => (king, rook),
+        _ => (m.from().expect("drops are not chess moves"), m.to()),
+    };
+    let promotion = match m.promotion() {
+        Some(Role::Knight) => 1,
+        Some(Role::Bishop) => 2,
+        Some(Role::Rook) => 3,
+        Some(Role::Queen) => 4,
+        _ => 0,
+    };
+    (promotion << 12) | ((from as u16) << 6) | to as u16
+}
+
+/// Accumulates the moves played in each position of the first `max_ply`
+/// plies of the games.
+pub struct BookBuilder {
+    max_ply: usize,
+    moves: HashMap<(u64, u16), MoveStats>,
+}
+
+impl BookBuilder {
+    pub fn new(max_ply: usize) -> Self {
+        Self {
+            max_ply,
+            moves: HashMap::new(),
+        }
+    }
+
+    pub fn add_game(&mut self, mut chess: Chess, moves: &[u8], result: Option<GameResult>) {
+        for &byte in mainline_moves(moves).iter().take(self.max_ply) {
+            let Some(m) = decode_move(byte, &chess) else {
+                break;
+            };
+            let stats = self
+                .moves
+                .entry((zobrist_key(&chess), polyglot_move(&m)))
+                .or_default();
+            stats.games += 1;
+            match (result, chess.turn()) {
+                (Some(GameResult::Draw), _) => stats.draws += 1,
+                (Some(GameResult::WhiteWins), Color::White)
+                | (Some(GameResult::BlackWins), Color::Black) => stats.wins += 1,
+                _ => {}
+            }
+            chess.play_unchecked(&m);
+        }
+    }
+
+    /// Prunes the moves below the thresholds and returns the entries sorted
+    /// by key, the heaviest move first. Weights are scaled down to fit in 16
+    /// bits, and moves left without weight are dropped.
+    pub fn finish(self, min_games: u32, min_score: f64, weight: BookWeight) -> Vec<BookEntry> {
+        let kept: Vec<((u64, u16), u64)> = self
+            .moves
+            .into_iter()
+            .filter(|(_, stats)| stats.games >= min_games && stats.score() >= min_score)
+            .map(|(key, stats)| (key, stats.weight(weight)))
+            .filter(|(_, weight)| *weight > 0)
+            .collect();
+        let max = kept.iter().map(|(_, weight)| *weight).max().unwrap_or(0);
+        let scale = |weight: u64| {
+            if max > u16::MAX as u64 {
+                (weight * u16::MAX as u64 / max).max(1) as u16
+            } else {
+                weight as u16
+            }
+        };
+
+        let mut entries: Vec<BookEntry> = kept
+            .into_iter()
+            .map(|((key, mv), weight)| BookEntry {
+                key,
+                mv,
+                weight: scale(weight),
+            })
+            .collect();
+        entries.sort_by(|a, b| {
+            a.key
+                .cmp(&b.key)
+                .then(b.weight.cmp(&a.weight))
+                .then(a.mv.cmp(&b.mv))
+        });
+        entries
+    }
+}
+
+/// Writes a Polyglot book of the first `max_ply` plies of every game. Moves
+/// played in fewer than `min_games` games, or scoring less than `min_score`
+/// (between 0 and 1) for the side playing them, are left out. Returns the
+/// number of entries written.
+#[allow(clippy::too_many_arguments)]
+#[tauri::command]
+pub async fn create_opening_book(
+    file: PathBuf,
+    dest_file: PathBuf,
+    max_ply: usize,
+    min_games: u32,
+    min_score: f64,
+    weight: Option<BookWeight>,
+    app: tauri::AppHandle,
+    state: tauri::State<'_, AppState>,
+) -> Result<usize, Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    let id = file.to_string_lossy().into_owned();
+    let total: i64 = games::table.count().get_result(db)?;
+
+    let mut builder = BookBuilder::new(max_ply);
+    let games = games::table
+        .select((games::moves, games::fen, games::result))
+        .load_iter::<(Vec<u8>, Option<String>, Option<i32>), DefaultLoadingMode>(db)?;
+    for (i, row) in games.enumerate() {
+        let (moves, fen, result) = row?;
+        if let Some(chess) = initial_position(fen.as_deref()) {
+            builder.add_game(chess, &moves, result.and_then(GameResult::from_i32));
+        }
+        if (i + 1) % 10000 == 0 {
+            let _ = DatabaseProgress {
+                id: id.clone(),
+                progress: (i + 1) as f64 / total as f64 * 100.0,
+            }
+            .emit_all(&app);
+        }
+    }
+
+    let entries = builder.finish(min_games, min_score, weight.unwrap_or_default());
+    let mut writer = BufWriter::new(File::create(dest_file)?);
+    for entry in &entries {
+        entry.write(&mut writer)?;
+    }
+    writer.flush()?;
+
+    let _ = DatabaseProgress {
+        id,
+        progress: 100.0,
+    }
+    .emit_all(&app);
+
+    Ok(entries.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::db::encoding::encode_move;
+    use shakmaty::{san::San, Square};
+
+    fn encode_line(sans: &[&str]) -> Vec<u8> {
+        let mut chess = Chess::default();
+        sans.iter()
+            .map(|san| {
+                let m = san.parse::<San>().unwrap().to_move(&chess).unwrap();
+                let byte = encode_move(&m, &chess).unwrap();
+                chess.play_unchecked(&m);
+                byte
+            })
+            .collect()
+    }
+
+    #[test]
+    fn polyglot_keys_and_moves() {
+        let mut chess = Chess::default();
+        assert_eq!(zobrist_key(&chess), 0x463b96181691fc9c);
+
+        let e4 = Move::Normal {
+            role: Role::Pawn,
+            from: Square::E2,
+            to: Square::E4,
+            capture: None,
+            promotion: None,
+        };
+        assert_eq!(polyglot_move(&e4), (12 << 6) | 28);
+        chess.play_unchecked(&e4);
+        assert_eq!(zobrist_key(&chess), 0x823c9b50fd114196);
+
+        let castle = Move::Castle {
+            king: Square::E1,
+            rook: Square::H1,
+        };
+        assert_eq!(polyglot_move(&castle), (4 << 6) | 7);
+    }
+
+    /// Two games with 1. e4 scoring 1.5/2 and one lost with 1. d4.
+    fn sample_builder() -> BookBuilder {
+        let mut builder = BookBuilder::new(1);
+        let e4 = encode_line(&["e4", "e5"]);
+        builder.add_game(Chess::default(), &e4, Some(GameResult::WhiteWins));
+        builder.add_game(Chess::default(), &e4, Some(GameResult::Draw));
+        let d4 = encode_line(&["d4"]);
+        builder.add_game(Chess::default(), &d4, Some(GameResult::BlackWins));
+        builder
+    }
+
+    #[test]
+    fn book_prunes_and_sorts_entries() {
+        let entries = sample_builder().finish(1, 0.0, BookWeight::Frequency);
+        let start = zobrist_key(&Chess::default());
+        // Only the first ply is kept
+        assert!(entries.iter().all(|e| e.key == start));
+        let weights: Vec<u16> = entries.iter().map(|e| e.weight).collect();
+        assert_eq!(weights, vec![2, 1]);
+        assert_eq!(entries[0].mv, (12 << 6) | 28);
+
+        let entries = sample_builder().finish(1, 0.5, BookWeight::Score);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].weight, 3);
+    }
+}
@@ -0,0 +1,170 @@
+//! Cache of the game counts of `get_games`.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use dashmap::DashMap;
+use serde::Serialize;
+use specta::Type;
+
+use super::GameQuery;
+
+/// Most counts kept by [`CountCache`], across all databases.
+const CAPACITY: usize = 64;
+
+/// Game counts of the recent queries of `get_games`, so that changing the page
+/// or the sort doesn't count the games again.
+///
+/// Every database has a generation, bumped by the commands that modify it. A
+/// count is only fresh if it was computed in the current generation.
+#[derive(Default)]
+pub struct CountCache {
+    generations: DashMap<String, u64>,
+    /// Most recently used first.
+    entries: Mutex<VecDeque<CountEntry>>,
+}
+
+struct CountEntry {
+    file: String,
+    query: GameQuery,
+    generation: u64,
+    count: i64,
+}
+
+/// Marks the counts of a database as stale when created and again when
+/// dropped, so that a count computed while the database was being modified
+/// isn't taken as fresh.
+pub struct Modification<'a> {
+    cache: &'a CountCache,
+    file: String,
+}
+
+impl Drop for Modification<'_> {
+    fn drop(&mut self) {
+        self.cache.bump(&self.file);
+    }
+}
+
+/// The parts of a query that change the count, leaving out the page, the
+/// sort and what's loaded of the games.
+fn fingerprint(query: &GameQuery) -> GameQuery {
+    GameQuery {
+        options: None,
+        include_moves: None,
+        ..query.clone()
+    }
+}
+
+impl CountCache {
+    pub fn generation(&self, file: &str) -> u64 {
+        self.generations.get(file).map_or(0, |g| *g)
+    }
+
+    fn bump(&self, file: &str) {
+        *self.generations.entry(file.to_string()).or_default() += 1;
+    }
+
+    /// To be held by the commands modifying the games of a database while
+    /// they do so.
+    pub fn modify(&self, file: &str) -> Modification<'_> {
+        self.bump(file);
+        Modification {
+            cache: self,
+            file: file.to_string(),
+        }
+    }
+
+    /// The last count of the query, and whether it's still fresh.
+    pub fn get(&self, file: &str, query: &GameQuery) -> Option<(i64, bool)> {
+        let query = fingerprint(query);
+        let generation = self.generation(file);
+        let mut entries = self.entries.lock().unwrap();
+        let index = entries
+            .iter()
+            .position(|e| e.file == file && e.query == query)?;
+        let entry = entries.remove(index)?;
+        let result = (entry.count, entry.generation == generation);
+        entries.push_front(entry);
+        Some(result)
+    }
+
+    /// Stores a count computed in `generation`, unless the database has been
+    /// modified since.
+    pub fn insert(&self, file: &str, query: &GameQuery, generation: u64, count: i64) {
+        if generation != self.generation(file) {
+            return;
+        }
+        let query = fingerprint(query);
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|e| !(e.file == file && e.query == query));
+        entries.push_front(CountEntry {
+            file: file.to_string(),
+            query,
+            generation,
+            count,
+        });
+        entries.truncate(CAPACITY);
+    }
+}
+
+/// Exact count of the games of an approximate `get_games`, once it's done.
+#[derive(Serialize, Debug, Clone, Type, tauri_specta::Event)]
+pub struct GamesCounted {
+    pub file: String,
+    /// The `request_id` of the query's options.
+    pub request_id: Option<u32>,
+    pub count: i64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_go_stale_when_modified() {
+        let cache = CountCache::default();
+        let query = GameQuery {
+            tournament_id: Some(1),
+            ..Default::default()
+        };
+        cache.insert("a.db3", &query, cache.generation("a.db3"), 10);
+        assert_eq!(cache.get("a.db3", &query), Some((10, true)));
+        assert_eq!(cache.get("b.db3", &query), None);
+
+        // The page doesn't change the count
+        let paged = GameQuery {
+            options: Some(Default::default()),
+            ..query.clone()
+        };
+        assert_eq!(cache.get("a.db3", &paged), Some((10, true)));
+
+        let generation = cache.generation("a.db3");
+        {
+            let _modification = cache.modify("a.db3");
+            assert_eq!(cache.get("a.db3", &query), Some((10, false)));
+            // Counted while the database was being modified
+            cache.insert("a.db3", &query, cache.generation("a.db3"), 11);
+        }
+        assert_eq!(cache.get("a.db3", &query), Some((11, false)));
+        assert!(cache.generation("a.db3") > generation);
+
+        cache.insert("a.db3", &query, cache.generation("a.db3"), 12);
+        assert_eq!(cache.get("a.db3", &query), Some((12, true)));
+    }
+
+    #[test]
+    fn least_recently_used_counts_are_dropped() {
+        let cache = CountCache::default();
+        let query = |id| GameQuery {
+            tournament_id: Some(id),
+            ..Default::default()
+        };
+        for id in 0..CAPACITY as i32 {
+            cache.insert("a.db3", &query(id), 0, id.into());
+        }
+        assert!(cache.get("a.db3", &query(0)).is_some());
+        cache.insert("a.db3", &query(-1), 0, -1);
+        assert!(cache.get("a.db3", &query(0)).is_some());
+        assert!(cache.get("a.db3", &query(1)).is_none());
+    }
+}
@@ -15,36 +15,66 @@ CREATE TABLE Sites (
 
 CREATE TABLE Players (
     ID INTEGER PRIMARY KEY,
-    Name TEXT UNIQUE,
-    Elo INTEGER
+    Name TEXT,
+    Elo INTEGER,
+    Title TEXT,
+    FideId INTEGER,
+    GameCount INTEGER
 );
 
+CREATE INDEX players_name_idx ON Players(Name);
+CREATE INDEX players_name_nocase_idx ON Players(Name COLLATE NOCASE);
+CREATE UNIQUE INDEX players_fide_id_idx ON Players(FideId);
+CREATE INDEX players_game_count_idx ON Players(GameCount);
+
 CREATE TABLE Games (
     ID INTEGER PRIMARY KEY AUTOINCREMENT,
     EventID INTEGER,
     SiteID INTEGER,
     Date TEXT,
     UTCTime TEXT,
-    Round INTEGER,
+    Round TEXT,
     WhiteID INTEGER,
     WhiteElo INTEGER,
+    WhiteTitle TEXT,
+    WhiteOriginalName TEXT,
     BlackID INTEGER,
     BlackElo INTEGER,
+    BlackTitle TEXT,
+    BlackOriginalName TEXT,
+    MaxRating INTEGER,
+    MaterialSignatures BLOB,
     WhiteMaterial INTEGER,
     BlackMaterial INTEGER,
     Result INTEGER,
+    Termination TEXT,
     TimeControl TEXT,
+    Speed INTEGER,
     ECO TEXT,
     PlyCount INTEGER,
     FEN TEXT,
+    Variant TEXT,
     Moves BLOB,
+    Clocks BLOB,
+    Evals BLOB,
     PawnHome BLOB,
+    GameHash INTEGER,
+    Rated INTEGER,
     FOREIGN KEY(EventID) REFERENCES Events,
     FOREIGN KEY(SiteID) REFERENCES Sites,
     FOREIGN KEY(WhiteID) REFERENCES Players,
     FOREIGN KEY(BlackID) REFERENCES Players
 );
 
-INSERT INTO Players (ID, Name, Elo) VALUES (0, 'Unknown', NULL);
+CREATE TABLE Comments (
+    ID INTEGER PRIMARY KEY AUTOINCREMENT,
+    GameID INTEGER,
+    Ply INTEGER,
+    Comment TEXT,
+    Nags TEXT,
+    FOREIGN KEY(GameID) REFERENCES Games ON DELETE CASCADE
+);
+
+INSERT INTO Players (ID, Name, Elo, GameCount) VALUES (0, 'Unknown', NULL, 0);
 INSERT INTO Events (ID, Name) VALUES (0, 'Unknown');
 INSERT INTO Sites (ID, Name) VALUES (0, 'Unknown');
\ No newline at end of file
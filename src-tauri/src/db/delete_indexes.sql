@@ -5,5 +5,9 @@ DROP INDEX IF EXISTS games_result_idx;
 DROP INDEX IF EXISTS games_white_elo_idx;
 DROP INDEX IF EXISTS games_black_elo_idx;
 DROP INDEX IF EXISTS games_plycount_idx;
+DROP INDEX IF EXISTS games_max_rating_idx;
+DROP INDEX IF EXISTS games_hash_idx;
+DROP INDEX IF EXISTS players_given_name_idx;
+DROP INDEX IF EXISTS comments_game_idx;
 
 VACUUM;
\ No newline at end of file
@@ -1,8 +1,55 @@
+use std::borrow::Cow;
+
 use crate::error::Error;
 use shakmaty::{
-    fen::Fen, san::SanPlus, CastlingMode, Chess, FromSetup, Move, Position, PositionError,
+    fen::Fen,
+    san::SanPlus,
+    zobrist::{Zobrist64, ZobristHash},
+    Board, CastlingMode, Chess, EnPassantMode, FromSetup, Move, Position, PositionError,
 };
 
+/// Marks the start of a variation in an encoded move tree. The variation
+/// replaces the move played right before the marker.
+pub const START_VARIATION: u8 = 254;
+
+/// Marks the end of the innermost open variation in an encoded move tree.
+pub const END_VARIATION: u8 = 255;
+
+/// Bytes of a [`material_signature`].
+pub const SIGNATURE_SIZE: usize = 5;
+
+/// Stored for a ply without a `[%clk]` annotation.
+pub const NO_CLOCK: u32 = u32::MAX;
+
+/// Stored for a ply without an `[%eval]` annotation.
+pub const NO_EVAL: i16 = i16::MIN;
+
+/// Evaluation of a mate in 0. A mate in `n` is stored as `MATE_EVAL - n`,
+/// negated when it's Black that mates.
+pub const MATE_EVAL: i16 = 30000;
+
+pub fn encode_clocks(clocks: &[u32]) -> Vec<u8> {
+    clocks.iter().flat_map(|c| c.to_le_bytes()).collect()
+}
+
+pub fn decode_clocks(bytes: &[u8]) -> Vec<u32> {
+    bytes
+        .chunks_exact(4)
+        .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+        .collect()
+}
+
+pub fn encode_evals(evals: &[i16]) -> Vec<u8> {
+    evals.iter().flat_map(|e| e.to_le_bytes()).collect()
+}
+
+pub fn decode_evals(bytes: &[u8]) -> Vec<i16> {
+    bytes
+        .chunks_exact(2)
+        .map(|c| i16::from_le_bytes([c[0], c[1]]))
+        .collect()
+}
+
 pub fn encode_move(m: &Move, chess: &Chess) -> Result<u8, Error> {
     let moves = chess.legal_moves();
     Ok(moves.iter().position(|x| x == m).unwrap() as u8)
@@ -13,15 +60,114 @@ pub fn decode_move(byte: u8, chess: &Chess) -> Option<Move> {
     legal_moves.get(byte as usize).cloned()
 }
 
+/// Starting position of a game, from its FEN if it has one.
+pub fn initial_position(fen: Option<&str>) -> Option<Chess> {
+    match fen {
+        Some(fen) => {
+            let fen = Fen::from_ascii(fen.as_bytes()).ok()?;
+            Chess::from_setup(fen.into_setup(), CastlingMode::Chess960).ok()
+        }
+        None => Some(Chess::default()),
+    }
+}
+
+/// Zobrist hash of a position, using the Polyglot random values so it can be
+/// looked up in opening books.
+pub fn zobrist_key(chess: &Chess) -> u64 {
+    chess.zobrist_hash::<Zobrist64>(EnPassantMode::Legal).0
+}
+
+/// Returns the mainline of an encoded move tree, dropping every variation.
+pub fn mainline_moves(moves_bytes: &[u8]) -> Cow<'_, [u8]> {
+    if !moves_bytes.contains(&START_VARIATION) {
+        return Cow::Borrowed(moves_bytes);
+    }
+    let mut depth = 0;
+    let mut mainline = Vec::with_capacity(moves_bytes.len());
+    for &byte in moves_bytes {
+        match byte {
+            START_VARIATION => depth += 1,
+            END_VARIATION => depth -= 1,
+            _ if depth == 0 => mainline.push(byte),
+            _ => {}
+        }
+    }
+    Cow::Owned(mainline)
+}
+
+/// The number of pawns, knights, bishops, rooks and queens of White and then
+/// Black, a nibble each.
+pub fn material_signature(board: &Board) -> [u8; SIGNATURE_SIZE] {
+    let material = board.material();
+    let counts = [&material.white, &material.black]
+        .into_iter()
+        .flat_map(|m| [m.pawn, m.knight, m.bishop, m.rook, m.queen]);
+    let mut signature = [0; SIGNATURE_SIZE];
+    for (i, count) in counts.enumerate() {
+        signature[i / 2] |= count.min(15) << (if i % 2 == 0 { 4 } else { 0 });
+    }
+    signature
+}
+
+/// Counts of a [`material_signature`], in the same order.
+pub fn signature_counts(signature: &[u8]) -> [u8; 2 * SIGNATURE_SIZE] {
+    let mut counts = [0; 2 * SIGNATURE_SIZE];
+    for (i, count) in counts.iter_mut().enumerate() {
+        *count = (signature[i / 2] >> (if i % 2 == 0 { 4 } else { 0 })) & 0xf;
+    }
+    counts
+}
+
+/// The distinct material signatures of the positions of the mainline, in the
+/// order they're reached. Only captures and promotions change the material,
+/// so a game has few of them. `None` if the moves can't be replayed.
+pub fn encode_material_signatures(moves_bytes: &[u8], fen: Option<&str>) -> Option<Vec<u8>> {
+    let mut chess = initial_position(fen)?;
+    let mut signatures = material_signature(chess.board()).to_vec();
+    for &byte in mainline_moves(moves_bytes).iter() {
+        let m = decode_move(byte, &chess)?;
+        let changes_material = m.is_capture() || m.is_promotion();
+        chess.play_unchecked(&m);
+        if changes_material {
+            let signature = material_signature(chess.board());
+            if !signatures.chunks(SIGNATURE_SIZE).any(|s| s == signature) {
+                signatures.extend_from_slice(&signature);
+            }
+        }
+    }
+    Some(signatures)
+}
+
+/// Decodes an encoded move tree into SAN tokens. Variations are delimited by
+/// `(` and `)` tokens, so every token corresponds to exactly one byte.
 pub fn decode_moves(moves_bytes: Vec<u8>, initial_fen: Fen) -> Result<Vec<String>, Error> {
     let mut chess = Chess::from_setup(initial_fen.into(), CastlingMode::Chess960)
         .or_else(PositionError::ignore_too_much_material)
         .unwrap();
+    let mut previous = chess.clone();
+    let mut stack = Vec::new();
     let mut moves = Vec::new();
     for byte in moves_bytes {
-        let m = decode_move(byte, &chess).unwrap();
-        let san = SanPlus::from_move_and_play_unchecked(&mut chess, &m);
-        moves.push(san.to_string());
+        match byte {
+            START_VARIATION => {
+                stack.push((chess.clone(), previous.clone()));
+                chess = previous.clone();
+                moves.push("(".to_string());
+            }
+            END_VARIATION => {
+                if let Some((position, before)) = stack.pop() {
+                    chess = position;
+                    previous = before;
+                }
+                moves.push(")".to_string());
+            }
+            _ => {
+                let m = decode_move(byte, &chess).unwrap();
+                previous = chess.clone();
+                let san = SanPlus::from_move_and_play_unchecked(&mut chess, &m);
+                moves.push(san.to_string());
+            }
+        }
     }
     Ok(moves)
 }
@@ -60,4 +206,47 @@ mod tests {
         let m2 = decode_move(byte, &chess).unwrap();
         assert_eq!(m, m2);
     }
+
+    #[test]
+    fn test_decode_variations() {
+        // 1. e4 (1. d4 d5) 1... e5
+        let moves = vec![12, START_VARIATION, 11, 11, END_VARIATION, 12];
+        let decoded = decode_moves(moves.clone(), Fen::default()).unwrap();
+        assert_eq!(decoded, vec!["e4", "(", "d4", "d5", ")", "e5"]);
+        assert_eq!(mainline_moves(&moves).as_ref(), &[12, 12]);
+    }
+
+    #[test]
+    fn test_material_signatures() {
+        // 1. e4 d5 2. exd5 Qxd5 3. Nc3
+        let mut chess = Chess::default();
+        let mut moves = Vec::new();
+        for san in ["e4", "d5", "exd5", "Qxd5", "Nc3"] {
+            let m = san.parse::<SanPlus>().unwrap().san.to_move(&chess).unwrap();
+            moves.push(encode_move(&m, &chess).unwrap());
+            chess.play_unchecked(&m);
+        }
+
+        let signatures = encode_material_signatures(&moves, None).unwrap();
+        let counts: Vec<_> = signatures
+            .chunks(SIGNATURE_SIZE)
+            .map(signature_counts)
+            .collect();
+        assert_eq!(
+            counts,
+            vec![
+                [8, 2, 2, 2, 1, 8, 2, 2, 2, 1],
+                [8, 2, 2, 2, 1, 7, 2, 2, 2, 1],
+                [7, 2, 2, 2, 1, 7, 2, 2, 2, 1],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_clocks_and_evals() {
+        let clocks = vec![180_000, NO_CLOCK, 1_500];
+        assert_eq!(decode_clocks(&encode_clocks(&clocks)), clocks);
+        let evals = vec![31, -MATE_EVAL + 2, NO_EVAL];
+        assert_eq!(decode_evals(&encode_evals(&evals)), evals);
+    }
 }
@@ -5,3 +5,7 @@ CREATE INDEX IF NOT EXISTS games_result_idx ON Games(Result);
 CREATE INDEX IF NOT EXISTS games_white_elo_idx ON Games(WhiteElo);
 CREATE INDEX IF NOT EXISTS games_black_elo_idx ON Games(BlackElo);
 CREATE INDEX IF NOT EXISTS games_plycount_idx ON Games(PlyCount);
+CREATE INDEX IF NOT EXISTS games_max_rating_idx ON Games(MaxRating);
+CREATE INDEX IF NOT EXISTS games_hash_idx ON Games(GameHash);
+CREATE INDEX IF NOT EXISTS players_given_name_idx ON Players(ltrim(substr(Name, instr(Name, ',') + 1)) COLLATE NOCASE);
+CREATE INDEX IF NOT EXISTS comments_game_idx ON Comments(GameID);
@@ -1,3 +1,5 @@
+mod book;
+mod counts;
 mod encoding;
 mod models;
 mod ops;
@@ -6,13 +8,21 @@ mod search;
 
 use crate::{
     db::{
-        encoding::{decode_move, decode_moves},
+        encoding::{
+            decode_clocks, decode_evals, decode_move, decode_moves, encode_clocks, encode_evals,
+            encode_material_signatures, initial_position, mainline_moves, END_VARIATION, MATE_EVAL,
+            NO_CLOCK, NO_EVAL, START_VARIATION,
+        },
         models::*,
         ops::*,
         schema::*,
     },
     error::Error,
-    opening::get_opening_from_setup,
+    fs::DownloadProgress,
+    opening::{
+        get_eco_codes_from_name, get_eco_from_setup, get_opening_from_setup,
+        get_opening_name_from_eco,
+    },
     AppState,
 };
 use chrono::{NaiveDate, NaiveTime};
@@ -23,21 +33,33 @@ use diesel::{
     prelude::*,
     r2d2::{ConnectionManager, Pool},
     sql_query,
-    sql_types::Text,
+    sql_types::{BigInt, Binary, Bool, Double, Integer, Nullable, Text},
+    sqlite::Sqlite,
 };
-use pgn_reader::{BufferedReader, RawHeader, SanPlus, Skip, Visitor};
+use pgn_reader::{BufferedReader, Nag, RawComment, RawHeader, SanPlus, Skip, Visitor};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use shakmaty::{
-    fen::Fen, Board, ByColor, Chess, EnPassantMode, FromSetup, Piece, Position, PositionError,
+    fen::{Epd, Fen},
+    san::{San, SanPlus},
+    Board, ByColor, CastlingMode, Chess, EnPassantMode, FromSetup, Piece, Position, PositionError,
 };
 use specta::Type;
-use std::io::{BufWriter, Write};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
 use std::{
     fs::{remove_file, File, OpenOptions},
-    path::PathBuf,
-    sync::atomic::{AtomicI32, AtomicUsize, Ordering},
-    time::{Duration, Instant},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{self, SyncSender},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant, UNIX_EPOCH},
 };
 use tauri::State;
 use tauri::{
@@ -48,19 +70,42 @@ use tauri_specta::Event as _;
 
 use self::encoding::encode_move;
 
+pub use self::book::create_opening_book;
+pub use self::counts::{CountCache, GamesCounted};
 pub use self::models::NormalizedGame;
 pub use self::models::Puzzle;
 pub use self::schema::puzzles;
-pub use self::search::{is_position_in_db, search_position, PositionQuery, PositionStats};
+pub use self::search::{
+    build_position_index, cancel_search, clear_position_cache, close_explorer_session,
+    explorer_pop_move, explorer_push_move, export_position_matches, is_position_in_db,
+    search_endgames, search_pattern, search_position, search_position_multi,
+    set_position_cache_limit, start_explorer_session, ExplorerSessions, LineCache, PositionCache,
+    PositionMode, PositionQuery, PositionSearchResult, PositionStats,
+};
 
 const DATABASE_VERSION: &str = "1.0.0";
 
+/// Info table row holding the version of the schema, as upgraded by `migrate_database`.
+const SCHEMA_VERSION_KEY: &str = "SchemaVersion";
+
+/// Current version of the schema. Bump it along with new steps of `migrate_database`.
+const SCHEMA_VERSION: u32 = 2;
+
 const INDEXES_SQL: &str = include_str!("indexes.sql");
 
 const DELETE_INDEXES_SQL: &str = include_str!("delete_indexes.sql");
 
 const CREATE_TABLES_SQL: &str = include_str!("create.sql");
 
+const CREATE_COMMENTS_SQL: &str = "CREATE TABLE IF NOT EXISTS Comments (
+    ID INTEGER PRIMARY KEY AUTOINCREMENT,
+    GameID INTEGER,
+    Ply INTEGER,
+    Comment TEXT,
+    Nags TEXT,
+    FOREIGN KEY(GameID) REFERENCES Games ON DELETE CASCADE
+);";
+
 const WHITE_PAWN: Piece = Piece {
     color: shakmaty::Color::White,
     role: shakmaty::Role::Pawn,
@@ -152,6 +197,7 @@ fn get_db_or_create(
                 .max_size(16)
                 .connection_customizer(Box::new(options))
                 .build(ConnectionManager::<SqliteConnection>::new(db_path))?;
+            migrate_database(&mut pool.get()?)?;
             state
                 .connection_pool
                 .insert(db_path.to_string(), pool.clone());
@@ -162,6 +208,157 @@ fn get_db_or_create(
     Ok(pool.get()?)
 }
 
+#[derive(QueryableByName, Debug)]
+struct ColumnInfo {
+    #[diesel(sql_type = Text)]
+    name: String,
+}
+
+fn get_columns(conn: &mut SqliteConnection, table: &str) -> Result<Vec<String>, Error> {
+    let columns: Vec<ColumnInfo> = sql_query("SELECT name FROM pragma_table_info(?);")
+        .bind::<Text, _>(table)
+        .load(conn)?;
+    Ok(columns.into_iter().map(|c| c.name).collect())
+}
+
+/// Columns added to the Games table by later versions, with their definition.
+const GAMES_COLUMNS: &[(&str, &str)] = &[
+    ("Speed", "INTEGER"),
+    ("GameHash", "INTEGER"),
+    ("WhiteTitle", "TEXT"),
+    ("BlackTitle", "TEXT"),
+    ("Variant", "TEXT"),
+    ("Clocks", "BLOB"),
+    ("Evals", "BLOB"),
+    ("Rated", "INTEGER"),
+    ("Termination", "TEXT"),
+    ("MaxRating", "INTEGER"),
+    ("MaterialSignatures", "BLOB"),
+    ("WhiteOriginalName", "TEXT"),
+    ("BlackOriginalName", "TEXT"),
+];
+
+/// Columns added to the Players table by later versions, with their definition.
+const PLAYERS_COLUMNS: &[(&str, &str)] = &[
+    ("Title", "TEXT"),
+    ("FideId", "INTEGER"),
+    ("GameCount", "INTEGER"),
+];
+
+/// Brings a database created by an older version up to date with the current schema.
+/// Databases already at [`SCHEMA_VERSION`] are left untouched.
+fn migrate_database(conn: &mut SqliteConnection) -> Result<(), Error> {
+    let games_columns = get_columns(conn, "Games")?;
+    if games_columns.is_empty() {
+        // Not a games database, or the tables haven't been created yet
+        return Ok(());
+    }
+
+    let version: Option<Option<String>> = info::table
+        .filter(info::name.eq(SCHEMA_VERSION_KEY))
+        .select(info::value)
+        .first(conn)
+        .optional()?;
+    let version = version
+        .flatten()
+        .and_then(|v| v.parse::<u32>().ok())
+        .unwrap_or(0);
+    if version >= SCHEMA_VERSION {
+        return Ok(());
+    }
+
+    for (name, definition) in GAMES_COLUMNS {
+        if !games_columns.iter().any(|c| c == name) {
+            conn.batch_execute(&format!(
+                "ALTER TABLE Games ADD COLUMN {name} {definition};"
+            ))?;
+        }
+    }
+
+    let players_columns = get_columns(conn, "Players")?;
+    for (name, definition) in PLAYERS_COLUMNS {
+        if !players_columns.iter().any(|c| c == name) {
+            conn.batch_execute(&format!(
+                "ALTER TABLE Players ADD COLUMN {name} {definition};"
+            ))?;
+        }
+    }
+    drop_unique_player_names(conn)?;
+    conn.batch_execute(
+        "CREATE UNIQUE INDEX IF NOT EXISTS players_fide_id_idx ON Players(FideId);
+        CREATE INDEX IF NOT EXISTS players_name_idx ON Players(Name);
+        CREATE INDEX IF NOT EXISTS players_name_nocase_idx ON Players(Name COLLATE NOCASE);
+        CREATE INDEX IF NOT EXISTS players_game_count_idx ON Players(GameCount);",
+    )?;
+
+    if get_columns(conn, "Comments")?.is_empty() {
+        conn.batch_execute(CREATE_COMMENTS_SQL)?;
+    }
+
+    // Older databases stored the PGN result text in the INTEGER column, which
+    // SQLite kept as text. `Round` stays declared as INTEGER there, but its
+    // affinity leaves non-numeric rounds like "4.1" as text anyway.
+    conn.batch_execute(
+        "UPDATE Games SET Result = CASE Result
+            WHEN '*' THEN 0
+            WHEN '1-0' THEN 1
+            WHEN '0-1' THEN 2
+            WHEN '1/2-1/2' THEN 3
+            ELSE NULL
+        END
+        WHERE typeof(Result) = 'text';",
+    )?;
+
+    set_info(conn, SCHEMA_VERSION_KEY, &SCHEMA_VERSION.to_string())
+}
+
+/// Rebuilds the Players table of databases created when player names were
+/// `UNIQUE`, so namesakes with different FIDE IDs get their own rows. The IDs
+/// and the indexes of the table are kept.
+fn drop_unique_player_names(conn: &mut SqliteConnection) -> Result<(), Error> {
+    let unique: Vec<ColumnInfo> =
+        sql_query("SELECT name FROM pragma_index_list('Players') WHERE origin = 'u';")
+            .load(conn)?;
+    if unique.is_empty() {
+        return Ok(());
+    }
+    let indexes: Vec<ColumnInfo> = sql_query(
+        "SELECT sql AS name FROM sqlite_master
+        WHERE type = 'index' AND tbl_name = 'Players' AND sql IS NOT NULL;",
+    )
+    .load(conn)?;
+    let foreign_keys: Vec<ColumnInfo> =
+        sql_query("SELECT CAST(foreign_keys AS TEXT) AS name FROM pragma_foreign_keys;")
+            .load(conn)?;
+
+    // The games keep referencing the players while the table is swapped
+    conn.batch_execute("PRAGMA foreign_keys = OFF;")?;
+    let result = conn.transaction::<_, Error, _>(|conn| {
+        conn.batch_execute(
+            "CREATE TABLE NewPlayers (
+                ID INTEGER PRIMARY KEY,
+                Name TEXT,
+                Elo INTEGER,
+                Title TEXT,
+                FideId INTEGER,
+                GameCount INTEGER
+            );
+            INSERT INTO NewPlayers (ID, Name, Elo, Title, FideId, GameCount)
+                SELECT ID, Name, Elo, Title, FideId, GameCount FROM Players;
+            DROP TABLE Players;
+            ALTER TABLE NewPlayers RENAME TO Players;",
+        )?;
+        for index in &indexes {
+            conn.batch_execute(&index.name)?;
+        }
+        Ok(())
+    });
+    if foreign_keys.first().is_some_and(|f| f.name == "1") {
+        conn.batch_execute("PRAGMA foreign_keys = ON;")?;
+    }
+    result
+}
+
 #[derive(Debug)]
 pub struct MaterialColor {
     white: u8,
@@ -184,6 +381,415 @@ pub struct TempPlayer {
     rating: Option<i32>,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Type)]
+#[serde(rename_all = "lowercase")]
+pub enum Speed {
+    Bullet = 0,
+    Blitz = 1,
+    Rapid = 2,
+    Classical = 3,
+    Correspondence = 4,
+}
+
+impl Speed {
+    /// Classifies a PGN `TimeControl` header like lichess does, from the
+    /// estimated duration of a 40 move game.
+    pub fn from_time_control(time_control: &str) -> Option<Speed> {
+        let time_control = time_control.trim();
+        if time_control == "-" {
+            return Some(Speed::Correspondence);
+        }
+
+        let first_period = time_control.split(':').next()?;
+        let estimated = if let Some((moves, seconds)) = first_period.split_once('/') {
+            // moves/seconds, as in 40/7200 or the 1/86400 of daily games
+            let moves: u32 = moves.parse().ok()?;
+            let seconds: u32 = seconds.parse().ok()?;
+            if seconds / moves.max(1) >= 86400 {
+                return Some(Speed::Correspondence);
+            }
+            seconds
+        } else {
+            let (base, increment) = first_period.split_once('+').unwrap_or((first_period, "0"));
+            let base: u32 = base.parse().ok()?;
+            let increment: u32 = increment.parse().ok()?;
+            base + 40 * increment
+        };
+
+        Some(match estimated {
+            0..=179 => Speed::Bullet,
+            180..=479 => Speed::Blitz,
+            480..=1499 => Speed::Rapid,
+            _ => Speed::Classical,
+        })
+    }
+
+    pub fn from_i32(value: i32) -> Option<Speed> {
+        match value {
+            0 => Some(Speed::Bullet),
+            1 => Some(Speed::Blitz),
+            2 => Some(Speed::Rapid),
+            3 => Some(Speed::Classical),
+            4 => Some(Speed::Correspondence),
+            _ => None,
+        }
+    }
+}
+
+/// Outcome of a game, stored in the `Result` column as a small integer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Type)]
+pub enum GameResult {
+    #[serde(rename = "*")]
+    Unknown = 0,
+    #[serde(rename = "1-0")]
+    WhiteWins = 1,
+    #[serde(rename = "0-1")]
+    BlackWins = 2,
+    #[serde(rename = "1/2-1/2")]
+    Draw = 3,
+}
+
+impl GameResult {
+    const DECIDED: [i32; 3] = [
+        GameResult::WhiteWins as i32,
+        GameResult::BlackWins as i32,
+        GameResult::Draw as i32,
+    ];
+
+    /// Parses a PGN `Result` tag, also accepting the `½-½` some sites write
+    /// for draws.
+    pub fn from_pgn(value: &str) -> Option<GameResult> {
+        match value.trim() {
+            "1-0" => Some(GameResult::WhiteWins),
+            "0-1" => Some(GameResult::BlackWins),
+            "1/2-1/2" | "½-½" => Some(GameResult::Draw),
+            "*" | "" => Some(GameResult::Unknown),
+            _ => None,
+        }
+    }
+
+    pub fn from_i32(value: i32) -> Option<GameResult> {
+        match value {
+            0 => Some(GameResult::Unknown),
+            1 => Some(GameResult::WhiteWins),
+            2 => Some(GameResult::BlackWins),
+            3 => Some(GameResult::Draw),
+            _ => None,
+        }
+    }
+
+    /// The points of the player with the given color, or `None` for an
+    /// unknown result.
+    pub fn points(self, as_white: bool) -> Option<f64> {
+        match (self, as_white) {
+            (GameResult::WhiteWins, true) | (GameResult::BlackWins, false) => Some(1.0),
+            (GameResult::Draw, _) => Some(0.5),
+            (GameResult::WhiteWins, false) | (GameResult::BlackWins, true) => Some(0.0),
+            (GameResult::Unknown, _) => None,
+        }
+    }
+
+    /// The result stored in the `Result` column, where `NULL` is also an
+    /// unknown result.
+    pub fn from_column(value: Option<i32>) -> GameResult {
+        value
+            .and_then(GameResult::from_i32)
+            .unwrap_or(GameResult::Unknown)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            GameResult::Unknown => "*",
+            GameResult::WhiteWins => "1-0",
+            GameResult::BlackWins => "0-1",
+            GameResult::Draw => "1/2-1/2",
+        }
+    }
+}
+
+/// How a game ended, grouping the common values of the `Termination` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Type)]
+#[serde(rename_all = "snake_case")]
+pub enum Termination {
+    Normal,
+    TimeForfeit,
+    Abandoned,
+    RulesInfraction,
+    /// Any other value, or no `Termination` header.
+    Unknown,
+}
+
+impl Termination {
+    const KNOWN: [Termination; 4] = [
+        Termination::Normal,
+        Termination::TimeForfeit,
+        Termination::Abandoned,
+        Termination::RulesInfraction,
+    ];
+
+    /// The header value of a known termination.
+    fn header(self) -> Option<&'static str> {
+        match self {
+            Termination::Normal => Some("Normal"),
+            Termination::TimeForfeit => Some("Time forfeit"),
+            Termination::Abandoned => Some("Abandoned"),
+            Termination::RulesInfraction => Some("Rules infraction"),
+            Termination::Unknown => None,
+        }
+    }
+
+    pub fn from_header(value: &str) -> Termination {
+        let value = value.trim();
+        Termination::KNOWN
+            .into_iter()
+            .find(|t| t.header().is_some_and(|h| h.eq_ignore_ascii_case(value)))
+            .unwrap_or(Termination::Unknown)
+    }
+}
+
+/// Hash identifying a game by its players, date, starting position and
+/// mainline, used to find duplicates. Other headers, like `Site`, are left out
+/// so the same game coming from different sources still matches.
+fn game_hash(
+    white: Option<&str>,
+    black: Option<&str>,
+    date: Option<&str>,
+    fen: Option<&str>,
+    moves: &[u8],
+) -> i64 {
+    let mut hasher = Sha256::new();
+    for field in [white, black, date, fen] {
+        hasher.update(field.unwrap_or_default().trim().as_bytes());
+        hasher.update([0]);
+    }
+    hasher.update(mainline_moves(moves));
+    let digest = hasher.finalize();
+    i64::from_be_bytes(digest[..8].try_into().unwrap())
+}
+
+/// Computes the missing `GameHash` of games imported before it existed.
+fn fill_game_hashes(db: &mut SqliteConnection) -> Result<(), Error> {
+    let mut names: Option<HashMap<i32, Option<String>>> = None;
+    let mut last_id = i32::MIN;
+    loop {
+        let missing: Vec<(i32, i32, i32, Option<String>, Option<String>, Vec<u8>)> = games::table
+            .filter(games::game_hash.is_null())
+            .filter(games::id.gt(last_id))
+            .select((
+                games::id,
+                games::white_id,
+                games::black_id,
+                games::date,
+                games::fen,
+                games::moves,
+            ))
+            .order(games::id)
+            .limit(DEFAULT_IMPORT_BATCH_SIZE as i64)
+            .load(db)?;
+        let Some((id, ..)) = missing.last() else {
+            return Ok(());
+        };
+        last_id = *id;
+
+        if names.is_none() {
+            names = Some(
+                players::table
+                    .select((players::id, players::name))
+                    .load(db)?
+                    .into_iter()
+                    .collect(),
+            );
+        }
+        let names = names.as_ref().unwrap();
+        let name = |id: i32| names.get(&id).and_then(|n| n.as_deref());
+
+        db.transaction::<_, Error, _>(|db| {
+            for (id, white_id, black_id, date, fen, moves) in &missing {
+                let hash = game_hash(
+                    name(*white_id),
+                    name(*black_id),
+                    date.as_deref(),
+                    fen.as_deref(),
+                    moves,
+                );
+                diesel::update(games::table.find(*id))
+                    .set(games::game_hash.eq(hash))
+                    .execute(db)?;
+            }
+            Ok(())
+        })?;
+    }
+}
+
+/// Loads the hashes of every game in the database, filling the missing ones first.
+fn load_game_hashes(db: &mut SqliteConnection) -> Result<HashSet<i64>, Error> {
+    fill_game_hashes(db)?;
+    let hashes: Vec<Option<i64>> = games::table.select(games::game_hash).load(db)?;
+    Ok(hashes.into_iter().flatten().collect())
+}
+
+#[derive(Default, Debug)]
+pub struct TempComment {
+    pub ply: i32,
+    pub comment: Option<String>,
+    pub nags: Vec<u8>,
+}
+
+/// Most names kept per table by [`NameCache`]. With names averaging around
+/// 20 bytes this bounds each table to a few tens of MB.
+const MAX_CACHED_NAMES: usize = 1_000_000;
+
+/// In-memory name to ID maps for players, events and sites, so each name only
+/// hits the database the first time it's seen during an import.
+#[derive(Default, Debug)]
+pub struct NameCache {
+    players: HashMap<String, i32>,
+    /// Players by FIDE ID, which takes precedence over the name.
+    fide_players: HashMap<i32, i32>,
+    /// Players whose title was already stored during this import.
+    titled_players: HashSet<i32>,
+    events: HashMap<String, i32>,
+    sites: HashMap<String, i32>,
+}
+
+impl NameCache {
+    /// Pre-warms the cache with the names already stored in `db`.
+    fn load(db: &mut SqliteConnection) -> Result<NameCache, diesel::result::Error> {
+        let load = |rows: Vec<(i32, Option<String>)>| {
+            rows.into_iter()
+                .filter_map(|(id, name)| Some((name?, id)))
+                .take(MAX_CACHED_NAMES)
+                .collect()
+        };
+        let fide_players: Vec<(i32, Option<i32>)> = players::table
+            .filter(players::fide_id.is_not_null())
+            .select((players::id, players::fide_id))
+            .load(db)?;
+        Ok(NameCache {
+            // Namesakes resolve to the first player, as in `create_player`
+            players: load(
+                players::table
+                    .select((players::id, players::name))
+                    .order(players::id.desc())
+                    .load(db)?,
+            ),
+            fide_players: fide_players
+                .into_iter()
+                .filter_map(|(id, fide_id)| Some((fide_id?, id)))
+                .take(MAX_CACHED_NAMES)
+                .collect(),
+            titled_players: HashSet::new(),
+            events: load(events::table.select((events::id, events::name)).load(db)?),
+            sites: load(sites::table.select((sites::id, sites::name)).load(db)?),
+        })
+    }
+
+    fn clear(&mut self) {
+        self.players.clear();
+        self.fide_players.clear();
+        self.titled_players.clear();
+        self.events.clear();
+        self.sites.clear();
+    }
+
+    fn get_or_create(
+        cache: &mut HashMap<String, i32>,
+        name: &str,
+        create: impl FnOnce() -> Result<i32, diesel::result::Error>,
+    ) -> Result<i32, diesel::result::Error> {
+        if let Some(id) = cache.get(name) {
+            return Ok(*id);
+        }
+        let id = create()?;
+        if cache.len() < MAX_CACHED_NAMES {
+            cache.insert(name.to_string(), id);
+        }
+        Ok(id)
+    }
+
+    /// Marks `id` as seen in `seen`, returning whether it's the first time.
+    /// Once the set is full every ID counts as new, which only costs a
+    /// redundant update.
+    fn first_seen(seen: &mut HashSet<i32>, id: i32) -> bool {
+        if seen.contains(&id) {
+            return false;
+        }
+        if seen.len() < MAX_CACHED_NAMES {
+            seen.insert(id);
+        }
+        true
+    }
+
+    fn player(
+        &mut self,
+        db: &mut SqliteConnection,
+        name: &str,
+        fide_id: Option<i32>,
+    ) -> Result<i32, diesel::result::Error> {
+        let Some(fide_id) = fide_id else {
+            return Self::get_or_create(&mut self.players, name, || {
+                Ok(create_player(db, name, None)?.id)
+            });
+        };
+        if let Some(id) = self.fide_players.get(&fide_id) {
+            return Ok(*id);
+        }
+        let id = create_player(db, name, Some(fide_id))?.id;
+        if self.fide_players.len() < MAX_CACHED_NAMES {
+            self.fide_players.insert(fide_id, id);
+        }
+        Ok(id)
+    }
+
+    /// Stores the title of a player, unless it already has one.
+    fn player_title(
+        &mut self,
+        db: &mut SqliteConnection,
+        id: i32,
+        title: &str,
+    ) -> Result<(), diesel::result::Error> {
+        if Self::first_seen(&mut self.titled_players, id) {
+            diesel::update(players::table.find(id))
+                .filter(players::title.is_null())
+                .set(players::title.eq(title))
+                .execute(db)?;
+        }
+        Ok(())
+    }
+
+    fn event(
+        &mut self,
+        db: &mut SqliteConnection,
+        name: &str,
+    ) -> Result<i32, diesel::result::Error> {
+        Self::get_or_create(&mut self.events, name, || Ok(create_event(db, name)?.id))
+    }
+
+    fn site(
+        &mut self,
+        db: &mut SqliteConnection,
+        name: &str,
+    ) -> Result<i32, diesel::result::Error> {
+        Self::get_or_create(&mut self.sites, name, || Ok(create_site(db, name)?.id))
+    }
+
+    /// Finds or creates the player of another database, with its title.
+    fn copy_player(
+        &mut self,
+        db: &mut SqliteConnection,
+        player: &Player,
+    ) -> Result<i32, diesel::result::Error> {
+        let Some(name) = player.name.as_deref().filter(|_| player.id != 0) else {
+            return Ok(0);
+        };
+        let id = self.player(db, name, player.fide_id)?;
+        if let Some(title) = &player.title {
+            self.player_title(db, id, title)?;
+        }
+        Ok(id)
+    }
+}
+
 #[derive(Default, Debug)]
 pub struct TempGame {
     pub event_name: Option<String>,
@@ -192,49 +798,97 @@ pub struct TempGame {
     pub time: Option<String>,
     pub round: Option<String>,
     pub white_name: Option<String>,
+    /// The `White` header, when `white_name` is its normalized form.
+    pub white_original_name: Option<String>,
     pub white_elo: Option<i32>,
+    pub white_title: Option<String>,
+    pub white_fide_id: Option<i32>,
     pub black_name: Option<String>,
+    pub black_original_name: Option<String>,
     pub black_elo: Option<i32>,
-    pub result: Option<String>,
+    pub black_title: Option<String>,
+    pub black_fide_id: Option<i32>,
+    pub result: Option<GameResult>,
+    pub termination: Option<String>,
     pub time_control: Option<String>,
+    pub speed: Option<Speed>,
+    pub rated: Option<bool>,
+    pub game_hash: Option<i64>,
     pub eco: Option<String>,
     pub fen: Option<String>,
+    /// `None` for standard chess.
+    pub variant: Option<String>,
     pub moves: Vec<u8>,
+    pub comments: Vec<TempComment>,
+    pub clocks: Vec<u32>,
+    pub evals: Vec<i16>,
+    /// Position of the game in its input, starting at 1.
+    pub index: usize,
     pub position: Chess,
     pub material_count: MaterialColor,
 }
 
 impl TempGame {
-    pub fn insert_to_db(&self, db: &mut SqliteConnection) -> Result<(), diesel::result::Error> {
+    /// A short description of the game for error messages.
+    fn describe(&self) -> String {
+        format!(
+            "{} - {}, {}, {}",
+            self.white_name.as_deref().unwrap_or("?"),
+            self.black_name.as_deref().unwrap_or("?"),
+            self.event_name.as_deref().unwrap_or("?"),
+            self.date.as_deref().unwrap_or("?"),
+        )
+    }
+
+    fn has_bot(&self) -> bool {
+        [&self.white_title, &self.black_title]
+            .iter()
+            .any(|title| title.as_deref() == Some("BOT"))
+    }
+
+    pub fn insert_to_db(
+        &self,
+        db: &mut SqliteConnection,
+        names: &mut NameCache,
+    ) -> Result<Game, diesel::result::Error> {
         let pawn_home = get_pawn_home(self.position.board());
 
         let white_id = if let Some(name) = &self.white_name {
-            create_player(db, name)?.id
+            names.player(db, name, self.white_fide_id)?
         } else {
             0
         };
         let black_id = if let Some(name) = &self.black_name {
-            create_player(db, name)?.id
+            names.player(db, name, self.black_fide_id)?
         } else {
             0
         };
+        for (id, title) in [(white_id, &self.white_title), (black_id, &self.black_title)] {
+            match title {
+                Some(title) if id != 0 => names.player_title(db, id, title)?,
+                _ => {}
+            }
+        }
 
         let event_id = if let Some(name) = &self.event_name {
-            create_event(db, name)?.id
+            names.event(db, name)?
         } else {
             0
         };
 
         let site_id = if let Some(name) = &self.site_name {
-            create_site(db, name)?.id
+            names.site(db, name)?
         } else {
             0
         };
 
-        let ply_count = (self.moves.len()) as i32;
+        let ply_count = mainline_moves(&self.moves).len() as i32;
+        let clocks = (!self.clocks.is_empty()).then(|| encode_clocks(&self.clocks));
+        let evals = (!self.evals.is_empty()).then(|| encode_evals(&self.evals));
         let final_material = get_material_count(self.position.board());
         let minimal_white_material = self.material_count.white.min(final_material.white) as i32;
         let minimal_black_material = self.material_count.black.min(final_material.black) as i32;
+        let material_signatures = encode_material_signatures(&self.moves, self.fen.as_deref());
 
         let new_game = NewGame {
             white_id,
@@ -246,36 +900,623 @@ impl TempGame {
             black_elo: self.black_elo,
             white_material: minimal_white_material,
             black_material: minimal_black_material,
-            // max_rating: self.game.white.rating.max(self.game.black.rating),
             date: self.date.as_deref(),
             time: self.time.as_deref(),
             time_control: self.time_control.as_deref(),
+            speed: self.speed.map(|s| s as i32),
+            game_hash: self.game_hash,
+            white_title: self.white_title.as_deref(),
+            black_title: self.black_title.as_deref(),
+            variant: self.variant.as_deref(),
+            clocks: clocks.as_deref(),
+            evals: evals.as_deref(),
+            rated: self.rated,
+            termination: self.termination.as_deref(),
+            max_rating: self.white_elo.max(self.black_elo),
+            material_signatures: material_signatures.as_deref(),
+            white_original_name: self.white_original_name.as_deref(),
+            black_original_name: self.black_original_name.as_deref(),
             site_id,
             event_id,
             fen: self.fen.as_deref(),
-            result: self.result.as_deref(),
+            result: self.result.map(|r| r as i32),
             moves: self.moves.as_slice(),
             pawn_home: pawn_home as i32,
         };
 
-        create_game(db, new_game)?;
+        let game = create_game(db, new_game)?;
+
+        if !self.comments.is_empty() {
+            let nags: Vec<Option<String>> = self
+                .comments
+                .iter()
+                .map(|c| {
+                    (!c.nags.is_empty()).then(|| {
+                        c.nags
+                            .iter()
+                            .map(|nag| format!("${nag}"))
+                            .collect::<Vec<_>>()
+                            .join(" ")
+                    })
+                })
+                .collect();
+            let new_comments: Vec<NewComment> = self
+                .comments
+                .iter()
+                .zip(nags.iter())
+                .map(|(c, nags)| NewComment {
+                    game_id: game.id,
+                    ply: c.ply,
+                    comment: c.comment.as_deref(),
+                    nags: nags.as_deref(),
+                })
+                .collect();
+            insert_into(comments::table)
+                .values(&new_comments)
+                .execute(db)?;
+        }
+        Ok(game)
+    }
+}
+
+/// The peak ratings and the number of games of the players of the games
+/// inserted in a transaction, to update the players once at the end.
+#[derive(Default)]
+struct PlayerTally {
+    peaks: HashMap<i32, i32>,
+    games: HashMap<i32, i32>,
+}
+
+impl PlayerTally {
+    /// Records a game of `player`, rated `elo` in it.
+    fn add(&mut self, player: i32, elo: Option<i32>) {
+        *self.games.entry(player).or_default() += 1;
+        if let Some(elo) = elo {
+            let peak = self.peaks.entry(player).or_insert(elo);
+            *peak = (*peak).max(elo);
+        }
+    }
+
+    /// Raises the `Elo` of each player to its peak rating, keeping the stored
+    /// one if it's higher, and adds the games to their `GameCount`.
+    fn apply(&self, db: &mut SqliteConnection) -> Result<(), diesel::result::Error> {
+        for (id, elo) in &self.peaks {
+            diesel::update(players::table.find(*id))
+                .filter(players::elo.is_null().or(players::elo.lt(*elo)))
+                .set(players::elo.eq(*elo))
+                .execute(db)?;
+        }
+        // Left uncounted in databases that haven't been counted yet
+        for (id, games) in &self.games {
+            diesel::update(players::table.find(*id))
+                .set(players::game_count.eq(players::game_count + *games))
+                .execute(db)?;
+        }
         Ok(())
     }
 }
 
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Type)]
+#[serde(default)]
+pub struct ImportOptions {
+    /// Keep the sidelines of every game instead of only the mainline.
+    pub include_variations: bool,
+    /// Keep the comments and NAGs of every move.
+    pub keep_annotations: bool,
+    pub filter: ImportFilter,
+    /// Number of games committed per transaction, defaults to
+    /// [`DEFAULT_IMPORT_BATCH_SIZE`].
+    pub batch_size: Option<u32>,
+    pub existing: ExistingDatabase,
+    /// Skip games whose players, date and moves match an already imported game.
+    pub deduplicate: bool,
+    /// Skip games where either player has the `BOT` title.
+    pub exclude_bot_games: bool,
+    /// Store the `[%clk]` and `[%eval]` annotations of the mainline moves.
+    pub keep_clocks_and_evals: bool,
+    /// Find the ECO code of games without an `ECO` header from their moves.
+    pub classify_openings: bool,
+    /// Most skipped games listed in the summary, defaults to
+    /// [`DEFAULT_MAX_REPORTED_SKIPS`].
+    pub max_reported_skips: Option<u32>,
+    /// Also write the skipped games to a `<database>.skipped.txt` file next to
+    /// the database.
+    pub write_skip_report: bool,
+    /// Stop reading once this many games are inserted. Games that are
+    /// filtered out or fail to insert don't count.
+    pub max_games: Option<usize>,
+    /// Text encoding of the names, headers and comments of the PGN.
+    pub encoding: PgnEncoding,
+    /// Build the indexes of a new database once the games are in, defaults
+    /// to true. They can be built later with `build_indexes`.
+    pub create_indexes: Option<bool>,
+    /// Store the player names as `Lastname, Firstname`, without doubled
+    /// spaces or title suffixes, so that the spellings of a player end up in
+    /// the same row. See [`normalize_player_name`].
+    pub normalize_names: bool,
+}
+
+/// Text encoding of a PGN file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "lowercase")]
+pub enum PgnEncoding {
+    /// UTF-8 when the text is valid UTF-8, Latin-1 otherwise.
+    #[default]
+    Auto,
+    Utf8,
+    /// ISO 8859-1, read as its Windows-1252 superset like older ChessBase and
+    /// TWIC exports.
+    Latin1,
+}
+
+/// Characters of the 0x80-0x9F range of Windows-1252, where ISO 8859-1 only has
+/// control characters. The undefined bytes keep their Latin-1 meaning.
+const WINDOWS_1252_HIGH: [char; 32] = [
+    '\u{20AC}', '\u{81}', '\u{201A}', '\u{192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{2C6}', '\u{2030}', '\u{160}', '\u{2039}', '\u{152}', '\u{8D}', '\u{17D}', '\u{8F}',
+    '\u{90}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{2DC}', '\u{2122}', '\u{161}', '\u{203A}', '\u{153}', '\u{9D}', '\u{17E}', '\u{178}',
+];
+
+impl PgnEncoding {
+    fn decode(self, bytes: &[u8]) -> Cow<'_, str> {
+        match self {
+            PgnEncoding::Utf8 => String::from_utf8_lossy(bytes),
+            PgnEncoding::Auto => match std::str::from_utf8(bytes) {
+                Ok(text) => Cow::Borrowed(text),
+                Err(_) => Cow::Owned(decode_windows_1252(bytes)),
+            },
+            PgnEncoding::Latin1 if bytes.is_ascii() => {
+                // ASCII is valid UTF-8
+                Cow::Borrowed(std::str::from_utf8(bytes).unwrap())
+            }
+            PgnEncoding::Latin1 => Cow::Owned(decode_windows_1252(bytes)),
+        }
+    }
+}
+
+fn decode_windows_1252(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|&b| match b {
+            0x80..=0x9f => WINDOWS_1252_HIGH[(b - 0x80) as usize],
+            _ => b as char,
+        })
+        .collect()
+}
+
+/// Whether an `Event` header names a rated game, following the lichess
+/// naming (`Rated Blitz game`, `Casual Bullet game`). Other events are `None`.
+fn is_rated_event(event: &str) -> Option<bool> {
+    let first_word = event.split_whitespace().next()?;
+    if first_word.eq_ignore_ascii_case("rated") {
+        Some(true)
+    } else if first_word.eq_ignore_ascii_case("casual") {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// Converts a PGN date (`2023.01.05`, `2023.??.??`) into a sortable
+/// `YYYY-MM-DD` string, with `00` for an unknown month or day. Dates without a
+/// known year are `None`. Already normalized dates are returned unchanged.
+fn normalize_date(date: &str) -> Option<String> {
+    let mut parts = date.trim().split(['.', '-', '/']);
+    let year = parts.next().filter(|y| y.len() == 4)?.parse::<u16>().ok()?;
+    let mut part = |max: u8| {
+        parts
+            .next()
+            .and_then(|p| p.parse::<u8>().ok())
+            .filter(|n| (1..=max).contains(n))
+            .unwrap_or(0)
+    };
+    let month = part(12);
+    let day = if month == 0 { 0 } else { part(31) };
+    Some(format!("{year:04}-{month:02}-{day:02}"))
+}
+
+/// Normalizes a date coming from a query, so both PGN and ISO dates compare
+/// correctly against the stored ones.
+fn normalize_query_date(date: &str) -> String {
+    normalize_date(date).unwrap_or_else(|| date.to_string())
+}
+
+/// Normalizes the end of a date range so it's inclusive: an unknown month or
+/// day becomes `99`, and `2023` or `2023.05` cover the whole year or month.
+fn normalize_query_end_date(date: &str) -> String {
+    normalize_query_date(date).replace("-00", "-99")
+}
+
+/// Converts a normalized date back into the PGN format.
+fn pgn_date(date: &str) -> String {
+    date.split('-')
+        .enumerate()
+        .map(|(i, part)| if i > 0 && part == "00" { "??" } else { part })
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// Returns the value of a `[%tag value]` command in a comment.
+fn comment_command<'a>(comment: &'a str, tag: &str) -> Option<&'a str> {
+    let start = comment.find(&format!("[%{tag} "))? + tag.len() + 3;
+    let end = comment[start..].find(']')? + start;
+    Some(comment[start..end].trim())
+}
+
+/// Parses a `[%clk 0:02:58.5]` value into milliseconds.
+fn parse_clock(clock: &str) -> Option<u32> {
+    let mut seconds = 0.0;
+    for part in clock.split(':') {
+        seconds = seconds * 60.0 + part.parse::<f64>().ok()?;
+    }
+    Some((seconds * 1000.0).round() as u32)
+}
+
+/// Parses a `[%eval 0.31]` or `[%eval #-3]` value, ignoring a trailing depth
+/// (`[%eval 0.31,20]`).
+fn parse_eval(eval: &str) -> Option<i16> {
+    let eval = eval.split(',').next()?;
+    if let Some(mate) = eval.strip_prefix('#') {
+        let mate: i16 = mate.parse().ok()?;
+        let score = MATE_EVAL - mate.abs().min(MATE_EVAL - 1);
+        return Some(if mate < 0 { -score } else { score });
+    }
+    let pawns: f64 = eval.parse().ok()?;
+    Some(
+        (pawns * 100.0)
+            .round()
+            .clamp(-(MATE_EVAL as f64) + 1000.0, MATE_EVAL as f64 - 1000.0) as i16,
+    )
+}
+
+/// Plies looked at when finding the ECO code of a game, the longest lines of
+/// the openings table are shorter.
+const ECO_PLIES: usize = 20;
+
+/// Finds the ECO code of the deepest known opening position reached in the
+/// first moves of a game played from the standard starting position.
+fn classify_opening(moves: &[u8]) -> Option<String> {
+    let mut chess = Chess::default();
+    let mut eco = None;
+    for byte in mainline_moves(moves).iter().take(ECO_PLIES) {
+        let m = decode_move(*byte, &chess)?;
+        chess.play_unchecked(&m);
+        if let Some(code) = get_eco_from_setup(chess.clone().into_setup(EnPassantMode::Legal)) {
+            eco = Some(code);
+        }
+    }
+    eco.map(str::to_string)
+}
+
+/// Whether a `Variant` header names standard chess (or a game from a position).
+fn is_standard_variant(variant: &str) -> bool {
+    ["", "standard", "chess", "from position"].contains(&variant.to_lowercase().trim())
+}
+
+fn is_chess960_variant(variant: &str) -> bool {
+    let variant: String = variant
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .collect::<String>()
+        .to_lowercase();
+    ["chess960", "fischerandom", "fischerrandom"].contains(&variant.as_str())
+}
+
+/// What to do when the destination database of an import already exists.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "lowercase")]
+pub enum ExistingDatabase {
+    /// Add the games to the existing database.
+    #[default]
+    Append,
+    /// Delete the existing database and create a new one.
+    Overwrite,
+    /// Fail with [`Error::DatabaseAlreadyExists`].
+    Fail,
+}
+
+pub const DEFAULT_IMPORT_BATCH_SIZE: usize = 5000;
+
+pub const DEFAULT_MAX_REPORTED_SKIPS: usize = 1000;
+
+impl ImportOptions {
+    fn batch_size(&self) -> usize {
+        self.batch_size
+            .map_or(DEFAULT_IMPORT_BATCH_SIZE, |n| n as usize)
+            .max(1)
+    }
+
+    fn create_indexes(&self) -> bool {
+        self.create_indexes.unwrap_or(true)
+    }
+
+    fn max_reported_skips(&self) -> usize {
+        self.max_reported_skips
+            .map_or(DEFAULT_MAX_REPORTED_SKIPS, |n| n as usize)
+    }
+}
+
+/// Conditions a game has to meet to be imported.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Type)]
+#[serde(default)]
+pub struct ImportFilter {
+    pub min_white_elo: Option<i32>,
+    pub min_black_elo: Option<i32>,
+    pub min_average_elo: Option<i32>,
+    /// Skip games where either player has no rating. Otherwise unrated
+    /// players pass the rating thresholds.
+    pub require_elo: bool,
+    pub speeds: Option<Vec<Speed>>,
+    /// Keep games without a valid `TimeControl` when filtering by speed.
+    pub include_unknown_time_control: bool,
+    /// Skip games with fewer plies in their mainline.
+    pub min_ply_count: usize,
+    /// Skip casual games, as told by the lichess `Event` header. Games whose
+    /// event doesn't say are kept.
+    pub rated_only: bool,
+}
+
+impl ImportFilter {
+    fn accepts_elo(&self, white_elo: Option<i32>, black_elo: Option<i32>) -> bool {
+        if self.require_elo && (white_elo.is_none() || black_elo.is_none()) {
+            return false;
+        }
+        if let (Some(min), Some(elo)) = (self.min_white_elo, white_elo) {
+            if elo < min {
+                return false;
+            }
+        }
+        if let (Some(min), Some(elo)) = (self.min_black_elo, black_elo) {
+            if elo < min {
+                return false;
+            }
+        }
+        if let (Some(min), Some(white), Some(black)) = (self.min_average_elo, white_elo, black_elo)
+        {
+            if (white + black) / 2 < min {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn accepts_rated(&self, rated: Option<bool>) -> bool {
+        !self.rated_only || rated != Some(false)
+    }
+
+    fn accepts_speed(&self, speed: Option<Speed>) -> bool {
+        match (&self.speeds, speed) {
+            (None, _) => true,
+            (Some(speeds), Some(speed)) => speeds.contains(&speed),
+            (Some(_), None) => self.include_unknown_time_control,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Type)]
+pub struct ImportSummary {
+    pub imported: u32,
+    /// Games left out by the import filter.
+    pub filtered: u32,
+    /// Games with fewer plies than the filter's `min_ply_count`.
+    pub too_short: u32,
+    /// Games with an illegal move in their mainline.
+    pub illegal_move: u32,
+    /// Games starting from a FEN that couldn't be parsed or isn't a legal position.
+    pub invalid_fen: u32,
+    /// Games played before the `timestamp` of the import.
+    pub already_imported: u32,
+    /// Numeric headers, like `WhiteElo`, that couldn't be parsed and were ignored.
+    pub invalid_headers: u32,
+    /// Games already in the database or earlier in the input.
+    pub duplicates: u32,
+    /// Imported games played by a `BOT` titled player.
+    pub bot_games_included: u32,
+    /// Games skipped because of `exclude_bot_games`.
+    pub bot_games_excluded: u32,
+    /// Casual games skipped because of `rated_only`.
+    pub casual_excluded: u32,
+    /// Games of a variant other than standard chess and Chess960.
+    pub unsupported_variant: u32,
+    /// Games whose ECO code was found from their moves.
+    pub classified: u32,
+    /// Games that couldn't be inserted.
+    pub failed: u32,
+    /// Why the games were not inserted, only the first [`MAX_REPORTED_ERRORS`].
+    pub errors: Vec<ImportError>,
+    /// Why games were skipped, up to the `max_reported_skips` of the options.
+    pub skipped: Vec<SkippedGame>,
+    /// Whether the import stopped early because it reached `max_games`.
+    pub truncated: bool,
+    /// Time spent reading and inserting the games, in milliseconds.
+    pub parse_time: u32,
+    /// Time spent creating the indexes and updating the counts, in milliseconds.
+    pub index_time: u32,
+    /// Size of the database file after the import, in bytes.
+    pub storage_size: f64,
+}
+
+/// A variation being parsed, with what's needed to go back to its parent line.
+struct OpenVariation {
+    position: Chess,
+    previous_position: Chess,
+    start: usize,
+    invalid: bool,
+}
+
 struct Importer {
     game: TempGame,
     timestamp: Option<i64>,
     skip: bool,
+    options: ImportOptions,
+    previous_position: Chess,
+    variations: Vec<OpenVariation>,
+    filtered: usize,
+    too_short: usize,
+    illegal_move: usize,
+    invalid_fen: usize,
+    already_imported: usize,
+    invalid_headers: usize,
+    duplicates: usize,
+    bot_games_included: usize,
+    bot_games_excluded: usize,
+    casual_excluded: usize,
+    unsupported_variant: usize,
+    classified: usize,
+    /// Number of mainline moves played in the current game.
+    mainline_ply: usize,
+    /// Games read from the current input.
+    games_read: usize,
+    /// Games at the start of the input to skip, already imported before a
+    /// checkpoint.
+    skip_games: usize,
+    writer: GameWriter,
+    /// Hashes of the games in the database, only tracked when deduplicating.
+    hashes: HashSet<i64>,
+    skipped: Vec<SkippedGame>,
 }
 
 impl Importer {
-    fn new(timestamp: Option<i64>) -> Importer {
+    fn new(timestamp: Option<i64>, options: ImportOptions) -> Importer {
         Importer {
             game: TempGame::default(),
             timestamp,
             skip: false,
+            previous_position: Chess::default(),
+            variations: Vec::new(),
+            filtered: 0,
+            too_short: 0,
+            illegal_move: 0,
+            invalid_fen: 0,
+            already_imported: 0,
+            invalid_headers: 0,
+            duplicates: 0,
+            bot_games_included: 0,
+            bot_games_excluded: 0,
+            casual_excluded: 0,
+            unsupported_variant: 0,
+            classified: 0,
+            mainline_ply: 0,
+            games_read: 0,
+            skip_games: 0,
+            writer: GameWriter {
+                max_games: options.max_games,
+                ..Default::default()
+            },
+            hashes: HashSet::new(),
+            skipped: Vec::new(),
+            options,
+        }
+    }
+
+    /// The counters of the import so far, without the timings and size.
+    fn summary(&self, imported: usize) -> ImportSummary {
+        ImportSummary {
+            imported: imported as u32,
+            filtered: self.filtered as u32,
+            too_short: self.too_short as u32,
+            illegal_move: self.illegal_move as u32,
+            invalid_fen: self.invalid_fen as u32,
+            already_imported: self.already_imported as u32,
+            invalid_headers: self.invalid_headers as u32,
+            duplicates: self.duplicates as u32,
+            bot_games_included: self.bot_games_included as u32,
+            bot_games_excluded: self.bot_games_excluded as u32,
+            casual_excluded: self.casual_excluded as u32,
+            unsupported_variant: self.unsupported_variant as u32,
+            classified: self.classified as u32,
+            failed: self.writer.failed as u32,
+            errors: self.writer.errors.clone(),
+            skipped: self.skipped.clone(),
+            truncated: self.writer.truncated,
+            ..Default::default()
+        }
+    }
+
+    /// Parses a numeric header, tolerating surrounding spaces and a trailing `?`
+    /// (as in `"2345?"`). Empty or unknown values (`""`, `"?"`, `"-"`) are
+    /// `None`, anything else that isn't a number is also counted as invalid.
+    fn parse_number(&mut self, value: RawHeader<'_>) -> Option<i32> {
+        let value = value.decode_utf8_lossy();
+        let value = value.trim();
+        let value = value.strip_suffix('?').unwrap_or(value);
+        if value.is_empty() || value == "-" {
+            return None;
+        }
+        let number = value.parse().ok();
+        if number.is_none() {
+            self.invalid_headers += 1;
+        }
+        number
+    }
+
+    /// Stores the `[%clk]` and `[%eval]` of a comment after a mainline move.
+    fn read_clock_and_eval(&mut self, comment: &str) {
+        let Some(index) = self.mainline_ply.checked_sub(1) else {
+            return;
+        };
+        if let Some(clock) = comment_command(comment, "clk").and_then(parse_clock) {
+            let clocks = &mut self.game.clocks;
+            clocks.resize(clocks.len().max(index + 1), NO_CLOCK);
+            clocks[index] = clock;
+        }
+        if let Some(eval) = comment_command(comment, "eval").and_then(parse_eval) {
+            let evals = &mut self.game.evals;
+            evals.resize(evals.len().max(index + 1), NO_EVAL);
+            evals[index] = eval;
+        }
+    }
+
+    /// Skips the current game, which is the `index`th of the input, and
+    /// reports why if there's still room for it.
+    fn skip_game(&mut self, index: usize, reason: SkipReason) {
+        self.skip = true;
+        if self.skipped.len() < self.options.max_reported_skips() {
+            self.skipped.push(SkippedGame {
+                index: index as u32,
+                reason,
+                game: self.game.describe(),
+            });
+        }
+    }
+
+    /// Decodes a header value, unescaping it, with the encoding of the input.
+    fn decode<'a>(&self, value: &RawHeader<'a>) -> Cow<'a, str> {
+        match value.decode() {
+            Cow::Borrowed(bytes) => self.options.encoding.decode(bytes),
+            Cow::Owned(bytes) => Cow::Owned(self.options.encoding.decode(&bytes).into_owned()),
+        }
+    }
+
+    /// The name to store for a player header, and the header itself if
+    /// that's a different one.
+    fn player_name(&self, name: String) -> (Option<String>, Option<String>) {
+        if !self.options.normalize_names {
+            return (Some(name), None);
+        }
+        let normalized = normalize_player_name(&name);
+        if normalized == name {
+            (Some(name), None)
+        } else {
+            (Some(normalized), Some(name))
+        }
+    }
+
+    fn in_invalid_variation(&self) -> bool {
+        self.variations.last().is_some_and(|v| v.invalid)
+    }
+
+    /// Returns the annotation attached to the last encoded move, creating it if needed.
+    fn current_annotation(&mut self) -> &mut TempComment {
+        let ply = self.game.moves.len() as i32;
+        if self.game.comments.last().map_or(true, |c| c.ply != ply) {
+            self.game.comments.push(TempComment {
+                ply,
+                ..Default::default()
+            });
         }
+        self.game.comments.last_mut().unwrap()
     }
 }
 
@@ -284,59 +1525,96 @@ impl Visitor for Importer {
 
     fn begin_game(&mut self) {
         self.skip = false;
+        self.variations.clear();
+        self.mainline_ply = 0;
     }
 
     fn header(&mut self, key: &[u8], value: RawHeader<'_>) {
         if key == b"White" {
-            self.game.white_name = Some(value.decode_utf8_lossy().into_owned());
+            let name = self.decode(&value).into_owned();
+            (self.game.white_name, self.game.white_original_name) = self.player_name(name);
         } else if key == b"Black" {
-            self.game.black_name = Some(value.decode_utf8_lossy().into_owned());
+            let name = self.decode(&value).into_owned();
+            (self.game.black_name, self.game.black_original_name) = self.player_name(name);
         } else if key == b"WhiteElo" {
-            self.game.white_elo = btoi::btoi(value.as_bytes()).ok();
+            self.game.white_elo = self.parse_number(value);
         } else if key == b"BlackElo" {
-            self.game.black_elo = btoi::btoi(value.as_bytes()).ok();
+            self.game.black_elo = self.parse_number(value);
+        } else if key == b"WhiteFideId" {
+            self.game.white_fide_id = self.parse_number(value);
+        } else if key == b"BlackFideId" {
+            self.game.black_fide_id = self.parse_number(value);
+        } else if key == b"WhiteTitle" {
+            self.game.white_title = Some(self.decode(&value).into_owned());
+        } else if key == b"BlackTitle" {
+            self.game.black_title = Some(self.decode(&value).into_owned());
         } else if key == b"TimeControl" {
-            self.game.time_control = Some(value.decode_utf8_lossy().into_owned());
+            self.game.time_control = Some(self.decode(&value).into_owned());
         } else if key == b"ECO" {
-            self.game.eco = Some(value.decode_utf8_lossy().into_owned());
+            self.game.eco = Some(self.decode(&value).into_owned());
         } else if key == b"Round" {
-            self.game.round = Some(value.decode_utf8_lossy().into_owned());
+            self.game.round = Some(self.decode(&value).into_owned());
         } else if key == b"Date" || key == b"UTCDate" {
-            self.game.date = Some(String::from_utf8_lossy(value.as_bytes()).to_string());
+            self.game.date = normalize_date(&value.decode_utf8_lossy());
         } else if key == b"UTCTime" {
-            self.game.time = Some(String::from_utf8_lossy(value.as_bytes()).to_string());
+            self.game.time = Some(self.decode(&value).into_owned());
         } else if key == b"Site" {
-            self.game.site_name = Some(String::from_utf8_lossy(value.as_bytes()).to_string());
+            self.game.site_name = Some(self.decode(&value).into_owned());
         } else if key == b"Event" {
-            self.game.event_name = Some(String::from_utf8_lossy(value.as_bytes()).to_string());
+            self.game.event_name = Some(self.decode(&value).into_owned());
         } else if key == b"Result" {
-            self.game.result = Some(String::from_utf8_lossy(value.as_bytes()).to_string());
+            self.game.result = GameResult::from_pgn(&value.decode_utf8_lossy());
         } else if key == b"FEN" {
             if value.as_bytes() == b"rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1" {
                 self.game.fen = None;
             } else {
-                let fen = Fen::from_ascii(value.as_bytes());
-                if let Ok(fen) = fen {
-                    self.game.fen = Some(value.decode_utf8_lossy().into_owned());
-                    if let Ok(setup) =
-                        Chess::from_setup(fen.into_setup(), shakmaty::CastlingMode::Standard)
-                            .or_else(PositionError::ignore_too_much_material)
-                    {
-                        self.game.position = setup;
-                    } else {
-                        self.skip = true;
-                    }
-                } else {
-                    self.skip = true;
-                }
+                // Parsed in end_headers, once the variant is known
+                self.game.fen = Some(self.decode(&value).into_owned());
             }
+        } else if key == b"Termination" {
+            self.game.termination = Some(self.decode(&value).into_owned());
+        } else if key == b"Variant" {
+            self.game.variant = Some(self.decode(&value).into_owned());
         }
     }
 
     fn end_headers(&mut self) -> Skip {
+        if self.games_read < self.skip_games {
+            self.skip = true;
+            return Skip(true);
+        }
+
+        let mut castling_mode = CastlingMode::Standard;
+        match self.game.variant.as_deref() {
+            None => {}
+            Some(variant) if is_standard_variant(variant) => self.game.variant = None,
+            Some(variant) if is_chess960_variant(variant) => {
+                self.game.variant = Some("Chess960".to_string());
+                castling_mode = CastlingMode::Chess960;
+            }
+            Some(_) => {
+                self.skip_game(self.games_read + 1, SkipReason::UnsupportedVariant);
+                self.unsupported_variant += 1;
+            }
+        }
+
+        if let (false, Some(fen)) = (self.skip, &self.game.fen) {
+            let position = Fen::from_ascii(fen.as_bytes()).ok().and_then(|fen| {
+                Chess::from_setup(fen.into_setup(), castling_mode)
+                    .or_else(PositionError::ignore_too_much_material)
+                    .ok()
+            });
+            if let Some(position) = position {
+                self.game.position = position;
+            } else {
+                self.skip_game(self.games_read + 1, SkipReason::InvalidFen);
+                self.invalid_fen += 1;
+            }
+        }
+
         // Skip games with timestamp before
         let cur_timestamp = self.game.date.as_ref().and_then(|date| {
-            let date = NaiveDate::parse_from_str(date, "%Y.%m.%d").ok()?;
+            let date = NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?;
             let time = self
                 .game
                 .time
@@ -346,20 +1624,53 @@ impl Visitor for Importer {
         });
 
         if let (Some(cur_timestamp), Some(timestamp)) = (cur_timestamp, self.timestamp) {
-            if cur_timestamp <= timestamp {
-                self.skip = true;
+            if cur_timestamp <= timestamp && !self.skip {
+                self.skip_game(self.games_read + 1, SkipReason::AlreadyImported);
+                self.already_imported += 1;
             }
         }
 
-        // Skip games without ELO
-        // self.skip |= self.current.white_elo.is_none() || self.current.black_elo.is_none();
+        self.game.speed = self
+            .game
+            .time_control
+            .as_deref()
+            .and_then(Speed::from_time_control);
+
+        if !self.skip && self.options.exclude_bot_games && self.game.has_bot() {
+            self.skip_game(self.games_read + 1, SkipReason::BotGame);
+            self.bot_games_excluded += 1;
+        }
+
+        self.game.rated = self.game.event_name.as_deref().and_then(is_rated_event);
+        if !self.skip && !self.options.filter.accepts_rated(self.game.rated) {
+            self.skip_game(self.games_read + 1, SkipReason::Casual);
+            self.casual_excluded += 1;
+        }
+
+        if !self.skip
+            && !(self
+                .options
+                .filter
+                .accepts_elo(self.game.white_elo, self.game.black_elo)
+                && self.options.filter.accepts_speed(self.game.speed))
+        {
+            self.skip_game(self.games_read + 1, SkipReason::Filtered);
+            self.filtered += 1;
+        }
+
+        if self.options.include_variations {
+            self.previous_position = self.game.position.clone();
+        }
         Skip(self.skip)
     }
 
     fn san(&mut self, san: SanPlus) {
+        if self.skip || self.in_invalid_variation() {
+            return;
+        }
         let m = san.san.to_move(&self.game.position).ok();
         if let Some(m) = m {
-            if m.is_promotion() {
+            if m.is_promotion() && self.variations.is_empty() {
                 let cur_material = get_material_count(self.game.position.board());
                 if cur_material.white < self.game.material_count.white {
                     self.game.material_count.white = cur_material.white;
@@ -371,101 +1682,171 @@ impl Visitor for Importer {
             self.game
                 .moves
                 .push(encode_move(&m, &self.game.position).unwrap());
+            if self.options.include_variations {
+                self.previous_position = self.game.position.clone();
+            }
             self.game.position.play_unchecked(&m);
+            if self.variations.is_empty() {
+                self.mainline_ply += 1;
+            }
+        } else if let Some(variation) = self.variations.last_mut() {
+            // Illegal moves in a sideline only discard that sideline
+            variation.invalid = true;
         } else {
-            self.skip = true;
+            self.skip_game(self.games_read + 1, SkipReason::IllegalMove);
+            self.illegal_move += 1;
         }
     }
 
     fn begin_variation(&mut self) -> Skip {
-        Skip(true) // stay in the mainline
+        if !self.options.include_variations || self.skip || self.in_invalid_variation() {
+            return Skip(true); // stay in the mainline
+        }
+        self.variations.push(OpenVariation {
+            position: self.game.position.clone(),
+            previous_position: self.previous_position.clone(),
+            start: self.game.moves.len(),
+            invalid: false,
+        });
+        self.game.moves.push(START_VARIATION);
+        self.game.position = self.previous_position.clone();
+        Skip(false)
+    }
+
+    fn end_variation(&mut self) {
+        if let Some(variation) = self.variations.pop() {
+            if variation.invalid {
+                self.game.moves.truncate(variation.start);
+                self.game
+                    .comments
+                    .retain(|c| c.ply <= variation.start as i32);
+            } else {
+                self.game.moves.push(END_VARIATION);
+            }
+            self.game.position = variation.position;
+            self.previous_position = variation.previous_position;
+        }
+    }
+
+    fn comment(&mut self, comment: RawComment<'_>) {
+        if self.in_invalid_variation() {
+            return;
+        }
+        let text = self.options.encoding.decode(comment.as_bytes());
+        if self.options.keep_clocks_and_evals && self.variations.is_empty() {
+            self.read_clock_and_eval(&text);
+        }
+        if !self.options.keep_annotations {
+            return;
+        }
+        let text = text.trim().to_string();
+        let annotation = self.current_annotation();
+        match &mut annotation.comment {
+            Some(existing) => {
+                existing.push(' ');
+                existing.push_str(&text);
+            }
+            None => annotation.comment = Some(text),
+        }
+    }
+
+    fn nag(&mut self, nag: Nag) {
+        if !self.options.keep_annotations || self.in_invalid_variation() {
+            return;
+        }
+        self.current_annotation().nags.push(nag.0);
     }
 
     fn end_game(&mut self) -> Self::Result {
+        self.games_read += 1;
+        // Checked on the parsed moves, the PlyCount header is often missing or wrong
+        if !self.skip && mainline_moves(&self.game.moves).len() < self.options.filter.min_ply_count
+        {
+            self.skip_game(self.games_read, SkipReason::TooShort);
+            self.too_short += 1;
+        }
+        if !self.skip {
+            let hash = game_hash(
+                self.game.white_name.as_deref(),
+                self.game.black_name.as_deref(),
+                self.game.date.as_deref(),
+                self.game.fen.as_deref(),
+                &self.game.moves,
+            );
+            if self.options.deduplicate && !self.hashes.insert(hash) {
+                self.skip_game(self.games_read, SkipReason::Duplicate);
+                self.duplicates += 1;
+            }
+            self.game.game_hash = Some(hash);
+        }
+        if !self.skip && self.game.has_bot() {
+            self.bot_games_included += 1;
+        }
+        if !self.skip
+            && self.options.classify_openings
+            && self.game.eco.is_none()
+            && self.game.fen.is_none()
+        {
+            self.game.eco = classify_opening(&self.game.moves);
+            if self.game.eco.is_some() {
+                self.classified += 1;
+            }
+        }
         if self.skip {
             self.game = TempGame::default();
             None
         } else {
+            self.game.index = self.games_read;
             Some(std::mem::take(&mut self.game))
         }
     }
 }
 
-#[tauri::command]
-#[specta::specta]
-pub async fn convert_pgn(
-    file: PathBuf,
-    db_path: PathBuf,
-    timestamp: Option<i32>,
-    app: tauri::AppHandle,
-    title: String,
-    description: Option<String>,
-    state: tauri::State<'_, AppState>,
-) -> Result<(), Error> {
-    let description = description.unwrap_or_default();
-    let extension = file.extension();
-
-    let db_exists = db_path.exists();
-
-    // create the database file
-    let db = &mut get_db_or_create(
-        &state,
-        db_path.to_str().unwrap(),
-        ConnectionOptions {
-            enable_foreign_keys: false,
-            busy_timeout: None,
-            journal_mode: JournalMode::Off,
-        },
-    )?;
-
-    if !db_exists {
-        db.batch_execute(CREATE_TABLES_SQL)?;
-        db.batch_execute(
-            format!(
-                "INSERT INTO Info (Name, Value) VALUES (\"Version\", \"{DATABASE_VERSION}\");
-                INSERT INTO Info (Name, Value) VALUES (\"Title\", \"{title}\");
-                INSERT INTO Info (Name, Value) VALUES (\"Description\", \"{description}\");"
-            )
-            .as_str(),
-        )?;
-    }
-
-    let file = File::open(&file)?;
-
-    let uncompressed: Box<dyn std::io::Read + Send> = if extension == Some("bz2".as_ref()) {
-        Box::new(bzip2::read::MultiBzDecoder::new(file))
-    } else if extension == Some("zst".as_ref()) {
-        Box::new(zstd::Decoder::new(file)?)
-    } else {
-        Box::new(file)
-    };
+#[derive(Serialize, Debug, Clone, Type, tauri_specta::Event)]
+pub struct ImportProgress {
+    pub file: String,
+    pub file_index: u32,
+    pub file_count: u32,
+    pub games: u32,
+    pub elapsed: u32,
+}
 
-    // start counting time
-    let start = Instant::now();
+/// Creates the tables of a new database and stores its metadata.
+/// The title defaults to the name of the database file.
+fn init_database(
+    db: &mut SqliteConnection,
+    db_path: &Path,
+    title: Option<&str>,
+    description: Option<&str>,
+) -> Result<(), Error> {
+    db.batch_execute(CREATE_TABLES_SQL)?;
+    set_info(db, "Version", DATABASE_VERSION)?;
+    set_info(db, SCHEMA_VERSION_KEY, &SCHEMA_VERSION.to_string())?;
+    set_info(db, "Title", title.unwrap_or(&default_title(db_path)))?;
+    set_info(db, "Description", description.unwrap_or_default())?;
+    Ok(())
+}
 
-    let mut importer = Importer::new(timestamp.map(|t| t as i64));
-    db.transaction::<_, diesel::result::Error, _>(|db| {
-        for (i, game) in BufferedReader::new(uncompressed)
-            .into_iter(&mut importer)
-            .flatten()
-            .flatten()
-            .enumerate()
-        {
-            if i % 1000 == 0 {
-                let elapsed = start.elapsed().as_millis() as u32;
-                app.emit_all("convert_progress", (i, elapsed)).unwrap();
-            }
-            game.insert_to_db(db)?;
-        }
-        Ok(())
-    })?;
+fn default_title(db_path: &Path) -> String {
+    db_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "Untitled".to_string())
+}
 
-    if !db_exists {
-        // Create all the necessary indexes
-        db.batch_execute(INDEXES_SQL)?;
-    }
+/// Inserts or replaces a row of the Info table.
+fn set_info(db: &mut SqliteConnection, name: &str, value: &str) -> Result<(), Error> {
+    insert_into(info::table)
+        .values((info::name.eq(name), info::value.eq(value)))
+        .on_conflict(info::name)
+        .do_update()
+        .set(info::value.eq(value))
+        .execute(db)?;
+    Ok(())
+}
 
-    // get game, player, event and site counts and to the info table
+/// Stores the game, player, event and site counts in the Info table.
+fn update_info_counts(db: &mut SqliteConnection) -> Result<(), Error> {
     let game_count: i64 = games::table.count().get_result(db)?;
     let player_count: i64 = players::table.count().get_result(db)?;
     let event_count: i64 = events::table.count().get_result(db)?;
@@ -478,1107 +1859,9662 @@ pub async fn convert_pgn(
         ("SiteCount", site_count),
     ];
 
-    for c in counts.iter() {
-        insert_into(info::table)
-            .values((info::name.eq(c.0), info::value.eq(c.1.to_string())))
-            .on_conflict(info::name)
-            .do_update()
-            .set(info::value.eq(c.1.to_string()))
-            .execute(db)?;
+    for (name, count) in counts {
+        set_info(db, name, &count.to_string())?;
     }
-
     Ok(())
 }
 
-#[derive(Serialize)]
-pub struct DatabaseInfo {
-    title: String,
-    description: String,
-    player_count: usize,
-    event_count: usize,
-    game_count: usize,
-    storage_size: usize,
-    filename: String,
-    indexed: bool,
+/// Runs the steps shared by every import once all the games are inserted.
+fn finish_import(db: &mut SqliteConnection, create_indexes: bool) -> Result<(), Error> {
+    if create_indexes {
+        // Create all the necessary indexes
+        db.batch_execute(INDEXES_SQL)?;
+    }
+    update_info_counts(db)
 }
 
-#[derive(QueryableByName, Debug, Serialize)]
-struct IndexInfo {
-    #[diesel(sql_type = Text, column_name = "name")]
-    _name: String,
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const BZIP2_MAGIC: &[u8] = b"BZh";
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// Wraps `reader` in the right decoder. The format is decided by the magic
+/// bytes, so misnamed files still work, and by the extension only when there
+/// are no bytes to look at.
+fn decompress(
+    reader: impl Read + Send + 'static,
+    extension: Option<&str>,
+) -> Result<Box<dyn Read + Send>, Error> {
+    let mut reader = BufReader::new(reader);
+    let magic: Vec<u8> = reader.fill_buf()?.iter().take(4).copied().collect();
+
+    let format = if magic.starts_with(GZIP_MAGIC) {
+        Some("gz")
+    } else if magic.starts_with(BZIP2_MAGIC) {
+        Some("bz2")
+    } else if magic.starts_with(ZSTD_MAGIC) {
+        Some("zst")
+    } else if magic.is_empty() {
+        extension
+    } else {
+        None
+    };
+    let uncompressed: Box<dyn Read + Send> = match format {
+        Some("gz") => Box::new(flate2::bufread::MultiGzDecoder::new(reader)),
+        Some("bz2") => Box::new(bzip2::read::MultiBzDecoder::new(reader)),
+        Some("zst") => Box::new(zstd::Decoder::with_buffer(reader)?),
+        _ => Box::new(reader),
+    };
+    Ok(uncompressed)
 }
 
-fn check_index_exists(conn: &mut SqliteConnection) -> Result<bool, Error> {
-    let query = sql_query("SELECT name FROM pragma_index_list('Games');");
-    let indexes: Vec<IndexInfo> = query.load(conn)?;
-    Ok(!indexes.is_empty())
+/// Opens a PGN file, decompressing it on the fly if needed.
+fn open_pgn(path: &Path) -> Result<Box<dyn Read + Send>, Error> {
+    let extension = path.extension().and_then(|e| e.to_str());
+    decompress(File::open(path)?, extension)
 }
 
-#[tauri::command]
-pub async fn get_db_info(
-    file: PathBuf,
-    app: tauri::AppHandle,
-    state: tauri::State<'_, AppState>,
-) -> Result<DatabaseInfo, Error> {
-    let db_path = PathBuf::from("db").join(file);
+/// Most errors kept in the import summary, the others are only counted.
+const MAX_REPORTED_ERRORS: usize = 100;
+
+/// A game that couldn't be inserted.
+#[derive(Debug, Clone, Serialize, Type)]
+pub struct ImportError {
+    /// The file or URL the game comes from, `None` for other inputs.
+    pub file: Option<String>,
+    /// Position of the game in its input, starting at 1.
+    pub index: u32,
+    /// Players, event and date of the game.
+    pub game: String,
+    pub error: String,
+}
 
-    let path = resolve_path(
-        &app.config(),
-        app.package_info(),
-        &app.env(),
-        db_path,
-        Some(BaseDirectory::AppData),
-    )?;
+/// Why a game of the input wasn't imported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Type)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    UnsupportedVariant,
+    InvalidFen,
+    AlreadyImported,
+    BotGame,
+    Casual,
+    Filtered,
+    IllegalMove,
+    TooShort,
+    Duplicate,
+}
 
-    let db = &mut get_db_or_create(&state, path.to_str().unwrap(), ConnectionOptions::default())?;
+impl SkipReason {
+    fn description(self) -> &'static str {
+        match self {
+            SkipReason::UnsupportedVariant => "unsupported variant",
+            SkipReason::InvalidFen => "invalid FEN",
+            SkipReason::AlreadyImported => "already imported",
+            SkipReason::BotGame => "bot game",
+            SkipReason::Casual => "casual game",
+            SkipReason::Filtered => "filtered out",
+            SkipReason::IllegalMove => "illegal move",
+            SkipReason::TooShort => "too short",
+            SkipReason::Duplicate => "duplicate",
+        }
+    }
+}
 
-    let player_count = players::table.count().get_result::<i64>(db)? as usize;
-    let game_count = games::table.count().get_result::<i64>(db)? as usize;
-    let event_count = events::table.count().get_result::<i64>(db)? as usize;
+/// A game of the input that was skipped.
+#[derive(Debug, Clone, Serialize, Type)]
+pub struct SkippedGame {
+    /// Position of the game in its input, starting at 1.
+    pub index: u32,
+    pub reason: SkipReason,
+    /// Players, event and date of the game.
+    pub game: String,
+}
 
-    let title = match info::table
-        .filter(info::name.eq("Title"))
-        .first(db)
-        .map(|title_info: Info| title_info.value)
-    {
-        Ok(Some(title)) => title,
-        _ => "Untitled".to_string(),
-    };
+/// Writes the skipped games to `<database>.skipped.txt`, one per line.
+fn write_skip_report(db_path: &Path, skipped: &[SkippedGame]) -> Result<(), Error> {
+    let mut path = db_path.as_os_str().to_owned();
+    path.push(".skipped.txt");
+    let mut writer = BufWriter::new(File::create(path)?);
+    for game in skipped {
+        writeln!(
+            writer,
+            "{}\t{}\t{}",
+            game.index,
+            game.reason.description(),
+            game.game
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
 
-    let description = match info::table
-        .filter(info::name.eq("Description"))
-        .first(db)
-        .map(|description_info: Info| description_info.value)
-    {
-        Ok(Some(description)) => description,
-        _ => "".to_string(),
-    };
+/// The state of the thread inserting the games.
+#[derive(Default, Debug)]
+struct GameWriter {
+    names: NameCache,
+    /// The file or URL being imported, recorded in the errors.
+    source: Option<String>,
+    max_games: Option<usize>,
+    /// Games inserted across every input, and those of the interrupted import
+    /// when resuming, counted against `max_games`.
+    inserted: usize,
+    /// Whether the insertion stopped at `max_games`.
+    truncated: bool,
+    failed: usize,
+    errors: Vec<ImportError>,
+}
 
-    let storage_size = path.metadata()?.len() as usize;
-    let filename = path.file_name().expect("get filename").to_string_lossy();
+/// Whether an insert error concerns the database rather than a single game,
+/// like a full disk or a locked database, so the import can't go on.
+fn is_fatal(error: &diesel::result::Error) -> bool {
+    use diesel::result::{DatabaseErrorKind, Error as DieselError};
+    match error {
+        DieselError::DatabaseError(kind, _) => !matches!(
+            kind,
+            DatabaseErrorKind::UniqueViolation
+                | DatabaseErrorKind::ForeignKeyViolation
+                | DatabaseErrorKind::NotNullViolation
+                | DatabaseErrorKind::CheckViolation
+        ),
+        DieselError::NotFound
+        | DieselError::SerializationError(_)
+        | DieselError::DeserializationError(_) => false,
+        _ => true,
+    }
+}
 
-    let is_indexed = check_index_exists(db)?;
-    Ok(DatabaseInfo {
-        title,
-        description,
-        player_count,
-        game_count,
-        event_count,
-        storage_size,
-        filename: filename.to_string(),
-        indexed: is_indexed,
-    })
+/// Inserts `batch` in a single transaction and adds them to `count`. A game
+/// that fails to insert is rolled back on its own and recorded in the
+/// writer's errors, unless the error is fatal. Once the writer's `max_games`
+/// are inserted, the rest of the batch is dropped and the writer marked as
+/// truncated. The checkpoint, if any, is saved in the same transaction.
+fn flush_games(
+    db: &mut SqliteConnection,
+    batch: &mut Vec<TempGame>,
+    writer: &mut GameWriter,
+    count: &mut usize,
+    checkpoint: Option<&ImportCheckpoint>,
+) -> Result<(), Error> {
+    if batch.is_empty() {
+        return Ok(());
+    }
+    let result = db.transaction::<_, Error, _>(|db| {
+        let mut inserted = 0;
+        let mut truncated = false;
+        let mut tally = PlayerTally::default();
+        for game in batch.iter() {
+            if writer
+                .max_games
+                .is_some_and(|max| writer.inserted + inserted >= max)
+            {
+                truncated = true;
+                break;
+            }
+            // Nested transactions are savepoints
+            match db.transaction(|db| game.insert_to_db(db, &mut writer.names)) {
+                Ok(game) => {
+                    inserted += 1;
+                    tally.add(game.white_id, game.white_elo);
+                    tally.add(game.black_id, game.black_elo);
+                }
+                Err(e) if is_fatal(&e) => return Err(e.into()),
+                Err(e) => {
+                    // IDs cached while inserting the game were rolled back
+                    writer.names.clear();
+                    writer.failed += 1;
+                    if writer.errors.len() < MAX_REPORTED_ERRORS {
+                        writer.errors.push(ImportError {
+                            file: writer.source.clone(),
+                            index: game.index as u32,
+                            game: game.describe(),
+                            error: e.to_string(),
+                        });
+                    }
+                }
+            }
+        }
+        tally.apply(db)?;
+        if let Some(checkpoint) = checkpoint {
+            ImportCheckpoint {
+                games_inserted: writer.inserted + inserted,
+                ..checkpoint.clone()
+            }
+            .save(db)?;
+        }
+        Ok((inserted, truncated))
+    });
+    match result {
+        Ok((inserted, truncated)) => {
+            *count += inserted;
+            writer.inserted += inserted;
+            writer.truncated |= truncated;
+        }
+        Err(e) => {
+            // IDs created inside the rolled back transaction no longer exist
+            writer.names.clear();
+            return Err(e);
+        }
+    }
+    batch.clear();
+    Ok(())
 }
 
-#[tauri::command]
-pub async fn create_indexes(file: PathBuf, state: tauri::State<'_, AppState>) -> Result<(), Error> {
-    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+/// A source file of an import, as it was when the import started.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct SourceFile {
+    path: PathBuf,
+    size: u64,
+    modified: u64,
+}
 
-    db.batch_execute(INDEXES_SQL)?;
+impl SourceFile {
+    fn read(path: &Path) -> Result<SourceFile, Error> {
+        let metadata = path.metadata()?;
+        Ok(SourceFile {
+            path: path.to_path_buf(),
+            size: metadata.len(),
+            modified: metadata.modified()?.duration_since(UNIX_EPOCH)?.as_secs(),
+        })
+    }
+}
 
-    Ok(())
+/// Info table row holding the checkpoint of an unfinished import.
+const CHECKPOINT_KEY: &str = "ImportCheckpoint";
+
+/// Progress of a file import, saved with every committed batch so an
+/// interrupted import can be continued by `resume_import`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ImportCheckpoint {
+    files: Vec<SourceFile>,
+    timestamp: Option<i64>,
+    options: ImportOptions,
+    create_indexes: bool,
+    file_index: usize,
+    /// Games read from the current file, skipped ones included.
+    games_read: usize,
+    /// Games inserted from every file so far, counted against `max_games`.
+    #[serde(default)]
+    games_inserted: usize,
 }
 
-#[tauri::command]
-pub async fn delete_indexes(file: PathBuf, state: tauri::State<'_, AppState>) -> Result<(), Error> {
-    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+impl ImportCheckpoint {
+    fn save(&self, db: &mut SqliteConnection) -> Result<(), Error> {
+        set_info(db, CHECKPOINT_KEY, &serde_json::to_string(self)?)
+    }
 
-    db.batch_execute(DELETE_INDEXES_SQL)?;
+    fn load(db: &mut SqliteConnection) -> Result<Option<ImportCheckpoint>, Error> {
+        let value: Option<Option<String>> = info::table
+            .filter(info::name.eq(CHECKPOINT_KEY))
+            .select(info::value)
+            .first(db)
+            .optional()?;
+        Ok(match value.flatten() {
+            Some(value) => Some(serde_json::from_str(&value)?),
+            None => None,
+        })
+    }
 
-    Ok(())
+    fn clear(db: &mut SqliteConnection) -> Result<(), Error> {
+        diesel::delete(info::table.filter(info::name.eq(CHECKPOINT_KEY))).execute(db)?;
+        Ok(())
+    }
 }
 
-#[tauri::command]
-pub async fn edit_db_info(
-    file: PathBuf,
-    title: Option<String>,
-    description: Option<String>,
-    state: tauri::State<'_, AppState>,
-) -> Result<(), Error> {
-    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+/// Info table row listing the imports that made up the database.
+const SOURCES_KEY: &str = "ImportSources";
 
-    if let Some(title) = title {
-        diesel::insert_into(info::table)
-            .values((info::name.eq("Title"), info::value.eq(title.clone())))
-            .on_conflict(info::name)
-            .do_update()
-            .set(info::value.eq(title))
-            .execute(db)?;
+/// Where a database's games came from, recorded after every finished import.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportSource {
+    /// Path or URL of the PGN.
+    pub source_file: String,
+    /// Size of the PGN as it was read, compressed or not, if known.
+    pub source_size_bytes: Option<u64>,
+    /// RFC 3339 time at which the import finished.
+    pub imported_at: String,
+    pub importer_version: String,
+    pub options: ImportOptions,
+}
+
+impl ImportSource {
+    fn new(source_file: String, source_size_bytes: Option<u64>, options: &ImportOptions) -> Self {
+        ImportSource {
+            source_file,
+            source_size_bytes,
+            imported_at: chrono::Utc::now().to_rfc3339(),
+            importer_version: env!("CARGO_PKG_VERSION").to_string(),
+            options: options.clone(),
+        }
     }
 
-    if let Some(description) = description {
-        diesel::insert_into(info::table)
-            .values((
-                info::name.eq("Description"),
-                info::value.eq(description.clone()),
-            ))
-            .on_conflict(info::name)
-            .do_update()
-            .set(info::value.eq(description))
-            .execute(db)?;
+    fn load_all(db: &mut SqliteConnection) -> Result<Vec<ImportSource>, Error> {
+        let value: Option<Option<String>> = info::table
+            .filter(info::name.eq(SOURCES_KEY))
+            .select(info::value)
+            .first(db)
+            .optional()?;
+        Ok(match value.flatten() {
+            Some(value) => serde_json::from_str(&value)?,
+            None => Vec::new(),
+        })
     }
 
-    Ok(())
+    /// Appends `sources` to the ones already recorded in the database.
+    fn record(db: &mut SqliteConnection, sources: Vec<ImportSource>) -> Result<(), Error> {
+        let mut all = ImportSource::load_all(db)?;
+        all.extend(sources);
+        set_info(db, SOURCES_KEY, &serde_json::to_string(&all)?)
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
-pub enum Sides {
-    BlackWhite,
-    WhiteBlack,
-    Any,
+/// Whether both paths lead to the same existing file, however they're spelled.
+fn is_same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
 }
 
-#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
-pub enum GameSort {
-    #[default]
-    #[serde(rename = "id")]
-    Id,
-    #[serde(rename = "date")]
-    Date,
-    #[serde(rename = "whiteElo")]
-    WhiteElo,
-    #[serde(rename = "blackElo")]
-    BlackElo,
-    #[serde(rename = "ply_count")]
-    PlyCount,
+/// Applies `mode` to the destination of an import, returning whether the games
+/// are going to be appended to an existing database.
+fn prepare_destination(
+    state: &AppState,
+    db_path: &Path,
+    mode: ExistingDatabase,
+) -> Result<bool, Error> {
+    if !db_path.exists() {
+        return Ok(false);
+    }
+    match mode {
+        ExistingDatabase::Append => Ok(true),
+        ExistingDatabase::Fail => Err(Error::DatabaseAlreadyExists(
+            db_path.to_string_lossy().into_owned(),
+        )),
+        ExistingDatabase::Overwrite => {
+            // Drop the pooled connections before the file goes away
+            state.connection_pool.remove(db_path.to_str().unwrap());
+            remove_file(db_path)?;
+            Ok(false)
+        }
+    }
 }
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
-pub enum SortDirection {
-    #[serde(rename = "asc")]
-    Asc,
-    #[default]
-    #[serde(rename = "desc")]
-    Desc,
+/// Number of parsed batches that can wait for the writer before the parser
+/// blocks.
+const PENDING_BATCHES: usize = 2;
+
+/// Imports every game of `reader`, committing them in batches of the
+/// importer's `batch_size`. `count` is the running number of committed games,
+/// reported through `on_progress` after every batch. When given, `checkpoint`
+/// is updated with the number of games read and saved with every batch.
+///
+/// The games are parsed on the current thread while another one inserts the
+/// previous batches, so parsing and writing overlap.
+///
+/// If reading fails, the games parsed so far are still committed before the
+/// error is returned.
+fn import_games(
+    db: &mut SqliteConnection,
+    reader: impl Read,
+    importer: &mut Importer,
+    count: &mut usize,
+    checkpoint: Option<&ImportCheckpoint>,
+    mut on_progress: impl FnMut(usize) + Send,
+) -> Result<(), Error> {
+    let batch_size = importer.options.batch_size();
+    importer.games_read = 0;
+    // The visitor borrows the importer while reading, so the writer state is
+    // moved out
+    let mut game_writer = std::mem::take(&mut importer.writer);
+    let (sender, receiver) = mpsc::sync_channel::<(Vec<TempGame>, usize)>(PENDING_BATCHES);
+
+    let (parsed, written) = thread::scope(|scope| {
+        let game_writer = &mut game_writer;
+        let writer = scope.spawn(move || -> Result<(), Error> {
+            for (mut batch, games_read) in receiver {
+                let checkpoint = checkpoint.map(|c| ImportCheckpoint {
+                    games_read,
+                    ..c.clone()
+                });
+                flush_games(db, &mut batch, game_writer, count, checkpoint.as_ref())?;
+                on_progress(*count);
+                if game_writer.truncated {
+                    // Dropping the receiver stops the parser
+                    break;
+                }
+            }
+            Ok(())
+        });
+        let parsed = parse_batches(reader, importer, batch_size, sender);
+        let written = writer.join().expect("import writer thread panicked");
+        (parsed, written)
+    });
+    importer.writer = game_writer;
+
+    // A failed write also stops the parser, so its error comes first
+    written?;
+    parsed?;
+    Ok(())
 }
 
-#[derive(Default, Debug, Clone, Deserialize, PartialEq, Eq, Hash)]
-pub struct QueryOptions<SortT> {
-    pub skip_count: bool,
-    pub page: Option<i64>,
-    pub page_size: Option<i64>,
-    pub sort: SortT,
-    pub direction: SortDirection,
+/// Parses `reader` and sends the games in batches of `batch_size`, along with
+/// the number of games read so far. Stops early, without an error, if the
+/// receiving end is gone, as when the writer reached `max_games`.
+fn parse_batches(
+    reader: impl Read,
+    importer: &mut Importer,
+    batch_size: usize,
+    sender: SyncSender<(Vec<TempGame>, usize)>,
+) -> std::io::Result<()> {
+    let mut batch = Vec::with_capacity(batch_size);
+    let mut games_read = 0;
+    for game in BufferedReader::new(reader).into_iter(importer) {
+        match game {
+            Ok(Some(game)) => batch.push(game),
+            Ok(None) => {}
+            Err(e) => {
+                let _ = sender.send((batch, games_read));
+                return Err(e);
+            }
+        }
+        games_read += 1;
+        if batch.len() >= batch_size {
+            let full = std::mem::replace(&mut batch, Vec::with_capacity(batch_size));
+            if sender.send((full, games_read)).is_err() {
+                return Ok(());
+            }
+        }
+    }
+    let _ = sender.send((batch, games_read));
+    Ok(())
 }
 
-#[derive(Debug, Clone, Deserialize, Default, PartialEq, Eq, Hash)]
-pub struct GameQuery {
-    pub options: Option<QueryOptions<GameSort>>,
-    pub player1: Option<i32>,
-    pub player2: Option<i32>,
-    pub tournament_id: Option<i32>,
-    pub start_date: Option<String>,
-    pub end_date: Option<String>,
-    pub range1: Option<(i32, i32)>,
-    pub range2: Option<(i32, i32)>,
-    pub sides: Option<Sides>,
-    pub outcome: Option<String>,
-    pub position: Option<PositionQuery>,
+#[tauri::command]
+#[specta::specta]
+#[allow(clippy::too_many_arguments)]
+pub async fn convert_pgn(
+    file: PathBuf,
+    db_path: PathBuf,
+    timestamp: Option<i32>,
+    app: tauri::AppHandle,
+    title: Option<String>,
+    description: Option<String>,
+    options: Option<ImportOptions>,
+    state: tauri::State<'_, AppState>,
+) -> Result<ImportSummary, Error> {
+    convert_pgns(
+        vec![file],
+        db_path,
+        timestamp,
+        app,
+        title,
+        description,
+        options,
+        state,
+    )
+    .await
 }
 
-impl GameQuery {
-    pub fn new() -> Self {
-        Self::default()
-    }
-    pub fn position(mut self, position: PositionQuery) -> Self {
-        self.position = Some(position);
-        self
+/// Imports several PGN files, compressed or not, into the same database.
+#[tauri::command]
+#[specta::specta]
+#[allow(clippy::too_many_arguments)]
+pub async fn convert_pgns(
+    files: Vec<PathBuf>,
+    db_path: PathBuf,
+    timestamp: Option<i32>,
+    app: tauri::AppHandle,
+    title: Option<String>,
+    description: Option<String>,
+    options: Option<ImportOptions>,
+    state: tauri::State<'_, AppState>,
+) -> Result<ImportSummary, Error> {
+    let _modification = state.count_cache.modify(db_path.to_str().unwrap());
+    let options = options.unwrap_or_default();
+
+    let db_exists = prepare_destination(&state, &db_path, options.existing)?;
+
+    // create the database file
+    let db = &mut get_db_or_create(
+        &state,
+        db_path.to_str().unwrap(),
+        ConnectionOptions {
+            enable_foreign_keys: false,
+            busy_timeout: None,
+            journal_mode: JournalMode::Off,
+        },
+    )?;
+
+    if !db_exists {
+        init_database(db, &db_path, title.as_deref(), description.as_deref())?;
     }
-}
 
-#[derive(Debug, Clone, Serialize)]
-pub struct QueryResponse<T> {
-    pub data: T,
-    pub count: Option<i64>,
+    let checkpoint = ImportCheckpoint {
+        files: files
+            .iter()
+            .map(|file| SourceFile::read(file))
+            .collect::<Result<_, _>>()?,
+        timestamp: timestamp.map(|t| t as i64),
+        create_indexes: !db_exists && options.create_indexes(),
+        options,
+        file_index: 0,
+        games_read: 0,
+        games_inserted: 0,
+    };
+    run_import(db, &db_path, &app, checkpoint, db_exists)
 }
 
+/// Continues a file import that was interrupted, from the last batch it
+/// committed. Fails if the source files changed since then.
 #[tauri::command]
-pub async fn get_games(
-    file: PathBuf,
-    query: GameQuery,
+#[specta::specta]
+pub async fn resume_import(
+    db_path: PathBuf,
+    app: tauri::AppHandle,
     state: tauri::State<'_, AppState>,
-) -> Result<QueryResponse<Vec<NormalizedGame>>, Error> {
-    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
-
-    let mut count: Option<i64> = None;
-    let query_options = query.options.unwrap_or_default();
+) -> Result<ImportSummary, Error> {
+    let _modification = state.count_cache.modify(db_path.to_str().unwrap());
+    let db = &mut get_db_or_create(
+        &state,
+        db_path.to_str().unwrap(),
+        ConnectionOptions {
+            enable_foreign_keys: false,
+            busy_timeout: None,
+            journal_mode: JournalMode::Off,
+        },
+    )?;
 
-    let (white_players, black_players) = diesel::alias!(players as white, players as black);
-    let mut sql_query = games::table
-        .inner_join(white_players.on(games::white_id.eq(white_players.field(players::id))))
-        .inner_join(black_players.on(games::black_id.eq(black_players.field(players::id))))
-        .inner_join(events::table.on(games::event_id.eq(events::id)))
-        .inner_join(sites::table.on(games::site_id.eq(sites::id)))
-        .into_boxed();
-    let mut count_query = games::table.into_boxed();
+    let checkpoint = ImportCheckpoint::load(db)?.ok_or(Error::NoImportCheckpoint)?;
+    for file in &checkpoint.files {
+        if SourceFile::read(&file.path).ok().as_ref() != Some(file) {
+            return Err(Error::ImportSourceChanged(
+                file.path.to_string_lossy().into_owned(),
+            ));
+        }
+    }
+    run_import(db, &db_path, &app, checkpoint, true)
+}
 
-    // if let Some(speed) = query.speed {
-    //     sql_query = sql_query.filter(games::speed.eq(speed as i32));
-    //     count_query = count_query.filter(games::speed.eq(speed as i32));
-    // }
+/// Imports the files of `checkpoint`, starting from its position, and removes
+/// the checkpoint once done. `append` tells if the database already has games.
+fn run_import(
+    db: &mut SqliteConnection,
+    db_path: &Path,
+    app: &tauri::AppHandle,
+    mut checkpoint: ImportCheckpoint,
+    append: bool,
+) -> Result<ImportSummary, Error> {
+    // start counting time
+    let start = Instant::now();
 
-    if let Some(outcome) = query.outcome {
-        sql_query = sql_query.filter(games::result.eq(outcome.clone()));
-        count_query = count_query.filter(games::result.eq(outcome));
+    let mut importer = Importer::new(checkpoint.timestamp, checkpoint.options.clone());
+    if append {
+        importer.writer.names = NameCache::load(db)?;
+        if importer.options.deduplicate {
+            importer.hashes = load_game_hashes(db)?;
+        }
     }
-
-    if let Some(start_date) = query.start_date {
-        sql_query = sql_query.filter(games::date.ge(start_date.clone()));
-        count_query = count_query.filter(games::date.ge(start_date));
+    importer.writer.inserted = checkpoint.games_inserted;
+    let file_count = checkpoint.files.len();
+    let mut count = 0;
+    for file_index in checkpoint.file_index..file_count {
+        let file_name = checkpoint.files[file_index]
+            .path
+            .to_string_lossy()
+            .to_string();
+        let uncompressed = open_pgn(&checkpoint.files[file_index].path)?;
+        if file_index != checkpoint.file_index {
+            checkpoint.file_index = file_index;
+            checkpoint.games_read = 0;
+        }
+        importer.skip_games = checkpoint.games_read;
+        importer.writer.source = Some(file_name.clone());
+
+        import_games(
+            db,
+            uncompressed,
+            &mut importer,
+            &mut count,
+            Some(&checkpoint),
+            |i| {
+                let elapsed = start.elapsed().as_millis() as u32;
+                app.emit_all("convert_progress", (i, elapsed)).unwrap();
+                let _ = ImportProgress {
+                    file: file_name.clone(),
+                    file_index: file_index as u32,
+                    file_count: file_count as u32,
+                    games: i as u32,
+                    elapsed,
+                }
+                .emit_all(app);
+            },
+        )?;
+        if importer.writer.truncated {
+            break;
+        }
     }
-
-    if let Some(end_date) = query.end_date {
-        sql_query = sql_query.filter(games::date.le(end_date.clone()));
-        count_query = count_query.filter(games::date.le(end_date));
+    let parse_time = start.elapsed();
+
+    let index_start = Instant::now();
+    finish_import(db, checkpoint.create_indexes)?;
+    let sources = checkpoint
+        .files
+        .iter()
+        .map(|file| {
+            ImportSource::new(
+                file.path.to_string_lossy().into_owned(),
+                Some(file.size),
+                &checkpoint.options,
+            )
+        })
+        .collect();
+    ImportSource::record(db, sources)?;
+    ImportCheckpoint::clear(db)?;
+    if checkpoint.options.write_skip_report {
+        write_skip_report(db_path, &importer.skipped)?;
     }
 
-    if let Some(tournament_id) = query.tournament_id {
-        sql_query = sql_query.filter(games::event_id.eq(tournament_id));
-        count_query = count_query.filter(games::event_id.eq(tournament_id));
-    }
+    Ok(ImportSummary {
+        parse_time: parse_time.as_millis() as u32,
+        index_time: index_start.elapsed().as_millis() as u32,
+        storage_size: db_path.metadata()?.len() as f64,
+        ..importer.summary(count)
+    })
+}
 
-    if let Some(limit) = query_options.page_size {
-        sql_query = sql_query.limit(limit);
-    }
+const MAX_DOWNLOAD_RETRIES: u32 = 5;
 
-    if let Some(page) = query_options.page {
-        sql_query = sql_query.offset((page - 1) * query_options.page_size.unwrap_or(10));
-    }
+/// Download progress is reported every time this many bytes are read.
+const DOWNLOAD_PROGRESS_STEP: u64 = 1 << 20;
 
-    match query.sides {
-        Some(Sides::BlackWhite) => {
-            if let Some(player1) = query.player1 {
-                sql_query = sql_query.filter(games::black_id.eq(player1));
-                count_query = count_query.filter(games::black_id.eq(player1));
-            }
-            if let Some(player2) = query.player2 {
-                sql_query = sql_query.filter(games::white_id.eq(player2));
-                count_query = count_query.filter(games::white_id.eq(player2));
-            }
+/// Streams an HTTP download, resuming it with a Range request after transient errors.
+struct UrlReader {
+    client: reqwest::blocking::Client,
+    url: String,
+    response: reqwest::blocking::Response,
+    downloaded: u64,
+    total_size: Option<u64>,
+    cancelled: Arc<AtomicBool>,
+    on_progress: Box<dyn FnMut(u64, Option<u64>) + Send>,
+}
 
-            if let Some(range1) = query.range1 {
-                sql_query = sql_query.filter(games::black_elo.between(range1.0, range1.1));
-                count_query = count_query.filter(games::black_elo.between(range1.0, range1.1));
-            }
+impl UrlReader {
+    fn new(
+        url: &str,
+        cancelled: Arc<AtomicBool>,
+        on_progress: impl FnMut(u64, Option<u64>) + Send + 'static,
+    ) -> Result<Self, Error> {
+        let client = reqwest::blocking::Client::new();
+        let response = client.get(url).send()?.error_for_status()?;
+        let total_size = response.content_length();
+        Ok(Self {
+            client,
+            url: url.to_string(),
+            response,
+            downloaded: 0,
+            total_size,
+            cancelled,
+            on_progress: Box::new(on_progress),
+        })
+    }
 
-            if let Some(range2) = query.range2 {
-                sql_query = sql_query.filter(games::white_elo.between(range2.0, range2.1));
-                count_query = count_query.filter(games::white_elo.between(range2.0, range2.1));
+    /// Guesses the compression format from the URL suffix or the Content-Type header.
+    fn compression_extension(&self) -> Option<&'static str> {
+        let path = self.url.split(['?', '#']).next().unwrap_or_default();
+        for extension in ["gz", "bz2", "zst"] {
+            if path.ends_with(&format!(".{extension}")) {
+                return Some(extension);
             }
         }
-        Some(Sides::WhiteBlack) => {
-            if let Some(player1) = query.player1 {
-                sql_query = sql_query.filter(games::white_id.eq(player1));
-                count_query = count_query.filter(games::white_id.eq(player1));
-            }
-            if let Some(player2) = query.player2 {
-                sql_query = sql_query.filter(games::black_id.eq(player2));
-                count_query = count_query.filter(games::black_id.eq(player2));
-            }
-
-            if let Some(range1) = query.range1 {
-                sql_query = sql_query.filter(games::white_elo.between(range1.0, range1.1));
-                count_query = count_query.filter(games::white_elo.between(range1.0, range1.1));
-            }
+        let content_type = self
+            .response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)?
+            .to_str()
+            .ok()?;
+        match content_type {
+            "application/gzip" | "application/x-gzip" => Some("gz"),
+            "application/x-bzip2" => Some("bz2"),
+            "application/zstd" => Some("zst"),
+            _ => None,
+        }
+    }
 
-            if let Some(range2) = query.range2 {
-                sql_query = sql_query.filter(games::black_elo.between(range2.0, range2.1));
-                count_query = count_query.filter(games::black_elo.between(range2.0, range2.1));
-            }
+    fn resume(&mut self) -> Result<(), Error> {
+        let mut response = self
+            .client
+            .get(&self.url)
+            .header(
+                reqwest::header::RANGE,
+                format!("bytes={}-", self.downloaded),
+            )
+            .send()?
+            .error_for_status()?;
+        if response.status() != reqwest::StatusCode::PARTIAL_CONTENT {
+            // The server ignored the range, skip what was already read
+            std::io::copy(
+                &mut (&mut response).take(self.downloaded),
+                &mut std::io::sink(),
+            )?;
         }
-        Some(Sides::Any) => {
-            if let Some(player1) = query.player1 {
-                sql_query =
-                    sql_query.filter(games::white_id.eq(player1).or(games::black_id.eq(player1)));
-                count_query =
-                    count_query.filter(games::white_id.eq(player1).or(games::black_id.eq(player1)));
-            }
-            if let Some(player2) = query.player2 {
-                sql_query =
-                    sql_query.filter(games::white_id.eq(player2).or(games::black_id.eq(player2)));
-                count_query =
-                    count_query.filter(games::white_id.eq(player2).or(games::black_id.eq(player2)));
-            }
+        self.response = response;
+        Ok(())
+    }
+}
 
-            if let (Some(range1), Some(range2)) = (query.range1, query.range2) {
-                sql_query = sql_query.filter(
-                    games::white_elo
-                        .between(range1.0, range1.1)
-                        .or(games::black_elo.between(range1.0, range1.1))
-                        .or(games::white_elo
-                            .between(range2.0, range2.1)
-                            .or(games::black_elo.between(range2.0, range2.1))),
-                );
-                count_query = count_query.filter(
-                    games::white_elo
-                        .between(range1.0, range1.1)
-                        .or(games::black_elo.between(range1.0, range1.1))
-                        .or(games::white_elo
-                            .between(range2.0, range2.1)
-                            .or(games::black_elo.between(range2.0, range2.1))),
-                );
-            } else {
-                if let Some(range1) = query.range1 {
-                    sql_query = sql_query.filter(
-                        games::white_elo
-                            .between(range1.0, range1.1)
-                            .or(games::black_elo.between(range1.0, range1.1)),
-                    );
-                    count_query = count_query.filter(
-                        games::white_elo
-                            .between(range1.0, range1.1)
-                            .or(games::black_elo.between(range1.0, range1.1)),
-                    );
+impl Read for UrlReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut retries = 0;
+        loop {
+            if self.cancelled.load(Ordering::Relaxed) {
+                return Err(std::io::Error::other("import cancelled"));
+            }
+            match self.response.read(buf) {
+                Ok(n) => {
+                    let before = self.downloaded;
+                    self.downloaded += n as u64;
+                    if n == 0
+                        || before / DOWNLOAD_PROGRESS_STEP
+                            != self.downloaded / DOWNLOAD_PROGRESS_STEP
+                    {
+                        (self.on_progress)(self.downloaded, self.total_size);
+                    }
+                    return Ok(n);
                 }
-
-                if let Some(range2) = query.range2 {
-                    sql_query = sql_query.filter(
-                        games::white_elo
-                            .between(range2.0, range2.1)
-                            .or(games::black_elo.between(range2.0, range2.1)),
-                    );
-                    count_query = count_query.filter(
-                        games::white_elo
-                            .between(range2.0, range2.1)
-                            .or(games::black_elo.between(range2.0, range2.1)),
-                    );
+                Err(e) if retries < MAX_DOWNLOAD_RETRIES => {
+                    retries += 1;
+                    log::warn!("Download of {} interrupted: {e}, retrying", self.url);
+                    std::thread::sleep(Duration::from_secs(1 << retries));
+                    if let Err(e) = self.resume() {
+                        log::warn!("Failed to resume download of {}: {e}", self.url);
+                    }
                 }
+                Err(e) => return Err(e),
             }
         }
-        None => {}
     }
+}
 
-    sql_query = match query_options.sort {
-        GameSort::Id => match query_options.direction {
-            SortDirection::Asc => sql_query.order(games::id.asc()),
-            SortDirection::Desc => sql_query.order(games::id.desc()),
-        },
-        GameSort::Date => match query_options.direction {
-            SortDirection::Asc => sql_query.order((games::date.asc(), games::time.asc())),
-            SortDirection::Desc => sql_query.order((games::date.desc(), games::time.desc())),
-        },
-        GameSort::WhiteElo => match query_options.direction {
-            SortDirection::Asc => sql_query.order(games::white_elo.asc()),
-            SortDirection::Desc => sql_query.order(games::white_elo.desc()),
-        },
-        GameSort::BlackElo => match query_options.direction {
-            SortDirection::Asc => sql_query.order(games::black_elo.asc()),
-            SortDirection::Desc => sql_query.order(games::black_elo.desc()),
-        },
-        GameSort::PlyCount => match query_options.direction {
-            SortDirection::Asc => sql_query.order(games::ply_count.asc()),
-            SortDirection::Desc => sql_query.order(games::ply_count.desc()),
-        },
-    };
+/// Imports a PGN file straight from a URL, without storing it on disk first.
+/// The import can be stopped with `cancel_import`, in which case a database
+/// created by this call is deleted.
+#[tauri::command]
+#[specta::specta]
+#[allow(clippy::too_many_arguments)]
+pub async fn convert_pgn_from_url(
+    id: String,
+    url: String,
+    db_path: PathBuf,
+    timestamp: Option<i32>,
+    app: tauri::AppHandle,
+    title: Option<String>,
+    description: Option<String>,
+    options: Option<ImportOptions>,
+    state: tauri::State<'_, AppState>,
+) -> Result<ImportSummary, Error> {
+    let _modification = state.count_cache.modify(db_path.to_str().unwrap());
+    let options = options.unwrap_or_default();
+    let db_exists = prepare_destination(&state, &db_path, options.existing)?;
+    let path_str = db_path.to_str().unwrap().to_string();
 
-    if !query_options.skip_count {
-        count = Some(
-            count_query
-                .select(diesel::dsl::count(games::id))
-                .first(db)?,
-        );
+    let mut db = get_db_or_create(
+        &state,
+        &path_str,
+        ConnectionOptions {
+            enable_foreign_keys: false,
+            busy_timeout: None,
+            journal_mode: JournalMode::Off,
+        },
+    )?;
+    if !db_exists {
+        init_database(&mut db, &db_path, title.as_deref(), description.as_deref())?;
     }
 
-    // println!(
-    //     "{:?}\n",
-    //     diesel::debug_query::<diesel::sqlite::Sqlite, _>(&sql_query)
-    // );
-
-    let games: Vec<(Game, Player, Player, Event, Site)> = sql_query.load(db)?;
-    let normalized_games = normalize_games(games);
+    let cancelled = Arc::new(AtomicBool::new(false));
+    state
+        .import_cancellations
+        .insert(id.clone(), cancelled.clone());
 
-    Ok(QueryResponse {
-        data: normalized_games,
-        count,
+    let mut importer = Importer::new(timestamp.map(|t| t as i64), options);
+    if db_exists {
+        importer.writer.names = NameCache::load(&mut db)?;
+        if importer.options.deduplicate {
+            importer.hashes = load_game_hashes(&mut db)?;
+        }
+    }
+    let task_cancelled = cancelled.clone();
+    let task_id = id.clone();
+    let task_db_path = db_path.clone();
+    // The blocking HTTP client can't run on the async runtime
+    let result = tokio::task::spawn_blocking(move || -> Result<ImportSummary, Error> {
+        let download_app = app.clone();
+        let download_id = task_id.clone();
+        let reader = UrlReader::new(&url, task_cancelled, move |downloaded, total_size| {
+            if let Some(total_size) = total_size {
+                let _ = DownloadProgress {
+                    progress: ((downloaded as f32 / total_size as f32) * 100.0).min(100.0),
+                    id: download_id.clone(),
+                    finished: downloaded >= total_size,
+                }
+                .emit_all(&download_app);
+            }
+        })?;
+        let extension = reader.compression_extension();
+        let source_size = reader.total_size;
+        let uncompressed = decompress(reader, extension)?;
+
+        let start = Instant::now();
+        let mut count = 0;
+        importer.writer.source = Some(url.clone());
+        import_games(
+            &mut db,
+            uncompressed,
+            &mut importer,
+            &mut count,
+            None,
+            |i| {
+                let _ = ImportProgress {
+                    file: url.clone(),
+                    file_index: 0,
+                    file_count: 1,
+                    games: i as u32,
+                    elapsed: start.elapsed().as_millis() as u32,
+                }
+                .emit_all(&app);
+            },
+        )?;
+        let parse_time = start.elapsed();
+
+        let index_start = Instant::now();
+        finish_import(&mut db, !db_exists && importer.options.create_indexes())?;
+        let source = ImportSource::new(url, source_size, &importer.options);
+        ImportSource::record(&mut db, vec![source])?;
+        if importer.options.write_skip_report {
+            write_skip_report(&task_db_path, &importer.skipped)?;
+        }
+        Ok(ImportSummary {
+            parse_time: parse_time.as_millis() as u32,
+            index_time: index_start.elapsed().as_millis() as u32,
+            storage_size: task_db_path.metadata()?.len() as f64,
+            ..importer.summary(count)
+        })
     })
+    .await?;
+
+    state.import_cancellations.remove(&id);
+
+    if result.is_err() && !db_exists {
+        state.connection_pool.remove(&path_str);
+        remove_file(&db_path)?;
+    }
+    if cancelled.load(Ordering::Relaxed) {
+        return Err(Error::ImportCancelled);
+    }
+    result
 }
 
-fn normalize_games(games: Vec<(Game, Player, Player, Event, Site)>) -> Vec<NormalizedGame> {
-    games
-        .into_iter()
-        .map(|(game, white, black, event, site)| {
-            let fen: Fen = game
-                .fen
-                .map(|f| Fen::from_ascii(f.as_bytes()).unwrap())
-                .unwrap_or_default();
+/// Imports the games of a PGN text into an existing database, returning the
+/// IDs of the inserted games.
+fn import_pgn_text(
+    db: &mut SqliteConnection,
+    pgn: &str,
+    options: ImportOptions,
+) -> Result<Vec<i32>, Error> {
+    let mut importer = Importer::new(None, options);
+    if importer.options.deduplicate {
+        importer.hashes = load_game_hashes(db)?;
+    }
+    let mut games = Vec::new();
+    for game in BufferedReader::new(pgn.as_bytes()).into_iter(&mut importer) {
+        games.extend(game?);
+    }
+    if games.is_empty() {
+        return Err(Error::NoGamesInPgn);
+    }
 
-            NormalizedGame {
-                id: game.id,
-                event: event.name.unwrap_or_default(),
-                event_id: event.id,
-                site: site.name.unwrap_or_default(),
-                site_id: site.id,
-                date: game.date,
-                time: game.time,
-                round: game.round,
-                white: white.name.unwrap_or_default(),
-                white_id: game.white_id,
-                white_elo: game.white_elo,
-                black: black.name.unwrap_or_default(),
-                black_id: game.black_id,
-                black_elo: game.black_elo,
-                result: game.result,
-                time_control: game.time_control,
-                eco: game.eco,
-                white_material: game.white_material,
-                black_material: game.black_material,
-                ply_count: game.ply_count,
-                fen: fen.to_string(),
-                moves: decode_moves(game.moves, fen).unwrap_or_default().join(" "),
-            }
-        })
-        .collect()
-}
-
-#[derive(Debug, Clone, Deserialize)]
-pub struct PlayerQuery {
-    pub options: QueryOptions<PlayerSort>,
-    pub name: Option<String>,
-    pub range: Option<(i32, i32)>,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum PlayerSort {
-    #[serde(rename = "id")]
-    Id,
-    #[serde(rename = "name")]
-    Name,
-    #[serde(rename = "elo")]
-    Elo,
+    // Only a handful of names are looked up, so the cache starts empty
+    let mut names = NameCache::default();
+    let ids = db.transaction::<_, Error, _>(|db| {
+        let mut ids = Vec::with_capacity(games.len());
+        let mut tally = PlayerTally::default();
+        for game in &games {
+            let game = game.insert_to_db(db, &mut names)?;
+            tally.add(game.white_id, game.white_elo);
+            tally.add(game.black_id, game.black_elo);
+            ids.push(game.id);
+        }
+        tally.apply(db)?;
+        update_info_counts(db)?;
+        Ok(ids)
+    })?;
+    Ok(ids)
 }
 
+/// Adds pasted games to a database, returning the IDs of the inserted games.
 #[tauri::command]
 #[specta::specta]
-pub async fn get_player(
-    file: PathBuf,
-    id: i32,
+pub async fn import_pgn_string(
+    db_path: PathBuf,
+    pgn: String,
+    options: Option<ImportOptions>,
     state: tauri::State<'_, AppState>,
-) -> Result<Option<Player>, Error> {
-    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
-    let player = players::table
-        .filter(players::id.eq(id))
-        .first::<Player>(db)
-        .optional()?;
-    Ok(player)
+) -> Result<Vec<i32>, Error> {
+    let _modification = state.count_cache.modify(db_path.to_str().unwrap());
+    let db = &mut get_db_or_create(
+        &state,
+        db_path.to_str().unwrap(),
+        ConnectionOptions::default(),
+    )?;
+    import_pgn_text(db, &pgn, options.unwrap_or_default())
 }
 
 #[tauri::command]
-pub async fn get_players(
-    file: PathBuf,
-    query: PlayerQuery,
-    state: tauri::State<'_, AppState>,
-) -> Result<QueryResponse<Vec<Player>>, Error> {
-    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
-    let mut count = None;
-
-    let mut sql_query = players::table.into_boxed();
-    let mut count_query = players::table.into_boxed();
-    sql_query = sql_query.filter(players::name.is_not("Unknown"));
-    count_query = count_query.filter(players::name.is_not("Unknown"));
-
-    if let Some(name) = query.name {
-        sql_query = sql_query.filter(players::name.like(format!("%{}%", name)));
-        count_query = count_query.filter(players::name.like(format!("%{}%", name)));
-    }
-
-    if let Some(range) = query.range {
-        sql_query = sql_query.filter(players::elo.between(range.0, range.1));
-        count_query = count_query.filter(players::elo.between(range.0, range.1));
-    }
-
-    if !query.options.skip_count {
-        count = Some(count_query.count().get_result(db)?);
-    }
-
-    if let Some(limit) = query.options.page_size {
-        sql_query = sql_query.limit(limit);
-    }
-
-    if let Some(page) = query.options.page {
-        sql_query = sql_query.offset((page - 1) * query.options.page_size.unwrap_or(10));
+#[specta::specta]
+pub async fn cancel_import(id: String, state: tauri::State<'_, AppState>) -> Result<(), Error> {
+    if let Some(cancelled) = state.import_cancellations.get(&id) {
+        cancelled.store(true, Ordering::Relaxed);
     }
+    Ok(())
+}
 
-    sql_query = match query.options.sort {
-        PlayerSort::Id => match query.options.direction {
-            SortDirection::Asc => sql_query.order(players::id.asc()),
-            SortDirection::Desc => sql_query.order(players::id.desc()),
-        },
-        PlayerSort::Name => match query.options.direction {
-            SortDirection::Asc => sql_query.order(players::name.asc()),
-            SortDirection::Desc => sql_query.order(players::name.desc()),
-        },
-        PlayerSort::Elo => match query.options.direction {
-            SortDirection::Asc => sql_query.order(players::elo.asc()),
-            SortDirection::Desc => sql_query.order(players::elo.desc()),
-        },
-    };
+#[derive(Serialize)]
+pub struct DatabaseInfo {
+    title: String,
+    description: String,
+    player_count: usize,
+    event_count: usize,
+    game_count: usize,
+    storage_size: usize,
+    filename: String,
+    indexed: bool,
+    /// Indexes of `indexes.sql` the database doesn't have.
+    missing_indexes: Vec<String>,
+    sources: Vec<ImportSource>,
+}
 
-    let players = sql_query.load::<Player>(db)?;
+#[derive(QueryableByName, Debug, Serialize)]
+struct IndexInfo {
+    #[diesel(sql_type = Text, column_name = "name")]
+    name: String,
+}
 
-    Ok(QueryResponse {
-        data: players,
-        count,
-    })
+fn get_index_names(conn: &mut SqliteConnection) -> Result<Vec<String>, Error> {
+    let query = sql_query("SELECT name FROM pragma_index_list('Games');");
+    let indexes: Vec<IndexInfo> = query.load(conn)?;
+    Ok(indexes.into_iter().map(|i| i.name).collect())
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum TournamentSort {
-    #[serde(rename = "id")]
-    Id,
-    #[serde(rename = "name")]
-    Name,
+/// The statements of `indexes.sql`, along with the name of the index each
+/// one creates.
+fn index_statements() -> impl Iterator<Item = (&'static str, &'static str)> {
+    INDEXES_SQL
+        .split(';')
+        .map(str::trim)
+        .filter(|statement| !statement.is_empty())
+        .map(|statement| {
+            // CREATE INDEX IF NOT EXISTS <name> ON ...
+            let name = statement.split_whitespace().nth(5).unwrap_or_default();
+            (name, statement)
+        })
 }
 
-#[derive(Debug, Clone, Deserialize)]
-pub struct TournamentQuery {
-    pub options: QueryOptions<TournamentSort>,
-    pub name: Option<String>,
+fn get_missing_indexes(conn: &mut SqliteConnection) -> Result<Vec<String>, Error> {
+    // Some are on other tables than Games
+    let existing: Vec<String> = sql_query("SELECT name FROM sqlite_master WHERE type = 'index';")
+        .load::<IndexInfo>(conn)?
+        .into_iter()
+        .map(|i| i.name)
+        .collect();
+    Ok(index_statements()
+        .map(|(name, _)| name)
+        .filter(|name| !existing.iter().any(|e| e == name))
+        .map(str::to_string)
+        .collect())
 }
 
 #[tauri::command]
-pub async fn get_tournaments(
+pub async fn get_db_info(
     file: PathBuf,
-    query: TournamentQuery,
+    app: tauri::AppHandle,
     state: tauri::State<'_, AppState>,
-) -> Result<QueryResponse<Vec<Event>>, Error> {
-    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
-    let mut count = None;
-
-    let mut sql_query = events::table.into_boxed();
-    let mut count_query = events::table.into_boxed();
-    sql_query = sql_query.filter(events::name.is_not("Unknown").and(events::name.is_not("")));
-    count_query = count_query.filter(events::name.is_not("Unknown").and(events::name.is_not("")));
+) -> Result<DatabaseInfo, Error> {
+    let db_path = PathBuf::from("db").join(file);
 
-    if let Some(name) = query.name {
-        sql_query = sql_query.filter(events::name.like(format!("%{}%", name)));
-        count_query = count_query.filter(events::name.like(format!("%{}%", name)));
-    }
+    let path = resolve_path(
+        &app.config(),
+        app.package_info(),
+        &app.env(),
+        db_path,
+        Some(BaseDirectory::AppData),
+    )?;
 
-    if !query.options.skip_count {
-        count = Some(count_query.count().get_result(db)?);
-    }
+    let db = &mut get_db_or_create(&state, path.to_str().unwrap(), ConnectionOptions::default())?;
 
-    if let Some(limit) = query.options.page_size {
-        sql_query = sql_query.limit(limit);
-    }
+    let player_count = players::table.count().get_result::<i64>(db)? as usize;
+    let game_count = games::table.count().get_result::<i64>(db)? as usize;
+    let event_count = events::table.count().get_result::<i64>(db)? as usize;
 
-    if let Some(page) = query.options.page {
-        sql_query = sql_query.offset((page - 1) * query.options.page_size.unwrap_or(10));
-    }
+    let title = match info::table
+        .filter(info::name.eq("Title"))
+        .first(db)
+        .map(|title_info: Info| title_info.value)
+    {
+        Ok(Some(title)) => title,
+        _ => default_title(&path),
+    };
 
-    sql_query = match query.options.sort {
-        TournamentSort::Id => match query.options.direction {
-            SortDirection::Asc => sql_query.order(events::id.asc()),
-            SortDirection::Desc => sql_query.order(events::id.desc()),
-        },
-        TournamentSort::Name => match query.options.direction {
-            SortDirection::Asc => sql_query.order(events::name.asc()),
-            SortDirection::Desc => sql_query.order(events::name.desc()),
-        },
+    let description = match info::table
+        .filter(info::name.eq("Description"))
+        .first(db)
+        .map(|description_info: Info| description_info.value)
+    {
+        Ok(Some(description)) => description,
+        _ => "".to_string(),
     };
 
-    let events = sql_query.load::<Event>(db)?;
+    let storage_size = path.metadata()?.len() as usize;
+    let filename = path.file_name().expect("get filename").to_string_lossy();
 
-    Ok(QueryResponse {
-        data: events,
-        count,
+    let is_indexed = !get_index_names(db)?.is_empty();
+    let missing_indexes = get_missing_indexes(db)?;
+    let sources = ImportSource::load_all(db)?;
+    Ok(DatabaseInfo {
+        title,
+        description,
+        player_count,
+        game_count,
+        event_count,
+        storage_size,
+        filename: filename.to_string(),
+        indexed: is_indexed,
+        missing_indexes,
+        sources,
     })
 }
 
-#[derive(Debug, Clone, Serialize, Type, Default)]
-pub struct PlayerGameInfo {
-    pub won: i32,
-    pub lost: i32,
-    pub draw: i32,
-    pub data_per_month: Vec<(String, MonthData)>,
-    pub white_openings: Vec<(String, Results)>,
-    pub black_openings: Vec<(String, Results)>,
-}
+#[tauri::command]
+pub async fn create_indexes(file: PathBuf, state: tauri::State<'_, AppState>) -> Result<(), Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
 
-#[derive(Debug, Clone, Serialize, Type, Default, Eq, Ord, PartialEq, PartialOrd)]
-pub struct Results {
-    pub won: i32,
-    pub lost: i32,
-    pub draw: i32,
-}
+    db.batch_execute(INDEXES_SQL)?;
 
-#[derive(Debug, Clone, Serialize, Type, Default)]
-pub struct MonthData {
-    pub count: i32,
-    pub avg_elo: i32,
-    #[serde(skip)]
-    avg_count: i32,
+    Ok(())
 }
 
-#[derive(Serialize, Debug, Clone, Type, tauri_specta::Event)]
-pub struct DatabaseProgress {
-    pub id: String,
-    pub progress: f64,
+/// Creates the missing indexes one at a time, calling `on_progress` with the
+/// percentage done after each one. With `rebuild`, every index is dropped
+/// first, as after appending many games to an indexed database.
+fn build_missing_indexes(
+    db: &mut SqliteConnection,
+    rebuild: bool,
+    mut on_progress: impl FnMut(f64),
+) -> Result<Vec<String>, Error> {
+    if rebuild {
+        for (name, _) in index_statements() {
+            db.batch_execute(&format!("DROP INDEX IF EXISTS {name};"))?;
+        }
+    }
+    let missing = get_missing_indexes(db)?;
+    for (i, name) in missing.iter().enumerate() {
+        let (_, statement) = index_statements().find(|(n, _)| n == name).unwrap();
+        db.batch_execute(statement)?;
+        on_progress((i + 1) as f64 / missing.len() as f64 * 100.0);
+    }
+    Ok(missing)
 }
 
+/// Creates the indexes the database is missing, returning their names.
+/// Progress is reported per index through `DatabaseProgress` events, with the
+/// database path as ID.
 #[tauri::command]
-#[specta::specta]
-pub async fn get_players_game_info(
+pub async fn build_indexes(
     file: PathBuf,
-    id: i32,
-    state: tauri::State<'_, AppState>,
+    rebuild: Option<bool>,
     app: tauri::AppHandle,
-) -> Result<PlayerGameInfo, Error> {
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<String>, Error> {
     let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
-    let timer = Instant::now();
+    let id = file.to_string_lossy().into_owned();
 
-    let sql_query = games::table
-        .select((
-            games::white_id,
-            games::black_id,
-            games::result,
-            games::date,
-            games::moves,
+    build_missing_indexes(db, rebuild.unwrap_or(false), |progress| {
+        let _ = DatabaseProgress {
+            id: id.clone(),
+            progress,
+        }
+        .emit_all(&app);
+    })
+}
+
+#[tauri::command]
+pub async fn delete_indexes(file: PathBuf, state: tauri::State<'_, AppState>) -> Result<(), Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+
+    db.batch_execute(DELETE_INDEXES_SQL)?;
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn edit_db_info(
+    file: PathBuf,
+    title: Option<String>,
+    description: Option<String>,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+
+    if let Some(title) = title {
+        set_info(db, "Title", &title)?;
+    }
+
+    if let Some(description) = description {
+        set_info(db, "Description", &description)?;
+    }
+
+    Ok(())
+}
+
+/// Colors of `player1` and `player2` in a [`GameQuery`].
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum Sides {
+    BlackWhite,
+    #[default]
+    WhiteBlack,
+    Any,
+}
+
+/// How the player names of a [`GameQuery`] are matched, ignoring case.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum NameMatch {
+    #[default]
+    Exact,
+    Contains,
+    StartsWith,
+    /// A wildcard pattern, where `*` matches any text and `?` any character,
+    /// like `Carlsen,*M*`.
+    #[serde(alias = "name_pattern")]
+    Pattern,
+}
+
+/// A player given by ID or by name.
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq, Hash)]
+#[serde(untagged)]
+pub enum PlayerRef {
+    Id(i32),
+    Name(String),
+}
+
+/// Most players a [`NameMatch::Pattern`] of a game query may match.
+const MAX_PATTERN_PLAYERS: i64 = 1_000;
+
+/// The LIKE pattern of a name, escaped with `\`.
+fn like_pattern(name: &str, mode: NameMatch) -> String {
+    let escaped = name
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    match mode {
+        NameMatch::Exact => escaped,
+        NameMatch::Contains => format!("%{escaped}%"),
+        NameMatch::StartsWith => format!("{escaped}%"),
+        NameMatch::Pattern => escaped.replace('*', "%").replace('?', "_"),
+    }
+}
+
+/// IDs of the players whose name matches, as a subquery. LIKE is
+/// case-insensitive, and `players_name_nocase_idx` serves the exact and
+/// prefix matches.
+fn players_named(name: &str, mode: NameMatch) -> players::BoxedQuery<'static, Sqlite, Integer> {
+    players::table
+        .select(players::id)
+        .filter(players::name.like(like_pattern(name, mode)).escape('\\'))
+        .into_boxed()
+}
+
+/// Fails if a name pattern of the query matches more than
+/// [`MAX_PATTERN_PLAYERS`] players, which would make the games query crawl.
+fn check_name_patterns(db: &mut SqliteConnection, query: &GameQuery) -> Result<(), Error> {
+    if query.match_mode != NameMatch::Pattern {
+        return Ok(());
+    }
+    let names = [
+        (query.player1, &query.player1_name),
+        (query.player2, &query.player2_name),
+    ];
+    for (_, name) in names.iter().filter(|(id, _)| id.is_none()) {
+        let Some(name) = name else {
+            continue;
+        };
+        let matched: i64 = players_named(name, NameMatch::Pattern)
+            .count()
+            .get_result(db)?;
+        if matched > MAX_PATTERN_PLAYERS {
+            return Err(Error::TooManyPlayersMatched(name.clone()));
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum GameSort {
+    #[default]
+    #[serde(rename = "id")]
+    Id,
+    #[serde(rename = "date")]
+    Date,
+    #[serde(rename = "whiteElo")]
+    WhiteElo,
+    #[serde(rename = "blackElo")]
+    BlackElo,
+    #[serde(rename = "ply_count")]
+    PlyCount,
+    #[serde(rename = "event")]
+    Event,
+    #[serde(rename = "site")]
+    Site,
+    #[serde(rename = "round")]
+    Round,
+    /// Higher rating of the two players, to list the strongest games first.
+    #[serde(rename = "maxElo")]
+    MaxElo,
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum SortDirection {
+    #[serde(rename = "asc")]
+    Asc,
+    #[default]
+    #[serde(rename = "desc")]
+    Desc,
+}
+
+#[derive(Default, Debug, Clone, Deserialize, PartialEq, Eq, Hash)]
+pub struct QueryOptions<SortT> {
+    pub skip_count: bool,
+    pub page: Option<i64>,
+    pub page_size: Option<i64>,
+    pub sort: SortT,
+    pub direction: SortDirection,
+    /// Start after this game instead of skipping `page - 1` pages, which
+    /// stays fast deep into large databases. Only used by `get_games`.
+    pub after: Option<Cursor>,
+    /// Return the last count of the query right away, even if the database
+    /// changed since, and send the exact count with a [`GamesCounted`] event.
+    /// Only used by `get_games`.
+    #[serde(default)]
+    pub approximate: bool,
+    /// Sent back in the [`GamesCounted`] event, to tell which query the count
+    /// is for. Only used by `get_games`.
+    pub request_id: Option<u32>,
+}
+
+/// Position of a game in a sorted list of games: the values of the sort
+/// columns and the ID, which breaks the ties.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct Cursor {
+    pub values: Vec<Option<SortValue>>,
+    pub id: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(untagged)]
+pub enum SortValue {
+    Int(i64),
+    Text(String),
+}
+
+impl SortValue {
+    /// The value of an integer column.
+    fn int(value: &Option<SortValue>) -> Result<Option<i32>, Error> {
+        match value {
+            None => Ok(None),
+            Some(SortValue::Int(value)) => i32::try_from(*value)
+                .map(Some)
+                .map_err(|_| Error::InvalidCursor),
+            Some(SortValue::Text(_)) => Err(Error::InvalidCursor),
+        }
+    }
+
+    /// The value of a text column.
+    fn text(value: &Option<SortValue>) -> Result<Option<String>, Error> {
+        match value {
+            None => Ok(None),
+            Some(SortValue::Text(value)) => Ok(Some(value.clone())),
+            Some(SortValue::Int(_)) => Err(Error::InvalidCursor),
+        }
+    }
+}
+
+impl GameSort {
+    /// Cursor of the games after this one.
+    fn cursor(&self, game: &Game) -> Option<Cursor> {
+        let int = |value: Option<i32>| value.map(|v| SortValue::Int(v.into()));
+        let text = |value: &Option<String>| value.clone().map(SortValue::Text);
+        let values = match self {
+            GameSort::Id => vec![],
+            GameSort::Date => vec![text(&game.date), text(&game.time)],
+            GameSort::WhiteElo => vec![int(game.white_elo)],
+            GameSort::BlackElo => vec![int(game.black_elo)],
+            GameSort::PlyCount => vec![int(game.ply_count)],
+            GameSort::MaxElo => vec![int(game.max_rating)],
+            GameSort::Event | GameSort::Site | GameSort::Round => return None,
+        };
+        Some(Cursor {
+            values,
+            id: game.id,
+        })
+    }
+}
+
+/// Condition for the rows after `$value` on `$column` in the order of
+/// `sort_games!`, where NULLs come first when ascending and last when
+/// descending. `$rest` is the condition on the next columns, for the ties.
+macro_rules! after_value {
+    (Asc, $column:expr, $value:expr, $rest:expr) => {{
+        let value = $value;
+        let null = value.is_none();
+        $column
+            .gt(value.clone())
+            .or($column.is_not_null().and(null.into_sql::<Bool>()))
+            .or($column
+                .eq(value)
+                .or($column.is_null().and(null.into_sql::<Bool>()))
+                .and($rest))
+    }};
+    (Desc, $column:expr, $value:expr, $rest:expr) => {{
+        let value = $value;
+        let null = value.is_none();
+        $column
+            .lt(value.clone())
+            .or($column.is_null().and((!null).into_sql::<Bool>()))
+            .or($column
+                .eq(value)
+                .or($column.is_null().and(null.into_sql::<Bool>()))
+                .and($rest))
+    }};
+}
+
+/// Keeps the games after `$cursor` in the order of `sort_games!`. Only sorts
+/// on the columns of `Games` can be paged with a cursor, the others and
+/// cursors that don't fit the sort are an [`Error::InvalidCursor`].
+macro_rules! filter_after_cursor {
+    ($sql_query:expr, $options:expr, $cursor:expr) => {{
+        let sql_query = $sql_query;
+        let options: &QueryOptions<GameSort> = &$options;
+        let cursor: &Cursor = $cursor;
+        let id = cursor.id;
+        match (&options.sort, cursor.values.as_slice()) {
+            (GameSort::Id, []) => Ok(match options.direction {
+                SortDirection::Asc => sql_query.filter(games::id.gt(id)),
+                SortDirection::Desc => sql_query.filter(games::id.lt(id)),
+            }),
+            (GameSort::Date, [date, time]) => {
+                match (SortValue::text(date), SortValue::text(time)) {
+                    (Ok(date), Ok(time)) => Ok(match options.direction {
+                        SortDirection::Asc => sql_query.filter(after_value!(
+                            Asc,
+                            games::date,
+                            date,
+                            after_value!(Asc, games::time, time, games::id.gt(id))
+                        )),
+                        SortDirection::Desc => sql_query.filter(after_value!(
+                            Desc,
+                            games::date,
+                            date,
+                            after_value!(Desc, games::time, time, games::id.lt(id))
+                        )),
+                    }),
+                    _ => Err(Error::InvalidCursor),
+                }
+            }
+            (GameSort::WhiteElo, [elo]) => SortValue::int(elo).map(|elo| match options.direction {
+                SortDirection::Asc => {
+                    sql_query.filter(after_value!(Asc, games::white_elo, elo, games::id.gt(id)))
+                }
+                SortDirection::Desc => {
+                    sql_query.filter(after_value!(Desc, games::white_elo, elo, games::id.lt(id)))
+                }
+            }),
+            (GameSort::BlackElo, [elo]) => SortValue::int(elo).map(|elo| match options.direction {
+                SortDirection::Asc => {
+                    sql_query.filter(after_value!(Asc, games::black_elo, elo, games::id.gt(id)))
+                }
+                SortDirection::Desc => {
+                    sql_query.filter(after_value!(Desc, games::black_elo, elo, games::id.lt(id)))
+                }
+            }),
+            (GameSort::PlyCount, [plies]) => {
+                SortValue::int(plies).map(|plies| match options.direction {
+                    SortDirection::Asc => sql_query.filter(after_value!(
+                        Asc,
+                        games::ply_count,
+                        plies,
+                        games::id.gt(id)
+                    )),
+                    SortDirection::Desc => sql_query.filter(after_value!(
+                        Desc,
+                        games::ply_count,
+                        plies,
+                        games::id.lt(id)
+                    )),
+                })
+            }
+            (GameSort::MaxElo, [elo]) => SortValue::int(elo).map(|elo| match options.direction {
+                SortDirection::Asc => {
+                    sql_query.filter(after_value!(Asc, games::max_rating, elo, games::id.gt(id)))
+                }
+                SortDirection::Desc => {
+                    sql_query.filter(after_value!(Desc, games::max_rating, elo, games::id.lt(id)))
+                }
+            }),
+            _ => Err(Error::InvalidCursor),
+        }
+    }};
+}
+
+/// ECO codes to filter games by, either one or a list from a multi-select.
+/// Each can be a prefix, like `B3` or `B`.
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq, Hash)]
+#[serde(untagged)]
+pub enum EcoFilter {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl EcoFilter {
+    /// Every ECO code matched, so prefixes can be looked up with `IN` on the
+    /// index. `B3` expands to `B30` to `B39`.
+    fn codes(&self) -> Vec<String> {
+        let prefixes: Vec<String> = match self {
+            EcoFilter::One(code) => vec![code.trim().to_ascii_uppercase()],
+            EcoFilter::Many(codes) => codes
+                .iter()
+                .map(|c| c.trim().to_ascii_uppercase())
+                .collect(),
+        };
+        ('A'..='E')
+            .flat_map(|volume| (0..100).map(move |n| format!("{volume}{n:02}")))
+            .filter(|code| {
+                prefixes
+                    .iter()
+                    .any(|prefix| !prefix.is_empty() && code.starts_with(prefix.as_str()))
+            })
+            .collect()
+    }
+}
+
+/// A move played in the mainline of a game, like `Bxh7` or `O-O-O`.
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq, Hash)]
+pub struct MoveFilter {
+    pub san: String,
+    /// Only moves of this side.
+    #[serde(default)]
+    pub side: PlayerSide,
+    /// Inclusive range of plies, counted from 1.
+    pub ply_range: Option<(u32, u32)>,
+}
+
+impl MoveFilter {
+    /// Parses the move, accepting `0-0` for castling and any check mark.
+    fn parse_san(&self) -> Result<San, Error> {
+        Ok(SanPlus::from_ascii(self.san.trim().replace('0', "O").as_bytes())?.san)
+    }
+
+    fn matches(&self, san: &San, moves: &[u8], fen: Option<&str>) -> bool {
+        let Some(mut chess) = initial_position(fen) else {
+            return false;
+        };
+        let (min_ply, max_ply) = self.ply_range.unwrap_or((1, u32::MAX));
+        for (ply, &byte) in (1..).zip(mainline_moves(moves).iter()) {
+            if ply > max_ply {
+                break;
+            }
+            let Some(m) = decode_move(byte, &chess) else {
+                return false;
+            };
+            if ply >= min_ply && self.side.includes(chess.turn()) && san.matches(&m) {
+                return true;
+            }
+            chess.play_unchecked(&m);
+        }
+        false
+    }
+}
+
+/// Games read at a time when looking for a move.
+const MOVE_SEARCH_BATCH_SIZE: usize = 10_000;
+
+/// Games with the move counted by `get_games` before it stops looking, when
+/// the page is already full. The response then tells the count is capped.
+const MOVE_SEARCH_COUNT_CAP: usize = 10_000;
+
+/// IDs of the rows with the move of `move_filter`, in order, stopping once
+/// `needed` are found.
+fn games_with_move(
+    mut rows: impl Iterator<Item = QueryResult<(i32, Vec<u8>, Option<String>)>>,
+    move_filter: &MoveFilter,
+    san: &San,
+    needed: usize,
+) -> Result<Vec<i32>, Error> {
+    let mut matched: Vec<i32> = Vec::new();
+    let mut batch = Vec::with_capacity(MOVE_SEARCH_BATCH_SIZE);
+    loop {
+        let row = rows.next().transpose()?;
+        let done = row.is_none();
+        batch.extend(row);
+        if batch.len() == MOVE_SEARCH_BATCH_SIZE || done {
+            let ids: Vec<i32> = batch
+                .par_drain(..)
+                .filter(|(_, moves, fen)| move_filter.matches(san, moves, fen.as_deref()))
+                .map(|(id, ..)| id)
+                .collect();
+            matched.extend(ids);
+        }
+        if done || matched.len() >= needed {
+            return Ok(matched);
+        }
+    }
+}
+
+/// Results to filter games by, either one or a list from a multi-select.
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq, Hash)]
+#[serde(untagged)]
+pub enum OutcomeFilter {
+    One(GameResult),
+    Many(Vec<GameResult>),
+}
+
+impl OutcomeFilter {
+    fn results(&self) -> Vec<i32> {
+        match self {
+            OutcomeFilter::One(result) => vec![*result as i32],
+            OutcomeFilter::Many(results) => results.iter().map(|r| *r as i32).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Default, PartialEq, Eq, Hash)]
+pub struct GameQuery {
+    pub options: Option<QueryOptions<GameSort>>,
+    /// ID of the first player, white unless `sides` says otherwise.
+    #[serde(alias = "player1_id")]
+    pub player1: Option<i32>,
+    /// ID of the second player, black unless `sides` says otherwise.
+    #[serde(alias = "player2_id")]
+    pub player2: Option<i32>,
+    /// Name of the first player, used when `player1` isn't set.
+    pub player1_name: Option<String>,
+    /// Name of the second player, used when `player2` isn't set.
+    pub player2_name: Option<String>,
+    #[serde(default)]
+    pub match_mode: NameMatch,
+    /// Leaves out the games of these players, on either side, even if they
+    /// are `player1` or `player2`. Names are matched exactly, ignoring case.
+    pub exclude_players: Option<Vec<PlayerRef>>,
+    pub tournament_id: Option<i32>,
+    pub start_date: Option<String>,
+    pub end_date: Option<String>,
+    /// Also match the games without a date when a date range is set.
+    #[serde(default)]
+    pub include_unknown_dates: bool,
+    pub range1: Option<(i32, i32)>,
+    pub range2: Option<(i32, i32)>,
+    /// Games where both players are rated at least this.
+    pub min_both_elo: Option<i32>,
+    /// Inclusive range of the average rating of the players, rounded down.
+    /// Games with an unrated player are left out.
+    pub average_elo_range: Option<(i32, i32)>,
+    pub sides: Option<Sides>,
+    pub outcome: Option<OutcomeFilter>,
+    /// Only won or lost games, leaving out draws and unknown results.
+    #[serde(default)]
+    pub decisive_only: bool,
+    /// Whether to match the games with an unknown result, like unfinished
+    /// games. Defaults to true.
+    pub include_unknown_result: Option<bool>,
+    /// Rated or casual games only. Games of unknown kind count as rated.
+    pub rated: Option<bool>,
+    pub termination: Option<Termination>,
+    pub eco: Option<EcoFilter>,
+    /// Games of the openings with this name, like "Sicilian Najdorf", through
+    /// their ECO codes.
+    pub opening: Option<String>,
+    /// Inclusive range of plies. `(1, i32::MAX)` leaves out the empty games.
+    pub ply_count_range: Option<(i32, i32)>,
+    /// Games of any of these speeds, classified from the time control.
+    pub speed: Option<Vec<Speed>>,
+    /// Exact `TimeControl` header, like `300+3`.
+    pub time_control: Option<String>,
+    /// Games with this move. Checked on the moves of the games matching the
+    /// other filters, by `get_games`, whose count stops at
+    /// `MOVE_SEARCH_COUNT_CAP` games, and before the position searches replay
+    /// the games.
+    pub contains_move: Option<MoveFilter>,
+    /// Load the moves, comments, clocks and evals of the games, defaults to
+    /// true. Tables that only show the headers can leave them out and fetch
+    /// a game with `get_game` when it's opened.
+    pub include_moves: Option<bool>,
+    pub position: Option<PositionQuery>,
+}
+
+impl GameQuery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn position(mut self, position: PositionQuery) -> Self {
+        self.position = Some(position);
+        self
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct QueryResponse<T> {
+    pub data: T,
+    pub count: Option<i64>,
+    /// Whether the counting stopped at a cap, so there may be more games
+    /// than `count`.
+    pub count_capped: bool,
+    /// Cursor of the next page, when the page is full and the sort allows it.
+    pub next_cursor: Option<Cursor>,
+}
+
+/// Applies the filters of a [`GameQuery`] to a boxed query over the games
+/// table, or a join starting from it. A macro, as the boxed query types of the
+/// joins differ.
+macro_rules! filter_games {
+    ($sql_query:expr, $query:expr) => {{
+        let mut sql_query = $sql_query;
+        let query: &GameQuery = &$query;
+
+        if let Some(outcome) = &query.outcome {
+            let results = outcome.results();
+            if results.contains(&(GameResult::Unknown as i32)) {
+                sql_query = sql_query.filter(
+                    games::result
+                        .is_null()
+                        .or(games::result.eq_any(results))
+                        .or(games::result.ne_all(GameResult::DECIDED)),
+                );
+            } else {
+                sql_query = sql_query.filter(games::result.eq_any(results));
+            }
+        }
+
+        if query.include_unknown_result == Some(false) {
+            sql_query = sql_query.filter(games::result.eq_any(GameResult::DECIDED));
+        }
+
+        if query.decisive_only {
+            sql_query = sql_query.filter(
+                games::result.eq_any([GameResult::WhiteWins as i32, GameResult::BlackWins as i32]),
+            );
+        }
+
+        match query.rated {
+            Some(true) => {
+                sql_query = sql_query.filter(games::rated.is_null().or(games::rated.eq(true)));
+            }
+            Some(false) => {
+                sql_query = sql_query.filter(games::rated.eq(false));
+            }
+            None => {}
+        }
+
+        // LIKE without wildcards is a case-insensitive comparison
+        match query.termination {
+            Some(Termination::Unknown) => {
+                for header in Termination::KNOWN.iter().filter_map(|t| t.header()) {
+                    sql_query = sql_query.filter(
+                        games::termination
+                            .is_null()
+                            .or(games::termination.not_like(header)),
+                    );
+                }
+            }
+            Some(termination) => {
+                let header = termination.header().unwrap_or_default();
+                sql_query = sql_query.filter(games::termination.like(header));
+            }
+            None => {}
+        }
+
+        if let Some(start_date) = query.start_date.as_deref().map(normalize_query_date) {
+            if query.include_unknown_dates {
+                sql_query = sql_query.filter(games::date.is_null().or(games::date.ge(start_date)));
+            } else {
+                sql_query = sql_query.filter(games::date.ge(start_date));
+            }
+        }
+
+        if let Some(end_date) = query.end_date.as_deref().map(normalize_query_end_date) {
+            if query.include_unknown_dates {
+                sql_query = sql_query.filter(games::date.is_null().or(games::date.le(end_date)));
+            } else {
+                sql_query = sql_query.filter(games::date.le(end_date));
+            }
+        }
+
+        if let Some(tournament_id) = query.tournament_id {
+            sql_query = sql_query.filter(games::event_id.eq(tournament_id));
+        }
+
+        if let Some(eco) = &query.eco {
+            sql_query = sql_query.filter(games::eco.eq_any(eco.codes()));
+        }
+
+        if let Some(opening) = &query.opening {
+            sql_query = sql_query.filter(games::eco.eq_any(get_eco_codes_from_name(opening)));
+        }
+
+        if let Some(min_elo) = query.min_both_elo {
+            sql_query = sql_query
+                .filter(games::white_elo.ge(min_elo))
+                .filter(games::black_elo.ge(min_elo));
+        }
+
+        // On the sum, so that the average is rounded down like with
+        // integer division
+        if let Some((min, max)) = query.average_elo_range {
+            sql_query = sql_query
+                .filter((games::white_elo + games::black_elo).between(2 * min, 2 * max + 1));
+        }
+
+        if let Some((min, max)) = query.ply_count_range {
+            sql_query = sql_query.filter(games::ply_count.between(min, max));
+        }
+
+        if let Some(speeds) = &query.speed {
+            let speeds: Vec<i32> = speeds.iter().map(|speed| *speed as i32).collect();
+            sql_query = sql_query.filter(games::speed.eq_any(speeds));
+        }
+
+        if let Some(time_control) = &query.time_control {
+            sql_query = sql_query.filter(games::time_control.eq(time_control));
+        }
+
+        // Without sides the players are white and black, as in `search_position`
+        match query.sides.clone().unwrap_or_default() {
+            Sides::BlackWhite => {
+                if let Some(player1) = query.player1 {
+                    sql_query = sql_query.filter(games::black_id.eq(player1));
+                } else if let Some(name) = &query.player1_name {
+                    sql_query = sql_query
+                        .filter(games::black_id.eq_any(players_named(name, query.match_mode)));
+                }
+                if let Some(player2) = query.player2 {
+                    sql_query = sql_query.filter(games::white_id.eq(player2));
+                } else if let Some(name) = &query.player2_name {
+                    sql_query = sql_query
+                        .filter(games::white_id.eq_any(players_named(name, query.match_mode)));
+                }
+
+                if let Some(range1) = query.range1 {
+                    sql_query = sql_query.filter(games::black_elo.between(range1.0, range1.1));
+                }
+
+                if let Some(range2) = query.range2 {
+                    sql_query = sql_query.filter(games::white_elo.between(range2.0, range2.1));
+                }
+            }
+            Sides::WhiteBlack => {
+                if let Some(player1) = query.player1 {
+                    sql_query = sql_query.filter(games::white_id.eq(player1));
+                } else if let Some(name) = &query.player1_name {
+                    sql_query = sql_query
+                        .filter(games::white_id.eq_any(players_named(name, query.match_mode)));
+                }
+                if let Some(player2) = query.player2 {
+                    sql_query = sql_query.filter(games::black_id.eq(player2));
+                } else if let Some(name) = &query.player2_name {
+                    sql_query = sql_query
+                        .filter(games::black_id.eq_any(players_named(name, query.match_mode)));
+                }
+
+                if let Some(range1) = query.range1 {
+                    sql_query = sql_query.filter(games::white_elo.between(range1.0, range1.1));
+                }
+
+                if let Some(range2) = query.range2 {
+                    sql_query = sql_query.filter(games::black_elo.between(range2.0, range2.1));
+                }
+            }
+            Sides::Any => {
+                if let Some(player1) = query.player1 {
+                    sql_query = sql_query
+                        .filter(games::white_id.eq(player1).or(games::black_id.eq(player1)));
+                } else if let Some(name) = &query.player1_name {
+                    sql_query = sql_query.filter(
+                        games::white_id
+                            .eq_any(players_named(name, query.match_mode))
+                            .or(games::black_id.eq_any(players_named(name, query.match_mode))),
+                    );
+                }
+                if let Some(player2) = query.player2 {
+                    sql_query = sql_query
+                        .filter(games::white_id.eq(player2).or(games::black_id.eq(player2)));
+                } else if let Some(name) = &query.player2_name {
+                    sql_query = sql_query.filter(
+                        games::white_id
+                            .eq_any(players_named(name, query.match_mode))
+                            .or(games::black_id.eq_any(players_named(name, query.match_mode))),
+                    );
+                }
+
+                if let (Some(range1), Some(range2)) = (query.range1, query.range2) {
+                    sql_query = sql_query.filter(
+                        games::white_elo
+                            .between(range1.0, range1.1)
+                            .or(games::black_elo.between(range1.0, range1.1))
+                            .or(games::white_elo
+                                .between(range2.0, range2.1)
+                                .or(games::black_elo.between(range2.0, range2.1))),
+                    );
+                } else {
+                    if let Some(range1) = query.range1 {
+                        sql_query = sql_query.filter(
+                            games::white_elo
+                                .between(range1.0, range1.1)
+                                .or(games::black_elo.between(range1.0, range1.1)),
+                        );
+                    }
+
+                    if let Some(range2) = query.range2 {
+                        sql_query = sql_query.filter(
+                            games::white_elo
+                                .between(range2.0, range2.1)
+                                .or(games::black_elo.between(range2.0, range2.1)),
+                        );
+                    }
+                }
+            }
+        }
+
+        // Whatever the sides, both players are checked
+        for excluded in query.exclude_players.iter().flatten() {
+            match excluded {
+                PlayerRef::Id(id) => {
+                    sql_query = sql_query
+                        .filter(games::white_id.ne(*id))
+                        .filter(games::black_id.ne(*id));
+                }
+                PlayerRef::Name(name) => {
+                    sql_query = sql_query
+                        .filter(games::white_id.ne_all(players_named(name, NameMatch::Exact)))
+                        .filter(games::black_id.ne_all(players_named(name, NameMatch::Exact)));
+                }
+            }
+        }
+
+        sql_query
+    }};
+}
+
+/// Number of the round, so that "10" comes after "9". Old databases declared
+/// `Round` as INTEGER, which the cast handles too.
+const ROUND_NUMBER_SQL: &str = "CAST(Games.Round AS INTEGER)";
+
+/// Number after the dot of rounds like "4.1", keeping the boards of a round
+/// in order under it.
+const ROUND_BOARD_SQL: &str = "CASE WHEN INSTR(Games.Round, '.') > 0 \
+    THEN CAST(SUBSTR(Games.Round, INSTR(Games.Round, '.') + 1) AS INTEGER) \
+    ELSE 0 END";
+
+/// Orders a boxed games query by the sort of the [`QueryOptions`], then by
+/// ID. Sorting by event or site needs the query joined with `events` and
+/// `sites`.
+macro_rules! sort_games {
+    ($sql_query:expr, $options:expr) => {{
+        let sql_query = $sql_query;
+        let options: &QueryOptions<GameSort> = &$options;
+        let sql_query = match options.sort {
+            GameSort::Id => match options.direction {
+                SortDirection::Asc => sql_query.order(games::id.asc()),
+                SortDirection::Desc => sql_query.order(games::id.desc()),
+            },
+            GameSort::Date => match options.direction {
+                SortDirection::Asc => sql_query.order((games::date.asc(), games::time.asc())),
+                SortDirection::Desc => sql_query.order((games::date.desc(), games::time.desc())),
+            },
+            GameSort::WhiteElo => match options.direction {
+                SortDirection::Asc => sql_query.order(games::white_elo.asc()),
+                SortDirection::Desc => sql_query.order(games::white_elo.desc()),
+            },
+            GameSort::BlackElo => match options.direction {
+                SortDirection::Asc => sql_query.order(games::black_elo.asc()),
+                SortDirection::Desc => sql_query.order(games::black_elo.desc()),
+            },
+            GameSort::PlyCount => match options.direction {
+                SortDirection::Asc => sql_query.order(games::ply_count.asc()),
+                SortDirection::Desc => sql_query.order(games::ply_count.desc()),
+            },
+            GameSort::MaxElo => match options.direction {
+                SortDirection::Asc => sql_query.order(games::max_rating.asc()),
+                SortDirection::Desc => sql_query.order(games::max_rating.desc()),
+            },
+            GameSort::Event => match options.direction {
+                SortDirection::Asc => sql_query.order((
+                    events::name.asc(),
+                    diesel::dsl::sql::<Nullable<Integer>>(ROUND_NUMBER_SQL).asc(),
+                    diesel::dsl::sql::<Integer>(ROUND_BOARD_SQL).asc(),
+                )),
+                SortDirection::Desc => sql_query.order((
+                    events::name.desc(),
+                    diesel::dsl::sql::<Nullable<Integer>>(ROUND_NUMBER_SQL).desc(),
+                    diesel::dsl::sql::<Integer>(ROUND_BOARD_SQL).desc(),
+                )),
+            },
+            GameSort::Site => match options.direction {
+                SortDirection::Asc => sql_query.order(sites::name.asc()),
+                SortDirection::Desc => sql_query.order(sites::name.desc()),
+            },
+            GameSort::Round => match options.direction {
+                SortDirection::Asc => sql_query.order((
+                    diesel::dsl::sql::<Nullable<Integer>>(ROUND_NUMBER_SQL).asc(),
+                    diesel::dsl::sql::<Integer>(ROUND_BOARD_SQL).asc(),
+                    games::round.asc(),
+                )),
+                SortDirection::Desc => sql_query.order((
+                    diesel::dsl::sql::<Nullable<Integer>>(ROUND_NUMBER_SQL).desc(),
+                    diesel::dsl::sql::<Integer>(ROUND_BOARD_SQL).desc(),
+                    games::round.desc(),
+                )),
+            },
+        };
+        match (&options.sort, &options.direction) {
+            (GameSort::Id, _) => sql_query,
+            (_, SortDirection::Asc) => sql_query.then_order_by(games::id.asc()),
+            (_, SortDirection::Desc) => sql_query.then_order_by(games::id.desc()),
+        }
+    }};
+}
+
+/// The columns of [`Game`], with empty moves and no clocks or evals, so the
+/// largest columns aren't read at all.
+macro_rules! game_columns_without_moves {
+    () => {
+        (
+            games::id,
+            games::event_id,
+            games::site_id,
+            games::date,
+            games::time,
+            games::round,
+            games::white_id,
             games::white_elo,
+            games::black_id,
             games::black_elo,
+            games::white_material,
+            games::black_material,
+            games::result,
+            games::time_control,
+            games::eco,
+            games::ply_count,
+            games::fen,
+            diesel::dsl::sql::<Binary>("X''"),
+            games::pawn_home,
+            games::speed,
+            games::game_hash,
+            games::white_title,
+            games::black_title,
+            games::variant,
+            diesel::dsl::sql::<Nullable<Binary>>("NULL"),
+            diesel::dsl::sql::<Nullable<Binary>>("NULL"),
+            games::rated,
+            games::termination,
+            games::max_rating,
+            diesel::dsl::sql::<Nullable<Binary>>("NULL"),
+            games::white_original_name,
+            games::black_original_name,
+        )
+    };
+}
+
+#[tauri::command]
+pub async fn get_games(
+    file: PathBuf,
+    query: GameQuery,
+    app: tauri::AppHandle,
+    state: tauri::State<'_, AppState>,
+) -> Result<QueryResponse<Vec<NormalizedGame>>, Error> {
+    let path_str = file.to_str().unwrap();
+    let db = &mut get_db_or_create(&state, path_str, ConnectionOptions::default())?;
+    check_name_patterns(db, &query)?;
+
+    let mut count: Option<i64> = None;
+    let mut count_capped = false;
+    let query_options = query.options.clone().unwrap_or_default();
+
+    let (white_players, black_players) = diesel::alias!(players as white, players as black);
+    let joined_games = || {
+        games::table
+            .inner_join(white_players.on(games::white_id.eq(white_players.field(players::id))))
+            .inner_join(black_players.on(games::black_id.eq(black_players.field(players::id))))
+            .inner_join(events::table.on(games::event_id.eq(events::id)))
+            .inner_join(sites::table.on(games::site_id.eq(sites::id)))
+            .into_boxed()
+    };
+    let mut sql_query = joined_games();
+
+    sql_query = filter_games!(sql_query, query);
+    sql_query = sort_games!(sql_query, query_options);
+
+    let mut offset = 0;
+    if let Some(cursor) = &query_options.after {
+        sql_query = filter_after_cursor!(sql_query, query_options, cursor)?;
+    } else if let Some(page) = query_options.page {
+        offset = (page - 1) * query_options.page_size.unwrap_or(10);
+    }
+
+    if let Some(move_filter) = &query.contains_move {
+        let san = move_filter.parse_san()?;
+        let page_end = query_options
+            .page_size
+            .map(|page_size| (offset + page_size) as usize);
+        let needed = match page_end {
+            Some(page_end) if query_options.skip_count => page_end,
+            Some(page_end) => page_end.max(MOVE_SEARCH_COUNT_CAP),
+            None => usize::MAX,
+        };
+
+        let rows = sql_query
+            .select((games::id, games::moves, games::fen))
+            .load_iter::<(i32, Vec<u8>, Option<String>), DefaultLoadingMode>(db)?;
+        let matched = games_with_move(rows, move_filter, &san, needed)?;
+
+        if !query_options.skip_count {
+            count = Some(matched.len() as i64);
+            count_capped = matched.len() >= needed;
+        }
+        let page: Vec<i32> = matched
+            .into_iter()
+            .skip(offset as usize)
+            .take(page_end.map_or(usize::MAX, |end| end - offset as usize))
+            .collect();
+        sql_query = sort_games!(joined_games().filter(games::id.eq_any(page)), query_options);
+    } else if let Some(limit) = query_options.page_size {
+        sql_query = sql_query.limit(limit).offset(offset);
+    }
+
+    if !query_options.skip_count && query.contains_move.is_none() {
+        let generation = state.count_cache.generation(path_str);
+        count = match state.count_cache.get(path_str, &query) {
+            Some((cached, true)) => Some(cached),
+            cached if query_options.approximate => {
+                let mut count_db =
+                    get_db_or_create(&state, path_str, ConnectionOptions::default())?;
+                let file = path_str.to_string();
+                let query = query.clone();
+                let request_id = query_options.request_id;
+                tokio::task::spawn_blocking(move || {
+                    if let Ok(count) = count_games(&mut count_db, &query) {
+                        let state = app.state::<AppState>();
+                        state.count_cache.insert(&file, &query, generation, count);
+                        let _ = GamesCounted {
+                            file,
+                            request_id,
+                            count,
+                        }
+                        .emit_all(&app);
+                    }
+                });
+                cached.map(|(cached, _)| cached)
+            }
+            _ => {
+                let exact = count_games(db, &query)?;
+                state
+                    .count_cache
+                    .insert(path_str, &query, generation, exact);
+                Some(exact)
+            }
+        };
+    }
+
+    // println!(
+    //     "{:?}\n",
+    //     diesel::debug_query::<diesel::sqlite::Sqlite, _>(&sql_query)
+    // );
+
+    let include_moves = query.include_moves.unwrap_or(true);
+    let games: Vec<(Game, Player, Player, Event, Site)> = if include_moves {
+        sql_query.load(db)?
+    } else {
+        sql_query
+            .select((
+                game_columns_without_moves!(),
+                white_players.fields(players::all_columns),
+                black_players.fields(players::all_columns),
+                events::all_columns,
+                sites::all_columns,
+            ))
+            .load(db)?
+    };
+
+    let next_cursor = match (games.last(), query_options.page_size) {
+        (Some((last, ..)), Some(page_size)) if games.len() as i64 == page_size => {
+            query_options.sort.cursor(last)
+        }
+        _ => None,
+    };
+    let mut normalized_games = normalize_games(games);
+    if include_moves {
+        load_comments(db, &mut normalized_games)?;
+    }
+
+    Ok(QueryResponse {
+        data: normalized_games,
+        count,
+        count_capped,
+        next_cursor,
+    })
+}
+
+fn count_games(db: &mut SqliteConnection, query: &GameQuery) -> Result<i64, Error> {
+    Ok(filter_games!(games::table.into_boxed(), query)
+        .select(diesel::dsl::count(games::id))
+        .first(db)?)
+}
+
+/// The sorted IDs of the games matching the filters of `query`, as in
+/// `get_games`, or `None` if it has none. The position filter is left to the
+/// caller.
+fn filtered_game_ids(
+    db: &mut SqliteConnection,
+    query: &GameQuery,
+) -> Result<Option<Vec<i32>>, Error> {
+    let filters = GameQuery {
+        options: None,
+        include_moves: None,
+        position: None,
+        ..query.clone()
+    };
+    if filters == GameQuery::default() {
+        return Ok(None);
+    }
+    let sql_query = filter_games!(games::table.into_boxed(), filters).order(games::id.asc());
+    if let Some(move_filter) = &filters.contains_move {
+        let san = move_filter.parse_san()?;
+        let rows = sql_query
+            .select((games::id, games::moves, games::fen))
+            .load_iter::<(i32, Vec<u8>, Option<String>), DefaultLoadingMode>(db)?;
+        return Ok(Some(games_with_move(rows, move_filter, &san, usize::MAX)?));
+    }
+    Ok(Some(sql_query.select(games::id).load(db)?))
+}
+
+/// Length of the periods of [`games_per_period`].
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub enum Granularity {
+    Year,
+    Month,
+}
+
+/// Key of the games without a known year, or month when counting by month.
+const UNKNOWN_PERIOD: &str = "Unknown";
+
+/// Number of games of each year or month, like `2023` or `2023-05`, in
+/// chronological order and followed by the games of unknown date.
+fn games_per_period(
+    db: &mut SqliteConnection,
+    granularity: Granularity,
+    query: &GameQuery,
+) -> Result<Vec<(String, i64)>, Error> {
+    // Substrings of the normalized date, so that the date index covers it
+    let period = diesel::dsl::sql::<Nullable<Text>>(match granularity {
+        Granularity::Year => "substr(Games.Date, 1, 4)",
+        Granularity::Month => {
+            "CASE WHEN substr(Games.Date, 6, 2) = '00' THEN NULL ELSE substr(Games.Date, 1, 7) END"
+        }
+    });
+    let counts: Vec<(Option<String>, i64)> = filter_games!(games::table.into_boxed(), query)
+        .group_by(period.clone())
+        .select((period, diesel::dsl::count_star()))
+        .load(db)?;
+
+    let mut unknown = 0;
+    let mut periods: Vec<(String, i64)> = counts
+        .into_iter()
+        .filter_map(|(period, count)| match period {
+            Some(period) => Some((period, count)),
+            None => {
+                unknown += count;
+                None
+            }
+        })
+        .collect();
+    periods.sort();
+    if unknown > 0 {
+        periods.push((UNKNOWN_PERIOD.to_string(), unknown));
+    }
+    Ok(periods)
+}
+
+/// Number of games per year or month, for the charts of the database
+/// overview. Only counts the games of `query` if given.
+#[tauri::command]
+pub async fn get_games_per_period(
+    file: PathBuf,
+    granularity: Granularity,
+    query: Option<GameQuery>,
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<(String, i64)>, Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    games_per_period(db, granularity, &query.unwrap_or_default())
+}
+
+/// Info table row caching the [`DatabaseStats`] of the database.
+const STATS_KEY: &str = "DatabaseStats";
+
+/// Overview of the games of a database.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct DatabaseStats {
+    pub game_count: i64,
+    pub white_wins: i64,
+    pub draws: i64,
+    pub black_wins: i64,
+    pub unknown_results: i64,
+    /// Ratings of both colors in buckets of 100 points, by their lower bound.
+    pub elo_histogram: Vec<(i32, i64)>,
+    pub average_ply_count: Option<f64>,
+    pub median_ply_count: Option<f64>,
+    /// Games where a player has no rating.
+    pub missing_ratings: i64,
+    pub missing_dates: i64,
+}
+
+/// Stats cached in the Info table, with the state of the Games table they
+/// were computed for. Apart from `normalize_dates`, which clears them, games
+/// are only ever added or deleted, and the IDs aren't reused, so the count
+/// and the last ID tell when they changed.
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedStats {
+    game_count: i64,
+    last_id: Option<i32>,
+    stats: DatabaseStats,
+}
+
+#[derive(QueryableByName)]
+struct StatsTotals {
+    #[diesel(sql_type = BigInt)]
+    game_count: i64,
+    #[diesel(sql_type = BigInt)]
+    white_wins: i64,
+    #[diesel(sql_type = BigInt)]
+    draws: i64,
+    #[diesel(sql_type = BigInt)]
+    black_wins: i64,
+    #[diesel(sql_type = Nullable<Double>)]
+    average_ply_count: Option<f64>,
+    #[diesel(sql_type = BigInt)]
+    missing_ratings: i64,
+    #[diesel(sql_type = BigInt)]
+    missing_dates: i64,
+}
+
+#[derive(QueryableByName)]
+struct EloBucket {
+    #[diesel(sql_type = Integer)]
+    bucket: i32,
+    #[diesel(sql_type = BigInt)]
+    count: i64,
+}
+
+fn compute_database_stats(db: &mut SqliteConnection) -> Result<DatabaseStats, Error> {
+    let totals: StatsTotals = sql_query(
+        "SELECT
+            COUNT(*) AS game_count,
+            COALESCE(SUM(Result = 1), 0) AS white_wins,
+            COALESCE(SUM(Result = 3), 0) AS draws,
+            COALESCE(SUM(Result = 2), 0) AS black_wins,
+            AVG(PlyCount) AS average_ply_count,
+            COALESCE(SUM(WhiteElo IS NULL OR BlackElo IS NULL), 0) AS missing_ratings,
+            COALESCE(SUM(Date IS NULL), 0) AS missing_dates
+        FROM Games;",
+    )
+    .get_result(db)?;
+
+    // Both sides of the union are served by the rating indexes
+    let elo_histogram = sql_query(
+        "SELECT Elo / 100 * 100 AS bucket, COUNT(*) AS count
+        FROM (
+            SELECT WhiteElo AS Elo FROM Games WHERE WhiteElo IS NOT NULL
+            UNION ALL
+            SELECT BlackElo AS Elo FROM Games WHERE BlackElo IS NOT NULL
+        )
+        GROUP BY bucket
+        ORDER BY bucket;",
+    )
+    .load::<EloBucket>(db)?
+    .into_iter()
+    .map(|b| (b.bucket, b.count))
+    .collect();
+
+    let with_ply_count: i64 = games::table
+        .filter(games::ply_count.is_not_null())
+        .count()
+        .get_result(db)?;
+    let median_ply_count = if with_ply_count == 0 {
+        None
+    } else {
+        // The middle one, or the two middle ones of an even count
+        let middle: Vec<Option<i32>> = games::table
+            .select(games::ply_count)
+            .filter(games::ply_count.is_not_null())
+            .order(games::ply_count)
+            .offset((with_ply_count - 1) / 2)
+            .limit(2 - with_ply_count % 2)
+            .load(db)?;
+        let middle: Vec<f64> = middle.into_iter().flatten().map(f64::from).collect();
+        Some(middle.iter().sum::<f64>() / middle.len() as f64)
+    };
+
+    Ok(DatabaseStats {
+        game_count: totals.game_count,
+        white_wins: totals.white_wins,
+        draws: totals.draws,
+        black_wins: totals.black_wins,
+        unknown_results: totals.game_count - totals.white_wins - totals.draws - totals.black_wins,
+        elo_histogram,
+        average_ply_count: totals.average_ply_count,
+        median_ply_count,
+        missing_ratings: totals.missing_ratings,
+        missing_dates: totals.missing_dates,
+    })
+}
+
+/// The stats of the database, from the Info table unless the games changed
+/// since they were computed.
+fn database_stats(db: &mut SqliteConnection) -> Result<DatabaseStats, Error> {
+    let (game_count, last_id): (i64, Option<i32>) = games::table
+        .select((diesel::dsl::count_star(), diesel::dsl::max(games::id)))
+        .first(db)?;
+
+    let cached: Option<Option<String>> = info::table
+        .filter(info::name.eq(STATS_KEY))
+        .select(info::value)
+        .first(db)
+        .optional()?;
+    if let Some(cached) = cached.flatten() {
+        // Stats of an older version may not parse, they are computed again
+        if let Ok(cached) = serde_json::from_str::<CachedStats>(&cached) {
+            if cached.game_count == game_count && cached.last_id == last_id {
+                return Ok(cached.stats);
+            }
+        }
+    }
+
+    let stats = compute_database_stats(db)?;
+    let cached = CachedStats {
+        game_count,
+        last_id,
+        stats,
+    };
+    set_info(db, STATS_KEY, &serde_json::to_string(&cached)?)?;
+    Ok(cached.stats)
+}
+
+fn clear_database_stats(db: &mut SqliteConnection) -> Result<(), Error> {
+    diesel::delete(info::table.filter(info::name.eq(STATS_KEY))).execute(db)?;
+    Ok(())
+}
+
+/// Result, rating and length statistics of a database, for its overview.
+#[tauri::command]
+pub async fn get_db_stats(
+    file: PathBuf,
+    state: tauri::State<'_, AppState>,
+) -> Result<DatabaseStats, Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    database_stats(db)
+}
+
+/// How the games of [`get_opening_stats`] are grouped.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub enum OpeningGroup {
+    /// The volume of the ECO code, from `A` to `E`.
+    EcoLetter,
+    EcoCode,
+    /// The name of the opening of the ECO code.
+    OpeningName,
+}
+
+/// Group of the games without an ECO code, or whose code has no name.
+const UNCLASSIFIED_OPENING: &str = "Unclassified";
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct OpeningStats {
+    pub group: String,
+    /// All the games of the group, including those of unknown result.
+    pub games: i64,
+    pub white_wins: i64,
+    pub draws: i64,
+    pub black_wins: i64,
+}
+
+/// Number of games and results per opening, the most played first.
+fn opening_stats(
+    db: &mut SqliteConnection,
+    group: OpeningGroup,
+    query: &GameQuery,
+) -> Result<Vec<OpeningStats>, Error> {
+    let counts: Vec<(Option<String>, Option<i32>, i64)> =
+        filter_games!(games::table.into_boxed(), query)
+            .group_by((games::eco, games::result))
+            .select((games::eco, games::result, diesel::dsl::count_star()))
+            .load(db)?;
+
+    let mut stats: HashMap<String, OpeningStats> = HashMap::new();
+    for (eco, result, count) in counts {
+        let key = eco.and_then(|eco| match group {
+            OpeningGroup::EcoLetter => eco.get(..1).map(String::from),
+            OpeningGroup::EcoCode => Some(eco),
+            OpeningGroup::OpeningName => get_opening_name_from_eco(&eco).map(String::from),
+        });
+        let key = key.unwrap_or_else(|| UNCLASSIFIED_OPENING.to_string());
+        let entry = stats.entry(key.clone()).or_insert(OpeningStats {
+            group: key,
+            games: 0,
+            white_wins: 0,
+            draws: 0,
+            black_wins: 0,
+        });
+        entry.games += count;
+        match GameResult::from_column(result) {
+            GameResult::WhiteWins => entry.white_wins += count,
+            GameResult::Draw => entry.draws += count,
+            GameResult::BlackWins => entry.black_wins += count,
+            GameResult::Unknown => {}
+        }
+    }
+
+    let mut stats: Vec<OpeningStats> = stats.into_values().collect();
+    stats.sort_by(|a, b| b.games.cmp(&a.games).then_with(|| a.group.cmp(&b.group)));
+    Ok(stats)
+}
+
+/// Number of games and results per opening of a database, or of the games of
+/// `query` if given, for the opening charts.
+#[tauri::command]
+pub async fn get_opening_stats(
+    file: PathBuf,
+    query: Option<GameQuery>,
+    group: OpeningGroup,
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<OpeningStats>, Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    opening_stats(db, group, &query.unwrap_or_default())
+}
+
+/// Games and score of a player in an opening.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct RepertoireStats {
+    pub group: String,
+    /// All the games of the group, including those of unknown result.
+    pub games: i64,
+    pub wins: i64,
+    pub draws: i64,
+    pub losses: i64,
+}
+
+/// The openings of a player with the colors of `side`, the most played first,
+/// with the results from their side of the board.
+fn player_openings(
+    db: &mut SqliteConnection,
+    id: i32,
+    side: PlayerSide,
+    group: OpeningGroup,
+    start_date: Option<String>,
+    end_date: Option<String>,
+) -> Result<Vec<RepertoireStats>, Error> {
+    let mut repertoire: HashMap<String, RepertoireStats> = HashMap::new();
+    for (sides, color) in [
+        (Sides::WhiteBlack, shakmaty::Color::White),
+        (Sides::BlackWhite, shakmaty::Color::Black),
+    ] {
+        if !side.includes(color) {
+            continue;
+        }
+        let query = GameQuery {
+            player1: Some(id),
+            sides: Some(sides),
+            start_date: start_date.clone(),
+            end_date: end_date.clone(),
+            ..Default::default()
+        };
+        for stats in opening_stats(db, group, &query)? {
+            let entry = repertoire
+                .entry(stats.group.clone())
+                .or_insert(RepertoireStats {
+                    group: stats.group,
+                    games: 0,
+                    wins: 0,
+                    draws: 0,
+                    losses: 0,
+                });
+            let (wins, losses) = if color.is_white() {
+                (stats.white_wins, stats.black_wins)
+            } else {
+                (stats.black_wins, stats.white_wins)
+            };
+            entry.games += stats.games;
+            entry.wins += wins;
+            entry.draws += stats.draws;
+            entry.losses += losses;
+        }
+    }
+
+    let mut repertoire: Vec<RepertoireStats> = repertoire.into_values().collect();
+    repertoire.sort_by(|a, b| b.games.cmp(&a.games).then_with(|| a.group.cmp(&b.group)));
+    Ok(repertoire)
+}
+
+/// The repertoire of a player, optionally between two dates.
+#[tauri::command]
+pub async fn get_player_openings(
+    file: PathBuf,
+    player_id: i32,
+    color: PlayerSide,
+    group: OpeningGroup,
+    start_date: Option<String>,
+    end_date: Option<String>,
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<RepertoireStats>, Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    player_openings(db, player_id, color, group, start_date, end_date)
+}
+
+/// The rating difference for each percentage of score from 50% to 100%, from
+/// the FIDE rating regulations. Lower scores use the opposite differences.
+const FIDE_RATING_DIFFERENCES: [i32; 51] = [
+    0, 7, 14, 21, 29, 36, 43, 50, 57, 65, 72, 80, 87, 95, 102, 110, 117, 125, 133, 141, 149, 158,
+    166, 175, 184, 193, 202, 211, 220, 230, 240, 251, 262, 273, 284, 296, 309, 322, 336, 351, 366,
+    383, 401, 422, 444, 470, 501, 538, 589, 677, 800,
+];
+
+/// The rating difference of a score between 0 and 1, ±800 for a perfect or
+/// a null score.
+fn fide_rating_difference(score: f64) -> i32 {
+    let percentage = (score * 100.0).round() as i32;
+    if percentage >= 50 {
+        FIDE_RATING_DIFFERENCES[(percentage - 50).min(50) as usize]
+    } else {
+        -FIDE_RATING_DIFFERENCES[(50 - percentage).min(50) as usize]
+    }
+}
+
+/// The performance of a player over the games of a query.
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+pub struct Performance {
+    /// The games of known result against a rated opponent.
+    pub games: i32,
+    pub points: f64,
+    pub average_opponent_rating: Option<f64>,
+    pub performance_rating: Option<i32>,
+    /// The games of known result left out for an unrated opponent.
+    pub excluded_games: i32,
+}
+
+fn player_performance(
+    db: &mut SqliteConnection,
+    id: i32,
+    query: &GameQuery,
+) -> Result<Performance, Error> {
+    let games: Vec<(i32, Option<i32>, Option<i32>, Option<i32>)> =
+        filter_games!(games::table.into_boxed(), query)
+            .filter(games::white_id.eq(id).or(games::black_id.eq(id)))
+            .select((
+                games::white_id,
+                games::result,
+                games::white_elo,
+                games::black_elo,
+            ))
+            .load(db)?;
+
+    let mut performance = Performance::default();
+    let mut opponent_ratings = 0;
+    for (white_id, result, white_elo, black_elo) in games {
+        let is_white = white_id == id;
+        let Some(points) = GameResult::from_column(result).points(is_white) else {
+            continue;
+        };
+        let opponent_elo = if is_white { black_elo } else { white_elo };
+        match opponent_elo.filter(|&elo| elo > 0) {
+            Some(elo) => {
+                performance.games += 1;
+                performance.points += points;
+                opponent_ratings += i64::from(elo);
+            }
+            None => performance.excluded_games += 1,
+        }
+    }
+
+    if performance.games > 0 {
+        let games = f64::from(performance.games);
+        let average = opponent_ratings as f64 / games;
+        performance.average_opponent_rating = Some(average);
+        performance.performance_rating =
+            Some(average.round() as i32 + fide_rating_difference(performance.points / games));
+    }
+    Ok(performance)
+}
+
+/// The performance rating of a player over the games of `query`, such as
+/// those of a tournament, with the FIDE formula.
+#[tauri::command]
+pub async fn get_player_performance(
+    file: PathBuf,
+    player_id: i32,
+    query: Option<GameQuery>,
+    state: tauri::State<'_, AppState>,
+) -> Result<Performance, Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    player_performance(db, player_id, &query.unwrap_or_default())
+}
+
+/// Loads a single game with its players, event, site and comments.
+fn load_game(db: &mut SqliteConnection, id: i32) -> Result<NormalizedGame, Error> {
+    let (white_players, black_players) = diesel::alias!(players as white, players as black);
+    let game: Option<(Game, Player, Player, Event, Site)> = games::table
+        .inner_join(white_players.on(games::white_id.eq(white_players.field(players::id))))
+        .inner_join(black_players.on(games::black_id.eq(black_players.field(players::id))))
+        .inner_join(events::table.on(games::event_id.eq(events::id)))
+        .inner_join(sites::table.on(games::site_id.eq(sites::id)))
+        .filter(games::id.eq(id))
+        .first(db)
+        .optional()?;
+    let game = game.ok_or(Error::GameNotFound(id))?;
+
+    let mut normalized_games = normalize_games(vec![game]);
+    load_comments(db, &mut normalized_games)?;
+    Ok(normalized_games.remove(0))
+}
+
+#[tauri::command]
+pub async fn get_game(
+    file: PathBuf,
+    id: i32,
+    state: tauri::State<'_, AppState>,
+) -> Result<NormalizedGame, Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    load_game(db, id)
+}
+
+/// Games sent at a time by `stream_games`.
+const STREAM_BATCH_SIZE: usize = 2_000;
+
+/// Sent by `stream_games` on the event named after its channel.
+#[derive(Serialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum GameStreamMessage {
+    Batch {
+        index: usize,
+        games: Vec<NormalizedGame>,
+    },
+    Finished {
+        /// Games sent, less than `matched` if cancelled or failed.
+        total: usize,
+        matched: usize,
+        cancelled: bool,
+        /// Why the stream stopped early, if it failed.
+        error: Option<String>,
+    },
+}
+
+/// IDs of all the games of a query in its sort order, ignoring the page.
+fn sorted_game_ids(db: &mut SqliteConnection, query: &GameQuery) -> Result<Vec<i32>, Error> {
+    check_name_patterns(db, query)?;
+    let query_options = query.options.clone().unwrap_or_default();
+    let mut sql_query = games::table
+        .inner_join(events::table.on(games::event_id.eq(events::id)))
+        .inner_join(sites::table.on(games::site_id.eq(sites::id)))
+        .into_boxed();
+    sql_query = filter_games!(sql_query, query);
+    sql_query = sort_games!(sql_query, query_options);
+
+    if let Some(move_filter) = &query.contains_move {
+        let san = move_filter.parse_san()?;
+        let rows = sql_query
+            .select((games::id, games::moves, games::fen))
+            .load_iter::<(i32, Vec<u8>, Option<String>), DefaultLoadingMode>(db)?;
+        return games_with_move(rows, move_filter, &san, usize::MAX);
+    }
+    Ok(sql_query.select(games::id).load(db)?)
+}
+
+/// Loads the games with the given IDs, in the same order.
+fn load_games_by_id(
+    db: &mut SqliteConnection,
+    ids: &[i32],
+    include_moves: bool,
+) -> Result<Vec<NormalizedGame>, Error> {
+    let (white_players, black_players) = diesel::alias!(players as white, players as black);
+    let sql_query = games::table
+        .inner_join(white_players.on(games::white_id.eq(white_players.field(players::id))))
+        .inner_join(black_players.on(games::black_id.eq(black_players.field(players::id))))
+        .inner_join(events::table.on(games::event_id.eq(events::id)))
+        .inner_join(sites::table.on(games::site_id.eq(sites::id)))
+        .filter(games::id.eq_any(ids))
+        .into_boxed();
+    let games: Vec<(Game, Player, Player, Event, Site)> = if include_moves {
+        sql_query.load(db)?
+    } else {
+        sql_query
+            .select((
+                game_columns_without_moves!(),
+                white_players.fields(players::all_columns),
+                black_players.fields(players::all_columns),
+                events::all_columns,
+                sites::all_columns,
+            ))
+            .load(db)?
+    };
+
+    let mut games = normalize_games(games);
+    if include_moves {
+        load_comments(db, &mut games)?;
+    }
+    let positions: HashMap<i32, usize> = ids.iter().enumerate().map(|(i, id)| (*id, i)).collect();
+    games.sort_by_key(|game| positions[&game.id]);
+    Ok(games)
+}
+
+/// Sends all the games of a query as events on `channel_id`, in batches of
+/// [`STREAM_BATCH_SIZE`], always followed by [`GameStreamMessage::Finished`],
+/// which carries the error if it failed.
+///
+/// A connection is only taken from the pool while a batch is being read, so
+/// that a slow consumer doesn't starve the other commands.
+#[tauri::command]
+pub async fn stream_games(
+    file: PathBuf,
+    query: GameQuery,
+    channel_id: String,
+    app: tauri::AppHandle,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), Error> {
+    let path_str = file.to_str().unwrap().to_string();
+    let cancelled = Arc::new(AtomicBool::new(false));
+    state
+        .stream_cancellations
+        .insert(channel_id.clone(), cancelled.clone());
+
+    let task_channel_id = channel_id.clone();
+    let result = tokio::task::spawn_blocking(move || -> Result<(), Error> {
+        let mut matched = 0;
+        let mut total = 0;
+        let streamed = send_game_batches(
+            &app,
+            &path_str,
+            &query,
+            &task_channel_id,
+            &cancelled,
+            &mut matched,
+            &mut total,
+        );
+        app.emit_all(
+            &task_channel_id,
+            GameStreamMessage::Finished {
+                total,
+                matched,
+                cancelled: cancelled.load(Ordering::Relaxed),
+                error: streamed.as_ref().err().map(|e| e.to_string()),
+            },
+        )?;
+        streamed
+    })
+    .await?;
+
+    state.stream_cancellations.remove(&channel_id);
+    result
+}
+
+/// Sends the batches of `stream_games`, keeping count of the games `matched`
+/// and sent so far in `total`, to report them even if it fails.
+fn send_game_batches(
+    app: &tauri::AppHandle,
+    path: &str,
+    query: &GameQuery,
+    channel_id: &str,
+    cancelled: &AtomicBool,
+    matched: &mut usize,
+    total: &mut usize,
+) -> Result<(), Error> {
+    let state = app.state::<AppState>();
+    let ids = {
+        let db = &mut get_db_or_create(&state, path, ConnectionOptions::default())?;
+        sorted_game_ids(db, query)?
+    };
+    *matched = ids.len();
+
+    let include_moves = query.include_moves.unwrap_or(true);
+    for (index, batch) in ids.chunks(STREAM_BATCH_SIZE).enumerate() {
+        if cancelled.load(Ordering::Relaxed) {
+            break;
+        }
+        let games = {
+            let db = &mut get_db_or_create(&state, path, ConnectionOptions::default())?;
+            load_games_by_id(db, batch, include_moves)?
+        };
+        let count = games.len();
+        app.emit_all(channel_id, GameStreamMessage::Batch { index, games })?;
+        *total += count;
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn cancel_stream(
+    channel_id: String,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), Error> {
+    if let Some(cancelled) = state.stream_cancellations.get(&channel_id) {
+        cancelled.store(true, Ordering::Relaxed);
+    }
+    Ok(())
+}
+
+/// Picks up to `n` of the items uniformly, reading them only once.
+fn reservoir_sample<T>(
+    items: impl Iterator<Item = QueryResult<T>>,
+    n: usize,
+    rng: &mut impl Rng,
+) -> Result<Vec<T>, Error> {
+    let mut sample = Vec::with_capacity(n);
+    for (i, item) in items.enumerate() {
+        let item = item?;
+        if i < n {
+            sample.push(item);
+        } else {
+            let j = rng.gen_range(0..=i);
+            if j < n {
+                sample[j] = item;
+            }
+        }
+    }
+    Ok(sample)
+}
+
+/// IDs of up to `n` random games of a query, in random order.
+fn sample_game_ids(
+    db: &mut SqliteConnection,
+    query: &GameQuery,
+    n: usize,
+    rng: &mut impl Rng,
+) -> Result<Vec<i32>, Error> {
+    check_name_patterns(db, query)?;
+    // Ordered so that a seed gives the same sample whatever index is used
+    let sql_query = filter_games!(games::table.into_boxed(), query).order(games::id);
+    let mut ids = if let Some(move_filter) = &query.contains_move {
+        let san = move_filter.parse_san()?;
+        let rows = sql_query
+            .select((games::id, games::moves, games::fen))
+            .load_iter::<(i32, Vec<u8>, Option<String>), DefaultLoadingMode>(db)?;
+        let matched = games_with_move(rows, move_filter, &san, usize::MAX)?;
+        reservoir_sample(matched.into_iter().map(Ok), n, rng)?
+    } else {
+        let rows = sql_query
+            .select(games::id)
+            .load_iter::<i32, DefaultLoadingMode>(db)?;
+        reservoir_sample(rows, n, rng)?
+    };
+    ids.shuffle(rng);
+    Ok(ids)
+}
+
+/// Up to `n` games of a query picked uniformly at random, only reading the
+/// IDs of the others. The same `seed` gives the same games as long as the
+/// database isn't modified.
+#[tauri::command]
+pub async fn sample_games(
+    file: PathBuf,
+    query: GameQuery,
+    n: usize,
+    seed: Option<u64>,
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<NormalizedGame>, Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    let mut rng = match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    let ids = sample_game_ids(db, &query, n, &mut rng)?;
+    load_games_by_id(db, &ids, query.include_moves.unwrap_or(true))
+}
+
+fn normalize_games(games: Vec<(Game, Player, Player, Event, Site)>) -> Vec<NormalizedGame> {
+    games
+        .into_iter()
+        .map(|(game, white, black, event, site)| {
+            let fen: Fen = game
+                .fen
+                .map(|f| Fen::from_ascii(f.as_bytes()).unwrap())
+                .unwrap_or_default();
+
+            NormalizedGame {
+                id: game.id,
+                event: event.name.unwrap_or_default(),
+                event_id: event.id,
+                site: site.name.unwrap_or_default(),
+                site_id: site.id,
+                date: game.date.as_deref().map(pgn_date),
+                time: game.time,
+                round: game.round,
+                white: white.name.unwrap_or_default(),
+                white_id: game.white_id,
+                white_elo: game.white_elo,
+                black: black.name.unwrap_or_default(),
+                black_id: game.black_id,
+                black_elo: game.black_elo,
+                result: Some(GameResult::from_column(game.result)),
+                termination: game.termination,
+                time_control: game.time_control,
+                opening_name: game
+                    .eco
+                    .as_deref()
+                    .and_then(get_opening_name_from_eco)
+                    .map(String::from),
+                eco: game.eco,
+                white_material: game.white_material,
+                black_material: game.black_material,
+                ply_count: game.ply_count,
+                fen: fen.to_string(),
+                moves: decode_moves(game.moves, fen).unwrap_or_default().join(" "),
+                comments: Vec::new(),
+                clocks: game.clocks.as_deref().map(decode_clocks),
+                evals: game.evals.as_deref().map(decode_evals),
+            }
+        })
+        .collect()
+}
+
+/// Fills in the comments and NAGs of the given games.
+fn load_comments(db: &mut SqliteConnection, games: &mut [NormalizedGame]) -> Result<(), Error> {
+    let ids: Vec<i32> = games.iter().map(|g| g.id).collect();
+    let mut comments = comments_by_game(db, &ids)?;
+    for game in games.iter_mut() {
+        game.comments = comments.remove(&game.id).unwrap_or_default();
+    }
+    Ok(())
+}
+
+/// The comments of the given games with one query, in the order of their plies.
+fn comments_by_game(
+    db: &mut SqliteConnection,
+    ids: &[i32],
+) -> Result<HashMap<i32, Vec<Comment>>, Error> {
+    let mut by_game: HashMap<i32, Vec<Comment>> = HashMap::new();
+    for comment in comments::table
+        .filter(comments::game_id.eq_any(ids))
+        .order((comments::game_id.asc(), comments::ply.asc()))
+        .load::<Comment>(db)?
+    {
+        by_game.entry(comment.game_id).or_default().push(comment);
+    }
+    Ok(by_game)
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PlayerQuery {
+    pub options: QueryOptions<PlayerSort>,
+    pub name: Option<String>,
+    /// How `name` is matched, by default anywhere in the name.
+    pub match_mode: Option<NameMatch>,
+    pub range: Option<(i32, i32)>,
+    /// Only players with this title, like `GM`.
+    pub title: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum PlayerSort {
+    #[serde(rename = "id")]
+    Id,
+    #[serde(rename = "name")]
+    Name,
+    #[serde(rename = "elo")]
+    Elo,
+    #[serde(rename = "game_count")]
+    GameCount,
+}
+
+/// ECO codes listed in [`PlayerDetails::favorite_openings`].
+const FAVORITE_OPENINGS: i64 = 5;
+
+/// A player along with a summary of their games.
+#[derive(Debug, Clone, Serialize, Type)]
+pub struct PlayerDetails {
+    /// With the `game_count` counted again, for databases whose games
+    /// haven't been counted.
+    #[serde(flatten)]
+    pub player: Player,
+    pub first_game_date: Option<String>,
+    pub last_game_date: Option<String>,
+    /// Highest rating of the player found in their games.
+    pub peak_rating: Option<i32>,
+    /// Most played ECO codes, with their number of games.
+    pub favorite_openings: Vec<(String, i32)>,
+}
+
+#[derive(QueryableByName)]
+struct PlayerTotals {
+    #[diesel(sql_type = Integer)]
+    game_count: i32,
+    #[diesel(sql_type = Nullable<Text>)]
+    first_date: Option<String>,
+    #[diesel(sql_type = Nullable<Text>)]
+    last_date: Option<String>,
+    #[diesel(sql_type = Nullable<Integer>)]
+    peak_rating: Option<i32>,
+}
+
+#[derive(QueryableByName)]
+struct EcoCount {
+    #[diesel(sql_type = Text)]
+    eco: String,
+    #[diesel(sql_type = Integer)]
+    count: i32,
+}
+
+/// The games of the player on both sides, with their rating in each. The
+/// white and black indexes serve each half.
+const PLAYER_GAMES_SQL: &str = "SELECT ID, Date, ECO, WhiteElo AS Elo FROM Games WHERE WhiteID = ?
+    UNION ALL
+    SELECT ID, Date, ECO, BlackElo AS Elo FROM Games WHERE BlackID = ?";
+
+fn player_details(db: &mut SqliteConnection, id: i32) -> Result<PlayerDetails, Error> {
+    let player = players::table
+        .filter(players::id.eq(id))
+        .first::<Player>(db)
+        .optional()?
+        .ok_or(Error::PlayerNotFound(id))?;
+
+    let totals: PlayerTotals = sql_query(format!(
+        "SELECT COUNT(*) AS game_count, MIN(Date) AS first_date, MAX(Date) AS last_date,
+            MAX(Elo) AS peak_rating
+        FROM ({PLAYER_GAMES_SQL});"
+    ))
+    .bind::<Integer, _>(id)
+    .bind::<Integer, _>(id)
+    .get_result(db)?;
+
+    let favorite_openings = sql_query(format!(
+        "SELECT ECO AS eco, COUNT(*) AS count
+        FROM ({PLAYER_GAMES_SQL})
+        WHERE ECO IS NOT NULL
+        GROUP BY ECO
+        ORDER BY count DESC, ECO
+        LIMIT {FAVORITE_OPENINGS};"
+    ))
+    .bind::<Integer, _>(id)
+    .bind::<Integer, _>(id)
+    .load::<EcoCount>(db)?
+    .into_iter()
+    .map(|e| (e.eco, e.count))
+    .collect();
+
+    Ok(PlayerDetails {
+        player: Player {
+            game_count: Some(totals.game_count),
+            ..player
+        },
+        first_game_date: totals.first_date.as_deref().map(pgn_date),
+        last_game_date: totals.last_date.as_deref().map(pgn_date),
+        peak_rating: totals.peak_rating,
+        favorite_openings,
+    })
+}
+
+/// A player with a summary of their games, for the player page.
+#[tauri::command]
+#[specta::specta]
+pub async fn get_player(
+    file: PathBuf,
+    id: i32,
+    state: tauri::State<'_, AppState>,
+) -> Result<PlayerDetails, Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    player_details(db, id)
+}
+
+/// The ratings of a player through time, for the chart of the player page.
+#[derive(Debug, Clone, Default, Serialize, Type, PartialEq)]
+pub struct RatingHistory {
+    /// The date and the rating of the player in each of their dated and rated
+    /// games, oldest first.
+    pub points: Vec<(String, i32)>,
+    /// The highest rating, with the date it was first reached.
+    pub peak: Option<(String, i32)>,
+}
+
+#[derive(QueryableByName)]
+struct RatingPoint {
+    #[diesel(sql_type = Text)]
+    date: String,
+    #[diesel(sql_type = Integer)]
+    elo: i32,
+}
+
+/// Keeps `n` evenly spaced items, including the first and the last ones.
+fn downsample<T>(items: Vec<T>, n: usize) -> Vec<T> {
+    let len = items.len();
+    if len <= n {
+        return items;
+    }
+    if n <= 1 {
+        return items.into_iter().last().into_iter().take(n).collect();
+    }
+    let mut kept = (0..n).map(|i| i * (len - 1) / (n - 1)).peekable();
+    items
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, item)| {
+            if kept.peek() == Some(&i) {
+                kept.next();
+                Some(item)
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// The rating history of a player, of at most `max_points` points. The peak
+/// is that of the whole history.
+fn rating_history(
+    db: &mut SqliteConnection,
+    id: i32,
+    max_points: Option<usize>,
+) -> Result<RatingHistory, Error> {
+    let points: Vec<(String, i32)> = sql_query(format!(
+        "SELECT Date AS date, Elo AS elo
+        FROM ({PLAYER_GAMES_SQL})
+        WHERE Date IS NOT NULL AND Elo IS NOT NULL
+        ORDER BY Date, ID;"
+    ))
+    .bind::<Integer, _>(id)
+    .bind::<Integer, _>(id)
+    .load::<RatingPoint>(db)?
+    .into_iter()
+    .map(|p| (pgn_date(&p.date), p.elo))
+    .collect();
+
+    let peak = points
+        .iter()
+        .fold(None, |peak: Option<&(String, i32)>, point| match peak {
+            Some(peak) if peak.1 >= point.1 => Some(peak),
+            _ => Some(point),
+        });
+    Ok(RatingHistory {
+        peak: peak.cloned(),
+        points: match max_points {
+            Some(n) => downsample(points, n),
+            None => points,
+        },
+    })
+}
+
+#[tauri::command]
+#[specta::specta]
+pub async fn get_player_rating_history(
+    file: PathBuf,
+    player_id: i32,
+    max_points: Option<usize>,
+    state: tauri::State<'_, AppState>,
+) -> Result<RatingHistory, Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    rating_history(db, player_id, max_points)
+}
+
+/// The games between two players, from the side of the first one.
+#[derive(Debug, Clone, Default, Serialize, Type, PartialEq)]
+pub struct HeadToHead {
+    pub as_white: Results,
+    pub as_black: Results,
+    /// Oldest first, those of unknown date before the others.
+    pub game_ids: Vec<i32>,
+    /// The most played ECO codes between them, with their number of games.
+    pub common_openings: Vec<(String, i32)>,
+}
+
+fn head_to_head(
+    db: &mut SqliteConnection,
+    player1: i32,
+    player2: i32,
+) -> Result<HeadToHead, Error> {
+    let games: Vec<(i32, i32, Option<i32>, Option<String>)> = games::table
+        .filter(games::white_id.eq(player1).and(games::black_id.eq(player2)))
+        .or_filter(games::white_id.eq(player2).and(games::black_id.eq(player1)))
+        .order((games::date, games::id))
+        .select((games::id, games::white_id, games::result, games::eco))
+        .load(db)?;
+
+    let mut head_to_head = HeadToHead::default();
+    let mut openings: HashMap<String, i32> = HashMap::new();
+    for (id, white_id, result, eco) in games {
+        let is_white = white_id == player1;
+        let results = if is_white {
+            &mut head_to_head.as_white
+        } else {
+            &mut head_to_head.as_black
+        };
+        results.record(GameResult::from_column(result), is_white);
+        head_to_head.game_ids.push(id);
+        if let Some(eco) = eco {
+            *openings.entry(eco).or_default() += 1;
+        }
+    }
+
+    let mut openings: Vec<(String, i32)> = openings.into_iter().collect();
+    openings.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    openings.truncate(FAVORITE_OPENINGS as usize);
+    head_to_head.common_openings = openings;
+    Ok(head_to_head)
+}
+
+/// The record of `player1` against `player2`, to prepare against an
+/// opponent.
+#[tauri::command]
+#[specta::specta]
+pub async fn get_head_to_head(
+    file: PathBuf,
+    player1_id: i32,
+    player2_id: i32,
+    state: tauri::State<'_, AppState>,
+) -> Result<HeadToHead, Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    head_to_head(db, player1_id, player2_id)
+}
+
+/// Renames a player or changes its rating. A name already used by another
+/// player is refused with its ID, so that they can be merged instead.
+fn update_player_row(
+    db: &mut SqliteConnection,
+    id: i32,
+    name: Option<String>,
+    elo: Option<i32>,
+) -> Result<Player, Error> {
+    db.transaction::<_, Error, _>(|db| {
+        let mut player: Player = players::table
+            .find(id)
+            .first(db)
+            .optional()?
+            .ok_or(Error::PlayerNotFound(id))?;
+
+        if let Some(name) = name {
+            let name = name.trim().to_string();
+            let existing: Option<i32> = players::table
+                .filter(players::name.eq(&name))
+                .filter(players::id.ne(id))
+                .select(players::id)
+                .first(db)
+                .optional()?;
+            if let Some(existing) = existing {
+                return Err(Error::PlayerNameTaken(existing));
+            }
+            player.name = Some(name);
+        }
+        if elo.is_some() {
+            player.elo = elo;
+        }
+
+        diesel::update(players::table.find(id))
+            .set((players::name.eq(&player.name), players::elo.eq(player.elo)))
+            .execute(db)?;
+        Ok(player)
+    })
+}
+
+#[tauri::command]
+#[specta::specta]
+pub async fn update_player(
+    file: PathBuf,
+    id: i32,
+    name: Option<String>,
+    elo: Option<i32>,
+    state: tauri::State<'_, AppState>,
+) -> Result<Player, Error> {
+    let _modification = state.count_cache.modify(file.to_str().unwrap());
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    update_player_row(db, id, name, elo)
+}
+
+/// Players listed in each of the [`Leaderboards`].
+const LEADERBOARD_SIZE: i64 = 10;
+
+/// A player with their score in their games of known result.
+#[derive(Debug, Clone, Serialize, Type)]
+pub struct PlayerScore {
+    #[serde(flatten)]
+    pub player: Player,
+    pub scored_games: i32,
+    /// Points per game, in percent.
+    pub score: f64,
+}
+
+/// The top players of a database, for its overview.
+#[derive(Debug, Clone, Default, Serialize, Type)]
+pub struct Leaderboards {
+    pub most_games: Vec<Player>,
+    pub highest_rated: Vec<Player>,
+    /// Among the players with at least `min_games` games of known result.
+    pub best_score: Vec<PlayerScore>,
+}
+
+#[derive(QueryableByName)]
+struct ScoreRow {
+    #[diesel(sql_type = Integer)]
+    id: i32,
+    #[diesel(sql_type = Integer)]
+    games: i32,
+    #[diesel(sql_type = Double)]
+    score: f64,
+}
+
+fn leaderboards(db: &mut SqliteConnection, min_games: i32) -> Result<Leaderboards, Error> {
+    // Ties go to the oldest player
+    let most_games = players::table
+        .filter(players::id.ne(0))
+        .filter(players::game_count.gt(0))
+        .order((players::game_count.desc(), players::id))
+        .limit(LEADERBOARD_SIZE)
+        .load(db)?;
+    let highest_rated = players::table
+        .filter(players::id.ne(0))
+        .filter(players::elo.is_not_null())
+        .order((players::elo.desc(), players::id))
+        .limit(LEADERBOARD_SIZE)
+        .load(db)?;
+
+    let scores: Vec<ScoreRow> = sql_query(format!(
+        "SELECT ID AS id, COUNT(*) AS games, 100.0 * SUM(Points) / COUNT(*) AS score
+        FROM (
+            SELECT WhiteID AS ID, CASE Result WHEN 1 THEN 1.0 WHEN 3 THEN 0.5 ELSE 0.0 END AS Points
+            FROM Games WHERE Result IN (1, 2, 3)
+            UNION ALL
+            SELECT BlackID, CASE Result WHEN 2 THEN 1.0 WHEN 3 THEN 0.5 ELSE 0.0 END
+            FROM Games WHERE Result IN (1, 2, 3)
+        )
+        WHERE ID != 0
+        GROUP BY ID
+        HAVING COUNT(*) >= ?
+        ORDER BY score DESC, games DESC, ID
+        LIMIT {LEADERBOARD_SIZE};"
+    ))
+    .bind::<Integer, _>(min_games.max(1))
+    .load(db)?;
+    let ids: Vec<i32> = scores.iter().map(|s| s.id).collect();
+    let mut players: HashMap<i32, Player> = players::table
+        .filter(players::id.eq_any(&ids))
+        .load::<Player>(db)?
+        .into_iter()
+        .map(|p| (p.id, p))
+        .collect();
+    let best_score = scores
+        .into_iter()
+        .filter_map(|s| {
+            Some(PlayerScore {
+                player: players.remove(&s.id)?,
+                scored_games: s.games,
+                score: s.score,
+            })
+        })
+        .collect();
+
+    Ok(Leaderboards {
+        most_games,
+        highest_rated,
+        best_score,
+    })
+}
+
+#[tauri::command]
+#[specta::specta]
+pub async fn get_player_leaderboards(
+    file: PathBuf,
+    min_games: i32,
+    state: tauri::State<'_, AppState>,
+) -> Result<Leaderboards, Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    leaderboards(db, min_games)
+}
+
+/// The given name of `Lastname, Firstname` names, as indexed by
+/// `players_given_name_idx`.
+const GIVEN_NAME_SQL: &str = "ltrim(substr(Name, instr(Name, ',') + 1)) COLLATE NOCASE";
+
+/// Players whose name, or given name after a comma, starts with `prefix`,
+/// ignoring case, those with the most games first. Both are range scans of
+/// an index, unlike the `LIKE '%name%'` of [`get_players`].
+fn players_by_prefix(
+    db: &mut SqliteConnection,
+    prefix: &str,
+    limit: u32,
+) -> Result<Vec<Player>, Error> {
+    let prefix = prefix.trim();
+    if prefix.is_empty() || limit == 0 {
+        return Ok(Vec::new());
+    }
+    // Greater than any text starting with the prefix
+    let end = format!("{prefix}\u{10FFFF}");
+
+    let mut players: Vec<Player> = Vec::new();
+    for column in ["Name COLLATE NOCASE", GIVEN_NAME_SQL] {
+        let mut query = players::table
+            .filter(
+                diesel::dsl::sql::<Bool>(&format!("{column} >= "))
+                    .bind::<Text, _>(prefix)
+                    .sql(&format!(" AND {column} < "))
+                    .bind::<Text, _>(&end),
+            )
+            .into_boxed();
+        if column == GIVEN_NAME_SQL {
+            query = query.filter(diesel::dsl::sql::<Bool>("instr(Name, ',') > 0"));
+        }
+        let matches: Vec<Player> = query
+            .order((
+                players::game_count.is_null(),
+                players::game_count.desc(),
+                players::id,
+            ))
+            .limit(limit.into())
+            .load(db)?;
+        players.extend(
+            matches
+                .into_iter()
+                .filter(|p| p.id != 0 && !players.iter().any(|other| other.id == p.id)),
+        );
+    }
+
+    players.sort_by_key(|p| (p.game_count.is_none(), std::cmp::Reverse(p.game_count)));
+    players.truncate(limit as usize);
+    Ok(players)
+}
+
+/// Suggestions for the player search box while typing.
+#[tauri::command]
+#[specta::specta]
+pub async fn autocomplete_players(
+    file: PathBuf,
+    prefix: String,
+    limit: u32,
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<Player>, Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    players_by_prefix(db, &prefix, limit)
+}
+
+#[tauri::command]
+pub async fn get_players(
+    file: PathBuf,
+    query: PlayerQuery,
+    state: tauri::State<'_, AppState>,
+) -> Result<QueryResponse<Vec<Player>>, Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    let mut count = None;
+
+    let mut sql_query = players::table.into_boxed();
+    let mut count_query = players::table.into_boxed();
+    sql_query = sql_query.filter(players::name.is_not("Unknown"));
+    count_query = count_query.filter(players::name.is_not("Unknown"));
+
+    if let Some(name) = query.name {
+        match query.match_mode {
+            Some(mode) => {
+                let pattern = like_pattern(&name, mode);
+                sql_query = sql_query.filter(players::name.like(pattern.clone()).escape('\\'));
+                count_query = count_query.filter(players::name.like(pattern).escape('\\'));
+            }
+            None => {
+                sql_query = sql_query.filter(players::name.like(format!("%{}%", name)));
+                count_query = count_query.filter(players::name.like(format!("%{}%", name)));
+            }
+        }
+    }
+
+    if let Some(range) = query.range {
+        sql_query = sql_query.filter(players::elo.between(range.0, range.1));
+        count_query = count_query.filter(players::elo.between(range.0, range.1));
+    }
+
+    if let Some(title) = query.title {
+        sql_query = sql_query.filter(players::title.eq(title.clone()));
+        count_query = count_query.filter(players::title.eq(title));
+    }
+
+    if !query.options.skip_count {
+        count = Some(count_query.count().get_result(db)?);
+    }
+
+    if let Some(limit) = query.options.page_size {
+        sql_query = sql_query.limit(limit);
+    }
+
+    if let Some(page) = query.options.page {
+        sql_query = sql_query.offset((page - 1) * query.options.page_size.unwrap_or(10));
+    }
+
+    sql_query = match query.options.sort {
+        PlayerSort::Id => match query.options.direction {
+            SortDirection::Asc => sql_query.order(players::id.asc()),
+            SortDirection::Desc => sql_query.order(players::id.desc()),
+        },
+        PlayerSort::Name => match query.options.direction {
+            SortDirection::Asc => sql_query.order(players::name.asc()),
+            SortDirection::Desc => sql_query.order(players::name.desc()),
+        },
+        // Unrated players go last in both directions
+        PlayerSort::Elo => match query.options.direction {
+            SortDirection::Asc => {
+                sql_query.order((players::elo.is_null().asc(), players::elo.asc()))
+            }
+            SortDirection::Desc => {
+                sql_query.order((players::elo.is_null().asc(), players::elo.desc()))
+            }
+        },
+        // As do the players of databases whose games haven't been counted
+        PlayerSort::GameCount => match query.options.direction {
+            SortDirection::Asc => sql_query.order((
+                players::game_count.is_null().asc(),
+                players::game_count.asc(),
+            )),
+            SortDirection::Desc => sql_query.order((
+                players::game_count.is_null().asc(),
+                players::game_count.desc(),
+            )),
+        },
+    };
+
+    let players = sql_query.load::<Player>(db)?;
+
+    Ok(QueryResponse {
+        data: players,
+        count,
+        count_capped: false,
+        next_cursor: None,
+    })
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum TournamentSort {
+    #[serde(rename = "id")]
+    Id,
+    #[serde(rename = "name")]
+    Name,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TournamentQuery {
+    pub options: QueryOptions<TournamentSort>,
+    pub name: Option<String>,
+}
+
+#[tauri::command]
+pub async fn get_tournaments(
+    file: PathBuf,
+    query: TournamentQuery,
+    state: tauri::State<'_, AppState>,
+) -> Result<QueryResponse<Vec<Event>>, Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    let mut count = None;
+
+    let mut sql_query = events::table.into_boxed();
+    let mut count_query = events::table.into_boxed();
+    sql_query = sql_query.filter(events::name.is_not("Unknown").and(events::name.is_not("")));
+    count_query = count_query.filter(events::name.is_not("Unknown").and(events::name.is_not("")));
+
+    if let Some(name) = query.name {
+        sql_query = sql_query.filter(events::name.like(format!("%{}%", name)));
+        count_query = count_query.filter(events::name.like(format!("%{}%", name)));
+    }
+
+    if !query.options.skip_count {
+        count = Some(count_query.count().get_result(db)?);
+    }
+
+    if let Some(limit) = query.options.page_size {
+        sql_query = sql_query.limit(limit);
+    }
+
+    if let Some(page) = query.options.page {
+        sql_query = sql_query.offset((page - 1) * query.options.page_size.unwrap_or(10));
+    }
+
+    sql_query = match query.options.sort {
+        TournamentSort::Id => match query.options.direction {
+            SortDirection::Asc => sql_query.order(events::id.asc()),
+            SortDirection::Desc => sql_query.order(events::id.desc()),
+        },
+        TournamentSort::Name => match query.options.direction {
+            SortDirection::Asc => sql_query.order(events::name.asc()),
+            SortDirection::Desc => sql_query.order(events::name.desc()),
+        },
+    };
+
+    let events = sql_query.load::<Event>(db)?;
+
+    Ok(QueryResponse {
+        data: events,
+        count,
+        count_capped: false,
+        next_cursor: None,
+    })
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum EventSort {
+    #[serde(rename = "name")]
+    Name,
+    #[serde(rename = "game_count")]
+    GameCount,
+}
+
+/// A query over the events or the sites of a database.
+#[derive(Debug, Clone, Deserialize)]
+pub struct EventQuery {
+    pub options: QueryOptions<EventSort>,
+    /// Matched anywhere in the name.
+    pub name: Option<String>,
+}
+
+/// An event or a site, with the number of games played there and the dates
+/// of the first and the last one.
+#[derive(Debug, Clone, Serialize, QueryableByName, PartialEq)]
+pub struct EventSummary {
+    #[diesel(sql_type = Integer)]
+    pub id: i32,
+    #[diesel(sql_type = Nullable<Text>)]
+    pub name: Option<String>,
+    #[diesel(sql_type = Integer)]
+    pub game_count: i32,
+    #[diesel(sql_type = Nullable<Text>)]
+    pub first_date: Option<String>,
+    #[diesel(sql_type = Nullable<Text>)]
+    pub last_date: Option<String>,
+}
+
+#[derive(QueryableByName)]
+struct RowCount {
+    #[diesel(sql_type = BigInt)]
+    count: i64,
+}
+
+/// The events or the sites, which share their layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EventTable {
+    Events,
+    Sites,
+}
+
+impl EventTable {
+    fn table(self) -> &'static str {
+        match self {
+            EventTable::Events => "Events",
+            EventTable::Sites => "Sites",
+        }
+    }
+
+    /// The column of the games pointing to the table.
+    fn column(self) -> &'static str {
+        match self {
+            EventTable::Events => "EventID",
+            EventTable::Sites => "SiteID",
+        }
+    }
+
+    /// The row counted in the `Info` table.
+    fn count_info(self) -> &'static str {
+        match self {
+            EventTable::Events => "EventCount",
+            EventTable::Sites => "SiteCount",
+        }
+    }
+
+    fn not_found(self, id: i32) -> Error {
+        match self {
+            EventTable::Events => Error::EventNotFound(id),
+            EventTable::Sites => Error::SiteNotFound(id),
+        }
+    }
+
+    fn name_taken(self, id: i32) -> Error {
+        match self {
+            EventTable::Events => Error::EventNameTaken(id),
+            EventTable::Sites => Error::SiteNameTaken(id),
+        }
+    }
+}
+
+/// The events or the sites, with a summary of the games played there. The
+/// page is picked first and only its games are summarized, though sorting by
+/// game count still has to count the games of every row.
+fn event_summaries(
+    db: &mut SqliteConnection,
+    kind: EventTable,
+    query: &EventQuery,
+) -> Result<QueryResponse<Vec<EventSummary>>, Error> {
+    let (table, column) = (kind.table(), kind.column());
+    let pattern = like_pattern(query.name.as_deref().unwrap_or(""), NameMatch::Contains);
+    let filter = "Name NOT IN ('Unknown', '') AND Name LIKE ? ESCAPE '\\'";
+
+    let mut count = None;
+    if !query.options.skip_count {
+        let rows: RowCount = sql_query(format!(
+            "SELECT COUNT(*) AS count FROM {table} WHERE {filter};"
+        ))
+        .bind::<Text, _>(&pattern)
+        .get_result(db)?;
+        count = Some(rows.count);
+    }
+
+    let direction = match query.options.direction {
+        SortDirection::Asc => "ASC",
+        SortDirection::Desc => "DESC",
+    };
+    let (page, order) = match query.options.sort {
+        EventSort::Name => (
+            format!(
+                "SELECT ID, Name FROM {table} WHERE {filter}
+                ORDER BY Name {direction}, ID
+                LIMIT ? OFFSET ?"
+            ),
+            format!("t.Name {direction}, t.ID"),
+        ),
+        EventSort::GameCount => (
+            format!(
+                "SELECT ID, Name FROM {table}
+                LEFT JOIN (
+                    SELECT {column} AS group_id, COUNT(*) AS game_count
+                    FROM Games
+                    GROUP BY {column}
+                ) ON group_id = ID
+                WHERE {filter}
+                ORDER BY COALESCE(game_count, 0) {direction}, ID
+                LIMIT ? OFFSET ?"
+            ),
+            format!("game_count {direction}, t.ID"),
+        ),
+    };
+    let limit = query.options.page_size.unwrap_or(-1);
+    let offset = query
+        .options
+        .page
+        .map_or(0, |page| (page - 1) * query.options.page_size.unwrap_or(10));
+
+    let mut summaries: Vec<EventSummary> = sql_query(format!(
+        "WITH page AS ({page})
+        SELECT t.ID AS id, t.Name AS name, COALESCE(g.game_count, 0) AS game_count,
+            g.first_date, g.last_date
+        FROM page t
+        LEFT JOIN (
+            SELECT {column} AS group_id, COUNT(*) AS game_count, MIN(Date) AS first_date,
+                MAX(Date) AS last_date
+            FROM Games
+            WHERE {column} IN (SELECT ID FROM page)
+            GROUP BY {column}
+        ) g ON g.group_id = t.ID
+        ORDER BY {order};"
+    ))
+    .bind::<Text, _>(&pattern)
+    .bind::<BigInt, _>(limit)
+    .bind::<BigInt, _>(offset)
+    .load(db)?;
+    for summary in &mut summaries {
+        summary.first_date = summary.first_date.as_deref().map(pgn_date);
+        summary.last_date = summary.last_date.as_deref().map(pgn_date);
+    }
+
+    Ok(QueryResponse {
+        data: summaries,
+        count,
+        count_capped: false,
+        next_cursor: None,
+    })
+}
+
+/// The events of a database with their number of games and dates.
+#[tauri::command]
+pub async fn get_events(
+    file: PathBuf,
+    query: EventQuery,
+    state: tauri::State<'_, AppState>,
+) -> Result<QueryResponse<Vec<EventSummary>>, Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    event_summaries(db, EventTable::Events, &query)
+}
+
+/// The sites of a database with their number of games and dates.
+#[tauri::command]
+pub async fn get_sites(
+    file: PathBuf,
+    query: EventQuery,
+    state: tauri::State<'_, AppState>,
+) -> Result<QueryResponse<Vec<EventSummary>>, Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    event_summaries(db, EventTable::Sites, &query)
+}
+
+#[derive(QueryableByName)]
+struct RowId {
+    #[diesel(sql_type = Integer)]
+    id: i32,
+}
+
+fn event_exists(db: &mut SqliteConnection, kind: EventTable, id: i32) -> Result<(), Error> {
+    let rows: RowCount = sql_query(format!(
+        "SELECT COUNT(*) AS count FROM {} WHERE ID = ?;",
+        kind.table()
+    ))
+    .bind::<Integer, _>(id)
+    .get_result(db)?;
+    if rows.count == 0 {
+        return Err(kind.not_found(id));
+    }
+    Ok(())
+}
+
+/// Renames an event or a site, unless another one already has the name.
+fn rename_event_row(
+    db: &mut SqliteConnection,
+    kind: EventTable,
+    id: i32,
+    name: &str,
+) -> Result<(), Error> {
+    let table = kind.table();
+    let name = name.trim();
+    db.transaction::<_, Error, _>(|db| {
+        event_exists(db, kind, id)?;
+        let existing: Option<RowId> = sql_query(format!(
+            "SELECT ID AS id FROM {table} WHERE Name = ? AND ID != ?;"
         ))
+        .bind::<Text, _>(name)
+        .bind::<Integer, _>(id)
+        .get_result(db)
+        .optional()?;
+        if let Some(existing) = existing {
+            return Err(kind.name_taken(existing.id));
+        }
+        sql_query(format!("UPDATE {table} SET Name = ? WHERE ID = ?;"))
+            .bind::<Text, _>(name)
+            .bind::<Integer, _>(id)
+            .execute(db)?;
+        Ok(())
+    })
+}
+
+/// Moves the games of the `sources` events or sites to `target` and deletes
+/// the sources, returning the number of games moved. Merging into the
+/// unknown one, with ID 0, untags the games, and it's never deleted.
+fn merge_event_rows(
+    db: &mut SqliteConnection,
+    kind: EventTable,
+    target: i32,
+    sources: &[i32],
+) -> Result<usize, Error> {
+    if sources.contains(&target) {
+        return Err(Error::MergeIntoItself);
+    }
+    let (table, column) = (kind.table(), kind.column());
+    db.transaction::<_, Error, _>(|db| {
+        event_exists(db, kind, target)?;
+        let mut moved = 0;
+        for &source in sources {
+            event_exists(db, kind, source)?;
+            moved += sql_query(format!("UPDATE Games SET {column} = ? WHERE {column} = ?;"))
+                .bind::<Integer, _>(target)
+                .bind::<Integer, _>(source)
+                .execute(db)?;
+            sql_query(format!("DELETE FROM {table} WHERE ID = ? AND ID != 0;"))
+                .bind::<Integer, _>(source)
+                .execute(db)?;
+        }
+
+        let rows: RowCount =
+            sql_query(format!("SELECT COUNT(*) AS count FROM {table};")).get_result(db)?;
+        set_info(db, kind.count_info(), &rows.count.to_string())?;
+        Ok(moved)
+    })
+}
+
+#[tauri::command]
+#[specta::specta]
+pub async fn update_event(
+    file: PathBuf,
+    id: i32,
+    name: String,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    rename_event_row(db, EventTable::Events, id, &name)
+}
+
+/// Merges the `source_ids` events into `target_id`, for events imported
+/// under several names. Returns the number of games moved.
+#[tauri::command]
+#[specta::specta]
+pub async fn merge_events(
+    file: PathBuf,
+    target_id: i32,
+    source_ids: Vec<i32>,
+    state: tauri::State<'_, AppState>,
+) -> Result<usize, Error> {
+    let _modification = state.count_cache.modify(file.to_str().unwrap());
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    merge_event_rows(db, EventTable::Events, target_id, &source_ids)
+}
+
+#[tauri::command]
+#[specta::specta]
+pub async fn update_site(
+    file: PathBuf,
+    id: i32,
+    name: String,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    rename_event_row(db, EventTable::Sites, id, &name)
+}
+
+/// Merges the `source_ids` sites into `target_id`, like [`merge_events`].
+#[tauri::command]
+#[specta::specta]
+pub async fn merge_sites(
+    file: PathBuf,
+    target_id: i32,
+    source_ids: Vec<i32>,
+    state: tauri::State<'_, AppState>,
+) -> Result<usize, Error> {
+    let _modification = state.count_cache.modify(file.to_str().unwrap());
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    merge_event_rows(db, EventTable::Sites, target_id, &source_ids)
+}
+
+/// Days without games after which the games of an event are taken as
+/// another edition of it.
+const DEFAULT_EDITION_GAP_DAYS: i64 = 180;
+
+/// A run of games of an event without long gaps between them, like a
+/// yearly edition of a tournament.
+#[derive(Debug, Clone, Serialize, Type, PartialEq)]
+pub struct EventEdition {
+    /// The name of the event with the year of the edition, or with the
+    /// month too if the event had several editions that year.
+    pub name: String,
+    pub first_date: String,
+    pub last_date: String,
+    pub game_count: i32,
+    /// The event of the edition, once split.
+    pub event_id: Option<i32>,
+}
+
+/// The date of a stored date, with its unknown month or day as the first
+/// one, for date arithmetic.
+fn calendar_date(date: &str) -> Option<NaiveDate> {
+    let mut parts = date.split('-').map(|p| p.parse::<u32>().ok());
+    let year = parts.next()??;
+    let month = parts.next().flatten().unwrap_or(0).max(1);
+    let day = parts.next().flatten().unwrap_or(0).max(1);
+    NaiveDate::from_ymd_opt(year as i32, month, day)
+}
+
+/// Splits the dated games of an event into editions wherever no game was
+/// played for more than `gap_days`. With `apply`, the first edition keeps
+/// the event, along with the undated games, and the other ones are moved to
+/// events of their own. Nothing is split if another event already has the
+/// name of an edition.
+fn event_editions(
+    db: &mut SqliteConnection,
+    event_id: i32,
+    gap_days: i64,
+    apply: bool,
+) -> Result<Vec<EventEdition>, Error> {
+    db.transaction::<_, Error, _>(|db| {
+        let event: Event = events::table
+            .find(event_id)
+            .first(db)
+            .optional()?
+            .ok_or(Error::EventNotFound(event_id))?;
+        let games: Vec<(i32, Option<String>)> = games::table
+            .filter(games::event_id.eq(event_id))
+            .filter(games::date.is_not_null())
+            .order((games::date, games::id))
+            .select((games::id, games::date))
+            .load(db)?;
+
+        let mut editions: Vec<Vec<(i32, String)>> = vec![];
+        let mut last: Option<NaiveDate> = None;
+        for (id, date) in games {
+            let Some(day) = date.as_deref().and_then(calendar_date) else {
+                continue;
+            };
+            let gap = match last {
+                Some(last) => (day - last).num_days() > gap_days,
+                None => true,
+            };
+            if gap {
+                editions.push(vec![]);
+            }
+            last = Some(day);
+            editions.last_mut().unwrap().push((id, date.unwrap()));
+        }
+
+        let base = event.name.unwrap_or_default();
+        let years: Vec<&str> = editions
+            .iter()
+            .map(|games| games[0].1.get(..4).unwrap_or(&games[0].1))
+            .collect();
+        let mut result: Vec<EventEdition> = editions
+            .iter()
+            .zip(&years)
+            .map(|(games, year)| {
+                let first_date = &games[0].1;
+                let month = first_date
+                    .get(5..7)
+                    .filter(|_| years.iter().filter(|y| *y == year).count() > 1);
+                let name = match month {
+                    Some(month) => format!("{base} {year}.{month}"),
+                    None => format!("{base} {year}"),
+                };
+                EventEdition {
+                    name,
+                    first_date: pgn_date(first_date),
+                    last_date: pgn_date(&games[games.len() - 1].1),
+                    game_count: games.len() as i32,
+                    event_id: None,
+                }
+            })
+            .collect();
+
+        if !apply || result.len() < 2 {
+            return Ok(result);
+        }
+        for (index, (edition, games)) in result.iter_mut().zip(&editions).enumerate() {
+            let existing: Option<i32> = events::table
+                .filter(events::name.eq(&edition.name))
+                .select(events::id)
+                .first(db)
+                .optional()?;
+            let id = match existing {
+                Some(id) if index == 0 && id == event_id => id,
+                Some(id) => return Err(Error::EventNameTaken(id)),
+                None if index == 0 => {
+                    diesel::update(events::table.find(event_id))
+                        .set(events::name.eq(&edition.name))
+                        .execute(db)?;
+                    event_id
+                }
+                None => insert_into(events::table)
+                    .values(events::name.eq(&edition.name))
+                    .returning(events::id)
+                    .get_result(db)?,
+            };
+            if id != event_id {
+                let ids: Vec<i32> = games.iter().map(|(id, _)| *id).collect();
+                for chunk in ids.chunks(DEFAULT_IMPORT_BATCH_SIZE) {
+                    diesel::update(games::table.filter(games::id.eq_any(chunk)))
+                        .set(games::event_id.eq(id))
+                        .execute(db)?;
+                }
+            }
+            edition.event_id = Some(id);
+        }
+
+        let event_count: i64 = events::table.count().get_result(db)?;
+        set_info(db, "EventCount", &event_count.to_string())?;
+        Ok(result)
+    })
+}
+
+/// The editions of an event whose name was reused across the years, split
+/// into events of their own with `apply`.
+#[tauri::command]
+#[specta::specta]
+pub async fn detect_event_editions(
+    file: PathBuf,
+    event_id: i32,
+    gap_days: Option<i64>,
+    apply: bool,
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<EventEdition>, Error> {
+    let _modification = apply.then(|| state.count_cache.modify(file.to_str().unwrap()));
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    event_editions(
+        db,
+        event_id,
+        gap_days.unwrap_or(DEFAULT_EDITION_GAP_DAYS),
+        apply,
+    )
+}
+
+/// A player of an event with their score in its finished games.
+#[derive(Debug, Clone, Serialize, Type, PartialEq)]
+pub struct Standing {
+    pub player_id: i32,
+    pub name: Option<String>,
+    pub points: f64,
+    /// The finished games.
+    pub games: i32,
+    pub unfinished: i32,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Type, PartialEq)]
+pub struct EventStandings {
+    /// The best score first, then by name.
+    pub standings: Vec<Standing>,
+    /// For a round-robin, where every player met every other one, the points
+    /// of each player of the standings against each of them, game by game,
+    /// with `None` for unfinished games. Left out for other events, like
+    /// Swiss ones.
+    pub crosstable: Option<Vec<Vec<Vec<Option<f64>>>>>,
+}
+
+fn event_standings(db: &mut SqliteConnection, event_id: i32) -> Result<EventStandings, Error> {
+    events::table
+        .find(event_id)
+        .first::<Event>(db)
+        .optional()?
+        .ok_or(Error::EventNotFound(event_id))?;
+
+    let games: Vec<(i32, i32, Option<i32>)> = games::table
+        .filter(games::event_id.eq(event_id))
+        .order((games::date, games::id))
+        .select((games::white_id, games::black_id, games::result))
+        .load(db)?;
+
+    let mut standings: HashMap<i32, Standing> = HashMap::new();
+    // The games of each pairing, by the IDs of its players in order
+    let mut pairings: HashMap<(i32, i32), Vec<(i32, GameResult)>> = HashMap::new();
+    for (white_id, black_id, result) in games {
+        let result = GameResult::from_column(result);
+        for (id, as_white) in [(white_id, true), (black_id, false)] {
+            let standing = standings.entry(id).or_insert_with(|| Standing {
+                player_id: id,
+                name: None,
+                points: 0.0,
+                games: 0,
+                unfinished: 0,
+            });
+            match result.points(as_white) {
+                Some(points) => {
+                    standing.points += points;
+                    standing.games += 1;
+                }
+                None => standing.unfinished += 1,
+            }
+        }
+        if white_id != black_id {
+            let pairing = (white_id.min(black_id), white_id.max(black_id));
+            pairings
+                .entry(pairing)
+                .or_default()
+                .push((white_id, result));
+        }
+    }
+
+    let ids: Vec<i32> = standings.keys().copied().collect();
+    let names: Vec<(i32, Option<String>)> = players::table
+        .filter(players::id.eq_any(&ids))
+        .select((players::id, players::name))
+        .load(db)?;
+    for (id, name) in names {
+        if let Some(standing) = standings.get_mut(&id) {
+            standing.name = name;
+        }
+    }
+
+    let mut standings: Vec<Standing> = standings.into_values().collect();
+    standings.sort_by(|a, b| {
+        b.points
+            .total_cmp(&a.points)
+            .then_with(|| a.name.cmp(&b.name))
+            .then(a.player_id.cmp(&b.player_id))
+    });
+
+    let players = standings.len();
+    let round_robin = players > 1 && pairings.len() == players * (players - 1) / 2;
+    let crosstable = round_robin.then(|| {
+        standings
+            .iter()
+            .map(|row| {
+                standings
+                    .iter()
+                    .map(|column| {
+                        let pairing = (
+                            row.player_id.min(column.player_id),
+                            row.player_id.max(column.player_id),
+                        );
+                        pairings.get(&pairing).map_or(vec![], |games| {
+                            games
+                                .iter()
+                                .map(|(white_id, result)| result.points(*white_id == row.player_id))
+                                .collect()
+                        })
+                    })
+                    .collect()
+            })
+            .collect()
+    });
+
+    Ok(EventStandings {
+        standings,
+        crosstable,
+    })
+}
+
+/// The standings of an event, with its crosstable if it's a round-robin.
+#[tauri::command]
+#[specta::specta]
+pub async fn get_event_standings(
+    file: PathBuf,
+    event_id: i32,
+    state: tauri::State<'_, AppState>,
+) -> Result<EventStandings, Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    event_standings(db, event_id)
+}
+
+#[derive(Debug, Clone, Serialize, Type, Default)]
+pub struct PlayerGameInfo {
+    pub won: i32,
+    pub lost: i32,
+    pub draw: i32,
+    /// Games with an unknown result, so that the four counts add up to the
+    /// games of the player.
+    pub unknown: i32,
+    pub as_white: Results,
+    pub as_black: Results,
+    pub data_per_month: Vec<(String, MonthData)>,
+    pub white_openings: Vec<(String, Results)>,
+    pub black_openings: Vec<(String, Results)>,
+}
+
+#[derive(Debug, Clone, Serialize, Type, Default, Eq, Ord, PartialEq, PartialOrd)]
+pub struct Results {
+    pub won: i32,
+    pub lost: i32,
+    pub draw: i32,
+}
+
+impl Results {
+    /// Counts a game of the player with the given color, returning whether
+    /// its result is known.
+    fn record(&mut self, result: GameResult, as_white: bool) -> bool {
+        match (result, as_white) {
+            (GameResult::WhiteWins, true) | (GameResult::BlackWins, false) => self.won += 1,
+            (GameResult::WhiteWins, false) | (GameResult::BlackWins, true) => self.lost += 1,
+            (GameResult::Draw, _) => self.draw += 1,
+            (GameResult::Unknown, _) => return false,
+        }
+        true
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Type, Default)]
+pub struct MonthData {
+    pub count: i32,
+    pub avg_elo: i32,
+    #[serde(skip)]
+    avg_count: i32,
+}
+
+#[derive(Serialize, Debug, Clone, Type, tauri_specta::Event)]
+pub struct DatabaseProgress {
+    pub id: String,
+    pub progress: f64,
+}
+
+/// The results, openings and activity of a player in the games of `query`,
+/// reporting the progress in percent.
+fn player_game_info(
+    db: &mut SqliteConnection,
+    id: i32,
+    query: &GameQuery,
+    on_progress: impl Fn(f64) + Sync,
+) -> Result<PlayerGameInfo, Error> {
+    let sql_query = filter_games!(games::table.into_boxed(), query)
         .filter(games::white_id.eq(id).or(games::black_id.eq(id)))
-        .filter(games::fen.is_null());
+        .filter(games::fen.is_null())
+        .select((
+            games::white_id,
+            games::black_id,
+            games::result,
+            games::date,
+            games::moves,
+            games::white_elo,
+            games::black_elo,
+        ));
+
+    type GameInfo = (
+        i32,
+        i32,
+        Option<i32>,
+        Option<String>,
+        Vec<u8>,
+        Option<i32>,
+        Option<i32>,
+    );
+    let info: Vec<GameInfo> = sql_query.load(db)?;
+
+    let mut game_info = PlayerGameInfo::default();
+    for (white_id, _, outcome, ..) in &info {
+        let is_white = *white_id == id;
+        let results = if is_white {
+            &mut game_info.as_white
+        } else {
+            &mut game_info.as_black
+        };
+        if !results.record(GameResult::from_column(*outcome), is_white) {
+            game_info.unknown += 1;
+        }
+    }
+    game_info.won = game_info.as_white.won + game_info.as_black.won;
+    game_info.lost = game_info.as_white.lost + game_info.as_black.lost;
+    game_info.draw = game_info.as_white.draw + game_info.as_black.draw;
+
+    let white_openings = DashMap::new();
+    let black_openings = DashMap::new();
+    let data_per_month = DashMap::new();
+    let progress = AtomicUsize::new(0);
+
+    info.par_iter().for_each(
+        |(white_id, black_id, outcome, date, moves, white_elo, black_elo)| {
+            let is_white = *white_id == id;
+            assert!(is_white || *black_id == id);
+            let outcome = Some(GameResult::from_column(*outcome));
+
+            let mut setups = vec![];
+            let mut chess = Chess::default();
+            for (i, byte) in mainline_moves(moves).iter().enumerate() {
+                if i > 54 {
+                    // max length of opening in data
+                    break;
+                }
+                let m = decode_move(*byte, &chess).unwrap();
+                chess.play_unchecked(&m);
+                setups.push(chess.clone().into_setup(EnPassantMode::Legal));
+            }
+
+            setups.reverse();
+            for setup in setups {
+                if let Ok(opening) = get_opening_from_setup(setup) {
+                    let openings = if is_white {
+                        &white_openings
+                    } else {
+                        &black_openings
+                    };
+                    if outcome == Some(GameResult::WhiteWins) {
+                        openings
+                            .entry(opening)
+                            .and_modify(|e: &mut Results| {
+                                if is_white {
+                                    e.won += 1;
+                                } else {
+                                    e.lost += 1;
+                                }
+                            })
+                            .or_insert(Results {
+                                won: 1,
+                                lost: 0,
+                                draw: 0,
+                            });
+                    } else if outcome == Some(GameResult::BlackWins) {
+                        openings
+                            .entry(opening)
+                            .and_modify(|e| {
+                                if is_white {
+                                    e.lost += 1;
+                                } else {
+                                    e.won += 1;
+                                }
+                            })
+                            .or_insert(Results {
+                                won: 0,
+                                lost: 1,
+                                draw: 0,
+                            });
+                    } else if outcome == Some(GameResult::Draw) {
+                        openings
+                            .entry(opening)
+                            .and_modify(|e| {
+                                e.draw += 1;
+                            })
+                            .or_insert(Results {
+                                won: 0,
+                                lost: 0,
+                                draw: 1,
+                            });
+                    }
+
+                    break;
+                }
+            }
+
+            if let Some(date) = date {
+                let date = match NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+                    Ok(date) => date,
+                    Err(_) => return,
+                };
+                let month = date.format("%Y-%m").to_string();
+
+                // update count and avg elo
+                let mut month_data = data_per_month.entry(month).or_insert(MonthData::default());
+                month_data.count += 1;
+                let elo = if is_white { white_elo } else { black_elo };
+                if let Some(elo) = elo {
+                    month_data.avg_elo += elo;
+                    month_data.avg_count += 1;
+                }
+            }
+
+            let p = progress.fetch_add(1, Ordering::Relaxed);
+            if p % 1000 == 0 || p == info.len() - 1 {
+                on_progress((p as f64 / info.len() as f64) * 100_f64);
+            }
+        },
+    );
+    game_info.white_openings = white_openings.into_iter().collect();
+    game_info.black_openings = black_openings.into_iter().collect();
+    game_info.data_per_month = data_per_month.into_iter().collect();
+    game_info.data_per_month = game_info
+        .data_per_month
+        .into_iter()
+        .map(|(month, data)| {
+            let avg_elo = if data.avg_count == 0 {
+                0
+            } else {
+                data.avg_elo / data.avg_count
+            };
+            (
+                month,
+                MonthData {
+                    count: data.count,
+                    avg_elo,
+                    avg_count: data.avg_count,
+                },
+            )
+        })
+        .collect();
+
+    // sort openings by count
+    game_info.white_openings.sort_by(|(_, a), (_, b)| b.cmp(a));
+    game_info.black_openings.sort_by(|(_, a), (_, b)| b.cmp(a));
+
+    Ok(game_info)
+}
+
+/// The stats of a player for the player cards, optionally only of their
+/// games between two dates or of one speed, like the games of the page.
+#[tauri::command]
+#[specta::specta]
+pub async fn get_players_game_info(
+    file: PathBuf,
+    id: i32,
+    include_unknown_result: Option<bool>,
+    start_date: Option<String>,
+    end_date: Option<String>,
+    speed: Option<Speed>,
+    state: tauri::State<'_, AppState>,
+    app: tauri::AppHandle,
+) -> Result<PlayerGameInfo, Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    let timer = Instant::now();
+
+    let query = GameQuery {
+        include_unknown_result,
+        start_date,
+        end_date,
+        speed: speed.map(|speed| vec![speed]),
+        ..Default::default()
+    };
+    let game_info = player_game_info(db, id, &query, |progress| {
+        let _ = DatabaseProgress {
+            id: id.to_string(),
+            progress,
+        }
+        .emit_all(&app);
+    })?;
+
+    println!("get_players_game_info {:?}: {:?}", file, timer.elapsed());
+
+    Ok(game_info)
+}
+
+#[tauri::command]
+pub async fn delete_database(
+    file: PathBuf,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), Error> {
+    let _modification = state.count_cache.modify(file.to_str().unwrap());
+    let pool = &state.connection_pool;
+    let path_str = file.to_str().unwrap();
+    pool.remove(path_str);
+
+    // delete file
+    remove_file(path_str)?;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn delete_duplicated_games(
+    file: PathBuf,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), Error> {
+    let _modification = state.count_cache.modify(file.to_str().unwrap());
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+
+    let duplicates = "
+        SELECT ID
+        FROM (
+            SELECT ID,
+                ROW_NUMBER() OVER (PARTITION BY EventID, SiteID, Round, WhiteID, BlackID, Moves, Date, UTCTime ORDER BY ID) AS RowNum
+            FROM Games
+        ) AS Subquery
+        WHERE RowNum > 1";
+    db.transaction::<_, Error, _>(|db| {
+        delete_game_comments(db, duplicates)?;
+        sql_query(format!("DELETE FROM Games WHERE ID IN ({duplicates});")).execute(db)?;
+        Ok(())
+    })?;
+    count_player_games(db)?;
+
+    Ok(())
+}
+
+/// Deletes the games with the same players, date and moves as an older game,
+/// returning how many were deleted. Works on databases imported before
+/// `GameHash` existed, by computing the missing hashes first.
+#[tauri::command]
+pub async fn remove_duplicate_games(
+    file: PathBuf,
+    state: tauri::State<'_, AppState>,
+) -> Result<usize, Error> {
+    let _modification = state.count_cache.modify(file.to_str().unwrap());
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+
+    fill_game_hashes(db)?;
+    let duplicates = "
+        SELECT ID
+        FROM (
+            SELECT ID,
+                ROW_NUMBER() OVER (PARTITION BY GameHash ORDER BY ID) AS RowNum
+            FROM Games
+            WHERE GameHash IS NOT NULL
+        ) AS Subquery
+        WHERE RowNum > 1";
+    let deleted = db.transaction::<_, Error, _>(|db| {
+        delete_game_comments(db, duplicates)?;
+        Ok(sql_query(format!("DELETE FROM Games WHERE ID IN ({duplicates});")).execute(db)?)
+    })?;
+    count_player_games(db)?;
+    update_info_counts(db)?;
+
+    Ok(deleted)
+}
+
+/// Fills in the ECO code of the games without one, returning how many were
+/// classified.
+#[tauri::command]
+pub async fn classify_openings(
+    file: PathBuf,
+    state: tauri::State<'_, AppState>,
+) -> Result<usize, Error> {
+    let _modification = state.count_cache.modify(file.to_str().unwrap());
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+
+    let games: Vec<(i32, Vec<u8>)> = games::table
+        .select((games::id, games::moves))
+        .filter(games::eco.is_null())
+        .filter(games::fen.is_null())
+        .load(db)?;
+
+    let classified: Vec<(i32, String)> = games
+        .par_iter()
+        .filter_map(|(id, moves)| Some((*id, classify_opening(moves)?)))
+        .collect();
+
+    db.transaction::<_, Error, _>(|db| {
+        for (id, eco) in &classified {
+            diesel::update(games::table.find(*id))
+                .set(games::eco.eq(eco))
+                .execute(db)?;
+        }
+        Ok(())
+    })?;
+
+    Ok(classified.len())
+}
+
+/// Stores the speed of the games with a time control but no speed, which
+/// were imported before it was classified. Returns how many were updated.
+fn fill_missing_speeds(db: &mut SqliteConnection) -> Result<usize, Error> {
+    let games: Vec<(i32, Option<String>)> = games::table
+        .select((games::id, games::time_control))
+        .filter(games::speed.is_null())
+        .filter(games::time_control.is_not_null())
+        .load(db)?;
+
+    let classified: Vec<(i32, Speed)> = games
+        .into_iter()
+        .filter_map(|(id, time_control)| {
+            Some((id, Speed::from_time_control(time_control.as_deref()?)?))
+        })
+        .collect();
+
+    db.transaction::<_, Error, _>(|db| {
+        for (id, speed) in &classified {
+            diesel::update(games::table.find(*id))
+                .set(games::speed.eq(*speed as i32))
+                .execute(db)?;
+        }
+        Ok(())
+    })?;
+
+    Ok(classified.len())
+}
+
+/// Stores the higher rating of the players of the games imported before it
+/// was stored. Returns how many were updated.
+fn fill_missing_max_ratings(db: &mut SqliteConnection) -> Result<usize, Error> {
+    // The scalar MAX of SQLite is NULL if any argument is
+    Ok(sql_query(
+        "UPDATE Games
+        SET MaxRating = MAX(COALESCE(WhiteElo, BlackElo), COALESCE(BlackElo, WhiteElo))
+        WHERE MaxRating IS NULL AND (WhiteElo IS NOT NULL OR BlackElo IS NOT NULL);",
+    )
+    .execute(db)?)
+}
+
+/// Stores the material signatures of the games imported before they were
+/// stored, a batch at a time. Returns how many were updated.
+fn fill_missing_material_signatures(db: &mut SqliteConnection) -> Result<usize, Error> {
+    let mut updated = 0;
+    let mut last_id = i32::MIN;
+    loop {
+        let games: Vec<(i32, Vec<u8>, Option<String>)> = games::table
+            .select((games::id, games::moves, games::fen))
+            .filter(games::material_signatures.is_null())
+            .filter(games::id.gt(last_id))
+            .order(games::id)
+            .limit(DEFAULT_IMPORT_BATCH_SIZE as i64)
+            .load(db)?;
+        let Some((id, _, _)) = games.last() else {
+            break;
+        };
+        last_id = *id;
+
+        // The games whose moves can't be replayed are left without signatures,
+        // so the position searches always replay them
+        let signatures: Vec<(i32, Vec<u8>)> = games
+            .par_iter()
+            .filter_map(|(id, moves, fen)| {
+                Some((*id, encode_material_signatures(moves, fen.as_deref())?))
+            })
+            .collect();
+        db.transaction::<_, Error, _>(|db| {
+            for (id, signatures) in &signatures {
+                diesel::update(games::table.find(*id))
+                    .set(games::material_signatures.eq(signatures))
+                    .execute(db)?;
+            }
+            Ok(())
+        })?;
+        updated += signatures.len();
+    }
+    Ok(updated)
+}
+
+/// Computes the material signatures that speed up the position searches for
+/// a database imported before they were stored, returning how many games
+/// were updated.
+#[tauri::command]
+pub async fn compute_material_signatures(
+    file: PathBuf,
+    state: tauri::State<'_, AppState>,
+) -> Result<usize, Error> {
+    let _modification = state.count_cache.modify(file.to_str().unwrap());
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    fill_missing_material_signatures(db)
+}
+
+/// Fills in the `MaxRating` used to sort by [`GameSort::MaxElo`] for a
+/// database imported before it was stored, returning how many games were
+/// updated.
+#[tauri::command]
+pub async fn compute_max_ratings(
+    file: PathBuf,
+    state: tauri::State<'_, AppState>,
+) -> Result<usize, Error> {
+    let _modification = state.count_cache.modify(file.to_str().unwrap());
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    fill_missing_max_ratings(db)
+}
+
+/// Classifies the speed of the games of a database imported before speeds
+/// were stored, returning how many games were updated.
+#[tauri::command]
+pub async fn compute_speeds(
+    file: PathBuf,
+    state: tauri::State<'_, AppState>,
+) -> Result<usize, Error> {
+    let _modification = state.count_cache.modify(file.to_str().unwrap());
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    fill_missing_speeds(db)
+}
+
+/// Rewrites the dates of a database imported before they were normalized,
+/// returning how many games were updated.
+#[tauri::command]
+pub async fn normalize_dates(
+    file: PathBuf,
+    state: tauri::State<'_, AppState>,
+) -> Result<usize, Error> {
+    let _modification = state.count_cache.modify(file.to_str().unwrap());
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+
+    let dates: Vec<(i32, Option<String>)> = games::table
+        .select((games::id, games::date))
+        .filter(games::date.is_not_null())
+        .load(db)?;
+
+    let updated = db.transaction::<_, Error, _>(|db| {
+        let mut updated = 0;
+        for (id, date) in dates {
+            let normalized = date.as_deref().and_then(normalize_date);
+            if normalized != date {
+                diesel::update(games::table.find(id))
+                    .set(games::date.eq(normalized))
+                    .execute(db)?;
+                updated += 1;
+            }
+        }
+        // Dates that can't be normalized are cleared, changing the stats
+        clear_database_stats(db)?;
+        Ok(updated)
+    })?;
+
+    Ok(updated)
+}
+
+/// Deletes the comments of the games selected by `ids`, a query of game IDs.
+/// The connections don't enforce the foreign keys, so the `ON DELETE CASCADE`
+/// of the Comments table never runs and games are deleted after this.
+fn delete_game_comments(db: &mut SqliteConnection, ids: &str) -> Result<usize, Error> {
+    Ok(sql_query(format!("DELETE FROM Comments WHERE GameID IN ({ids});")).execute(db)?)
+}
+
+#[tauri::command]
+pub async fn delete_empty_games(
+    file: PathBuf,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), Error> {
+    let _modification = state.count_cache.modify(file.to_str().unwrap());
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+
+    db.transaction::<_, Error, _>(|db| {
+        delete_game_comments(db, "SELECT ID FROM Games WHERE PlyCount = 0")?;
+        diesel::delete(games::table.filter(games::ply_count.eq(0))).execute(db)?;
+        Ok(())
+    })?;
+    count_player_games(db)?;
+
+    Ok(())
+}
+
+/// Deletes the empty games without players that older versions inserted at
+/// the end of every import, returning how many were deleted.
+#[tauri::command]
+pub async fn delete_phantom_games(
+    file: PathBuf,
+    state: tauri::State<'_, AppState>,
+) -> Result<usize, Error> {
+    let _modification = state.count_cache.modify(file.to_str().unwrap());
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+
+    let deleted = db.transaction::<_, Error, _>(|db| {
+        delete_game_comments(
+            db,
+            "SELECT ID FROM Games WHERE PlyCount = 0 AND WhiteID = 0 AND BlackID = 0",
+        )?;
+        Ok(diesel::delete(
+            games::table
+                .filter(games::ply_count.eq(0))
+                .filter(games::white_id.eq(0))
+                .filter(games::black_id.eq(0)),
+        )
+        .execute(db)?)
+    })?;
+    recount_player_games(db, &[0])?;
+    update_info_counts(db)?;
+
+    Ok(deleted)
+}
+
+/// Sets the `Elo` of every player to the highest rating they had in their
+/// games, returning the number of rated players.
+fn recompute_peak_elos(db: &mut SqliteConnection) -> Result<usize, Error> {
+    db.transaction::<_, Error, _>(|db| {
+        diesel::update(players::table)
+            .set(players::elo.eq(None::<i32>))
+            .execute(db)?;
+        let rated = diesel::sql_query(
+            "UPDATE Players SET Elo = Peaks.Elo
+            FROM (
+                SELECT ID, MAX(Elo) AS Elo FROM (
+                    SELECT WhiteID AS ID, WhiteElo AS Elo FROM Games
+                    UNION ALL
+                    SELECT BlackID, BlackElo FROM Games
+                )
+                WHERE Elo IS NOT NULL
+                GROUP BY ID
+            ) AS Peaks
+            WHERE Players.ID = Peaks.ID",
+        )
+        .execute(db)?;
+        Ok(rated)
+    })
+}
+
+/// Sets the `GameCount` of every player, returning the number of players
+/// with games.
+fn count_player_games(db: &mut SqliteConnection) -> Result<usize, Error> {
+    db.transaction::<_, Error, _>(|db| {
+        diesel::update(players::table)
+            .set(players::game_count.eq(0))
+            .execute(db)?;
+        let counted = diesel::sql_query(
+            "UPDATE Players SET GameCount = Counts.Games
+            FROM (
+                SELECT ID, COUNT(*) AS Games FROM (
+                    SELECT WhiteID AS ID FROM Games
+                    UNION ALL
+                    SELECT BlackID FROM Games
+                )
+                GROUP BY ID
+            ) AS Counts
+            WHERE Players.ID = Counts.ID",
+        )
+        .execute(db)?;
+        Ok(counted)
+    })
+}
+
+/// Counts the games of a few players again, after some of their games were
+/// deleted or moved.
+fn recount_player_games(
+    db: &mut SqliteConnection,
+    ids: &[i32],
+) -> Result<(), diesel::result::Error> {
+    for &id in ids {
+        let games: i64 = games::table
+            .filter(games::white_id.eq(id))
+            .count()
+            .get_result(db)?
+            + games::table
+                .filter(games::black_id.eq(id))
+                .count()
+                .get_result::<i64>(db)?;
+        diesel::update(players::table.find(id))
+            .set(players::game_count.eq(games as i32))
+            .execute(db)?;
+    }
+    Ok(())
+}
+
+/// Fills in the `GameCount` of the players of a database imported before it
+/// was stored, returning the number of players with games.
+#[tauri::command]
+pub async fn compute_player_game_counts(
+    file: PathBuf,
+    state: tauri::State<'_, AppState>,
+) -> Result<usize, Error> {
+    let _modification = state.count_cache.modify(file.to_str().unwrap());
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    count_player_games(db)
+}
+
+/// Fills the `Elo` of the players of databases imported before it was
+/// tracked, returning the number of rated players.
+#[tauri::command]
+pub async fn recompute_player_ratings(
+    file: PathBuf,
+    state: tauri::State<'_, AppState>,
+) -> Result<usize, Error> {
+    let _modification = state.count_cache.modify(file.to_str().unwrap());
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    recompute_peak_elos(db)
+}
+
+struct PgnGame {
+    event: Option<String>,
+    site: Option<String>,
+    date: Option<String>,
+    round: Option<String>,
+    white: Option<String>,
+    black: Option<String>,
+    result: Option<GameResult>,
+    termination: Option<String>,
+    time_control: Option<String>,
+    eco: Option<String>,
+    white_elo: Option<String>,
+    black_elo: Option<String>,
+    ply_count: Option<String>,
+    fen: Option<String>,
+    moves: Option<Vec<String>>,
+    comments: Vec<Comment>,
+}
+
+impl PgnGame {
+    fn write<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        writeln!(
+            writer,
+            "[Event \"{}\"]",
+            self.event.as_deref().unwrap_or("")
+        )?;
+        writeln!(writer, "[Site \"{}\"]", self.site.as_deref().unwrap_or(""))?;
+        writeln!(
+            writer,
+            "[Date \"{}\"]",
+            self.date.as_deref().unwrap_or("????.??.??")
+        )?;
+        writeln!(
+            writer,
+            "[Round \"{}\"]",
+            self.round.as_deref().unwrap_or("")
+        )?;
+        writeln!(
+            writer,
+            "[White \"{}\"]",
+            self.white.as_deref().unwrap_or("")
+        )?;
+        writeln!(
+            writer,
+            "[Black \"{}\"]",
+            self.black.as_deref().unwrap_or("")
+        )?;
+        writeln!(
+            writer,
+            "[Result \"{}\"]",
+            self.result.unwrap_or(GameResult::Unknown).as_str()
+        )?;
+        if let Some(termination) = self.termination.as_deref() {
+            writeln!(writer, "[Termination \"{}\"]", termination)?;
+        }
+        if let Some(time_control) = self.time_control.as_deref() {
+            writeln!(writer, "[TimeControl \"{}\"]", time_control)?;
+        }
+        if let Some(eco) = self.eco.as_deref() {
+            writeln!(writer, "[ECO \"{}\"]", eco)?;
+        }
+        if let Some(white_elo) = self.white_elo.as_deref() {
+            writeln!(writer, "[WhiteElo \"{}\"]", white_elo)?;
+        }
+        if let Some(black_elo) = self.black_elo.as_deref() {
+            writeln!(writer, "[BlackElo \"{}\"]", black_elo)?;
+        }
+        if let Some(ply_count) = self.ply_count.as_deref() {
+            writeln!(writer, "[PlyCount \"{}\"]", ply_count)?;
+        }
+        if let Some(fen) = self.fen.as_deref() {
+            writeln!(writer, "[SetUp \"1\"]")?;
+            writeln!(writer, "[FEN \"{}\"]", fen)?;
+        }
+        writeln!(writer)?;
+        let mut ply = 0;
+        let mut variation_plies = Vec::new();
+        let mut needs_number = false;
+        let mut comments = self.comments.iter().peekable();
+        let mut write_comments = |writer: &mut W, index: i32| -> Result<bool, Error> {
+            let mut written = false;
+            while let Some(comment) = comments.next_if(|c| c.ply.unwrap_or(0) <= index) {
+                if let Some(nags) = comment.nags.as_deref() {
+                    write!(writer, "{} ", nags)?;
+                }
+                if let Some(text) = comment.comment.as_deref() {
+                    write!(writer, "{{{}}} ", text)?;
+                    written = true;
+                }
+            }
+            Ok(written)
+        };
+        needs_number |= write_comments(writer, 0)?;
+        for (index, move_) in self.moves.as_ref().unwrap().iter().enumerate() {
+            match move_.as_str() {
+                "(" => {
+                    variation_plies.push(ply);
+                    ply -= 1;
+                    needs_number = true;
+                    write!(writer, "( ")?;
+                }
+                ")" => {
+                    ply = variation_plies.pop().unwrap_or(ply);
+                    needs_number = true;
+                    write!(writer, ") ")?;
+                }
+                _ => {
+                    if ply % 2 == 0 {
+                        write!(writer, "{}. ", ply / 2 + 1)?;
+                    } else if needs_number {
+                        write!(writer, "{}... ", ply / 2 + 1)?;
+                    }
+                    write!(writer, "{} ", move_)?;
+                    ply += 1;
+                    needs_number = false;
+                }
+            }
+            needs_number |= write_comments(writer, index as i32 + 1)?;
+        }
+        writeln!(
+            writer,
+            "{}",
+            self.result.unwrap_or(GameResult::Unknown).as_str()
+        )?;
+        writeln!(writer)?;
+        Ok(())
+    }
+}
+
+/// Compression of an exported file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportCompression {
+    None,
+    Gzip,
+    Bzip2,
+    Zstd,
+}
+
+impl ExportCompression {
+    /// Infers the compression from the extension of `path`, as imports do.
+    fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("gz") => ExportCompression::Gzip,
+            Some("bz2") => ExportCompression::Bzip2,
+            Some("zst") => ExportCompression::Zstd,
+            _ => ExportCompression::None,
+        }
+    }
+}
+
+/// Destination of an export, compressed as asked or as the extension of the
+/// file says.
+enum ExportWriter {
+    Plain(BufWriter<File>),
+    Gzip(flate2::write::GzEncoder<BufWriter<File>>),
+    Bzip2(bzip2::write::BzEncoder<BufWriter<File>>),
+    Zstd(zstd::Encoder<'static, BufWriter<File>>),
+}
+
+impl ExportWriter {
+    fn create(path: &Path, compression: Option<ExportCompression>) -> Result<Self, Error> {
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(path)?;
+        let writer = BufWriter::new(file);
+        Ok(
+            match compression.unwrap_or_else(|| ExportCompression::from_path(path)) {
+                ExportCompression::None => ExportWriter::Plain(writer),
+                ExportCompression::Gzip => ExportWriter::Gzip(flate2::write::GzEncoder::new(
+                    writer,
+                    flate2::Compression::default(),
+                )),
+                ExportCompression::Bzip2 => ExportWriter::Bzip2(bzip2::write::BzEncoder::new(
+                    writer,
+                    bzip2::Compression::default(),
+                )),
+                ExportCompression::Zstd => ExportWriter::Zstd(zstd::Encoder::new(writer, 0)?),
+            },
+        )
+    }
+
+    /// Flushes the file, writing the end of the compressed stream if any.
+    fn finish(self) -> Result<(), Error> {
+        match self {
+            ExportWriter::Plain(mut writer) => writer.flush()?,
+            ExportWriter::Gzip(encoder) => encoder.finish()?.flush()?,
+            ExportWriter::Bzip2(encoder) => encoder.finish()?.flush()?,
+            ExportWriter::Zstd(encoder) => encoder.finish()?.flush()?,
+        }
+        Ok(())
+    }
+
+    /// Creates the file at `path`, runs `write` on it and finishes it. The
+    /// file is finished even when `write` fails, so an interrupted export is
+    /// still readable up to where it stopped.
+    fn export<T>(
+        path: &Path,
+        compression: Option<ExportCompression>,
+        write: impl FnOnce(&mut ExportWriter) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let mut writer = ExportWriter::create(path, compression)?;
+        let result = write(&mut writer);
+        let finished = writer.finish();
+        let value = result?;
+        finished?;
+        Ok(value)
+    }
+}
+
+impl Write for ExportWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            ExportWriter::Plain(writer) => writer.write(buf),
+            ExportWriter::Gzip(encoder) => encoder.write(buf),
+            ExportWriter::Bzip2(encoder) => encoder.write(buf),
+            ExportWriter::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            ExportWriter::Plain(writer) => writer.flush(),
+            ExportWriter::Gzip(encoder) => encoder.flush(),
+            ExportWriter::Bzip2(encoder) => encoder.flush(),
+            ExportWriter::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Writes joined game rows as PGN, a batch at a time, loading the comments
+/// of each batch with one query on `comment_db`. Returns the number of games
+/// written.
+fn write_pgn_games(
+    writer: &mut impl Write,
+    comment_db: &mut SqliteConnection,
+    has_comments: bool,
+    rows: impl Iterator<Item = Result<(Game, Player, Player, Event, Site), Error>>,
+) -> Result<usize, Error> {
+    let mut written = 0;
+    let mut rows = rows.peekable();
+    while rows.peek().is_some() {
+        let batch = rows
+            .by_ref()
+            .take(DEFAULT_IMPORT_BATCH_SIZE)
+            .collect::<Result<Vec<_>, Error>>()?;
+        let mut comments = if has_comments {
+            let ids: Vec<i32> = batch.iter().map(|(game, ..)| game.id).collect();
+            comments_by_game(comment_db, &ids)?
+        } else {
+            HashMap::new()
+        };
+        for row in batch {
+            let game_comments = comments.remove(&row.0.id).unwrap_or_default();
+            write_pgn_game(writer, game_comments, row)?;
+            written += 1;
+        }
+    }
+    Ok(written)
+}
+
+/// Writes one joined game row as PGN with its comments.
+fn write_pgn_game(
+    writer: &mut impl Write,
+    comments: Vec<Comment>,
+    (game, white, black, event, site): (Game, Player, Player, Event, Site),
+) -> Result<(), Error> {
+    let pgn = PgnGame {
+        event: event.name,
+        site: site.name,
+        date: game.date.as_deref().map(pgn_date),
+        round: game.round,
+        white: game.white_original_name.or(white.name),
+        black: game.black_original_name.or(black.name),
+        result: game.result.and_then(GameResult::from_i32),
+        termination: game.termination,
+        time_control: game.time_control,
+        eco: game.eco,
+        white_elo: game.white_elo.map(|e| e.to_string()),
+        black_elo: game.black_elo.map(|e| e.to_string()),
+        ply_count: game.ply_count.map(|e| e.to_string()),
+        fen: game.fen.clone(),
+        moves: decode_moves(
+            game.moves,
+            if let Some(fen) = game.fen {
+                Fen::from_ascii(fen.as_bytes()).unwrap_or_default()
+            } else {
+                Fen::default()
+            },
+        )
+        .ok(),
+        comments,
+    };
+
+    pgn.write(writer)?;
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn export_to_pgn(
+    file: PathBuf,
+    dest_file: PathBuf,
+    compression: Option<ExportCompression>,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    // Comments are loaded on a second connection while the games are streamed
+    let comment_db =
+        &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    let has_comments = comments::table.count().get_result::<i64>(comment_db)? > 0;
+
+    let (white_players, black_players) = diesel::alias!(players as white, players as black);
+    ExportWriter::export(&dest_file, compression, |writer| {
+        let rows = games::table
+            .inner_join(white_players.on(games::white_id.eq(white_players.field(players::id))))
+            .inner_join(black_players.on(games::black_id.eq(black_players.field(players::id))))
+            .inner_join(events::table.on(games::event_id.eq(events::id)))
+            .inner_join(sites::table.on(games::site_id.eq(sites::id)))
+            .load_iter::<(Game, Player, Player, Event, Site), DefaultLoadingMode>(db)?
+            .flatten();
+        write_pgn_games(writer, comment_db, has_comments, rows.map(Ok))?;
+        Ok(())
+    })
+}
+
+/// Exports every game matching `query` as PGN, in the order of its sort.
+/// Pagination is ignored and the games are streamed. Returns the number of
+/// games written.
+#[tauri::command]
+pub async fn export_games(
+    file: PathBuf,
+    query: GameQuery,
+    dest_file: PathBuf,
+    compression: Option<ExportCompression>,
+    state: tauri::State<'_, AppState>,
+) -> Result<usize, Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    let comment_db =
+        &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+
+    let count: i64 = filter_games!(games::table.into_boxed(), query)
+        .select(diesel::dsl::count(games::id))
+        .first(db)?;
+    if count == 0 {
+        return Err(Error::NoMatchingGames);
+    }
+    let has_comments = comments::table.count().get_result::<i64>(comment_db)? > 0;
+
+    let (white_players, black_players) = diesel::alias!(players as white, players as black);
+    let sql_query = games::table
+        .inner_join(white_players.on(games::white_id.eq(white_players.field(players::id))))
+        .inner_join(black_players.on(games::black_id.eq(black_players.field(players::id))))
+        .inner_join(events::table.on(games::event_id.eq(events::id)))
+        .inner_join(sites::table.on(games::site_id.eq(sites::id)))
+        .into_boxed();
+    let query_options = query.options.clone().unwrap_or_default();
+    let sql_query = sort_games!(filter_games!(sql_query, query), query_options);
+
+    ExportWriter::export(&dest_file, compression, |writer| {
+        let rows = sql_query
+            .load_iter::<(Game, Player, Player, Event, Site), DefaultLoadingMode>(db)?
+            .map(|row| row.map_err(Error::from));
+        write_pgn_games(writer, comment_db, has_comments, rows)
+    })
+}
+
+const CSV_HEADER: [&str; 13] = [
+    "id",
+    "white",
+    "white_elo",
+    "black",
+    "black_elo",
+    "result",
+    "date",
+    "event",
+    "site",
+    "round",
+    "eco",
+    "time_control",
+    "ply_count",
+];
+
+/// Streams the metadata of the games matching `query` as CSV rows, with NULLs
+/// left empty. Returns the number of games written.
+fn write_games_csv(
+    db: &mut SqliteConnection,
+    query: &GameQuery,
+    writer: impl Write,
+) -> Result<usize, Error> {
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record(CSV_HEADER)?;
+
+    let (white_players, black_players) = diesel::alias!(players as white, players as black);
+    let sql_query = games::table
+        .inner_join(white_players.on(games::white_id.eq(white_players.field(players::id))))
+        .inner_join(black_players.on(games::black_id.eq(black_players.field(players::id))))
+        .inner_join(events::table.on(games::event_id.eq(events::id)))
+        .inner_join(sites::table.on(games::site_id.eq(sites::id)))
+        .into_boxed();
+    let query_options = query.options.clone().unwrap_or_default();
+    let sql_query = sort_games!(filter_games!(sql_query, query), query_options);
+
+    let mut written = 0;
+    for row in sql_query.load_iter::<(Game, Player, Player, Event, Site), DefaultLoadingMode>(db)? {
+        let (game, white, black, event, site) = row?;
+        let number = |n: Option<i32>| n.map(|n| n.to_string()).unwrap_or_default();
+        writer.write_record([
+            game.id.to_string(),
+            white.name.unwrap_or_default(),
+            number(game.white_elo),
+            black.name.unwrap_or_default(),
+            number(game.black_elo),
+            game.result
+                .and_then(GameResult::from_i32)
+                .map(|r| r.as_str().to_string())
+                .unwrap_or_default(),
+            game.date.unwrap_or_default(),
+            event.name.unwrap_or_default(),
+            site.name.unwrap_or_default(),
+            game.round.unwrap_or_default(),
+            game.eco.unwrap_or_default(),
+            game.time_control.unwrap_or_default(),
+            number(game.ply_count),
+        ])?;
+        written += 1;
+    }
+    writer.flush()?;
+
+    Ok(written)
+}
+
+/// Exports the metadata of the games matching `query`, or of every game, as
+/// CSV. Returns the number of games written.
+#[tauri::command]
+pub async fn export_csv(
+    file: PathBuf,
+    query: Option<GameQuery>,
+    dest_file: PathBuf,
+    compression: Option<ExportCompression>,
+    state: tauri::State<'_, AppState>,
+) -> Result<usize, Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    ExportWriter::export(&dest_file, compression, |writer| {
+        write_games_csv(db, &query.unwrap_or_default(), writer)
+    })
+}
+
+/// What [`export_epd`] wrote.
+#[derive(Debug, Clone, Default, Serialize, Type, PartialEq, Eq)]
+pub struct EpdExport {
+    /// Distinct positions written.
+    pub positions: usize,
+    /// Games left out because their moves couldn't be replayed up to the ply.
+    pub skipped: usize,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct EpdPosition {
+    count: u32,
+    white: u32,
+    draws: u32,
+    black: u32,
+}
+
+/// Streams the games to collect the distinct positions reached after `ply`
+/// plies of the mainline, and writes those seen at least `min_count` times
+/// as EPD with a `c0` comment holding the count and results. Games that are
+/// too short are left out, as are games from a custom position unless
+/// `include_custom_start`, and games whose moves can't be replayed are counted
+/// as skipped. Positions are sorted by count, then by EPD.
+fn write_epd_positions(
+    db: &mut SqliteConnection,
+    ply: usize,
+    min_count: u32,
+    include_custom_start: bool,
+    writer: &mut impl Write,
+) -> Result<EpdExport, Error> {
+    let mut positions: HashMap<String, EpdPosition> = HashMap::new();
+    let mut skipped = 0;
+
+    let mut sql_query = games::table
+        .select((games::moves, games::fen, games::result))
+        .into_boxed();
+    if !include_custom_start {
+        sql_query = sql_query.filter(games::fen.is_null());
+    }
+    for row in
+        sql_query.load_iter::<(Vec<u8>, Option<String>, Option<i32>), DefaultLoadingMode>(db)?
+    {
+        let (moves, fen, result) = row?;
+        let moves = mainline_moves(&moves);
+        if moves.len() < ply {
+            continue;
+        }
+        let Some(mut chess) = initial_position(fen.as_deref()) else {
+            skipped += 1;
+            continue;
+        };
+        let replayed = moves[..ply]
+            .iter()
+            .all(|&byte| match decode_move(byte, &chess) {
+                Some(m) => {
+                    chess.play_unchecked(&m);
+                    true
+                }
+                None => false,
+            });
+        if !replayed {
+            skipped += 1;
+            continue;
+        }
+
+        let epd = Epd::from_position(chess, EnPassantMode::Legal).to_string();
+        let position = positions.entry(epd).or_default();
+        position.count += 1;
+        match result.and_then(GameResult::from_i32) {
+            Some(GameResult::WhiteWins) => position.white += 1,
+            Some(GameResult::Draw) => position.draws += 1,
+            Some(GameResult::BlackWins) => position.black += 1,
+            _ => {}
+        }
+    }
+
+    let mut positions: Vec<(String, EpdPosition)> = positions
+        .into_iter()
+        .filter(|(_, position)| position.count >= min_count)
+        .collect();
+    positions.sort_by(|(a_epd, a), (b_epd, b)| b.count.cmp(&a.count).then(a_epd.cmp(b_epd)));
+
+    for (epd, position) in &positions {
+        writeln!(
+            writer,
+            "{} c0 \"count {}, +{} ={} -{}\";",
+            epd, position.count, position.white, position.draws, position.black
+        )?;
+    }
+
+    Ok(EpdExport {
+        positions: positions.len(),
+        skipped,
+    })
+}
+
+/// Exports the distinct positions reached after `ply` plies as EPD. Returns
+/// the number of positions written and of games skipped.
+#[tauri::command]
+pub async fn export_epd(
+    file: PathBuf,
+    ply: usize,
+    dest_file: PathBuf,
+    min_count: Option<u32>,
+    include_custom_start: Option<bool>,
+    compression: Option<ExportCompression>,
+    state: tauri::State<'_, AppState>,
+) -> Result<EpdExport, Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    ExportWriter::export(&dest_file, compression, |writer| {
+        write_epd_positions(
+            db,
+            ply,
+            min_count.unwrap_or(1),
+            include_custom_start.unwrap_or(false),
+            writer,
+        )
+    })
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq, Hash)]
+pub enum PlayerSide {
+    White,
+    Black,
+    #[default]
+    Both,
+}
+
+impl PlayerSide {
+    fn includes(self, color: shakmaty::Color) -> bool {
+        match self {
+            PlayerSide::White => color.is_white(),
+            PlayerSide::Black => color.is_black(),
+            PlayerSide::Both => true,
+        }
+    }
+}
+
+/// Exports the games of a player on the given side, or both, as PGN ordered
+/// by date. Returns the number of games written.
+#[tauri::command]
+pub async fn export_player_games(
+    file: PathBuf,
+    player_id: i32,
+    color: Option<PlayerSide>,
+    dest_file: PathBuf,
+    compression: Option<ExportCompression>,
+    state: tauri::State<'_, AppState>,
+) -> Result<usize, Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    let comment_db =
+        &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    ExportWriter::export(&dest_file, compression, |writer| {
+        write_player_games(db, comment_db, player_id, color.unwrap_or_default(), writer)
+    })
+}
+
+/// Writes the games of a player on `side` as PGN ordered by date, loading
+/// their comments on `comment_db`. Returns the number of games written.
+fn write_player_games(
+    db: &mut SqliteConnection,
+    comment_db: &mut SqliteConnection,
+    player_id: i32,
+    side: PlayerSide,
+    writer: &mut impl Write,
+) -> Result<usize, Error> {
+    let has_comments = comments::table.count().get_result::<i64>(comment_db)? > 0;
+
+    let (white_players, black_players) = diesel::alias!(players as white, players as black);
+    let mut sql_query = games::table
+        .inner_join(white_players.on(games::white_id.eq(white_players.field(players::id))))
+        .inner_join(black_players.on(games::black_id.eq(black_players.field(players::id))))
+        .inner_join(events::table.on(games::event_id.eq(events::id)))
+        .inner_join(sites::table.on(games::site_id.eq(sites::id)))
+        .order((games::date.asc(), games::time.asc(), games::id.asc()))
+        .into_boxed();
+    // A single OR filter, so games against themselves after a merge are only
+    // written once
+    sql_query = match side {
+        PlayerSide::White => sql_query.filter(games::white_id.eq(player_id)),
+        PlayerSide::Black => sql_query.filter(games::black_id.eq(player_id)),
+        PlayerSide::Both => sql_query.filter(
+            games::white_id
+                .eq(player_id)
+                .or(games::black_id.eq(player_id)),
+        ),
+    };
+
+    let rows = sql_query
+        .load_iter::<(Game, Player, Player, Event, Site), DefaultLoadingMode>(db)?
+        .map(|row| row.map_err(Error::from));
+    write_pgn_games(writer, comment_db, has_comments, rows)
+}
+
+/// Writes the games with the given IDs, which must be sorted, as PGN in
+/// batches. Returns the number of games written.
+fn write_games_by_id(
+    db: &mut SqliteConnection,
+    ids: &[i32],
+    writer: &mut impl Write,
+) -> Result<usize, Error> {
+    let has_comments = comments::table.count().get_result::<i64>(db)? > 0;
+
+    let (white_players, black_players) = diesel::alias!(players as white, players as black);
+    let mut written = 0;
+    for chunk in ids.chunks(DEFAULT_IMPORT_BATCH_SIZE) {
+        let games: Vec<(Game, Player, Player, Event, Site)> = games::table
+            .inner_join(white_players.on(games::white_id.eq(white_players.field(players::id))))
+            .inner_join(black_players.on(games::black_id.eq(black_players.field(players::id))))
+            .inner_join(events::table.on(games::event_id.eq(events::id)))
+            .inner_join(sites::table.on(games::site_id.eq(sites::id)))
+            .filter(games::id.eq_any(chunk))
+            .order(games::id.asc())
+            .load(db)?;
+        written += write_pgn_games(writer, db, has_comments, games.into_iter().map(Ok))?;
+    }
+    Ok(written)
+}
+
+/// SAN and UCI of the mainline moves of a game.
+fn mainline_san_and_uci(moves: &[u8], fen: Option<&str>) -> (Vec<String>, Vec<String>) {
+    let mut san = Vec::new();
+    let mut uci = Vec::new();
+    let Some(mut chess) = initial_position(fen) else {
+        return (san, uci);
+    };
+    for &byte in mainline_moves(moves).iter() {
+        let Some(m) = decode_move(byte, &chess) else {
+            break;
+        };
+        uci.push(m.to_uci(CastlingMode::Standard).to_string());
+        san.push(SanPlus::from_move_and_play_unchecked(&mut chess, &m).to_string());
+    }
+    (san, uci)
+}
+
+/// Streams the games matching `query` as JSON Lines, one object per game with
+/// the fields of [`NormalizedGame`]. `moves` is replaced by the list of SAN
+/// mainline moves, or left out without `include_moves`, and `uci` holds the
+/// same moves in UCI with `include_uci`. Comments are loaded on `comment_db`
+/// and the output is flushed after every batch of games. Returns the number
+/// of games written.
+fn write_games_jsonl(
+    db: &mut SqliteConnection,
+    comment_db: &mut SqliteConnection,
+    query: &GameQuery,
+    include_moves: bool,
+    include_uci: bool,
+    writer: &mut impl Write,
+) -> Result<usize, Error> {
+    let has_comments = comments::table.count().get_result::<i64>(comment_db)? > 0;
+
+    let (white_players, black_players) = diesel::alias!(players as white, players as black);
+    let sql_query = games::table
+        .inner_join(white_players.on(games::white_id.eq(white_players.field(players::id))))
+        .inner_join(black_players.on(games::black_id.eq(black_players.field(players::id))))
+        .inner_join(events::table.on(games::event_id.eq(events::id)))
+        .inner_join(sites::table.on(games::site_id.eq(sites::id)))
+        .into_boxed();
+    let query_options = query.options.clone().unwrap_or_default();
+    let sql_query = sort_games!(filter_games!(sql_query, query), query_options);
+
+    let mut written = 0;
+    let mut rows = sql_query
+        .load_iter::<(Game, Player, Player, Event, Site), DefaultLoadingMode>(db)?
+        .peekable();
+    while rows.peek().is_some() {
+        let mut batch = rows
+            .by_ref()
+            .take(DEFAULT_IMPORT_BATCH_SIZE)
+            .collect::<Result<Vec<_>, _>>()?;
+        // The moves are written as lists, not as the text of NormalizedGame
+        let lines: Vec<(Vec<String>, Vec<String>)> = batch
+            .iter_mut()
+            .map(|row| {
+                let moves = std::mem::take(&mut row.0.moves);
+                mainline_san_and_uci(&moves, row.0.fen.as_deref())
+            })
+            .collect();
+        let mut games = normalize_games(batch);
+        if has_comments {
+            load_comments(comment_db, &mut games)?;
+        }
+
+        for (game, (san, uci)) in games.iter().zip(lines) {
+            let mut value = serde_json::to_value(game)?;
+            if let Some(object) = value.as_object_mut() {
+                object.remove("moves");
+                if include_moves {
+                    object.insert("moves".to_string(), san.into());
+                }
+                if include_uci {
+                    object.insert("uci".to_string(), uci.into());
+                }
+            }
+            serde_json::to_writer(&mut *writer, &value)?;
+            writer.write_all(b"\n")?;
+            written += 1;
+        }
+        writer.flush()?;
+    }
+    writer.flush()?;
+
+    Ok(written)
+}
+
+/// Exports the games matching `query`, or every game, as JSON Lines. See
+/// [`write_games_jsonl`] for the format. Returns the number of games written.
+#[tauri::command]
+pub async fn export_jsonl(
+    file: PathBuf,
+    query: Option<GameQuery>,
+    dest_file: PathBuf,
+    include_moves: bool,
+    include_uci: Option<bool>,
+    compression: Option<ExportCompression>,
+    state: tauri::State<'_, AppState>,
+) -> Result<usize, Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    // Comments are loaded on a second connection while the games are streamed
+    let comment_db =
+        &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+
+    ExportWriter::export(&dest_file, compression, |writer| {
+        write_games_jsonl(
+            db,
+            comment_db,
+            &query.unwrap_or_default(),
+            include_moves,
+            include_uci.unwrap_or(false),
+            writer,
+        )
+    })
+}
+
+/// Copies the games matching `query` from `src` to `dest`, in batches and in
+/// ID order, with their comments and the players, events and sites they
+/// reference. Those are matched by name in `dest`, as an import would.
+/// Returns the number of games copied.
+fn copy_games(
+    src: &mut SqliteConnection,
+    dest: &mut SqliteConnection,
+    names: &mut NameCache,
+    query: &GameQuery,
+    mut on_progress: impl FnMut(f64),
+) -> Result<usize, Error> {
+    let ids: Vec<i32> = filter_games!(games::table.into_boxed(), query)
+        .select(games::id)
+        .order(games::id.asc())
+        .load(src)?;
+
+    let (white_players, black_players) = diesel::alias!(players as white, players as black);
+    let mut copied = 0;
+    for chunk in ids.chunks(DEFAULT_IMPORT_BATCH_SIZE) {
+        let games: Vec<(Game, Player, Player, Event, Site)> = games::table
+            .inner_join(white_players.on(games::white_id.eq(white_players.field(players::id))))
+            .inner_join(black_players.on(games::black_id.eq(black_players.field(players::id))))
+            .inner_join(events::table.on(games::event_id.eq(events::id)))
+            .inner_join(sites::table.on(games::site_id.eq(sites::id)))
+            .filter(games::id.eq_any(chunk))
+            .order(games::id.asc())
+            .load(src)?;
+        let mut comments: HashMap<i32, Vec<Comment>> = HashMap::new();
+        for comment in comments::table
+            .filter(comments::game_id.eq_any(chunk))
+            .order(comments::id.asc())
+            .load::<Comment>(src)?
+        {
+            comments.entry(comment.game_id).or_default().push(comment);
+        }
+
+        let result = dest.transaction::<_, Error, _>(|dest| {
+            let mut tally = PlayerTally::default();
+            for (game, white, black, event, site) in &games {
+                let white_id = names.copy_player(dest, white)?;
+                let black_id = names.copy_player(dest, black)?;
+                let event_id = match &event.name {
+                    Some(name) if event.id != 0 => names.event(dest, name)?,
+                    _ => 0,
+                };
+                let site_id = match &site.name {
+                    Some(name) if site.id != 0 => names.site(dest, name)?,
+                    _ => 0,
+                };
+
+                let copy = create_game(
+                    dest,
+                    NewGame {
+                        event_id,
+                        site_id,
+                        date: game.date.as_deref(),
+                        time: game.time.as_deref(),
+                        round: game.round.as_deref(),
+                        white_id,
+                        white_elo: game.white_elo,
+                        black_id,
+                        black_elo: game.black_elo,
+                        white_material: game.white_material,
+                        black_material: game.black_material,
+                        result: game.result,
+                        time_control: game.time_control.as_deref(),
+                        eco: game.eco.as_deref(),
+                        ply_count: game
+                            .ply_count
+                            .unwrap_or_else(|| mainline_moves(&game.moves).len() as i32),
+                        fen: game.fen.as_deref(),
+                        moves: &game.moves,
+                        pawn_home: game.pawn_home,
+                        speed: game.speed,
+                        game_hash: game.game_hash,
+                        white_title: game.white_title.as_deref(),
+                        black_title: game.black_title.as_deref(),
+                        variant: game.variant.as_deref(),
+                        clocks: game.clocks.as_deref(),
+                        evals: game.evals.as_deref(),
+                        rated: game.rated,
+                        termination: game.termination.as_deref(),
+                        max_rating: game.white_elo.max(game.black_elo),
+                        material_signatures: game.material_signatures.as_deref(),
+                        white_original_name: game.white_original_name.as_deref(),
+                        black_original_name: game.black_original_name.as_deref(),
+                    },
+                )?;
+                tally.add(white_id, game.white_elo);
+                tally.add(black_id, game.black_elo);
+
+                if let Some(comments) = comments.get(&game.id) {
+                    let new_comments: Vec<NewComment> = comments
+                        .iter()
+                        .map(|c| NewComment {
+                            game_id: copy.id,
+                            ply: c.ply.unwrap_or_default(),
+                            comment: c.comment.as_deref(),
+                            nags: c.nags.as_deref(),
+                        })
+                        .collect();
+                    insert_into(comments::table)
+                        .values(&new_comments)
+                        .execute(dest)?;
+                }
+            }
+            tally.apply(dest)?;
+            Ok(())
+        });
+        if let Err(e) = result {
+            // IDs created inside the rolled back transaction no longer exist
+            names.clear();
+            return Err(e);
+        }
+
+        copied += games.len();
+        on_progress(copied as f64 / ids.len() as f64 * 100.0);
+    }
+    Ok(copied)
+}
+
+/// Creates a database with the games of `file` matching `query`, indexed and
+/// with its counts up to date like an imported one. `existing` tells what to
+/// do if `dest_file` already exists, failing by default. Returns the number
+/// of games copied.
+#[allow(clippy::too_many_arguments)]
+#[tauri::command]
+pub async fn create_database_from_query(
+    file: PathBuf,
+    dest_file: PathBuf,
+    query: GameQuery,
+    title: Option<String>,
+    description: Option<String>,
+    existing: Option<ExistingDatabase>,
+    app: tauri::AppHandle,
+    state: tauri::State<'_, AppState>,
+) -> Result<usize, Error> {
+    let _modification = state.count_cache.modify(dest_file.to_str().unwrap());
+    if is_same_file(&dest_file, &file) {
+        return Err(Error::DatabaseAlreadyExists(
+            dest_file.to_string_lossy().into_owned(),
+        ));
+    }
+    let db_exists = prepare_destination(
+        &state,
+        &dest_file,
+        existing.unwrap_or(ExistingDatabase::Fail),
+    )?;
+
+    let src = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    let dest = &mut get_db_or_create(
+        &state,
+        dest_file.to_str().unwrap(),
+        ConnectionOptions::default(),
+    )?;
+    let mut names = if db_exists {
+        NameCache::load(dest)?
+    } else {
+        init_database(dest, &dest_file, title.as_deref(), description.as_deref())?;
+        NameCache::default()
+    };
+
+    let id = dest_file.to_string_lossy().into_owned();
+    let copied = copy_games(src, dest, &mut names, &query, |progress| {
+        let _ = DatabaseProgress {
+            id: id.clone(),
+            progress,
+        }
+        .emit_all(&app);
+    })?;
+    finish_import(dest, true)?;
+
+    Ok(copied)
+}
+
+#[tauri::command]
+pub async fn delete_db_game(
+    file: PathBuf,
+    game_id: i32,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), Error> {
+    let _modification = state.count_cache.modify(file.to_str().unwrap());
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+
+    let players: Option<(i32, i32)> = db.transaction::<_, Error, _>(|db| {
+        diesel::delete(comments::table.filter(comments::game_id.eq(game_id))).execute(db)?;
+        Ok(diesel::delete(games::table.filter(games::id.eq(game_id)))
+            .returning((games::white_id, games::black_id))
+            .get_result(db)
+            .optional()?)
+    })?;
+    if let Some((white_id, black_id)) = players {
+        recount_player_games(db, &[white_id, black_id])?;
+    }
+
+    Ok(())
+}
+
+/// What happens to the games of a deleted player.
+#[derive(Debug, Clone, Copy, Deserialize, Type, PartialEq, Eq)]
+pub enum PlayerDeletion {
+    /// Refuse to delete a player with games.
+    Forbid,
+    /// Give the games to the `Unknown` player.
+    Anonymize,
+    /// Delete the games too.
+    DeleteGames,
+}
+
+/// Deletes a player, returning the number of games given to the `Unknown`
+/// player or deleted. The games are checked here rather than left to the
+/// foreign keys, which aren't enforced by the connections of the imports.
+fn delete_player_row(
+    db: &mut SqliteConnection,
+    id: i32,
+    mode: PlayerDeletion,
+) -> Result<usize, Error> {
+    // The anonymized games point to it
+    if id == 0 {
+        return Err(Error::UnknownPlayerDeletion);
+    }
+
+    db.transaction::<_, Error, _>(|db| {
+        let of_player = || {
+            games::table
+                .filter(games::white_id.eq(id).or(games::black_id.eq(id)))
+                .select(games::id)
+        };
+        let affected = match mode {
+            PlayerDeletion::Forbid => {
+                if diesel::select(diesel::dsl::exists(of_player())).get_result::<bool>(db)? {
+                    return Err(Error::PlayerHasGames(id));
+                }
+                0
+            }
+            PlayerDeletion::Anonymize => {
+                let affected = diesel::update(games::table.filter(games::white_id.eq(id)))
+                    .set(games::white_id.eq(0))
+                    .execute(db)?
+                    + diesel::update(games::table.filter(games::black_id.eq(id)))
+                        .set(games::black_id.eq(0))
+                        .execute(db)?;
+                recount_player_games(db, &[0])?;
+                affected
+            }
+            PlayerDeletion::DeleteGames => {
+                let opponents: Vec<(i32, i32)> = games::table
+                    .filter(games::id.eq_any(of_player()))
+                    .select((games::white_id, games::black_id))
+                    .load(db)?;
+                diesel::delete(comments::table.filter(comments::game_id.eq_any(of_player())))
+                    .execute(db)?;
+                let affected = diesel::delete(games::table.filter(games::id.eq_any(of_player())))
+                    .execute(db)?;
+                let opponents: Vec<i32> = opponents
+                    .into_iter()
+                    .flat_map(|(white_id, black_id)| [white_id, black_id])
+                    .filter(|player| *player != id)
+                    .collect::<HashSet<_>>()
+                    .into_iter()
+                    .collect();
+                recount_player_games(db, &opponents)?;
+                affected
+            }
+        };
+
+        let deleted = diesel::delete(players::table.find(id)).execute(db)?;
+        if deleted == 0 {
+            return Err(Error::PlayerNotFound(id));
+        }
+
+        update_info_counts(db)?;
+        Ok(affected)
+    })
+}
+
+#[tauri::command]
+#[specta::specta]
+pub async fn delete_player(
+    file: PathBuf,
+    id: i32,
+    mode: PlayerDeletion,
+    state: tauri::State<'_, AppState>,
+) -> Result<usize, Error> {
+    let _modification = state.count_cache.modify(file.to_str().unwrap());
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    delete_player_row(db, id, mode)
+}
+
+/// Titles that some PGNs append to the names, as in `Carlsen, Magnus (GM)`.
+const NAME_TITLE_SUFFIXES: &[&str] = &[
+    "GM", "IM", "FM", "CM", "WGM", "WIM", "WFM", "WCM", "NM", "LM", "BOT",
+];
+
+/// Whether a word of a name is an initial, like the `M.` of `Carlsen M.`.
+fn is_initial(word: &str) -> bool {
+    let letters = word.trim_end_matches('.');
+    !letters.is_empty() && letters.chars().count() <= 2 && letters.chars().all(char::is_uppercase)
+}
+
+/// Canonical form of a player name: `Lastname, Firstname`, with single
+/// spaces and without a title suffix. Names that don't look like a first and
+/// last name, like `Stockfish 16` or single word usernames, are only cleaned
+/// up.
+fn normalize_player_name(name: &str) -> String {
+    let mut name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+    if let Some(rest) = name.strip_suffix(')') {
+        if let Some((rest, suffix)) = rest.rsplit_once('(') {
+            if NAME_TITLE_SUFFIXES
+                .iter()
+                .any(|title| title.eq_ignore_ascii_case(suffix.trim()))
+            {
+                name = rest.trim_end().to_string();
+            }
+        }
+    }
+
+    if let Some((last, first)) = name.split_once(',') {
+        let (last, first) = (last.trim(), first.trim());
+        return if first.is_empty() {
+            last.to_string()
+        } else {
+            format!("{last}, {first}")
+        };
+    }
+
+    let words: Vec<&str> = name.split(' ').collect();
+    if words.len() < 2 || !words.iter().all(|w| w.starts_with(char::is_alphabetic)) {
+        return name;
+    }
+    let initials = words.iter().rev().take_while(|w| is_initial(w)).count();
+    if initials > 0 && initials < words.len() {
+        // Lastname F. M.
+        let (last, first) = words.split_at(words.len() - initials);
+        format!("{}, {}", last.join(" "), first.join(" "))
+    } else {
+        // Firstname Lastname
+        let (first, last) = words.split_at(words.len() - 1);
+        format!("{}, {}", last.join(" "), first.join(" "))
+    }
+}
+
+/// What [`normalize_player_names`] changed.
+#[derive(Debug, Clone, Default, Serialize, Type, PartialEq, Eq)]
+pub struct NameNormalization {
+    /// Players whose name was normalized.
+    pub renamed: usize,
+    /// Players merged into another one with the same normalized name.
+    pub merged: usize,
+    /// Players left apart from another one with the same normalized name,
+    /// as they have played each other or have different FIDE IDs.
+    pub skipped: usize,
+}
+
+fn normalize_stored_player_names(db: &mut SqliteConnection) -> Result<NameNormalization, Error> {
+    let players: Vec<Player> = players::table
+        .filter(players::id.ne(0))
+        .order(players::id)
+        .load(db)?;
+    let mut groups: HashMap<String, Vec<Player>> = HashMap::new();
+    for player in players {
+        if let Some(name) = &player.name {
+            groups
+                .entry(normalize_player_name(name))
+                .or_default()
+                .push(player);
+        }
+    }
+
+    let mut normalization = NameNormalization::default();
+    db.transaction::<_, Error, _>(|db| {
+        for (name, mut players) in groups {
+            // The games keep the spelling of their headers for export
+            for player in &players {
+                if player.name.as_deref() != Some(name.as_str()) {
+                    diesel::update(games::table)
+                        .filter(games::white_id.eq(player.id))
+                        .filter(games::white_original_name.is_null())
+                        .set(games::white_original_name.eq(&player.name))
+                        .execute(db)?;
+                    diesel::update(games::table)
+                        .filter(games::black_id.eq(player.id))
+                        .filter(games::black_original_name.is_null())
+                        .set(games::black_original_name.eq(&player.name))
+                        .execute(db)?;
+                }
+            }
+
+            // The player already named so keeps its row
+            let target = players
+                .iter()
+                .position(|p| p.name.as_deref() == Some(name.as_str()))
+                .unwrap_or(0);
+            let target = players.remove(target);
+
+            // The target takes the FIDE ID of the first source merged if it has none
+            let mut fide_id = target.fide_id;
+            for source in players {
+                if fide_id.is_some() && source.fide_id.is_some() && fide_id != source.fide_id {
+                    normalization.skipped += 1;
+                    continue;
+                }
+                match merge_player_rows(db, target.id, &[source.id]) {
+                    Ok(_) => {
+                        normalization.merged += 1;
+                        fide_id = fide_id.or(source.fide_id);
+                    }
+                    Err(Error::NotDistinctPlayers) => normalization.skipped += 1,
+                    Err(e) => return Err(e),
+                }
+            }
+
+            if target.name.as_deref() != Some(name.as_str()) {
+                diesel::update(players::table.find(target.id))
+                    .set(players::name.eq(&name))
+                    .execute(db)?;
+                normalization.renamed += 1;
+            }
+        }
+        Ok(())
+    })?;
+    Ok(normalization)
+}
+
+/// Applies the normalization of the `normalize_names` import option to the
+/// players of a database, merging those that end up with the same name.
+#[tauri::command]
+#[specta::specta]
+pub async fn normalize_player_names(
+    file: PathBuf,
+    state: tauri::State<'_, AppState>,
+) -> Result<NameNormalization, Error> {
+    let _modification = state.count_cache.modify(file.to_str().unwrap());
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    normalize_stored_player_names(db)
+}
+
+/// Moves the games of the `sources` players to `target` and deletes them,
+/// keeping the highest rating. A target without a title or FIDE ID takes
+/// those of the first source that has one. Returns the number of games moved.
+fn merge_player_rows(
+    db: &mut SqliteConnection,
+    target: i32,
+    sources: &[i32],
+) -> Result<usize, Error> {
+    if sources.contains(&target) {
+        return Err(Error::MergeIntoItself);
+    }
+    let mut all = sources.to_vec();
+    all.push(target);
+
+    db.transaction::<_, Error, _>(|db| {
+        // Games between the players would become games against themselves
+        let between: i64 = games::table
+            .filter(games::white_id.eq_any(&all))
+            .filter(games::black_id.eq_any(&all))
+            .count()
+            .get_result(db)?;
+        if between > 0 {
+            return Err(Error::NotDistinctPlayers);
+        }
+
+        let moved = diesel::update(games::table.filter(games::white_id.eq_any(sources)))
+            .set(games::white_id.eq(target))
+            .execute(db)?
+            + diesel::update(games::table.filter(games::black_id.eq_any(sources)))
+                .set(games::black_id.eq(target))
+                .execute(db)?;
+
+        let mut merged: Vec<Player> = players::table.filter(players::id.eq_any(&all)).load(db)?;
+        merged.sort_by_key(|p| (p.id != target, sources.iter().position(|id| *id == p.id)));
+        let elo = merged.iter().filter_map(|p| p.elo).max();
+        let title = merged.iter().find_map(|p| p.title.clone());
+        let fide_id = merged.iter().find_map(|p| p.fide_id);
+
+        // The sources go first, as FIDE IDs are unique
+        diesel::delete(players::table.filter(players::id.eq_any(sources))).execute(db)?;
+        diesel::update(players::table.find(target))
+            .set((
+                players::elo.eq(elo),
+                players::title.eq(title),
+                players::fide_id.eq(fide_id),
+            ))
+            .execute(db)?;
+        recount_player_games(db, &[target])?;
+
+        let player_count: i64 = players::table.count().get_result(db)?;
+        set_info(db, "PlayerCount", &player_count.to_string())?;
+        Ok(moved)
+    })
+}
+
+/// Merges the `source_ids` players into `target_id`, for players imported
+/// under several spellings of their name. Returns the number of games moved.
+#[tauri::command]
+#[specta::specta]
+pub async fn merge_players(
+    file: PathBuf,
+    target_id: i32,
+    source_ids: Vec<i32>,
+    state: tauri::State<'_, AppState>,
+) -> Result<usize, Error> {
+    let _modification = state.count_cache.modify(file.to_str().unwrap());
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    merge_player_rows(db, target_id, &source_ids)
+}
+
+/// The words of a name, lowercased and sorted, so that `Carlsen, Magnus`,
+/// `Carlsen,Magnus` and `Magnus Carlsen` are the same.
+fn normalized_player_name(name: &str) -> String {
+    let mut words: Vec<String> = name
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+    words.sort();
+    words.join(" ")
+}
+
+/// Groups of players with the same normalized name, ordered by name.
+fn similar_player_groups(db: &mut SqliteConnection) -> Result<Vec<Vec<Player>>, Error> {
+    let players: Vec<Player> = players::table
+        .filter(players::id.ne(0))
+        .order(players::id)
+        .load(db)?;
+
+    let mut groups: HashMap<String, Vec<Player>> = HashMap::new();
+    for player in players {
+        let Some(name) = player.name.as_deref() else {
+            continue;
+        };
+        let key = normalized_player_name(name);
+        if !key.is_empty() {
+            groups.entry(key).or_default().push(player);
+        }
+    }
+
+    let mut groups: Vec<(String, Vec<Player>)> = groups
+        .into_iter()
+        .filter(|(_, players)| players.len() > 1)
+        .collect();
+    groups.sort_by(|(a, _), (b, _)| a.cmp(b));
+    Ok(groups.into_iter().map(|(_, players)| players).collect())
+}
+
+/// Players that are probably the same as others, for `merge_players`.
+#[tauri::command]
+#[specta::specta]
+pub async fn find_similar_players(
+    file: PathBuf,
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<Vec<Player>>, Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    similar_player_groups(db)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TWO_GAMES_PGN: &str = r#"[Event "Test"]
+[White "Alice"]
+[Black "Bob"]
+[Result "1-0"]
+
+1. e4 e5 2. Qh5 Nc6 3. Bc4 Nf6 4. Qxf7# 1-0
+
+[Event "Test"]
+[White "Bob"]
+[Black "Alice"]
+[Result "1/2-1/2"]
+
+1. d4 d5 1/2-1/2
+"#;
+
+    fn test_db() -> SqliteConnection {
+        let mut db = SqliteConnection::establish(":memory:").unwrap();
+        db.batch_execute(CREATE_TABLES_SQL).unwrap();
+        db
+    }
+
+    fn import_with(
+        db: &mut SqliteConnection,
+        bytes: Vec<u8>,
+        extension: Option<&str>,
+        options: ImportOptions,
+    ) -> (usize, Importer) {
+        let mut importer = Importer::new(None, options);
+        let reader = decompress(std::io::Cursor::new(bytes), extension).unwrap();
+        let mut count = 0;
+        import_games(db, reader, &mut importer, &mut count, None, |_| {}).unwrap();
+        (count, importer)
+    }
+
+    fn import_bytes(db: &mut SqliteConnection, bytes: Vec<u8>, extension: Option<&str>) -> usize {
+        import_with(db, bytes, extension, ImportOptions::default()).0
+    }
+
+    fn gzip(text: &str) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(text.as_bytes()).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn import_gzip_pgn() {
+        let db = &mut test_db();
+        // Detected from the magic bytes even without the extension
+        assert_eq!(import_bytes(db, gzip(TWO_GAMES_PGN), None), 2);
+        assert_eq!(games::table.count().get_result::<i64>(db).unwrap(), 2);
+    }
+
+    #[test]
+    fn import_multi_member_gzip_pgn() {
+        let mut compressed = gzip(TWO_GAMES_PGN);
+        compressed.extend(gzip(TWO_GAMES_PGN));
+
+        let db = &mut test_db();
+        assert_eq!(import_bytes(db, compressed, Some("gz")), 4);
+    }
+
+    #[test]
+    fn import_plain_pgn_named_as_compressed() {
+        for extension in ["gz", "bz2", "zst"] {
+            let db = &mut test_db();
+            assert_eq!(
+                import_bytes(db, TWO_GAMES_PGN.into(), Some(extension)),
+                2,
+                "{extension}"
+            );
+        }
+    }
+
+    #[test]
+    fn home_row() {
+        use shakmaty::Board;
+
+        let pawn_home = get_pawn_home(&Board::default());
+        assert_eq!(pawn_home, 0b1111111111111111);
+
+        let pawn_home = get_pawn_home(
+            &Board::from_ascii_board_fen(b"8/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/8").unwrap(),
+        );
+        assert_eq!(pawn_home, 0b1110111111101111);
+
+        let pawn_home = get_pawn_home(&Board::from_ascii_board_fen(b"8/8/8/8/8/8/8/8").unwrap());
+        assert_eq!(pawn_home, 0b0000000000000000);
+    }
+
+    #[test]
+    fn import_filter_min_elo() {
+        let pgn = r#"[White "Alice"]
+[Black "Bob"]
+[WhiteElo "2300"]
+[BlackElo "2250"]
+[Result "1-0"]
+
+1. e4 1-0
+
+[White "Carol"]
+[Black "Dave"]
+[WhiteElo "2400"]
+[BlackElo "1900"]
+[Result "0-1"]
+
+1. d4 0-1
+
+[White "Eve"]
+[Black "Frank"]
+[Result "*"]
+
+1. c4 *
+"#;
+        let options = ImportOptions {
+            filter: ImportFilter {
+                min_black_elo: Some(2200),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let (imported, importer) = import_with(&mut test_db(), pgn.into(), None, options);
+        assert_eq!((imported, importer.filtered), (2, 1));
+
+        let options = ImportOptions {
+            filter: ImportFilter {
+                min_average_elo: Some(2200),
+                require_elo: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let (imported, importer) = import_with(&mut test_db(), pgn.into(), None, options);
+        assert_eq!((imported, importer.filtered), (1, 2));
+    }
+
+    #[test]
+    fn speed_from_time_control() {
+        assert_eq!(Speed::from_time_control("60+0"), Some(Speed::Bullet));
+        assert_eq!(Speed::from_time_control("180+2"), Some(Speed::Blitz));
+        assert_eq!(Speed::from_time_control("600+5"), Some(Speed::Rapid));
+        assert_eq!(Speed::from_time_control("1800+20"), Some(Speed::Classical));
+        assert_eq!(Speed::from_time_control("5400"), Some(Speed::Classical));
+        assert_eq!(
+            Speed::from_time_control("40/7200:3600"),
+            Some(Speed::Classical)
+        );
+        assert_eq!(Speed::from_time_control("-"), Some(Speed::Correspondence));
+        assert_eq!(
+            Speed::from_time_control("1/86400"),
+            Some(Speed::Correspondence)
+        );
+        assert_eq!(Speed::from_time_control("?"), None);
+    }
+
+    #[test]
+    fn import_filter_min_ply_count() {
+        let options = ImportOptions {
+            filter: ImportFilter {
+                min_ply_count: 4,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let (imported, importer) = import_with(&mut test_db(), TWO_GAMES_PGN.into(), None, options);
+        assert_eq!((imported, importer.too_short), (1, 1));
+    }
+
+    #[test]
+    fn import_in_batches() {
+        let options = ImportOptions {
+            batch_size: Some(1),
+            ..Default::default()
+        };
+        let mut db = test_db();
+        let (imported, _) = import_with(&mut db, TWO_GAMES_PGN.into(), None, options);
+        assert_eq!(imported, 2);
+        let stored: i64 = games::table.count().get_result(&mut db).unwrap();
+        assert_eq!(stored, 2);
+    }
+
+    #[test]
+    fn import_reuses_existing_players() {
+        let mut db = test_db();
+        import_bytes(&mut db, TWO_GAMES_PGN.into(), None);
+        let mut importer = Importer::new(None, ImportOptions::default());
+        importer.writer.names = NameCache::load(&mut db).unwrap();
+        // Alice, Bob and the Unknown placeholder
+        assert_eq!(importer.writer.names.players.len(), 3);
+        let mut count = 0;
+        let reader = std::io::Cursor::new(TWO_GAMES_PGN);
+        import_games(&mut db, reader, &mut importer, &mut count, None, |_| {}).unwrap();
+        let players: i64 = players::table.count().get_result(&mut db).unwrap();
+        assert_eq!((count, players), (2, 3));
+    }
+
+    #[test]
+    fn import_counts_skipped_games() {
+        let pgn = format!(
+            "{TWO_GAMES_PGN}
+[FEN \"not a fen\"]
+
+1. e4 *
+
+[White \"Carol\"]
+
+1. e4 e5 2. Ke3 *
+"
+        );
+        let (imported, importer) =
+            import_with(&mut test_db(), pgn.into(), None, ImportOptions::default());
+        let summary = importer.summary(imported);
+        assert_eq!(summary.imported, 2);
+        assert_eq!(summary.invalid_fen, 1);
+        assert_eq!(summary.illegal_move, 1);
+        let skipped: Vec<(u32, SkipReason)> = summary
+            .skipped
+            .iter()
+            .map(|s| (s.index, s.reason))
+            .collect();
+        assert_eq!(
+            skipped,
+            vec![(3, SkipReason::InvalidFen), (4, SkipReason::IllegalMove)]
+        );
+        assert!(summary.skipped[1].game.starts_with("Carol - ?"));
+    }
+
+    #[test]
+    fn import_max_games() {
+        let pgn = format!("1. e4 e5 2. Ke3 *\n\n{TWO_GAMES_PGN}\n{TWO_GAMES_PGN}");
+        let options = ImportOptions {
+            max_games: Some(3),
+            ..Default::default()
+        };
+        let mut db = test_db();
+        let (imported, importer) = import_with(&mut db, pgn.into(), None, options);
+        // The game with an illegal move doesn't count towards the limit
+        assert_eq!(imported, 3);
+        assert_eq!(importer.illegal_move, 1);
+        assert!(importer.summary(imported).truncated);
+
+        let (imported, importer) = import_with(
+            &mut test_db(),
+            TWO_GAMES_PGN.into(),
+            None,
+            ImportOptions::default(),
+        );
+        assert_eq!(imported, 2);
+        assert!(!importer.summary(imported).truncated);
+    }
+
+    #[test]
+    fn import_max_games_counts_inserted_games() {
+        let mut db = test_db();
+        // Only one game per white player can be inserted
+        db.batch_execute("CREATE UNIQUE INDEX white_idx ON Games(WhiteID);")
+            .unwrap();
+        let pgn = format!("{TWO_GAMES_PGN}\n{TWO_GAMES_PGN}\n[White \"Carol\"]\n\n1. e4 *\n");
+        let options = ImportOptions {
+            max_games: Some(3),
+            ..Default::default()
+        };
+        let (imported, importer) = import_with(&mut db, pgn.into(), None, options);
+        assert_eq!((imported, importer.writer.failed), (3, 2));
+        assert!(!importer.summary(imported).truncated);
+    }
+
+    #[test]
+    fn import_rated_only() {
+        let pgn = r#"[Event "Rated Blitz game"]
+
+1. e4 e5 *
+
+[Event "Casual Blitz game"]
+
+1. d4 d5 *
+
+[Event "Tata Steel"]
+
+1. c4 c5 *
+"#;
+        let mut db = test_db();
+        let options = ImportOptions {
+            filter: ImportFilter {
+                rated_only: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let (imported, importer) = import_with(&mut db, pgn.into(), None, options);
+        assert_eq!(imported, 2);
+        assert_eq!(importer.summary(imported).casual_excluded, 1);
+        let rated: Vec<Option<bool>> = games::table
+            .select(games::rated)
+            .order(games::id)
+            .load(&mut db)
+            .unwrap();
+        assert_eq!(rated, vec![Some(true), None]);
+    }
+
+    #[test]
+    fn filter_and_sort_games() {
+        let pgn = r#"[Result "1-0"]
+
+1. e4 e5 2. Nf3 1-0
+
+[Result "0-1"]
+
+1. d4 d5 0-1
+
+[Result "1-0"]
+
+1. c4 1-0
+"#;
+        let mut db = test_db();
+        let (imported, _) = import_with(&mut db, pgn.into(), None, ImportOptions::default());
+        assert_eq!(imported, 3);
+
+        let query = GameQuery {
+            outcome: Some(OutcomeFilter::One(GameResult::WhiteWins)),
+            ..Default::default()
+        };
+        let options = QueryOptions {
+            sort: GameSort::PlyCount,
+            direction: SortDirection::Asc,
+            ..Default::default()
+        };
+        let sql_query = games::table
+            .inner_join(events::table.on(games::event_id.eq(events::id)))
+            .inner_join(sites::table.on(games::site_id.eq(sites::id)))
+            .into_boxed();
+        let ply_counts: Vec<Option<i32>> = sort_games!(filter_games!(sql_query, query), options)
+            .select(games::ply_count)
+            .load(&mut db)
+            .unwrap();
+        assert_eq!(ply_counts, vec![Some(1), Some(3)]);
+    }
+
+    #[test]
+    fn filter_games_by_date_range() {
+        let pgn = r#"[Date "2019.12.31"]
+
+1. e4 *
+
+[Date "2020.05.??"]
+
+1. e4 *
+
+[Date "2023.12.31"]
+
+1. e4 *
+
+[Date "2024.01.01"]
+
+1. e4 *
+
+[Date "????.??.??"]
+
+1. e4 *
+"#;
+        let mut db = test_db();
+        import_bytes(&mut db, pgn.into(), None);
+
+        let mut query = GameQuery {
+            start_date: Some("2020".to_string()),
+            end_date: Some("2023".to_string()),
+            ..Default::default()
+        };
+        let dates = |db: &mut SqliteConnection, query: &GameQuery| -> Vec<Option<String>> {
+            filter_games!(games::table.into_boxed(), query)
+                .select(games::date)
+                .order(games::id)
+                .load(db)
+                .unwrap()
+        };
+        assert_eq!(
+            dates(&mut db, &query),
+            vec![
+                Some("2020-05-00".to_string()),
+                Some("2023-12-31".to_string())
+            ]
+        );
 
-    type GameInfo = (
-        i32,
-        i32,
-        Option<String>,
-        Option<String>,
-        Vec<u8>,
-        Option<i32>,
-        Option<i32>,
-    );
-    let info: Vec<GameInfo> = sql_query.load(db)?;
+        query.include_unknown_dates = true;
+        assert_eq!(dates(&mut db, &query).len(), 3);
+    }
+
+    #[test]
+    fn eco_filter_codes() {
+        assert_eq!(EcoFilter::One("B33".into()).codes(), vec!["B33"]);
+        let codes = EcoFilter::One("b3".into()).codes();
+        assert_eq!(codes.len(), 10);
+        assert_eq!(codes.first().map(String::as_str), Some("B30"));
+        assert_eq!(codes.last().map(String::as_str), Some("B39"));
+        assert_eq!(EcoFilter::One("B".into()).codes().len(), 100);
+        assert_eq!(
+            EcoFilter::Many(vec!["C4".into(), "C42".into(), "".into()])
+                .codes()
+                .len(),
+            10
+        );
+        assert!(EcoFilter::One("Z1".into()).codes().is_empty());
+    }
+
+    #[test]
+    fn filter_games_by_eco() {
+        let pgn = r#"[ECO "B33"]
+
+1. e4 c5 *
+
+[ECO "B90"]
+
+1. e4 c5 *
+
+[ECO "C42"]
+
+1. e4 e5 *
+
+1. d4 *
+"#;
+        let mut db = test_db();
+        import_bytes(&mut db, pgn.into(), None);
+
+        let count = |db: &mut SqliteConnection, eco: EcoFilter| -> i64 {
+            let query = GameQuery {
+                eco: Some(eco),
+                ..Default::default()
+            };
+            filter_games!(games::table.into_boxed(), query)
+                .count()
+                .get_result(db)
+                .unwrap()
+        };
+        assert_eq!(count(&mut db, EcoFilter::One("B".into())), 2);
+        assert_eq!(count(&mut db, EcoFilter::One("B3".into())), 1);
+        assert_eq!(
+            count(&mut db, EcoFilter::Many(vec!["B33".into(), "C".into()])),
+            2
+        );
+    }
+
+    #[test]
+    fn filter_games_by_ply_count() {
+        let pgn = "1. e4 e5 2. Nf3 *\n\n[Event \"Empty\"]\n\n*\n\n1. d4 d5 *\n";
+        let mut db = test_db();
+        import_bytes(&mut db, pgn.into(), None);
+
+        let ply_counts = |db: &mut SqliteConnection, range: (i32, i32)| -> Vec<Option<i32>> {
+            let query = GameQuery {
+                ply_count_range: Some(range),
+                ..Default::default()
+            };
+            filter_games!(games::table.into_boxed(), query)
+                .select(games::ply_count)
+                .order(games::id)
+                .load(db)
+                .unwrap()
+        };
+        assert_eq!(ply_counts(&mut db, (1, i32::MAX)), vec![Some(3), Some(2)]);
+        assert_eq!(ply_counts(&mut db, (0, 2)), vec![Some(0), Some(2)]);
+    }
+
+    #[test]
+    fn filter_games_by_player_id() {
+        let pgn = r#"[White "Ivanov, A."]
+[Black "Carlsen, Magnus"]
+
+*
+
+[White "Carlsen, Magnus"]
+[Black "Ivanov, A."]
+
+*
+"#;
+        let mut db = test_db();
+        import_bytes(&mut db, pgn.into(), None);
+        let ivanov: i32 = players::table
+            .filter(players::name.eq("Ivanov, A."))
+            .select(players::id)
+            .first(&mut db)
+            .unwrap();
+
+        let white_ids = |db: &mut SqliteConnection, query: GameQuery| -> Vec<i32> {
+            filter_games!(games::table.into_boxed(), query)
+                .select(games::white_id)
+                .order(games::id)
+                .load(db)
+                .unwrap()
+        };
+        let query: GameQuery =
+            serde_json::from_value(serde_json::json!({ "player1_id": ivanov })).unwrap();
+        assert_eq!(white_ids(&mut db, query.clone()), vec![ivanov]);
+
+        let query = GameQuery {
+            sides: Some(Sides::Any),
+            ..query
+        };
+        assert_eq!(white_ids(&mut db, query).len(), 2);
+    }
+
+    #[test]
+    fn move_filter_matches_mainline_moves() {
+        let mut db = test_db();
+        let pgn = "1. e4 e5 2. Nf3 Nc6 3. Bc4 (3. Bb5 a6) 3... Nf6 4. O-O Be7 5. Bxf7+ *";
+        import_bytes(&mut db, pgn.into(), None);
+        let moves: Vec<u8> = games::table.select(games::moves).first(&mut db).unwrap();
+
+        let matches = |san: &str, side: PlayerSide, ply_range: Option<(u32, u32)>| {
+            let filter = MoveFilter {
+                san: san.to_string(),
+                side,
+                ply_range,
+            };
+            filter.matches(&filter.parse_san().unwrap(), &moves, None)
+        };
+        assert!(matches("Bxf7", PlayerSide::Both, None));
+        assert!(matches("Bxf7+", PlayerSide::White, None));
+        assert!(!matches("Bxf7", PlayerSide::Black, None));
+        assert!(!matches("Bxf7", PlayerSide::Both, Some((1, 8))));
+        assert!(matches("0-0", PlayerSide::White, Some((7, 7))));
+        assert!(!matches("O-O-O", PlayerSide::Both, None));
+        assert!(matches("Nc6", PlayerSide::Black, None));
+        // Only the mainline is searched
+        assert!(!matches("Bb5", PlayerSide::Both, None));
+    }
+
+    #[test]
+    fn cursor_pages_follow_the_sort() {
+        let pgn = [
+            "2020.01.01",
+            "2021.05.05",
+            "????.??.??",
+            "2020.01.01",
+            "2019.03.03",
+            "????.??.??",
+            "2020.01.01",
+        ]
+        .map(|date| format!("[Date \"{date}\"]\n\n1. e4 *\n"))
+        .join("\n");
+        let mut db = test_db();
+        import_bytes(&mut db, pgn.into(), None);
+        let joined_games = || {
+            games::table
+                .inner_join(events::table.on(games::event_id.eq(events::id)))
+                .inner_join(sites::table.on(games::site_id.eq(sites::id)))
+                .select(games::all_columns)
+                .into_boxed()
+        };
+
+        for direction in [SortDirection::Asc, SortDirection::Desc] {
+            let options = QueryOptions {
+                sort: GameSort::Date,
+                direction: direction.clone(),
+                ..Default::default()
+            };
+            let all: Vec<i32> = sort_games!(joined_games(), options)
+                .select(games::id)
+                .load(&mut db)
+                .unwrap();
+
+            let mut paged = vec![];
+            let mut after: Option<Cursor> = None;
+            loop {
+                let mut sql_query = sort_games!(joined_games(), options).limit(2);
+                if let Some(cursor) = &after {
+                    sql_query = filter_after_cursor!(sql_query, options, cursor).unwrap();
+                }
+                let page: Vec<Game> = sql_query.load(&mut db).unwrap();
+                let Some(last) = page.last() else {
+                    break;
+                };
+                after = GameSort::Date.cursor(last);
+                paged.extend(page.iter().map(|game| game.id));
+            }
+            assert_eq!(paged, all, "{direction:?}");
+        }
+    }
+
+    #[test]
+    fn games_without_moves() {
+        let mut db = test_db();
+        import_bytes(&mut db, TWO_GAMES_PGN.into(), None);
+
+        let games: Vec<Game> = games::table
+            .select(game_columns_without_moves!())
+            .order(games::id)
+            .load(&mut db)
+            .unwrap();
+        assert_eq!(games.len(), 2);
+        assert!(games
+            .iter()
+            .all(|game| game.moves.is_empty() && game.clocks.is_none()));
+        assert!(games.iter().all(|game| game.ply_count > Some(0)));
+    }
+
+    #[test]
+    fn load_game_by_id() {
+        let pgn = r#"[Event "Casual"]
+[White "Alice"]
+[Black "Bob"]
+[Result "1-0"]
+
+1. e4 { [%clk 0:03:00] } e5 { [%clk 0:03:00] } 2. Qh5 { Threat } Nc6 3. Bc4 Nf6 4. Qxf7# 1-0
+"#;
+        let mut db = test_db();
+        let options = ImportOptions {
+            keep_annotations: true,
+            keep_clocks_and_evals: true,
+            ..Default::default()
+        };
+        import_with(&mut db, pgn.into(), None, options);
+        let id: i32 = games::table.select(games::id).first(&mut db).unwrap();
+
+        let game = load_game(&mut db, id).unwrap();
+        assert_eq!(game.event, "Casual");
+        assert_eq!((game.white.as_str(), game.black.as_str()), ("Alice", "Bob"));
+        assert_eq!(game.result, Some(GameResult::WhiteWins));
+        assert_eq!(game.moves, "e4 e5 Qh5 Nc6 Bc4 Nf6 Qxf7#");
+        assert!(game.clocks.is_some());
+        assert!(!game.comments.is_empty());
+
+        assert!(matches!(
+            load_game(&mut db, id + 1),
+            Err(Error::GameNotFound(missing)) if missing == id + 1
+        ));
+    }
+
+    #[test]
+    fn streamed_games_follow_the_sort() {
+        let pgn = ["1. e4 e5 2. Nf3 *", "1. d4 *", "1. c4 c5 *", "1. e4 c5 *"]
+            .map(|moves| format!("[Event \"Test\"]\n\n{moves}\n"))
+            .join("\n");
+        let mut db = test_db();
+        import_bytes(&mut db, pgn.into(), None);
+
+        let query = GameQuery {
+            options: Some(QueryOptions {
+                sort: GameSort::PlyCount,
+                direction: SortDirection::Desc,
+                page_size: Some(1),
+                ..Default::default()
+            }),
+            contains_move: Some(MoveFilter {
+                san: "e4".to_string(),
+                side: PlayerSide::White,
+                ply_range: None,
+            }),
+            ..Default::default()
+        };
+        // The page is ignored
+        let ids = sorted_game_ids(&mut db, &query).unwrap();
+        assert_eq!(ids.len(), 2);
+
+        let games = load_games_by_id(&mut db, &ids, false).unwrap();
+        let plies: Vec<Option<i32>> = games.iter().map(|game| game.ply_count).collect();
+        assert_eq!(plies, vec![Some(3), Some(2)]);
+        assert!(games.iter().all(|game| game.moves.is_empty()));
+
+        let reversed: Vec<i32> = ids.iter().rev().copied().collect();
+        let games = load_games_by_id(&mut db, &reversed, true).unwrap();
+        assert_eq!(games[0].moves, "e4 c5");
+        assert_eq!(games[1].moves, "e4 e5 Nf3");
+    }
+
+    #[test]
+    fn sampled_games_are_distinct_and_reproducible() {
+        let pgn = (1..=10)
+            .map(|round| format!("[Round \"{round}\"]\n\n1. e4 *\n"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let mut db = test_db();
+        import_bytes(&mut db, pgn.into(), None);
+        let query = GameQuery::default();
+
+        let sample = |db: &mut SqliteConnection, n, seed| {
+            sample_game_ids(db, &query, n, &mut StdRng::seed_from_u64(seed)).unwrap()
+        };
+        let ids = sample(&mut db, 4, 7);
+        assert_eq!(ids.len(), 4);
+        assert_eq!(ids.iter().collect::<HashSet<_>>().len(), 4);
+        assert_eq!(sample(&mut db, 4, 7), ids);
+        assert!((0..10).any(|seed| sample(&mut db, 4, seed) != ids));
+
+        let mut all = sample(&mut db, 20, 7);
+        all.sort();
+        let expected: Vec<i32> = games::table
+            .select(games::id)
+            .order(games::id)
+            .load(&mut db)
+            .unwrap();
+        assert_eq!(all, expected);
+    }
+
+    #[test]
+    fn filter_games_by_ratings() {
+        let pgn = [
+            (2600, Some(2450)),
+            (2550, Some(2510)),
+            (2000, Some(2201)),
+            (2300, None),
+        ]
+        .map(|(white, black)| {
+            let black = black.map_or("?".to_string(), |elo: i32| elo.to_string());
+            format!("[WhiteElo \"{white}\"]\n[BlackElo \"{black}\"]\n\n*\n")
+        })
+        .join("\n");
+        let mut db = test_db();
+        import_bytes(&mut db, pgn.into(), None);
+
+        let white_elos = |db: &mut SqliteConnection, query: GameQuery| -> Vec<Option<i32>> {
+            filter_games!(games::table.into_boxed(), query)
+                .select(games::white_elo)
+                .order(games::id)
+                .load(db)
+                .unwrap()
+        };
+        let query = GameQuery {
+            min_both_elo: Some(2450),
+            ..Default::default()
+        };
+        assert_eq!(white_elos(&mut db, query), vec![Some(2600), Some(2550)]);
+
+        // Averages of 2525, 2530 and 2100
+        let query = GameQuery {
+            average_elo_range: Some((2100, 2525)),
+            ..Default::default()
+        };
+        assert_eq!(white_elos(&mut db, query), vec![Some(2600), Some(2000)]);
+    }
+
+    #[test]
+    fn filter_games_by_opening_name() {
+        let pgn = ["B90", "E60", "C20", "A05"]
+            .map(|eco| format!("[ECO \"{eco}\"]\n\n*\n"))
+            .join("\n");
+        let mut db = test_db();
+        import_bytes(&mut db, pgn.into(), None);
+
+        let codes = |db: &mut SqliteConnection, opening: &str| -> Vec<Option<String>> {
+            let query = GameQuery {
+                opening: Some(opening.to_string()),
+                ..Default::default()
+            };
+            filter_games!(games::table.into_boxed(), query)
+                .select(games::eco)
+                .order(games::id)
+                .load(db)
+                .unwrap()
+        };
+        assert_eq!(
+            codes(&mut db, "sicilian najdorf"),
+            vec![Some("B90".to_string())]
+        );
+        assert_eq!(
+            codes(&mut db, "King's Indian"),
+            vec![Some("E60".to_string()), Some("A05".to_string())]
+        );
+        assert!(codes(&mut db, "no such opening").is_empty());
+    }
+
+    #[test]
+    fn filter_games_by_outcomes() {
+        let pgn = ["1-0", "0-1", "1/2-1/2", "*"]
+            .map(|result| format!("[Result \"{result}\"]\n\n1. e4 {result}\n"))
+            .join("\n");
+        let mut db = test_db();
+        import_bytes(&mut db, pgn.into(), None);
+
+        let results = |db: &mut SqliteConnection, query: GameQuery| -> Vec<Option<i32>> {
+            filter_games!(games::table.into_boxed(), query)
+                .select(games::result)
+                .order(games::id)
+                .load(db)
+                .unwrap()
+        };
+        let query: GameQuery =
+            serde_json::from_value(serde_json::json!({ "outcome": ["1-0", "*"] })).unwrap();
+        assert_eq!(results(&mut db, query), vec![Some(1), Some(0)]);
+        let query: GameQuery =
+            serde_json::from_value(serde_json::json!({ "outcome": "1/2-1/2" })).unwrap();
+        assert_eq!(results(&mut db, query), vec![Some(3)]);
+
+        let query = GameQuery {
+            decisive_only: true,
+            ..Default::default()
+        };
+        assert_eq!(results(&mut db, query), vec![Some(1), Some(2)]);
+    }
+
+    #[test]
+    fn unknown_results_are_filtered_alike() {
+        let pgn = [
+            "[Result \"1-0\"]",
+            "[Result \"*\"]",
+            "[Result \"\"]",
+            "[White \"A\"]",
+        ]
+        .map(|headers| format!("{headers}\n\n1. e4\n"))
+        .join("\n");
+        let mut db = test_db();
+        import_bytes(&mut db, pgn.into(), None);
+        // An empty result is stored like `*`, a missing one as NULL
+        let stored: Vec<Option<i32>> = games::table
+            .select(games::result)
+            .order(games::id)
+            .load(&mut db)
+            .unwrap();
+        assert_eq!(stored, vec![Some(1), Some(0), Some(0), None]);
+
+        let count = |db: &mut SqliteConnection, query: GameQuery| count_games(db, &query).unwrap();
+        let query = GameQuery {
+            include_unknown_result: Some(false),
+            ..Default::default()
+        };
+        assert_eq!(count(&mut db, query), 1);
+        let query = GameQuery {
+            outcome: Some(OutcomeFilter::One(GameResult::Unknown)),
+            ..Default::default()
+        };
+        assert_eq!(count(&mut db, query), 3);
+        assert_eq!(count(&mut db, GameQuery::default()), 4);
+
+        assert_eq!(GameResult::from_column(None), GameResult::Unknown);
+        assert_eq!(GameResult::from_column(Some(3)), GameResult::Draw);
+    }
+
+    #[test]
+    fn excluded_players_win_over_included_ones() {
+        let pgn = [("A", "B"), ("B", "C"), ("C", "A"), ("C", "D")]
+            .map(|(white, black)| format!("[White \"{white}\"]\n[Black \"{black}\"]\n\n*\n"))
+            .join("\n");
+        let mut db = test_db();
+        import_bytes(&mut db, pgn.into(), None);
+        let id_of = |db: &mut SqliteConnection, name: &str| -> i32 {
+            players::table
+                .filter(players::name.eq(name))
+                .select(players::id)
+                .first(db)
+                .unwrap()
+        };
+        let a = id_of(&mut db, "A");
+
+        let count = |db: &mut SqliteConnection, query: GameQuery| count_games(db, &query).unwrap();
+        let query = GameQuery {
+            exclude_players: Some(vec![PlayerRef::Id(a), PlayerRef::Name("d".to_string())]),
+            ..Default::default()
+        };
+        assert_eq!(count(&mut db, query), 1);
+
+        let query = GameQuery {
+            player1_name: Some("C".to_string()),
+            sides: Some(Sides::Any),
+            exclude_players: Some(vec![PlayerRef::Name("A".to_string())]),
+            ..Default::default()
+        };
+        assert_eq!(count(&mut db, query), 2);
+
+        let query = GameQuery {
+            player1: Some(a),
+            exclude_players: Some(vec![PlayerRef::Id(a)]),
+            ..Default::default()
+        };
+        assert_eq!(count(&mut db, query), 0);
+    }
+
+    #[test]
+    fn filter_games_by_player_name() {
+        let pgn = r#"[White "Carlsen, Magnus"]
+[Black "Ivanov, A."]
+
+*
+
+[White "Ivanov, A."]
+[Black "Caruana, Fabiano"]
+
+*
+
+[White "Ivanov_A"]
+[Black "Carlsen, Magnus"]
+
+*
+"#;
+        let mut db = test_db();
+        import_bytes(&mut db, pgn.into(), None);
+
+        let count = |db: &mut SqliteConnection, name: &str, mode: NameMatch, sides: Sides| {
+            let query = GameQuery {
+                player1_name: Some(name.to_string()),
+                match_mode: mode,
+                sides: Some(sides),
+                ..Default::default()
+            };
+            filter_games!(games::table.into_boxed(), query)
+                .count()
+                .get_result::<i64>(db)
+                .unwrap()
+        };
+        assert_eq!(
+            count(
+                &mut db,
+                "carlsen, magnus",
+                NameMatch::Exact,
+                Sides::WhiteBlack
+            ),
+            1
+        );
+        assert_eq!(count(&mut db, "carlsen", NameMatch::Exact, Sides::Any), 0);
+        assert_eq!(
+            count(&mut db, "carlsen", NameMatch::StartsWith, Sides::Any),
+            2
+        );
+        assert_eq!(
+            count(&mut db, "Car", NameMatch::StartsWith, Sides::BlackWhite),
+            2
+        );
+        assert_eq!(count(&mut db, "A.", NameMatch::Contains, Sides::Any), 2);
+        // Wildcards in the name are matched literally
+        assert_eq!(
+            count(&mut db, "ivanov_", NameMatch::StartsWith, Sides::Any),
+            1
+        );
+        assert_eq!(
+            count(&mut db, "carlsen,*m*", NameMatch::Pattern, Sides::Any),
+            2
+        );
+        assert_eq!(
+            count(&mut db, "ivanov*a*", NameMatch::Pattern, Sides::Any),
+            3
+        );
+        assert_eq!(
+            count(&mut db, "ivanov?a", NameMatch::Pattern, Sides::Any),
+            1
+        );
+        assert_eq!(
+            count(&mut db, "car*", NameMatch::Pattern, Sides::WhiteBlack),
+            1
+        );
+
+        let plan: Vec<String> = sql_query(
+            "EXPLAIN QUERY PLAN SELECT ID FROM Players WHERE Name LIKE 'carl%' ESCAPE '\\'",
+        )
+        .load::<QueryPlanRow>(&mut db)
+        .unwrap()
+        .into_iter()
+        .map(|row| row.detail)
+        .collect();
+        assert!(plan
+            .iter()
+            .any(|detail| detail.contains("players_name_nocase_idx")));
+    }
+
+    #[derive(QueryableByName)]
+    struct QueryPlanRow {
+        #[diesel(sql_type = Text)]
+        detail: String,
+    }
+
+    #[test]
+    fn merge_similar_players() {
+        let pgn = [
+            ("Carlsen, Magnus", "2830", "Giri, Anish", "2760"),
+            ("Giri, Anish", "2750", "Carlsen,Magnus", "2860"),
+            ("Magnus Carlsen", "2840", "Caruana, Fabiano", "2800"),
+        ]
+        .map(|(white, white_elo, black, black_elo)| {
+            format!(
+                "[White \"{white}\"]\n[WhiteElo \"{white_elo}\"]\n[Black \"{black}\"]\n[BlackElo \"{black_elo}\"]\n\n*\n"
+            )
+        })
+        .join("\n");
+        let mut db = test_db();
+        import_bytes(&mut db, pgn.into(), None);
+
+        let groups = similar_player_groups(&mut db).unwrap();
+        assert_eq!(groups.len(), 1);
+        let ids: Vec<i32> = groups[0].iter().map(|p| p.id).collect();
+        assert_eq!(ids.len(), 3);
+        diesel::update(players::table.find(ids[2]))
+            .set((players::title.eq("GM"), players::fide_id.eq(1503014)))
+            .execute(&mut db)
+            .unwrap();
+
+        assert!(matches!(
+            merge_player_rows(&mut db, ids[0], &ids),
+            Err(Error::MergeIntoItself)
+        ));
+        assert_eq!(merge_player_rows(&mut db, ids[0], &ids[1..]).unwrap(), 2);
+
+        let carlsen: Vec<Player> = players::table
+            .filter(players::name.like("%Carlsen%"))
+            .load(&mut db)
+            .unwrap();
+        assert_eq!(carlsen.len(), 1);
+        assert_eq!(carlsen[0].id, ids[0]);
+        assert_eq!(carlsen[0].elo, Some(2860));
+        assert_eq!(carlsen[0].title.as_deref(), Some("GM"));
+        assert_eq!(carlsen[0].fide_id, Some(1503014));
+        let games = games::table
+            .filter(games::white_id.eq(ids[0]).or(games::black_id.eq(ids[0])))
+            .count()
+            .get_result::<i64>(&mut db)
+            .unwrap();
+        assert_eq!(games, 3);
+        assert!(similar_player_groups(&mut db).unwrap().is_empty());
+
+        let giri: i32 = players::table
+            .filter(players::name.eq("Giri, Anish"))
+            .select(players::id)
+            .first(&mut db)
+            .unwrap();
+        assert!(matches!(
+            merge_player_rows(&mut db, ids[0], &[giri]),
+            Err(Error::NotDistinctPlayers)
+        ));
+    }
+
+    #[test]
+    fn renamed_players_keep_their_games() {
+        let mut db = test_db();
+        import_bytes(&mut db, TWO_GAMES_PGN.into(), None);
+        let id = |db: &mut SqliteConnection, name: &str| -> i32 {
+            players::table
+                .filter(players::name.eq(name))
+                .select(players::id)
+                .first(db)
+                .unwrap()
+        };
+        let alice = id(&mut db, "Alice");
+        let bob = id(&mut db, "Bob");
+
+        assert!(matches!(
+            update_player_row(&mut db, alice, Some("Bob".into()), None),
+            Err(Error::PlayerNameTaken(taken)) if taken == bob
+        ));
+        assert!(matches!(
+            update_player_row(&mut db, -1, Some("Carol".into()), None),
+            Err(Error::PlayerNotFound(-1))
+        ));
+
+        let player =
+            update_player_row(&mut db, alice, Some(" Alicia ".into()), Some(2500)).unwrap();
+        assert_eq!(player.name.as_deref(), Some("Alicia"));
+        assert_eq!(player.elo, Some(2500));
+        // Keeping its own name isn't a conflict
+        update_player_row(&mut db, alice, Some("Alicia".into()), None).unwrap();
+
+        let games = games::table
+            .filter(games::white_id.eq_any(players_named("Alicia", NameMatch::Exact)))
+            .count()
+            .get_result::<i64>(&mut db)
+            .unwrap();
+        assert_eq!(games, 1);
+    }
+
+    #[test]
+    fn player_game_info_by_color() {
+        let pgn = [
+            ("Alice", "Bob", "0-1", "2021.01.01", "600"),
+            ("Bob", "Alice", "0-1", "2021.06.01", "600"),
+            ("Carol", "Alice", "1/2-1/2", "2022.01.01", "600"),
+            ("Alice", "Carol", "1-0", "2022.06.01", "60"),
+            ("Alice", "Bob", "*", "2023.01.01", "600"),
+        ]
+        .map(|(white, black, result, date, time_control)| {
+            format!(
+                "[White \"{white}\"]\n[Black \"{black}\"]\n[Date \"{date}\"]\n[TimeControl \"{time_control}\"]\n[Result \"{result}\"]\n\n{result}\n"
+            )
+        })
+        .join("\n");
+        let mut db = test_db();
+        import_bytes(&mut db, pgn.into(), None);
+        let alice: i32 = players::table
+            .filter(players::name.eq("Alice"))
+            .select(players::id)
+            .first(&mut db)
+            .unwrap();
+
+        let info = player_game_info(&mut db, alice, &GameQuery::default(), |_| {}).unwrap();
+        assert_eq!(
+            info.as_white,
+            Results {
+                won: 1,
+                lost: 1,
+                draw: 0
+            }
+        );
+        assert_eq!(
+            info.as_black,
+            Results {
+                won: 1,
+                lost: 0,
+                draw: 1
+            }
+        );
+        assert_eq!((info.won, info.lost, info.draw, info.unknown), (2, 1, 1, 1));
+
+        let query = GameQuery {
+            start_date: Some("2021.03.01".into()),
+            end_date: Some("2022.12.31".into()),
+            speed: Some(vec![Speed::Rapid]),
+            ..Default::default()
+        };
+        let info = player_game_info(&mut db, alice, &query, |_| {}).unwrap();
+        assert_eq!(info.as_white, Results::default());
+        assert_eq!((info.won, info.lost, info.draw, info.unknown), (1, 0, 1, 0));
+    }
+
+    #[test]
+    fn events_with_their_games() {
+        let pgn = [
+            ("Candidates 2024", "Toronto", "2024.04.04"),
+            ("Candidates 2024", "Toronto", "2024.04.22"),
+            ("Candidates 2022", "Madrid", "2022.06.17"),
+            ("Candidates 2022", "Madrid", "????.??.??"),
+            ("Candidates 2022", "Madrid", "2022.07.05"),
+            ("Tata Steel 2024", "Wijk aan Zee", "2024.01.13"),
+        ]
+        .map(|(event, site, date)| {
+            format!(
+                "[Event \"{event}\"]\n[Site \"{site}\"]\n[Date \"{date}\"]\n[Result \"*\"]\n\n*\n"
+            )
+        })
+        .join("\n");
+        let mut db = test_db();
+        import_bytes(&mut db, pgn.into(), None);
+        let query = |sort, direction, name: Option<&str>| EventQuery {
+            options: QueryOptions {
+                sort,
+                direction,
+                page: Some(1),
+                page_size: Some(2),
+                ..Default::default()
+            },
+            name: name.map(String::from),
+        };
+        let summary = |s: &EventSummary| {
+            (
+                s.name.clone().unwrap(),
+                s.game_count,
+                s.first_date.clone(),
+                s.last_date.clone(),
+            )
+        };
+
+        let events = event_summaries(
+            &mut db,
+            EventTable::Events,
+            &query(EventSort::GameCount, SortDirection::Desc, None),
+        )
+        .unwrap();
+        assert_eq!(events.count, Some(3));
+        assert_eq!(
+            events.data.iter().map(summary).collect::<Vec<_>>(),
+            vec![
+                (
+                    "Candidates 2022".to_string(),
+                    3,
+                    Some("2022.06.17".to_string()),
+                    Some("2022.07.05".to_string())
+                ),
+                (
+                    "Candidates 2024".to_string(),
+                    2,
+                    Some("2024.04.04".to_string()),
+                    Some("2024.04.22".to_string())
+                ),
+            ]
+        );
+
+        let events = event_summaries(
+            &mut db,
+            EventTable::Events,
+            &query(EventSort::Name, SortDirection::Asc, Some("2024")),
+        )
+        .unwrap();
+        assert_eq!(events.count, Some(2));
+        let names: Vec<_> = events.data.iter().filter_map(|e| e.name.clone()).collect();
+        assert_eq!(names, vec!["Candidates 2024", "Tata Steel 2024"]);
+
+        let sites = event_summaries(
+            &mut db,
+            EventTable::Sites,
+            &query(EventSort::GameCount, SortDirection::Asc, None),
+        )
+        .unwrap();
+        let sites: Vec<_> = sites
+            .data
+            .iter()
+            .map(|s| (s.name.clone().unwrap(), s.game_count))
+            .collect();
+        assert_eq!(
+            sites,
+            vec![("Wijk aan Zee".to_string(), 1), ("Toronto".to_string(), 2)]
+        );
+    }
+
+    #[test]
+    fn rename_and_merge_events() {
+        let pgn = [
+            ("Tata Steel Masters", "Wijk aan Zee"),
+            ("Tata Steel Masters 2023", "Wijk aan Zee NED"),
+            ("86th Tata Steel", "Wijk aan Zee"),
+            ("Casual", "Wijk aan Zee NED"),
+        ]
+        .map(|(event, site)| {
+            format!("[Event \"{event}\"]\n[Site \"{site}\"]\n[Result \"*\"]\n\n*\n")
+        })
+        .join("\n");
+        let mut db = test_db();
+        import_bytes(&mut db, pgn.into(), None);
+        let id = |db: &mut SqliteConnection, kind: EventTable, name: &str| -> i32 {
+            sql_query(format!(
+                "SELECT ID AS id FROM {} WHERE Name = ?;",
+                kind.table()
+            ))
+            .bind::<Text, _>(name)
+            .get_result::<RowId>(db)
+            .unwrap()
+            .id
+        };
+        let masters = id(&mut db, EventTable::Events, "Tata Steel Masters");
+        let masters_2023 = id(&mut db, EventTable::Events, "Tata Steel Masters 2023");
+        let tata_86 = id(&mut db, EventTable::Events, "86th Tata Steel");
+        let casual = id(&mut db, EventTable::Events, "Casual");
+
+        assert!(matches!(
+            rename_event_row(&mut db, EventTable::Events, masters, "Casual"),
+            Err(Error::EventNameTaken(taken)) if taken == casual
+        ));
+        rename_event_row(
+            &mut db,
+            EventTable::Events,
+            masters,
+            " Tata Steel Masters 2023 ",
+        )
+        .unwrap_err();
+        rename_event_row(
+            &mut db,
+            EventTable::Events,
+            masters,
+            "Tata Steel Masters 2022",
+        )
+        .unwrap();
+
+        assert!(matches!(
+            merge_event_rows(&mut db, EventTable::Events, masters, &[masters]),
+            Err(Error::MergeIntoItself)
+        ));
+        assert_eq!(
+            merge_event_rows(
+                &mut db,
+                EventTable::Events,
+                masters,
+                &[masters_2023, tata_86]
+            )
+            .unwrap(),
+            2
+        );
+        let event_games = |db: &mut SqliteConnection, event: i32| -> i64 {
+            games::table
+                .filter(games::event_id.eq(event))
+                .count()
+                .get_result(db)
+                .unwrap()
+        };
+        assert_eq!(event_games(&mut db, masters), 3);
+        let events: i64 = events::table.count().get_result(&mut db).unwrap();
+        assert_eq!(events, 3);
+        let info: String = info::table
+            .filter(info::name.eq("EventCount"))
+            .select(info::value)
+            .first::<Option<String>>(&mut db)
+            .unwrap()
+            .unwrap();
+        assert_eq!(info, "3");
+
+        // Untags the games
+        assert_eq!(
+            merge_event_rows(&mut db, EventTable::Events, 0, &[casual]).unwrap(),
+            1
+        );
+        assert_eq!(event_games(&mut db, 0), 1);
+
+        let site = id(&mut db, EventTable::Sites, "Wijk aan Zee");
+        let other = id(&mut db, EventTable::Sites, "Wijk aan Zee NED");
+        assert_eq!(
+            merge_event_rows(&mut db, EventTable::Sites, site, &[other]).unwrap(),
+            2
+        );
+        assert!(matches!(
+            merge_event_rows(&mut db, EventTable::Sites, site, &[other]),
+            Err(Error::SiteNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn split_event_editions_by_date_gaps() {
+        let pgn = [
+            ("Wijk aan Zee", "2022.01.15"),
+            ("Wijk aan Zee", "2022.01.30"),
+            ("Wijk aan Zee", "2023.01.14"),
+            ("Wijk aan Zee", "2023.01.29"),
+            ("Wijk aan Zee", "2023.09.01"),
+            ("Wijk aan Zee", "????.??.??"),
+            ("Wijk aan Zee 2022", "2022.01.16"),
+            ("Tata Steel", "2023.01.14"),
+            ("Tata Steel", "2023.09.01"),
+        ]
+        .map(|(event, date)| {
+            format!("[Event \"{event}\"]\n[Date \"{date}\"]\n[Result \"*\"]\n\n*\n")
+        })
+        .join("\n");
+        let mut db = test_db();
+        import_bytes(&mut db, pgn.into(), None);
+        let event = |db: &mut SqliteConnection, name: &str| -> i32 {
+            events::table
+                .filter(events::name.eq(name))
+                .select(events::id)
+                .first(db)
+                .unwrap()
+        };
+        let wijk = event(&mut db, "Wijk aan Zee");
+        let wijk_2022 = event(&mut db, "Wijk aan Zee 2022");
+
+        let editions = event_editions(&mut db, wijk, 180, false).unwrap();
+        let summary: Vec<_> = editions
+            .iter()
+            .map(|e| (e.name.as_str(), e.first_date.as_str(), e.game_count))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("Wijk aan Zee 2022", "2022.01.15", 2),
+                ("Wijk aan Zee 2023.01", "2023.01.14", 2),
+                ("Wijk aan Zee 2023.09", "2023.09.01", 1),
+            ]
+        );
+        assert!(editions.iter().all(|e| e.event_id.is_none()));
+        assert_eq!(event_editions(&mut db, wijk, 300, false).unwrap().len(), 2);
+
+        let event_games = |db: &mut SqliteConnection, event: i32| -> i64 {
+            games::table
+                .filter(games::event_id.eq(event))
+                .count()
+                .get_result(db)
+                .unwrap()
+        };
+        // Another event already has the name of the 2022 edition
+        assert!(matches!(
+            event_editions(&mut db, wijk, 180, true),
+            Err(Error::EventNameTaken(id)) if id == wijk_2022
+        ));
+        assert_eq!(event_games(&mut db, wijk), 6);
+
+        rename_event_row(&mut db, EventTable::Events, wijk_2022, "Wijk aan Zee B").unwrap();
+        let editions = event_editions(&mut db, wijk, 180, true).unwrap();
+        assert_eq!(editions[0].event_id, Some(wijk));
+        assert_eq!(event(&mut db, "Wijk aan Zee 2022"), wijk);
+        assert_eq!(
+            event_games(&mut db, event(&mut db, "Wijk aan Zee 2023.01")),
+            2
+        );
+        assert_eq!(
+            event_games(&mut db, event(&mut db, "Wijk aan Zee 2023.09")),
+            1
+        );
+        // The undated game stays
+        assert_eq!(event_games(&mut db, wijk), 3);
+
+        // A date with only the year names the edition without a month
+        let tata = event(&mut db, "Tata Steel");
+        diesel::update(games::table)
+            .filter(games::event_id.eq(tata))
+            .filter(games::date.eq("2023-01-14"))
+            .set(games::date.eq("2023"))
+            .execute(&mut db)
+            .unwrap();
+        let names: Vec<_> = event_editions(&mut db, tata, 180, false)
+            .unwrap()
+            .into_iter()
+            .map(|e| e.name)
+            .collect();
+        assert_eq!(names, vec!["Tata Steel 2023", "Tata Steel 2023.09"]);
+    }
+
+    #[test]
+    fn crosstable_of_a_double_round_robin() {
+        let pgn = [
+            ("Double", "Alice", "Bob", "1-0", "2024.01.01"),
+            ("Double", "Carol", "Alice", "1/2-1/2", "2024.01.02"),
+            ("Double", "Bob", "Carol", "0-1", "2024.01.03"),
+            ("Double", "Bob", "Alice", "1/2-1/2", "2024.01.04"),
+            ("Double", "Alice", "Carol", "*", "2024.01.05"),
+            ("Swiss", "Alice", "Bob", "1-0", "2024.02.01"),
+            ("Swiss", "Carol", "Dave", "0-1", "2024.02.01"),
+            ("Swiss", "Dave", "Alice", "1/2-1/2", "2024.02.02"),
+        ]
+        .map(|(event, white, black, result, date)| {
+            format!(
+                "[Event \"{event}\"]\n[White \"{white}\"]\n[Black \"{black}\"]\n[Date \"{date}\"]\n[Result \"{result}\"]\n\n{result}\n"
+            )
+        })
+        .join("\n");
+        let mut db = test_db();
+        import_bytes(&mut db, pgn.into(), None);
+        let event = |db: &mut SqliteConnection, name: &str| -> i32 {
+            events::table
+                .filter(events::name.eq(name))
+                .select(events::id)
+                .first(db)
+                .unwrap()
+        };
+        let scores = |standings: &EventStandings| -> Vec<(String, f64, i32, i32)> {
+            standings
+                .standings
+                .iter()
+                .map(|s| (s.name.clone().unwrap(), s.points, s.games, s.unfinished))
+                .collect()
+        };
+
+        let double = event(&mut db, "Double");
+        let standings = event_standings(&mut db, double).unwrap();
+        assert_eq!(
+            scores(&standings),
+            vec![
+                ("Alice".to_string(), 2.0, 3, 1),
+                ("Carol".to_string(), 1.5, 2, 1),
+                ("Bob".to_string(), 0.5, 3, 0),
+            ]
+        );
+        assert_eq!(
+            standings.crosstable,
+            Some(vec![
+                vec![vec![], vec![Some(0.5), None], vec![Some(1.0), Some(0.5)]],
+                vec![vec![Some(0.5), None], vec![], vec![Some(1.0)]],
+                vec![vec![Some(0.0), Some(0.5)], vec![Some(0.0)], vec![]],
+            ])
+        );
+
+        // Alice and Carol never met
+        let swiss = event(&mut db, "Swiss");
+        let standings = event_standings(&mut db, swiss).unwrap();
+        assert_eq!(
+            scores(&standings),
+            vec![
+                ("Alice".to_string(), 1.5, 2, 0),
+                ("Dave".to_string(), 1.5, 2, 0),
+                ("Bob".to_string(), 0.0, 1, 0),
+                ("Carol".to_string(), 0.0, 1, 0),
+            ]
+        );
+        assert_eq!(standings.crosstable, None);
+
+        assert!(matches!(
+            event_standings(&mut db, 1000),
+            Err(Error::EventNotFound(1000))
+        ));
+    }
+
+    #[test]
+    fn performance_rating_against_rated_opponents() {
+        let pgn = [
+            ("Alice", "Bob", "2400", "1-0", "Open"),
+            ("Carol", "Alice", "2600", "1/2-1/2", "Open"),
+            ("Alice", "Dave", "?", "1-0", "Open"),
+            ("Alice", "Bob", "2400", "*", "Open"),
+            ("Alice", "Bob", "2400", "1-0", "Cup"),
+            ("Carol", "Alice", "2600", "1-0", "Cup"),
+        ]
+        .map(|(white, black, elo, result, event)| {
+            let elo_tag = if white == "Alice" { "BlackElo" } else { "WhiteElo" };
+            format!(
+                "[Event \"{event}\"]\n[White \"{white}\"]\n[Black \"{black}\"]\n[{elo_tag} \"{elo}\"]\n[Result \"{result}\"]\n\n{result}\n"
+            )
+        })
+        .join("\n");
+        let mut db = test_db();
+        import_bytes(&mut db, pgn.into(), None);
+        let alice: i32 = players::table
+            .filter(players::name.eq("Alice"))
+            .select(players::id)
+            .first(&mut db)
+            .unwrap();
+        let open: i32 = events::table
+            .filter(events::name.eq("Open"))
+            .select(events::id)
+            .first(&mut db)
+            .unwrap();
+
+        let query = GameQuery {
+            tournament_id: Some(open),
+            ..Default::default()
+        };
+        let performance = player_performance(&mut db, alice, &query).unwrap();
+        // 1.5 out of 2 is 75%, 193 points above the average of 2500
+        assert_eq!(
+            performance,
+            Performance {
+                games: 2,
+                points: 1.5,
+                average_opponent_rating: Some(2500.0),
+                performance_rating: Some(2693),
+                excluded_games: 1,
+            }
+        );
+
+        let performance = player_performance(&mut db, alice, &GameQuery::default()).unwrap();
+        assert_eq!((performance.games, performance.points), (4, 2.5));
+        // 62.5% rounds up to 63%
+        assert_eq!(performance.performance_rating, Some(2500 + 95));
+
+        assert_eq!(fide_rating_difference(1.0), 800);
+        assert_eq!(fide_rating_difference(0.0), -800);
+        assert_eq!(fide_rating_difference(0.25), -193);
+    }
+
+    #[test]
+    fn head_to_head_by_color() {
+        let pgn = [
+            ("Alice", "Bob", "B90", "1-0", "2021.01.01"),
+            ("Bob", "Alice", "B90", "1-0", "2020.01.01"),
+            ("Alice", "Carol", "C20", "1-0", "2019.01.01"),
+            ("Bob", "Alice", "C20", "1/2-1/2", "2022.01.01"),
+            ("Alice", "Bob", "D30", "*", "2023.01.01"),
+        ]
+        .map(|(white, black, eco, result, date)| {
+            format!(
+                "[White \"{white}\"]\n[Black \"{black}\"]\n[ECO \"{eco}\"]\n[Date \"{date}\"]\n[Result \"{result}\"]\n\n{result}\n"
+            )
+        })
+        .join("\n");
+        let mut db = test_db();
+        import_bytes(&mut db, pgn.into(), None);
+        let id = |db: &mut SqliteConnection, name: &str| -> i32 {
+            players::table
+                .filter(players::name.eq(name))
+                .select(players::id)
+                .first(db)
+                .unwrap()
+        };
+        let alice = id(&mut db, "Alice");
+        let bob = id(&mut db, "Bob");
+
+        let record = head_to_head(&mut db, alice, bob).unwrap();
+        assert_eq!(
+            record.as_white,
+            Results {
+                won: 1,
+                lost: 0,
+                draw: 0
+            }
+        );
+        assert_eq!(
+            record.as_black,
+            Results {
+                won: 0,
+                lost: 1,
+                draw: 1
+            }
+        );
+        let dates: Vec<Option<String>> = record
+            .game_ids
+            .iter()
+            .map(|id| {
+                games::table
+                    .find(id)
+                    .select(games::date)
+                    .first(&mut db)
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(
+            dates,
+            ["2020-01-01", "2021-01-01", "2022-01-01", "2023-01-01"].map(|d| Some(d.to_string()))
+        );
+        assert_eq!(
+            record.common_openings,
+            vec![
+                ("B90".to_string(), 2),
+                ("C20".to_string(), 1),
+                ("D30".to_string(), 1)
+            ]
+        );
+
+        let reversed = head_to_head(&mut db, bob, alice).unwrap();
+        assert_eq!(reversed.as_white.won, 1);
+        assert_eq!(reversed.as_black.lost, 1);
+    }
+
+    #[test]
+    fn rating_history_skips_unrated_games() {
+        let pgn = [
+            ("Alice", "2300", "Bob", "2021.05.01"),
+            ("Bob", "2340", "Alice", "2019.01.10"),
+            ("Alice", "?", "Bob", "2020.03.??"),
+            ("Bob", "2340", "Alice", "2022.02.02"),
+            ("Alice", "2500", "Bob", "????.??.??"),
+            ("Alice", "2280", "Bob", "2020.07.15"),
+        ]
+        .map(|(white, white_elo, black, date)| {
+            let (white_elo, black_elo) = if white == "Alice" {
+                (white_elo, "2000")
+            } else {
+                ("2000", white_elo)
+            };
+            format!(
+                "[White \"{white}\"]\n[WhiteElo \"{white_elo}\"]\n[Black \"{black}\"]\n[BlackElo \"{black_elo}\"]\n[Date \"{date}\"]\n\n*\n"
+            )
+        })
+        .join("\n");
+        let mut db = test_db();
+        import_bytes(&mut db, pgn.into(), None);
+        let alice: i32 = players::table
+            .filter(players::name.eq("Alice"))
+            .select(players::id)
+            .first(&mut db)
+            .unwrap();
+
+        let history = rating_history(&mut db, alice, None).unwrap();
+        assert_eq!(
+            history.points,
+            vec![
+                ("2019.01.10".to_string(), 2340),
+                ("2020.07.15".to_string(), 2280),
+                ("2021.05.01".to_string(), 2300),
+                ("2022.02.02".to_string(), 2340),
+            ]
+        );
+        assert_eq!(history.peak, Some(("2019.01.10".to_string(), 2340)));
+
+        let sampled = rating_history(&mut db, alice, Some(2)).unwrap();
+        assert_eq!(
+            sampled.points,
+            vec![
+                ("2019.01.10".to_string(), 2340),
+                ("2022.02.02".to_string(), 2340),
+            ]
+        );
+        assert_eq!(sampled.peak, history.peak);
+        assert_eq!(downsample((0..10).collect(), 4), vec![0, 3, 6, 9]);
+    }
+
+    #[test]
+    fn delete_players_with_their_games() {
+        // Like the connections of the imports, without the foreign keys
+        let mut db = test_db();
+        import_bytes(&mut db, TWO_GAMES_PGN.into(), None);
+        let id = |db: &mut SqliteConnection, name: &str| -> i32 {
+            players::table
+                .filter(players::name.eq(name))
+                .select(players::id)
+                .first(db)
+                .unwrap()
+        };
+        let alice = id(&mut db, "Alice");
+        let bob = id(&mut db, "Bob");
+        let count = |db: &mut SqliteConnection, name: &str| -> String {
+            info::table
+                .filter(info::name.eq(name))
+                .select(info::value)
+                .first(db)
+                .unwrap()
+        };
+
+        assert!(matches!(
+            delete_player_row(&mut db, alice, PlayerDeletion::Forbid),
+            Err(Error::PlayerHasGames(player)) if player == alice
+        ));
+        for mode in [PlayerDeletion::Anonymize, PlayerDeletion::DeleteGames] {
+            assert!(matches!(
+                delete_player_row(&mut db, 0, mode),
+                Err(Error::UnknownPlayerDeletion)
+            ));
+        }
+        assert_eq!(
+            delete_player_row(&mut db, alice, PlayerDeletion::Anonymize).unwrap(),
+            2
+        );
+        assert_eq!(
+            games::table
+                .filter(games::white_id.eq(0).or(games::black_id.eq(0)))
+                .count()
+                .get_result::<i64>(&mut db)
+                .unwrap(),
+            2
+        );
+        assert!(matches!(
+            delete_player_row(&mut db, alice, PlayerDeletion::Forbid),
+            Err(Error::PlayerNotFound(_))
+        ));
+
+        assert_eq!(
+            delete_player_row(&mut db, bob, PlayerDeletion::DeleteGames).unwrap(),
+            2
+        );
+        assert_eq!(count(&mut db, "GameCount"), "0");
+        // Only the `Unknown` player is left
+        assert_eq!(count(&mut db, "PlayerCount"), "1");
+    }
+
+    #[test]
+    fn deleted_games_lose_their_comments() {
+        let pgn = r#"[White "Alice"]
+[Black "Bob"]
+
+1. e4 { Best by test } e5 1-0
+
+[White "Carol"]
+[Black "Dave"]
+
+1. d4 { Solid } d5 1/2-1/2
+"#;
+        let mut db = test_db();
+        let options = ImportOptions {
+            keep_annotations: true,
+            ..Default::default()
+        };
+        import_with(&mut db, pgn.into(), None, options);
+        let comments = |db: &mut SqliteConnection| -> Vec<Option<String>> {
+            comments::table
+                .order(comments::id)
+                .select(comments::comment)
+                .load(db)
+                .unwrap()
+        };
+        assert_eq!(comments(&mut db).len(), 2);
+
+        let alice: i32 = players::table
+            .filter(players::name.eq("Alice"))
+            .select(players::id)
+            .first(&mut db)
+            .unwrap();
+        delete_player_row(&mut db, alice, PlayerDeletion::DeleteGames).unwrap();
+        assert_eq!(comments(&mut db), vec![Some("Solid".to_string())]);
+    }
+
+    #[test]
+    fn player_game_counts_follow_the_games() {
+        let mut db = test_db();
+        import_bytes(&mut db, TWO_GAMES_PGN.into(), None);
+        import_bytes(
+            &mut db,
+            "[White \"Alice\"]\n[Black \"Carol\"]\n\n1. e4 *\n".into(),
+            None,
+        );
+        let game_counts = |db: &mut SqliteConnection| -> Vec<(String, Option<i32>)> {
+            players::table
+                .filter(players::id.ne(0))
+                .order(players::name)
+                .select((players::name.assume_not_null(), players::game_count))
+                .load(db)
+                .unwrap()
+        };
+        assert_eq!(
+            game_counts(&mut db),
+            vec![
+                ("Alice".to_string(), Some(3)),
+                ("Bob".to_string(), Some(2)),
+                ("Carol".to_string(), Some(1)),
+            ]
+        );
 
-    let mut game_info = PlayerGameInfo::default();
-    let white_openings = DashMap::new();
-    let black_openings = DashMap::new();
-    let won = AtomicI32::new(0);
-    let lost = AtomicI32::new(0);
-    let draw = AtomicI32::new(0);
-    let data_per_month = DashMap::new();
-    let progress = AtomicUsize::new(0);
+        let bob: i32 = players::table
+            .filter(players::name.eq("Bob"))
+            .select(players::id)
+            .first(&mut db)
+            .unwrap();
+        delete_player_row(&mut db, bob, PlayerDeletion::DeleteGames).unwrap();
+        let remaining = vec![
+            ("Alice".to_string(), Some(1)),
+            ("Carol".to_string(), Some(1)),
+        ];
+        assert_eq!(game_counts(&mut db), remaining);
+
+        // As in a database imported before the games were counted
+        diesel::update(players::table)
+            .set(players::game_count.eq(None::<i32>))
+            .execute(&mut db)
+            .unwrap();
+        assert_eq!(count_player_games(&mut db).unwrap(), 2);
+        assert_eq!(game_counts(&mut db), remaining);
+    }
 
-    info.par_iter().for_each(
-        |(white_id, black_id, outcome, date, moves, white_elo, black_elo)| {
-            let is_white = *white_id == id;
-            assert!(is_white || *black_id == id);
+    #[test]
+    fn player_details_sum_up_both_sides() {
+        let pgn = [
+            ("Alice", "2300", "Bob", "2200", "2021.05.01", "B90"),
+            ("Bob", "2250", "Alice", "2340", "2019.01.10", "C20"),
+            ("Alice", "2310", "Carol", "?", "????.??.??", "B90"),
+            ("Carol", "?", "Bob", "2210", "2022.02.02", "A00"),
+        ]
+        .map(|(white, white_elo, black, black_elo, date, eco)| {
+            format!(
+                "[White \"{white}\"]\n[WhiteElo \"{white_elo}\"]\n[Black \"{black}\"]\n[BlackElo \"{black_elo}\"]\n[Date \"{date}\"]\n[ECO \"{eco}\"]\n\n*\n"
+            )
+        })
+        .join("\n");
+        let mut db = test_db();
+        import_bytes(&mut db, pgn.into(), None);
+        let alice: i32 = players::table
+            .filter(players::name.eq("Alice"))
+            .select(players::id)
+            .first(&mut db)
+            .unwrap();
+
+        let details = player_details(&mut db, alice).unwrap();
+        assert_eq!(details.player.name.as_deref(), Some("Alice"));
+        assert_eq!(details.player.game_count, Some(3));
+        assert_eq!(details.first_game_date.as_deref(), Some("2019.01.10"));
+        assert_eq!(details.last_game_date.as_deref(), Some("2021.05.01"));
+        assert_eq!(details.peak_rating, Some(2340));
+        assert_eq!(
+            details.favorite_openings,
+            vec![("B90".to_string(), 2), ("C20".to_string(), 1)]
+        );
 
-            let mut setups = vec![];
-            let mut chess = Chess::default();
-            for (i, byte) in moves.iter().enumerate() {
-                if i > 54 {
-                    // max length of opening in data
-                    break;
-                }
-                let m = decode_move(*byte, &chess).unwrap();
-                chess.play_unchecked(&m);
-                setups.push(chess.clone().into_setup(EnPassantMode::Legal));
+        assert!(matches!(
+            player_details(&mut db, 1000),
+            Err(Error::PlayerNotFound(1000))
+        ));
+    }
+
+    #[test]
+    fn database_stats_are_cached_until_games_change() {
+        let pgn = [
+            ("1-0", "2450", "2380", "1. e4 e5 2. Nf3 Nc6"),
+            ("1/2-1/2", "2399", "?", "1. d4 d5"),
+            ("0-1", "2510", "2405", "1. c4"),
+            ("*", "?", "?", "1. e4 c5 2. Nf3"),
+        ]
+        .map(|(result, white, black, moves)| {
+            format!(
+                "[Result \"{result}\"]\n[WhiteElo \"{white}\"]\n[BlackElo \"{black}\"]\n\n{moves} {result}\n"
+            )
+        })
+        .join("\n");
+        let mut db = test_db();
+        import_bytes(&mut db, pgn.into(), None);
+
+        let stats = database_stats(&mut db).unwrap();
+        assert_eq!(
+            stats,
+            DatabaseStats {
+                game_count: 4,
+                white_wins: 1,
+                draws: 1,
+                black_wins: 1,
+                unknown_results: 1,
+                elo_histogram: vec![(2300, 2), (2400, 2), (2500, 1)],
+                average_ply_count: Some(2.5),
+                median_ply_count: Some(2.5),
+                missing_ratings: 2,
+                missing_dates: 4,
             }
+        );
 
-            setups.reverse();
-            for setup in setups {
-                if let Ok(opening) = get_opening_from_setup(setup) {
-                    let openings = if is_white {
-                        &white_openings
-                    } else {
-                        &black_openings
-                    };
-                    if outcome.as_deref() == Some("1-0") {
-                        openings
-                            .entry(opening)
-                            .and_modify(|e: &mut Results| {
-                                if is_white {
-                                    e.won += 1;
-                                } else {
-                                    e.lost += 1;
-                                }
-                            })
-                            .or_insert(Results {
-                                won: 1,
-                                lost: 0,
-                                draw: 0,
-                            });
-                    } else if outcome.as_deref() == Some("0-1") {
-                        openings
-                            .entry(opening)
-                            .and_modify(|e| {
-                                if is_white {
-                                    e.lost += 1;
-                                } else {
-                                    e.won += 1;
-                                }
-                            })
-                            .or_insert(Results {
-                                won: 0,
-                                lost: 1,
-                                draw: 0,
-                            });
-                    } else if outcome.as_deref() == Some("1/2-1/2") {
-                        openings
-                            .entry(opening)
-                            .and_modify(|e| {
-                                e.draw += 1;
-                            })
-                            .or_insert(Results {
-                                won: 0,
-                                lost: 0,
-                                draw: 1,
-                            });
-                    }
+        // Served from the Info table while the games are the same
+        diesel::update(games::table)
+            .set(games::result.eq(GameResult::Draw as i32))
+            .execute(&mut db)
+            .unwrap();
+        assert_eq!(database_stats(&mut db).unwrap(), stats);
+
+        import_bytes(&mut db, TWO_GAMES_PGN.into(), None);
+        let stats = database_stats(&mut db).unwrap();
+        assert_eq!(stats.game_count, 6);
+        assert_eq!(stats.draws, 5);
+    }
 
-                    break;
-                }
-            }
+    #[test]
+    fn opening_stats_by_group() {
+        let pgn = [
+            (Some("B90"), "1-0"),
+            (Some("B90"), "1/2-1/2"),
+            (Some("B33"), "0-1"),
+            (Some("E60"), "1-0"),
+            (None, "*"),
+        ]
+        .map(|(eco, result)| {
+            let eco = eco.map_or(String::new(), |eco| format!("[ECO \"{eco}\"]\n"));
+            // No moves, so that the game without a code isn't classified
+            format!("{eco}[Result \"{result}\"]\n\n{result}\n")
+        })
+        .join("\n");
+        let mut db = test_db();
+        import_bytes(&mut db, pgn.into(), None);
+        let stats = |group: &str, games, white_wins, draws, black_wins| OpeningStats {
+            group: group.to_string(),
+            games,
+            white_wins,
+            draws,
+            black_wins,
+        };
 
-            if let Some(date) = date {
-                let date = match NaiveDate::parse_from_str(date, "%Y.%m.%d") {
-                    Ok(date) => date,
-                    Err(_) => return,
-                };
-                let month = date.format("%Y-%m").to_string();
+        assert_eq!(
+            opening_stats(&mut db, OpeningGroup::EcoLetter, &GameQuery::default()).unwrap(),
+            vec![
+                stats("B", 3, 1, 1, 1),
+                stats("E", 1, 1, 0, 0),
+                stats(UNCLASSIFIED_OPENING, 1, 0, 0, 0),
+            ]
+        );
+        let decided = GameQuery {
+            decisive_only: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            opening_stats(&mut db, OpeningGroup::EcoCode, &decided).unwrap(),
+            vec![
+                stats("B33", 1, 0, 0, 1),
+                stats("B90", 1, 1, 0, 0),
+                stats("E60", 1, 1, 0, 0),
+            ]
+        );
 
-                // update count and avg elo
-                let mut month_data = data_per_month.entry(month).or_insert(MonthData::default());
-                month_data.count += 1;
-                let elo = if is_white { white_elo } else { black_elo };
-                if let Some(elo) = elo {
-                    month_data.avg_elo += elo;
-                    month_data.avg_count += 1;
-                }
+        let by_name =
+            opening_stats(&mut db, OpeningGroup::OpeningName, &GameQuery::default()).unwrap();
+        let najdorf = get_opening_name_from_eco("B90").unwrap();
+        assert!(by_name.iter().any(|s| s.group == najdorf && s.games >= 2));
+        assert_eq!(by_name.iter().map(|s| s.games).sum::<i64>(), 5);
+    }
+
+    #[test]
+    fn player_repertoire_from_their_side() {
+        let pgn = [
+            ("Alice", "Bob", "B90", "1-0", "2021.01.01"),
+            ("Alice", "Bob", "B90", "0-1", "2022.01.01"),
+            ("Alice", "Carol", "C20", "1/2-1/2", "2022.06.01"),
+            ("Bob", "Alice", "B90", "0-1", "2023.01.01"),
+            ("Bob", "Alice", "D30", "1-0", "2019.01.01"),
+        ]
+        .map(|(white, black, eco, result, date)| {
+            format!(
+                "[White \"{white}\"]\n[Black \"{black}\"]\n[ECO \"{eco}\"]\n[Date \"{date}\"]\n[Result \"{result}\"]\n\n{result}\n"
+            )
+        })
+        .join("\n");
+        let mut db = test_db();
+        import_bytes(&mut db, pgn.into(), None);
+        let alice: i32 = players::table
+            .filter(players::name.eq("Alice"))
+            .select(players::id)
+            .first(&mut db)
+            .unwrap();
+        let stats = |group: &str, games, wins, draws, losses| RepertoireStats {
+            group: group.to_string(),
+            games,
+            wins,
+            draws,
+            losses,
+        };
+
+        assert_eq!(
+            player_openings(
+                &mut db,
+                alice,
+                PlayerSide::White,
+                OpeningGroup::EcoCode,
+                None,
+                None
+            )
+            .unwrap(),
+            vec![stats("B90", 2, 1, 0, 1), stats("C20", 1, 0, 1, 0)]
+        );
+        assert_eq!(
+            player_openings(
+                &mut db,
+                alice,
+                PlayerSide::Black,
+                OpeningGroup::EcoCode,
+                None,
+                None
+            )
+            .unwrap(),
+            vec![stats("B90", 1, 1, 0, 0), stats("D30", 1, 0, 0, 1)]
+        );
+        assert_eq!(
+            player_openings(
+                &mut db,
+                alice,
+                PlayerSide::Both,
+                OpeningGroup::EcoLetter,
+                Some("2022.01.01".into()),
+                None
+            )
+            .unwrap(),
+            vec![stats("B", 2, 1, 0, 1), stats("C", 1, 0, 1, 0)]
+        );
+    }
+
+    #[test]
+    fn count_games_per_year_and_month() {
+        let pgn = [
+            ("2021.03.04", "1-0"),
+            ("2019.??.??", "1-0"),
+            ("2021.03.20", "0-1"),
+            ("????.??.??", "1-0"),
+            ("2021.11.01", "1-0"),
+            ("2019.07.07", "1-0"),
+        ]
+        .map(|(date, result)| {
+            format!("[Date \"{date}\"]\n[Result \"{result}\"]\n\n1. e4 {result}\n")
+        })
+        .join("\n");
+        let mut db = test_db();
+        import_bytes(&mut db, pgn.into(), None);
+        let mut periods = |granularity, query: GameQuery| -> Vec<(String, i64)> {
+            games_per_period(&mut db, granularity, &query).unwrap()
+        };
+        let expected = |periods: &[(&str, i64)]| -> Vec<(String, i64)> {
+            periods.iter().map(|(p, c)| (p.to_string(), *c)).collect()
+        };
+
+        assert_eq!(
+            periods(Granularity::Year, GameQuery::default()),
+            expected(&[("2019", 2), ("2021", 3), ("Unknown", 1)])
+        );
+        assert_eq!(
+            periods(Granularity::Month, GameQuery::default()),
+            expected(&[
+                ("2019-07", 1),
+                ("2021-03", 2),
+                ("2021-11", 1),
+                ("Unknown", 2)
+            ])
+        );
+        let white_wins = GameQuery {
+            outcome: Some(OutcomeFilter::One(GameResult::WhiteWins)),
+            ..Default::default()
+        };
+        assert_eq!(
+            periods(Granularity::Month, white_wins),
+            expected(&[
+                ("2019-07", 1),
+                ("2021-03", 1),
+                ("2021-11", 1),
+                ("Unknown", 2)
+            ])
+        );
+    }
+
+    #[test]
+    fn sort_games_by_max_elo() {
+        let pgn = [
+            ("2400", "2700"),
+            ("?", "?"),
+            ("2500", "?"),
+            ("2650", "2100"),
+            ("2500", "2300"),
+        ]
+        .map(|(white, black)| {
+            format!("[WhiteElo \"{white}\"]\n[BlackElo \"{black}\"]\n\n1. e4 *\n")
+        })
+        .join("\n");
+        let mut db = test_db();
+        import_bytes(&mut db, pgn.into(), None);
+        let stored: Vec<Option<i32>> = games::table
+            .select(games::max_rating)
+            .order(games::id)
+            .load(&mut db)
+            .unwrap();
+        assert_eq!(
+            stored,
+            vec![Some(2700), None, Some(2500), Some(2650), Some(2500)]
+        );
+
+        // As if imported before it was stored
+        diesel::update(games::table)
+            .set(games::max_rating.eq(None::<i32>))
+            .execute(&mut db)
+            .unwrap();
+        assert_eq!(fill_missing_max_ratings(&mut db).unwrap(), 4);
+
+        let options = QueryOptions {
+            sort: GameSort::MaxElo,
+            direction: SortDirection::Desc,
+            ..Default::default()
+        };
+        let joined_games = || {
+            games::table
+                .inner_join(events::table.on(games::event_id.eq(events::id)))
+                .inner_join(sites::table.on(games::site_id.eq(sites::id)))
+                .select(games::all_columns)
+                .into_boxed()
+        };
+        let all: Vec<(Option<i32>, i32)> = sort_games!(joined_games(), options)
+            .select((games::max_rating, games::id))
+            .load(&mut db)
+            .unwrap();
+        let ratings: Vec<Option<i32>> = all.iter().map(|(rating, _)| *rating).collect();
+        assert_eq!(
+            ratings,
+            vec![Some(2700), Some(2650), Some(2500), Some(2500), None]
+        );
+
+        // Keyset pages over the ties and the unrated game
+        let mut paged = vec![];
+        let mut after: Option<Cursor> = None;
+        loop {
+            let mut sql_query = sort_games!(joined_games(), options).limit(2);
+            if let Some(cursor) = &after {
+                sql_query = filter_after_cursor!(sql_query, options, cursor).unwrap();
             }
-            match outcome.as_deref() {
-                Some("1-0") => match is_white {
-                    true => won.fetch_add(1, Ordering::Relaxed),
-                    false => lost.fetch_add(1, Ordering::Relaxed),
-                },
-                Some("0-1") => match is_white {
-                    true => lost.fetch_add(1, Ordering::Relaxed),
-                    false => won.fetch_add(1, Ordering::Relaxed),
-                },
-                Some("1/2-1/2") => draw.fetch_add(1, Ordering::Relaxed),
-                _ => 0,
+            let page: Vec<Game> = sql_query.load(&mut db).unwrap();
+            let Some(last) = page.last() else {
+                break;
             };
+            after = GameSort::MaxElo.cursor(last);
+            paged.extend(page.iter().map(|game| (game.max_rating, game.id)));
+        }
+        assert_eq!(paged, all);
+    }
 
-            let p = progress.fetch_add(1, Ordering::Relaxed);
-            if p % 1000 == 0 || p == info.len() - 1 {
-                let _ = DatabaseProgress {
-                    id: id.to_string(),
-                    progress: (p as f64 / info.len() as f64) * 100_f64,
-                }
-                .emit_all(&app);
+    #[test]
+    fn sort_games_by_event_and_round() {
+        let pgn = r#"[Event "B"]
+[Site "Wijk aan Zee"]
+[Round "1"]
+
+*
+
+[Event "A"]
+[Site "Berlin"]
+[Round "10"]
+
+*
+
+[Event "A"]
+[Site "Berlin"]
+[Round "4.2"]
+
+*
+
+[Event "A"]
+[Site "Berlin"]
+[Round "9"]
+
+*
+
+[Event "A"]
+[Site "Berlin"]
+[Round "4.1"]
+
+*
+"#;
+        let mut db = test_db();
+        import_bytes(&mut db, pgn.into(), None);
+
+        let sorted = |db: &mut SqliteConnection, sort: GameSort| -> Vec<(String, Option<String>)> {
+            let options = QueryOptions {
+                sort,
+                direction: SortDirection::Asc,
+                ..Default::default()
+            };
+            let sql_query = games::table
+                .inner_join(events::table.on(games::event_id.eq(events::id)))
+                .inner_join(sites::table.on(games::site_id.eq(sites::id)))
+                .into_boxed();
+            sort_games!(sql_query, options)
+                .select((events::name, games::round))
+                .load::<(Option<String>, Option<String>)>(db)
+                .unwrap()
+                .into_iter()
+                .map(|(event, round)| (event.unwrap(), round))
+                .collect()
+        };
+        let game = |event: &str, round: &str| (event.to_string(), Some(round.to_string()));
+
+        assert_eq!(
+            sorted(&mut db, GameSort::Event),
+            vec![
+                game("A", "4.1"),
+                game("A", "4.2"),
+                game("A", "9"),
+                game("A", "10"),
+                game("B", "1"),
+            ]
+        );
+        assert_eq!(
+            sorted(&mut db, GameSort::Round),
+            vec![
+                game("B", "1"),
+                game("A", "4.1"),
+                game("A", "4.2"),
+                game("A", "9"),
+                game("A", "10"),
+            ]
+        );
+        let by_site = sorted(&mut db, GameSort::Site);
+        assert!(by_site[..4].iter().all(|(event, _)| event == "A"));
+        assert_eq!(by_site[4], game("B", "1"));
+    }
+
+    #[test]
+    fn filter_games_by_speed_and_time_control() {
+        let pgn = r#"[TimeControl "60+0"]
+
+1. e4 *
+
+[TimeControl "300+3"]
+
+1. e4 *
+
+[TimeControl "180+2"]
+
+1. e4 *
+
+[TimeControl "5400+30"]
+
+1. e4 *
+"#;
+        let mut db = test_db();
+        import_bytes(&mut db, pgn.into(), None);
+        // As if imported before speeds were stored
+        diesel::update(games::table)
+            .set(games::speed.eq(None::<i32>))
+            .execute(&mut db)
+            .unwrap();
+        assert_eq!(fill_missing_speeds(&mut db).unwrap(), 4);
+
+        let time_controls = |db: &mut SqliteConnection, query: GameQuery| -> Vec<Option<String>> {
+            filter_games!(games::table.into_boxed(), query)
+                .select(games::time_control)
+                .order(games::id)
+                .load(db)
+                .unwrap()
+        };
+        let query = GameQuery {
+            speed: Some(vec![Speed::Bullet, Speed::Blitz]),
+            ..Default::default()
+        };
+        assert_eq!(
+            time_controls(&mut db, query),
+            vec![
+                Some("60+0".to_string()),
+                Some("300+3".to_string()),
+                Some("180+2".to_string())
+            ]
+        );
+        let query = GameQuery {
+            speed: Some(vec![Speed::Blitz]),
+            time_control: Some("300+3".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            time_controls(&mut db, query),
+            vec![Some("300+3".to_string())]
+        );
+    }
+
+    #[test]
+    fn import_latin1_pgn() {
+        let mut pgn = b"[White \"Kortschnoj, Viktor\"]\n[Black \"".to_vec();
+        // "Hübner, Robert" and "Öztürk" in Latin-1
+        pgn.extend(b"H\xfcbner, Robert\"]\n\n1. e4 e5 *\n\n[White \"H\xfcbner, Robert\"]\n");
+        pgn.extend(b"[Black \"\xd6zt\xfcrk\"]\n\n1. d4 { \x93ok\x94 } d5 *\n");
+
+        let mut db = test_db();
+        let options = ImportOptions {
+            keep_annotations: true,
+            ..Default::default()
+        };
+        let (imported, _) = import_with(&mut db, pgn.clone(), None, options);
+        assert_eq!(imported, 2);
+        let names: Vec<Option<String>> = players::table
+            .filter(players::id.ne(0))
+            .select(players::name)
+            .order(players::id)
+            .load(&mut db)
+            .unwrap();
+        let names: Vec<&str> = names.iter().flatten().map(String::as_str).collect();
+        assert_eq!(
+            names,
+            vec!["Kortschnoj, Viktor", "Hübner, Robert", "Öztürk"]
+        );
+        let comment: Option<String> = comments::table
+            .select(comments::comment)
+            .first(&mut db)
+            .unwrap();
+        assert_eq!(comment.as_deref(), Some("\u{201C}ok\u{201D}"));
+
+        assert_eq!(PgnEncoding::Latin1.decode("Hübner".as_bytes()), "HÃ¼bner");
+        assert_eq!(PgnEncoding::Utf8.decode(b"H\xfcbner"), "H\u{FFFD}bner");
+    }
+
+    #[test]
+    fn termination_from_header() {
+        assert_eq!(Termination::from_header("Normal"), Termination::Normal);
+        assert_eq!(
+            Termination::from_header("time forfeit"),
+            Termination::TimeForfeit
+        );
+        assert_eq!(
+            Termination::from_header("Abandoned"),
+            Termination::Abandoned
+        );
+        assert_eq!(
+            Termination::from_header("Rules infraction"),
+            Termination::RulesInfraction
+        );
+        assert_eq!(
+            Termination::from_header("Unterminated"),
+            Termination::Unknown
+        );
+    }
+
+    #[test]
+    fn import_stores_termination_verbatim() {
+        let pgn = r#"[Termination "Time forfeit"]
+
+1. e4 e5 *
+
+1. d4 d5 *
+"#;
+        let mut db = test_db();
+        import_bytes(&mut db, pgn.into(), None);
+        let terminations: Vec<Option<String>> = games::table
+            .select(games::termination)
+            .order(games::id)
+            .load(&mut db)
+            .unwrap();
+        assert_eq!(terminations, vec![Some("Time forfeit".to_string()), None]);
+    }
+
+    #[test]
+    fn import_players_by_fide_id() {
+        let pgn = r#"[White "Nguyen, Minh"]
+[Black "Carlsen, Magnus"]
+[WhiteFideId "100"]
+[BlackFideId "1503014"]
+[BlackTitle "GM"]
+
+1. e4 e5 *
+
+[White "Nguyen, Minh"]
+[Black "Nguyen, Minh"]
+[WhiteFideId "200"]
+[WhiteTitle "IM"]
+
+1. d4 d5 *
+
+[White "Carlsen, Magnus"]
+[Black "Nguyen, Minh"]
+[WhiteTitle "WGM"]
+[BlackFideId "100"]
+
+1. c4 c5 *
+"#;
+        let mut db = test_db();
+        import_bytes(&mut db, pgn.into(), None);
+        let players: Vec<(Option<String>, Option<String>, Option<i32>)> = players::table
+            .filter(players::id.ne(0))
+            .select((players::name, players::title, players::fide_id))
+            .order(players::id)
+            .load(&mut db)
+            .unwrap();
+        let nguyen = Some("Nguyen, Minh".to_string());
+        let carlsen = Some("Carlsen, Magnus".to_string());
+        assert_eq!(
+            players,
+            vec![
+                (nguyen.clone(), None, Some(100)),
+                (carlsen, Some("GM".to_string()), Some(1503014)),
+                (nguyen, Some("IM".to_string()), Some(200)),
+            ]
+        );
+        let game_players: Vec<(i32, i32)> = games::table
+            .select((games::white_id, games::black_id))
+            .order(games::id)
+            .load(&mut db)
+            .unwrap();
+        // A name without FIDE ID goes to the first namesake
+        assert_eq!(game_players, vec![(1, 2), (3, 1), (2, 1)]);
+    }
+
+    #[test]
+    fn normalize_player_names_to_lastname_first() {
+        for (name, normalized) in [
+            ("Carlsen, Magnus", "Carlsen, Magnus"),
+            ("Carlsen,Magnus", "Carlsen, Magnus"),
+            ("  Magnus   Carlsen ", "Carlsen, Magnus"),
+            ("Carlsen, Magnus (GM)", "Carlsen, Magnus"),
+            ("Carlsen M.", "Carlsen, M."),
+            ("Van Wely, Loek", "Van Wely, Loek"),
+            ("DrNykterstein", "DrNykterstein"),
+            ("Stockfish 16", "Stockfish 16"),
+            ("Caruana,", "Caruana"),
+        ] {
+            assert_eq!(normalize_player_name(name), normalized, "{name}");
+        }
+    }
+
+    #[test]
+    fn import_and_merge_normalized_names() {
+        let pgn = [
+            ("Magnus Carlsen", "Giri, Anish"),
+            ("Carlsen,Magnus", "Anish  Giri"),
+        ]
+        .map(|(white, black)| format!("[White \"{white}\"]\n[Black \"{black}\"]\n\n*\n"))
+        .join("\n");
+        let names = |db: &mut SqliteConnection| -> Vec<Option<String>> {
+            players::table
+                .filter(players::id.ne(0))
+                .order(players::name)
+                .select(players::name)
+                .load(db)
+                .unwrap()
+        };
+        // Each game keeps its own spelling, even once the players are merged
+        let originals = |db: &mut SqliteConnection| -> Vec<(Option<String>, Option<String>)> {
+            games::table
+                .order(games::id)
+                .select((games::white_original_name, games::black_original_name))
+                .load(db)
+                .unwrap()
+        };
+        let expected_originals = vec![
+            (Some("Magnus Carlsen".to_string()), None),
+            (
+                Some("Carlsen,Magnus".to_string()),
+                Some("Anish  Giri".to_string()),
+            ),
+        ];
+        let expected_names = vec![
+            Some("Carlsen, Magnus".to_string()),
+            Some("Giri, Anish".to_string()),
+        ];
+
+        let mut db = test_db();
+        let options = ImportOptions {
+            normalize_names: true,
+            ..Default::default()
+        };
+        import_with(&mut db, pgn.clone().into(), None, options);
+        assert_eq!(names(&mut db), expected_names);
+        assert_eq!(originals(&mut db), expected_originals);
+
+        let mut db = test_db();
+        import_bytes(&mut db, pgn.into(), None);
+        assert_eq!(names(&mut db).len(), 4);
+        assert_eq!(
+            normalize_stored_player_names(&mut db).unwrap(),
+            NameNormalization {
+                renamed: 1,
+                merged: 2,
+                skipped: 0
             }
-        },
-    );
-    game_info.white_openings = white_openings.into_iter().collect();
-    game_info.black_openings = black_openings.into_iter().collect();
-    game_info.won = won.into_inner();
-    game_info.lost = lost.into_inner();
-    game_info.draw = draw.into_inner();
-    game_info.data_per_month = data_per_month.into_iter().collect();
-    game_info.data_per_month = game_info
-        .data_per_month
-        .into_iter()
-        .map(|(month, data)| {
-            let avg_elo = if data.avg_count == 0 {
-                0
+        );
+        assert_eq!(names(&mut db), expected_names);
+        assert_eq!(originals(&mut db), expected_originals);
+        let carlsen: i32 = players::table
+            .filter(players::name.eq("Carlsen, Magnus"))
+            .select(players::id)
+            .first(&mut db)
+            .unwrap();
+        let games = games::table
+            .filter(games::white_id.eq(carlsen))
+            .count()
+            .get_result::<i64>(&mut db)
+            .unwrap();
+        assert_eq!(games, 2);
+    }
+
+    #[test]
+    fn leaderboards_break_ties_by_id() {
+        let pgn = [
+            ("Alice", "2500", "Bob", "2400", "1-0"),
+            ("Alice", "2500", "Carol", "2600", "1/2-1/2"),
+            ("Bob", "2400", "Carol", "2600", "0-1"),
+            ("Dave", "2600", "Alice", "2500", "0-1"),
+            ("Dave", "2600", "?", "?", "1-0"),
+        ]
+        .map(|(white, white_elo, black, black_elo, result)| {
+            let black = if black == "?" {
+                String::new()
             } else {
-                data.avg_elo / data.avg_count
+                format!("[Black \"{black}\"]\n[BlackElo \"{black_elo}\"]\n")
             };
-            (
-                month,
-                MonthData {
-                    count: data.count,
-                    avg_elo,
-                    avg_count: data.avg_count,
-                },
+            format!(
+                "[White \"{white}\"]\n[WhiteElo \"{white_elo}\"]\n{black}[Result \"{result}\"]\n\n{result}\n"
             )
         })
-        .collect();
+        .join("\n");
+        let mut db = test_db();
+        import_bytes(&mut db, pgn.into(), None);
+        let names = |players: &[Player]| -> Vec<String> {
+            players.iter().filter_map(|p| p.name.clone()).collect()
+        };
 
-    // sort openings by count
-    game_info.white_openings.sort_by(|(_, a), (_, b)| b.cmp(a));
-    game_info.black_openings.sort_by(|(_, a), (_, b)| b.cmp(a));
+        let boards = leaderboards(&mut db, 2).unwrap();
+        assert_eq!(
+            names(&boards.most_games),
+            vec!["Alice", "Bob", "Carol", "Dave"]
+        );
+        // Carol and Dave share the highest rating, Carol comes first by the lower ID
+        assert_eq!(
+            names(&boards.highest_rated),
+            vec!["Carol", "Dave", "Alice", "Bob"]
+        );
+        let best: Vec<(String, i32, f64)> = boards
+            .best_score
+            .into_iter()
+            .map(|s| (s.player.name.unwrap(), s.scored_games, s.score))
+            .collect();
+        assert_eq!(
+            best,
+            vec![
+                ("Carol".to_string(), 2, 75.0),
+                ("Alice".to_string(), 3, 2.5 / 3.0 * 100.0),
+                ("Dave".to_string(), 2, 50.0),
+                ("Bob".to_string(), 2, 0.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn autocomplete_players_by_prefix() {
+        let pgn = [
+            ("Carlsen, Magnus", "Caruana, Fabiano"),
+            ("Carlsen, Magnus", "Magnusson, Erik"),
+            ("Carlsen, Magnus", "Caruana, Fabiano"),
+            ("carlos", "Giri, Anish"),
+        ]
+        .map(|(white, black)| format!("[White \"{white}\"]\n[Black \"{black}\"]\n\n*\n"))
+        .join("\n");
+        let mut db = test_db();
+        import_bytes(&mut db, pgn.into(), None);
+        db.batch_execute(INDEXES_SQL).unwrap();
+        let names = |db: &mut SqliteConnection, prefix: &str, limit: u32| -> Vec<String> {
+            players_by_prefix(db, prefix, limit)
+                .unwrap()
+                .into_iter()
+                .filter_map(|p| p.name)
+                .collect()
+        };
+
+        assert_eq!(
+            names(&mut db, "CAR", 5),
+            vec!["Carlsen, Magnus", "Caruana, Fabiano", "carlos"]
+        );
+        assert_eq!(
+            names(&mut db, "car", 2),
+            vec!["Carlsen, Magnus", "Caruana, Fabiano"]
+        );
+        // The surname, then the given name after the comma
+        assert_eq!(
+            names(&mut db, "magnus", 5),
+            vec!["Carlsen, Magnus", "Magnusson, Erik"]
+        );
+        assert_eq!(names(&mut db, "anish", 5), vec!["Giri, Anish"]);
+        assert!(names(&mut db, "  ", 5).is_empty());
+
+        let plan: Vec<QueryPlanRow> = sql_query(format!(
+            "EXPLAIN QUERY PLAN SELECT ID FROM Players WHERE {GIVEN_NAME_SQL} >= 'a' AND {GIVEN_NAME_SQL} < 'b'"
+        ))
+        .load(&mut db)
+        .unwrap();
+        assert!(plan
+            .iter()
+            .any(|row| row.detail.contains("players_given_name_idx")));
+    }
+
+    #[test]
+    fn build_indexes_creates_missing_ones() {
+        let mut db = test_db();
+        let all = get_missing_indexes(&mut db).unwrap();
+        assert_eq!(all.len(), 10);
+        assert!(all.contains(&"games_hash_idx".to_string()));
+
+        db.batch_execute("CREATE INDEX games_date_idx ON Games(Date);")
+            .unwrap();
+        let mut progress = Vec::new();
+        let built = build_missing_indexes(&mut db, false, |p| progress.push(p)).unwrap();
+        assert_eq!(built.len(), 9);
+        assert_eq!(progress.last(), Some(&100.0));
+        assert!(get_missing_indexes(&mut db).unwrap().is_empty());
+
+        let rebuilt = build_missing_indexes(&mut db, true, |_| {}).unwrap();
+        assert_eq!(rebuilt, all);
+    }
+
+    #[test]
+    fn import_caps_skipped_games() {
+        let pgn = "1. e4 e5 2. Ke3 *\n\n".repeat(5);
+        let options = ImportOptions {
+            max_reported_skips: Some(2),
+            ..Default::default()
+        };
+        let (_, importer) = import_with(&mut test_db(), pgn.into(), None, options);
+        assert_eq!(importer.illegal_move, 5);
+        let indexes: Vec<u32> = importer.skipped.iter().map(|s| s.index).collect();
+        assert_eq!(indexes, vec![1, 2]);
+    }
 
-    println!("get_players_game_info {:?}: {:?}", file, timer.elapsed());
+    #[test]
+    fn import_dirty_elo_headers() {
+        let pgn = r#"[White "Alice"]
+[Black "Bob"]
+[WhiteElo "2345?"]
+[BlackElo "Unrated"]
+
+1. e4 e5 *
+
+[White "Bob"]
+[Black "Alice"]
+[WhiteElo ""]
+[BlackElo " 2100 "]
+
+1. d4 d5 *
+"#;
+        let mut db = test_db();
+        let (imported, importer) = import_with(&mut db, pgn.into(), None, ImportOptions::default());
+        assert_eq!((imported, importer.invalid_headers), (2, 1));
+        let elos: Vec<(Option<i32>, Option<i32>)> = games::table
+            .select((games::white_elo, games::black_elo))
+            .order(games::id)
+            .load(&mut db)
+            .unwrap();
+        assert_eq!(elos, vec![(Some(2345), None), (None, Some(2100))]);
+    }
 
-    Ok(game_info)
-}
+    #[test]
+    fn import_deduplicate() {
+        let options = ImportOptions {
+            deduplicate: true,
+            ..Default::default()
+        };
+        let mut db = test_db();
+        let pgn = format!("{TWO_GAMES_PGN}\n{TWO_GAMES_PGN}");
+        let (imported, importer) = import_with(&mut db, pgn.into(), None, options.clone());
+        assert_eq!((imported, importer.duplicates), (2, 2));
+
+        let mut importer = Importer::new(None, options);
+        importer.hashes = load_game_hashes(&mut db).unwrap();
+        let mut count = 0;
+        let reader = std::io::Cursor::new(TWO_GAMES_PGN);
+        import_games(&mut db, reader, &mut importer, &mut count, None, |_| {}).unwrap();
+        assert_eq!((count, importer.duplicates), (0, 2));
+    }
 
-#[tauri::command]
-pub async fn delete_database(
-    file: PathBuf,
-    state: tauri::State<'_, AppState>,
-) -> Result<(), Error> {
-    let pool = &state.connection_pool;
-    let path_str = file.to_str().unwrap();
-    pool.remove(path_str);
+    #[test]
+    fn import_exclude_bot_games() {
+        let pgn = format!("[WhiteTitle \"BOT\"]\n\n1. e4 e5 *\n\n{TWO_GAMES_PGN}");
+        let (imported, importer) =
+            import_with(&mut test_db(), pgn.clone().into(), None, Default::default());
+        assert_eq!((imported, importer.bot_games_included), (3, 1));
+
+        let options = ImportOptions {
+            exclude_bot_games: true,
+            ..Default::default()
+        };
+        let (imported, importer) = import_with(&mut test_db(), pgn.into(), None, options);
+        assert_eq!((imported, importer.bot_games_excluded), (2, 1));
+    }
 
-    // delete file
-    remove_file(path_str)?;
-    Ok(())
-}
+    #[test]
+    fn import_chess960() {
+        let game = r#"[FEN "bqnbrkrn/pppppppp/8/8/8/8/PPPPPPPP/BQNBRKRN w KQkq - 0 1"]
+
+1. O-O O-O *
+"#;
+        let pgn =
+            format!("[Variant \"Chess960\"]\n{game}\n[Variant \"Atomic\"]\n\n1. e4 *\n\n{game}");
+        let mut db = test_db();
+        let (imported, importer) = import_with(&mut db, pgn.into(), None, Default::default());
+        let summary = importer.summary(imported);
+        assert_eq!(summary.imported, 1);
+        assert_eq!(summary.unsupported_variant, 1);
+        // Without the header the castling rights aren't valid in standard chess
+        assert_eq!(summary.invalid_fen, 1);
+
+        let (variant, moves): (Option<String>, Vec<u8>) = games::table
+            .select((games::variant, games::moves))
+            .first(&mut db)
+            .unwrap();
+        assert_eq!(variant.as_deref(), Some("Chess960"));
+        let fen = Fen::from_ascii(b"bqnbrkrn/pppppppp/8/8/8/8/PPPPPPPP/BQNBRKRN w KQkq - 0 1");
+        assert_eq!(
+            decode_moves(moves, fen.unwrap()).unwrap(),
+            vec!["O-O", "O-O"]
+        );
+    }
 
-#[tauri::command]
-pub async fn delete_duplicated_games(
-    file: PathBuf,
-    state: tauri::State<'_, AppState>,
-) -> Result<(), Error> {
-    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    #[test]
+    fn normalize_pgn_dates() {
+        assert_eq!(normalize_date("2023.01.05").as_deref(), Some("2023-01-05"));
+        assert_eq!(normalize_date("2023.??.??").as_deref(), Some("2023-00-00"));
+        assert_eq!(normalize_date("2023.04.??").as_deref(), Some("2023-04-00"));
+        assert_eq!(normalize_date("2023-01-05").as_deref(), Some("2023-01-05"));
+        assert_eq!(normalize_date("????.??.??"), None);
+        assert_eq!(pgn_date("2023-04-00"), "2023.04.??");
+        assert_eq!(pgn_date("2023-01-05"), "2023.01.05");
+    }
 
-    db.batch_execute(
-        "
-        DELETE FROM Games
-        WHERE ID IN (
-            SELECT ID
-            FROM (
-                SELECT ID,
-                    ROW_NUMBER() OVER (PARTITION BY EventID, SiteID, Round, WhiteID, BlackID, Moves, Date, UTCTime ORDER BY ID) AS RowNum
-                FROM Games
-            ) AS Subquery
-            WHERE RowNum > 1
+    #[test]
+    fn import_clocks_and_evals() {
+        let pgn = r#"[White "Alice"]
+
+1. e4 { [%eval 0.31] [%clk 0:03:00] } 1... e5 { [%clk 0:02:58.5] } 2. Qh5 { [%eval #-3] } *
+"#;
+        let options = ImportOptions {
+            keep_clocks_and_evals: true,
+            ..Default::default()
+        };
+        let mut db = test_db();
+        import_with(&mut db, pgn.into(), None, options);
+        let (clocks, evals): (Option<Vec<u8>>, Option<Vec<u8>>) = games::table
+            .select((games::clocks, games::evals))
+            .first(&mut db)
+            .unwrap();
+        assert_eq!(decode_clocks(&clocks.unwrap()), vec![180_000, 178_500]);
+        assert_eq!(
+            decode_evals(&evals.unwrap()),
+            vec![31, NO_EVAL, -MATE_EVAL + 3]
         );
-        ",
-    )?;
 
-    Ok(())
-}
+        import_bytes(&mut db, TWO_GAMES_PGN.into(), None);
+        let clocks: Option<Vec<u8>> = games::table
+            .select(games::clocks)
+            .order(games::id.desc())
+            .first(&mut db)
+            .unwrap();
+        assert_eq!(clocks, None);
+    }
 
-#[tauri::command]
-pub async fn delete_empty_games(
-    file: PathBuf,
-    state: tauri::State<'_, AppState>,
-) -> Result<(), Error> {
-    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    #[test]
+    fn import_resume_from_checkpoint() {
+        let mut db = test_db();
+        let options = ImportOptions {
+            batch_size: Some(1),
+            ..Default::default()
+        };
+        let checkpoint = ImportCheckpoint {
+            files: Vec::new(),
+            timestamp: None,
+            options: options.clone(),
+            create_indexes: false,
+            file_index: 0,
+            games_read: 0,
+            games_inserted: 0,
+        };
+        let mut importer = Importer::new(None, options.clone());
+        let mut count = 0;
+        let reader = std::io::Cursor::new(TWO_GAMES_PGN);
+        import_games(
+            &mut db,
+            reader,
+            &mut importer,
+            &mut count,
+            Some(&checkpoint),
+            |_| {},
+        )
+        .unwrap();
+        let saved = ImportCheckpoint::load(&mut db).unwrap().unwrap();
+        assert_eq!((saved.games_read, saved.games_inserted), (2, 2));
+
+        // Pretend only the first game had been committed
+        let mut importer = Importer::new(None, options);
+        importer.skip_games = 1;
+        let mut count = 0;
+        let reader = std::io::Cursor::new(TWO_GAMES_PGN);
+        import_games(&mut db, reader, &mut importer, &mut count, None, |_| {}).unwrap();
+        assert_eq!(count, 1);
+
+        ImportCheckpoint::clear(&mut db).unwrap();
+        assert!(ImportCheckpoint::load(&mut db).unwrap().is_none());
+    }
 
-    diesel::delete(games::table.filter(games::ply_count.eq(0))).execute(db)?;
+    #[test]
+    fn import_classify_openings() {
+        let options = ImportOptions {
+            classify_openings: true,
+            ..Default::default()
+        };
+        let pgn = "[ECO \"A00\"]\n\n1. e4 e5 2. Ke2 *\n\n1. e4 e5 2. Ke2 *\n";
+        let mut db = test_db();
+        let (imported, importer) = import_with(&mut db, pgn.into(), None, options);
+        assert_eq!((imported, importer.classified), (2, 1));
+        let ecos: Vec<Option<String>> = games::table
+            .select(games::eco)
+            .order(games::id)
+            .load(&mut db)
+            .unwrap();
+        assert_eq!(ecos, vec![Some("A00".to_string()), Some("C20".to_string())]);
+    }
 
-    Ok(())
-}
+    #[test]
+    fn import_records_failed_games() {
+        let mut db = test_db();
+        // Only one game per white player can be inserted
+        db.batch_execute("CREATE UNIQUE INDEX white_idx ON Games(WhiteID);")
+            .unwrap();
+        let pgn = format!("{TWO_GAMES_PGN}\n{TWO_GAMES_PGN}");
+        let mut importer = Importer::new(None, Default::default());
+        importer.writer.source = Some("games.pgn".to_string());
+        let mut imported = 0;
+        import_games(
+            &mut db,
+            pgn.as_bytes(),
+            &mut importer,
+            &mut imported,
+            None,
+            |_| {},
+        )
+        .unwrap();
+        let summary = importer.summary(imported);
+        assert_eq!((summary.imported, summary.failed), (2, 2));
+        assert_eq!(summary.errors[0].file.as_deref(), Some("games.pgn"));
+        assert_eq!(summary.errors[0].index, 3);
+        assert_eq!(summary.errors[0].game, "Alice - Bob, Test, ?");
+    }
 
-struct PgnGame {
-    event: Option<String>,
-    site: Option<String>,
-    date: Option<String>,
-    round: Option<String>,
-    white: Option<String>,
-    black: Option<String>,
-    result: Option<String>,
-    time_control: Option<String>,
-    eco: Option<String>,
-    white_elo: Option<String>,
-    black_elo: Option<String>,
-    ply_count: Option<String>,
-    fen: Option<String>,
-    moves: Option<Vec<String>>,
-}
+    #[test]
+    fn import_inserts_no_phantom_game() {
+        let mut db = test_db();
+        import_bytes(&mut db, TWO_GAMES_PGN.into(), None);
+        let games: i64 = games::table.count().get_result(&mut db).unwrap();
+        assert_eq!(games, 2);
+
+        // An unterminated final game is still imported once
+        let mut db = test_db();
+        let pgn = format!("{TWO_GAMES_PGN}\n1. e4 e5");
+        assert_eq!(import_bytes(&mut db, pgn.into(), None), 3);
+        let games: i64 = games::table.count().get_result(&mut db).unwrap();
+        assert_eq!(games, 3);
+    }
 
-impl PgnGame {
-    fn write(&self, writer: &mut impl Write) -> Result<(), Error> {
-        writeln!(
-            writer,
-            "[Event \"{}\"]",
-            self.event.as_deref().unwrap_or("")
-        )?;
-        writeln!(writer, "[Site \"{}\"]", self.site.as_deref().unwrap_or(""))?;
-        writeln!(writer, "[Date \"{}\"]", self.date.as_deref().unwrap_or(""))?;
-        writeln!(
-            writer,
-            "[Round \"{}\"]",
-            self.round.as_deref().unwrap_or("")
-        )?;
-        writeln!(
-            writer,
-            "[White \"{}\"]",
-            self.white.as_deref().unwrap_or("")
-        )?;
-        writeln!(
-            writer,
-            "[Black \"{}\"]",
-            self.black.as_deref().unwrap_or("")
-        )?;
-        writeln!(
-            writer,
-            "[Result \"{}\"]",
-            self.result.as_deref().unwrap_or("*")
-        )?;
-        if let Some(time_control) = self.time_control.as_deref() {
-            writeln!(writer, "[TimeControl \"{}\"]", time_control)?;
-        }
-        if let Some(eco) = self.eco.as_deref() {
-            writeln!(writer, "[ECO \"{}\"]", eco)?;
-        }
-        if let Some(white_elo) = self.white_elo.as_deref() {
-            writeln!(writer, "[WhiteElo \"{}\"]", white_elo)?;
-        }
-        if let Some(black_elo) = self.black_elo.as_deref() {
-            writeln!(writer, "[BlackElo \"{}\"]", black_elo)?;
-        }
-        if let Some(ply_count) = self.ply_count.as_deref() {
-            writeln!(writer, "[PlyCount \"{}\"]", ply_count)?;
-        }
-        if let Some(fen) = self.fen.as_deref() {
-            writeln!(writer, "[SetUp \"1\"]")?;
-            writeln!(writer, "[FEN \"{}\"]", fen)?;
-        }
-        writeln!(writer)?;
-        for (i, move_) in self.moves.as_ref().unwrap().iter().enumerate() {
-            if i % 2 == 0 {
-                write!(writer, "{}. ", i / 2 + 1)?;
-            }
-            write!(writer, "{} ", move_)?;
-        }
-        match self.result.as_deref() {
-            Some("1-0") => writeln!(writer, "1-0"),
-            Some("0-1") => writeln!(writer, "0-1"),
-            Some("1/2-1/2") => writeln!(writer, "1/2-1/2"),
-            _ => writeln!(writer, "*"),
-        }?;
-        writeln!(writer)?;
-        Ok(())
+    #[test]
+    fn migrate_drops_unique_player_names() {
+        let mut db = test_db();
+        // The Players table of the first versions
+        db.batch_execute(
+            "DROP TABLE Players;
+            CREATE TABLE Players (ID INTEGER PRIMARY KEY, Name TEXT UNIQUE, Elo INTEGER);
+            INSERT INTO Players (ID, Name, Elo) VALUES (0, 'Unknown', NULL), (1, 'Nguyen, Minh', 2400);",
+        )
+        .unwrap();
+        migrate_database(&mut db).unwrap();
+
+        let first = create_player(&mut db, "Nguyen, Minh", Some(1)).unwrap();
+        let second = create_player(&mut db, "Nguyen, Minh", Some(2)).unwrap();
+        assert_eq!((first.id, first.elo), (1, Some(2400)));
+        assert_ne!(second.id, first.id);
+        let indexes: Vec<ColumnInfo> = sql_query("SELECT name FROM pragma_index_list('Players');")
+            .load(&mut db)
+            .unwrap();
+        assert!(indexes.iter().any(|i| i.name == "players_name_idx"));
     }
-}
 
-#[tauri::command]
-pub async fn export_to_pgn(
-    file: PathBuf,
-    dest_file: PathBuf,
-    state: tauri::State<'_, AppState>,
-) -> Result<(), Error> {
-    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    #[test]
+    fn import_stores_result_as_integer() {
+        let mut db = test_db();
+        import_bytes(&mut db, TWO_GAMES_PGN.into(), None);
+        let results: Vec<Option<i32>> = games::table
+            .select(games::result)
+            .order(games::id)
+            .load(&mut db)
+            .unwrap();
+        assert_eq!(results, vec![Some(1), Some(3)]);
+
+        // Databases from older versions stored the result text
+        db.batch_execute("UPDATE Games SET Result = '0-1' WHERE ID = 1;")
+            .unwrap();
+        migrate_database(&mut db).unwrap();
+        let result: Option<i32> = games::table
+            .select(games::result)
+            .filter(games::id.eq(1))
+            .first(&mut db)
+            .unwrap();
+        assert_eq!(
+            result.and_then(GameResult::from_i32),
+            Some(GameResult::BlackWins)
+        );
 
-    let file = OpenOptions::new()
-        .create(true)
-        .write(true)
-        .truncate(true)
-        .open(dest_file)?;
+        // Once migrated, the database isn't scanned again
+        db.batch_execute("UPDATE Games SET Result = '0-1' WHERE ID = 2;")
+            .unwrap();
+        migrate_database(&mut db).unwrap();
+        let kind: String = games::table
+            .select(diesel::dsl::sql::<Text>("typeof(Result)"))
+            .filter(games::id.eq(2))
+            .first(&mut db)
+            .unwrap();
+        assert_eq!(kind, "text");
+    }
 
-    let mut writer = BufWriter::new(file);
+    #[test]
+    fn import_tracks_peak_elo() {
+        let pgn = r#"[White "Alice"]
+[Black "Bob"]
+[WhiteElo "1500"]
+[BlackElo "1700"]
+
+1. e4 e5 *
+
+[White "Carol"]
+[Black "Alice"]
+[BlackElo "1600"]
+
+1. d4 d5 *
+"#;
+        let mut db = test_db();
+        import_bytes(&mut db, pgn.into(), None);
+        let elos = |db: &mut SqliteConnection| -> Vec<(Option<String>, Option<i32>)> {
+            players::table
+                .filter(players::id.ne(0))
+                .select((players::name, players::elo))
+                .order((players::elo.is_null().asc(), players::elo.desc()))
+                .load(db)
+                .unwrap()
+        };
+        let expected = vec![
+            (Some("Bob".to_string()), Some(1700)),
+            (Some("Alice".to_string()), Some(1600)),
+            (Some("Carol".to_string()), None),
+        ];
+        assert_eq!(elos(&mut db), expected);
+
+        diesel::update(players::table)
+            .set(players::elo.eq(None::<i32>))
+            .execute(&mut db)
+            .unwrap();
+        assert_eq!(recompute_peak_elos(&mut db).unwrap(), 2);
+        assert_eq!(elos(&mut db), expected);
+    }
 
-    let (white_players, black_players) = diesel::alias!(players as white, players as black);
-    games::table
-        .inner_join(white_players.on(games::white_id.eq(white_players.field(players::id))))
-        .inner_join(black_players.on(games::black_id.eq(black_players.field(players::id))))
-        .inner_join(events::table.on(games::event_id.eq(events::id)))
-        .inner_join(sites::table.on(games::site_id.eq(sites::id)))
-        .load_iter::<(Game, Player, Player, Event, Site), DefaultLoadingMode>(db)?
-        .flatten()
-        .map(|(game, white, black, event, site)| {
-            let pgn = PgnGame {
-                event: event.name,
-                site: site.name,
-                date: game.date,
-                round: game.round,
-                white: white.name,
-                black: black.name,
-                result: game.result,
-                time_control: game.time_control,
-                eco: game.eco,
-                white_elo: game.white_elo.map(|e| e.to_string()),
-                black_elo: game.black_elo.map(|e| e.to_string()),
-                ply_count: game.ply_count.map(|e| e.to_string()),
-                fen: game.fen.clone(),
-                moves: decode_moves(
-                    game.moves,
-                    if let Some(fen) = game.fen {
-                        Fen::from_ascii(fen.as_bytes()).unwrap_or_default()
-                    } else {
-                        Fen::default()
-                    },
-                )
-                .ok(),
-            };
+    #[test]
+    fn import_sources_accumulate() {
+        let mut db = test_db();
+        assert!(ImportSource::load_all(&mut db).unwrap().is_empty());
+
+        let options = ImportOptions::default();
+        ImportSource::record(
+            &mut db,
+            vec![ImportSource::new("a.pgn".into(), Some(10), &options)],
+        )
+        .unwrap();
+        ImportSource::record(
+            &mut db,
+            vec![ImportSource::new("b.pgn".into(), None, &options)],
+        )
+        .unwrap();
+        let sources = ImportSource::load_all(&mut db).unwrap();
+        let files: Vec<&str> = sources.iter().map(|s| s.source_file.as_str()).collect();
+        assert_eq!(files, vec!["a.pgn", "b.pgn"]);
+        assert_eq!(sources[0].source_size_bytes, Some(10));
+        assert!(chrono::DateTime::parse_from_rfc3339(&sources[1].imported_at).is_ok());
+    }
 
-            pgn.write(&mut writer)?;
+    #[test]
+    fn games_csv_quotes_and_leaves_nulls_empty() {
+        let pgn = r#"[Event "Test"]
+[Site "Oslo"]
+[White "Carlsen, Magnus"]
+[Black "Nakamura, Hikaru"]
+[WhiteElo "2850"]
+[Result "1-0"]
+
+1. e4 e5 1-0
+"#;
+        let mut db = test_db();
+        import_bytes(&mut db, pgn.into(), None);
+
+        let mut csv = Vec::new();
+        let written = write_games_csv(&mut db, &GameQuery::default(), &mut csv).unwrap();
+        assert_eq!(written, 1);
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], CSV_HEADER.join(","));
+        assert!(lines[1]
+            .ends_with(r#","Carlsen, Magnus",2850,"Nakamura, Hikaru",,1-0,,Test,Oslo,,,,2"#));
+    }
 
-            Ok(())
-        })
-        .collect::<Result<Vec<_>, Error>>()?;
-    Ok(())
-}
+    #[test]
+    fn epd_positions_after_ply() {
+        let pgn = r#"[Result "1-0"]
 
-#[tauri::command]
-pub async fn delete_db_game(
-    file: PathBuf,
-    game_id: i32,
-    state: tauri::State<'_, AppState>,
-) -> Result<(), Error> {
-    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+1. e4 e5 2. Nf3 1-0
 
-    diesel::delete(games::table.filter(games::id.eq(game_id))).execute(db)?;
+[Result "1/2-1/2"]
 
-    Ok(())
-}
+1. e4 e5 2. Nc3 1/2-1/2
 
-#[tauri::command]
-#[specta::specta]
-pub async fn merge_players(
-    file: PathBuf,
-    player1: i32,
-    player2: i32,
-    state: tauri::State<'_, AppState>,
-) -> Result<(), Error> {
-    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+[Result "0-1"]
 
-    // Check if the players never played against each other
-    let count: i64 = games::table
-        .filter(games::white_id.eq(player1).and(games::black_id.eq(player2)))
-        .or_filter(games::white_id.eq(player2).and(games::black_id.eq(player1)))
-        .limit(1)
-        .count()
-        .get_result(db)?;
+1. d4 d5 0-1
 
-    if count > 0 {
-        return Err(Error::NotDistinctPlayers);
-    }
+[Result "1-0"]
 
-    diesel::update(games::table.filter(games::white_id.eq(player1)))
-        .set(games::white_id.eq(player2))
-        .execute(db)?;
-    diesel::update(games::table.filter(games::black_id.eq(player1)))
-        .set(games::black_id.eq(player2))
-        .execute(db)?;
+1. e4 1-0
 
-    diesel::delete(players::table.filter(players::id.eq(player1))).execute(db)?;
+[FEN "4k3/8/8/8/8/8/8/4K3 w - - 0 1"]
+[Result "1/2-1/2"]
 
-    let player_count: i64 = players::table.count().get_result(db)?;
-    diesel::insert_into(info::table)
-        .values((
-            info::name.eq("PlayerCount"),
-            info::value.eq(player_count.to_string()),
-        ))
-        .on_conflict(info::name)
-        .do_update()
-        .set(info::value.eq(player_count.to_string()))
-        .execute(db)?;
+1. Kd2 Kd7 1/2-1/2
+"#;
+        let mut db = test_db();
+        import_bytes(&mut db, pgn.into(), None);
 
-    Ok(())
-}
+        let mut epd = Vec::new();
+        let written = write_epd_positions(&mut db, 2, 1, false, &mut epd).unwrap();
+        assert_eq!(
+            written,
+            EpdExport {
+                positions: 2,
+                skipped: 0
+            }
+        );
+        assert_eq!(
+            String::from_utf8(epd).unwrap(),
+            "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - c0 \"count 2, +1 =1 -0\";\n\
+             rnbqkbnr/ppp1pppp/8/3p4/3P4/8/PPP1PPPP/RNBQKBNR w KQkq - c0 \"count 1, +0 =0 -1\";\n"
+        );
 
-#[tauri::command]
-pub fn clear_games(state: tauri::State<'_, AppState>) {
-    let mut state = state.db_cache.lock().unwrap();
-    state.clear();
-}
+        let mut epd = Vec::new();
+        assert_eq!(
+            write_epd_positions(&mut db, 2, 2, false, &mut epd)
+                .unwrap()
+                .positions,
+            1
+        );
+        let mut epd = Vec::new();
+        assert_eq!(
+            write_epd_positions(&mut db, 2, 1, true, &mut epd)
+                .unwrap()
+                .positions,
+            3
+        );
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        // A game whose second move can't be replayed isn't counted at the first
+        db.batch_execute("UPDATE Games SET Moves = X'0C64' WHERE ID = 3;")
+            .unwrap();
+        let mut epd = Vec::new();
+        let written = write_epd_positions(&mut db, 2, 1, false, &mut epd).unwrap();
+        assert_eq!(
+            written,
+            EpdExport {
+                positions: 1,
+                skipped: 1
+            }
+        );
+    }
 
     #[test]
-    fn home_row() {
-        use shakmaty::Board;
+    fn copy_games_remaps_references() {
+        let pgn = r#"[Event "Wijk aan Zee"]
+[Site "NED"]
+[White "Carlsen, Magnus"]
+[Black "Giri, Anish"]
+[WhiteElo "2830"]
+[Result "1-0"]
+
+1. e4 { Best by test } e5 1-0
+
+[Event "Tata Steel"]
+[White "Giri, Anish"]
+[Black "Caruana, Fabiano"]
+[Result "1/2-1/2"]
+
+1. d4 d5 1/2-1/2
+"#;
+        let mut src = test_db();
+        let options = ImportOptions {
+            keep_annotations: true,
+            ..Default::default()
+        };
+        import_with(&mut src, pgn.into(), None, options);
+        // Shift the IDs of the source so they can't line up by chance
+        create_player(&mut src, "Nobody", None).unwrap();
+        import_bytes(&mut src, pgn.into(), None);
+        let carlsen: i32 = players::table
+            .filter(players::name.eq("Carlsen, Magnus"))
+            .select(players::id)
+            .first(&mut src)
+            .unwrap();
+
+        let mut dest = test_db();
+        let query = GameQuery {
+            player1: Some(carlsen),
+            sides: Some(Sides::Any),
+            ..Default::default()
+        };
+        let copied = copy_games(
+            &mut src,
+            &mut dest,
+            &mut NameCache::default(),
+            &query,
+            |_| {},
+        )
+        .unwrap();
+        assert_eq!(copied, 2);
+        update_info_counts(&mut dest).unwrap();
+
+        let (white_players, black_players) = diesel::alias!(players as white, players as black);
+        let games: Vec<(
+            Option<String>,
+            Option<String>,
+            Option<String>,
+            Option<String>,
+        )> = games::table
+            .inner_join(white_players.on(games::white_id.eq(white_players.field(players::id))))
+            .inner_join(black_players.on(games::black_id.eq(black_players.field(players::id))))
+            .inner_join(events::table.on(games::event_id.eq(events::id)))
+            .inner_join(sites::table.on(games::site_id.eq(sites::id)))
+            .select((
+                white_players.field(players::name),
+                black_players.field(players::name),
+                events::name,
+                sites::name,
+            ))
+            .load(&mut dest)
+            .unwrap();
+        let expected = (
+            Some("Carlsen, Magnus".to_string()),
+            Some("Giri, Anish".to_string()),
+            Some("Wijk aan Zee".to_string()),
+            Some("NED".to_string()),
+        );
+        assert_eq!(games, vec![expected.clone(), expected]);
+
+        let comments: Vec<Option<String>> = comments::table
+            .select(comments::comment)
+            .load(&mut dest)
+            .unwrap();
+        assert_eq!(comments, vec![Some("Best by test".to_string())]);
+        let elo: Option<i32> = players::table
+            .filter(players::name.eq("Carlsen, Magnus"))
+            .select(players::elo)
+            .first(&mut dest)
+            .unwrap();
+        assert_eq!(elo, Some(2830));
+        // Unknown, Carlsen and Giri
+        let player_count: String = info::table
+            .filter(info::name.eq("PlayerCount"))
+            .select(info::value.assume_not_null())
+            .first(&mut dest)
+            .unwrap();
+        assert_eq!(player_count, "3");
+    }
 
-        let pawn_home = get_pawn_home(&Board::default());
-        assert_eq!(pawn_home, 0b1111111111111111);
+    #[test]
+    fn games_jsonl_round_trip() {
+        let pgn = r#"[Event "Test"]
+[White "O'Kelly de Galway, Alberic"]
+[Black "Smith"]
+[Result "1-0"]
+
+1. e4 { Main } e5 (1... c5) 2. Qh5 1-0
+"#;
+        // A file, as the comments are loaded on a second connection
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("games.db3");
+        let mut db = SqliteConnection::establish(path.to_str().unwrap()).unwrap();
+        db.batch_execute(CREATE_TABLES_SQL).unwrap();
+        let options = ImportOptions {
+            keep_annotations: true,
+            ..Default::default()
+        };
+        import_with(&mut db, pgn.into(), None, options);
+        let mut comment_db = SqliteConnection::establish(path.to_str().unwrap()).unwrap();
+
+        let mut jsonl = Vec::new();
+        let written = write_games_jsonl(
+            &mut db,
+            &mut comment_db,
+            &GameQuery::default(),
+            true,
+            true,
+            &mut jsonl,
+        )
+        .unwrap();
+        assert_eq!(written, 1);
+        let jsonl = String::from_utf8(jsonl).unwrap();
+        let lines: Vec<&str> = jsonl.lines().collect();
+        assert_eq!(lines.len(), 1);
+
+        let game: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(game["white"], "O'Kelly de Galway, Alberic");
+        assert_eq!(game["result"], "1-0");
+        assert_eq!(game["moves"], serde_json::json!(["e4", "e5", "Qh5"]));
+        assert_eq!(game["uci"], serde_json::json!(["e2e4", "e7e5", "d1h5"]));
+        assert_eq!(game["comments"][0]["comment"], "Main");
+
+        let mut jsonl = Vec::new();
+        write_games_jsonl(
+            &mut db,
+            &mut comment_db,
+            &GameQuery::default(),
+            false,
+            false,
+            &mut jsonl,
+        )
+        .unwrap();
+        let game: serde_json::Value = serde_json::from_slice(&jsonl).unwrap();
+        assert!(game.get("moves").is_none());
+        assert!(game.get("uci").is_none());
+    }
 
-        let pawn_home = get_pawn_home(
-            &Board::from_ascii_board_fen(b"8/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/8").unwrap(),
+    #[test]
+    fn export_games_of_a_player() {
+        let pgn = format!(
+            r#"{TWO_GAMES_PGN}
+[White "Alice"]
+[Black "Alice"]
+[Date "2020.01.01"]
+
+1. e4 *
+
+[White "Alice"]
+[Black "Alice"]
+[WhiteFideId "1"]
+[BlackFideId "2"]
+[Date "2021.01.01"]
+
+1. d4 *
+"#
         );
-        assert_eq!(pawn_home, 0b1110111111101111);
+        // A file, as the comments are loaded on a second connection
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("games.db3");
+        let mut db = SqliteConnection::establish(path.to_str().unwrap()).unwrap();
+        db.batch_execute(CREATE_TABLES_SQL).unwrap();
+        import_bytes(&mut db, pgn.into(), None);
+        let mut comment_db = SqliteConnection::establish(path.to_str().unwrap()).unwrap();
+        let alice: i32 = players::table
+            .filter(players::fide_id.eq(1))
+            .select(players::id)
+            .first(&mut db)
+            .unwrap();
+
+        let mut export = |side| {
+            let mut pgn = Vec::new();
+            let written =
+                write_player_games(&mut db, &mut comment_db, alice, side, &mut pgn).unwrap();
+            (written, String::from_utf8(pgn).unwrap())
+        };
+        // The game against the same row is written once, the one against the
+        // namesake with another FIDE ID too
+        let (written, pgn) = export(PlayerSide::Both);
+        assert_eq!(written, 4);
+        assert_eq!(pgn.matches("[Event ").count(), 4);
+        assert_eq!(pgn.matches("[Date \"2020.01.01\"]").count(), 1);
+        assert_eq!(export(PlayerSide::White).0, 3);
+        assert_eq!(export(PlayerSide::Black).0, 2);
+    }
 
-        let pawn_home = get_pawn_home(&Board::from_ascii_board_fen(b"8/8/8/8/8/8/8/8").unwrap());
-        assert_eq!(pawn_home, 0b0000000000000000);
+    #[test]
+    fn same_file_through_other_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("games.db3");
+        File::create(&path).unwrap();
+        let other = dir.path().join(".").join("games.db3");
+        assert!(is_same_file(&path, &other));
+        assert!(!is_same_file(&path, &dir.path().join("other.db3")));
+    }
+
+    #[test]
+    fn compressed_exports_can_be_imported() {
+        let dir = tempfile::tempdir().unwrap();
+        let cases = [
+            ("games.pgn.gz", None, GZIP_MAGIC),
+            ("games.pgn.bz2", None, BZIP2_MAGIC),
+            ("games.pgn.zst", None, ZSTD_MAGIC),
+            // An explicit compression wins over the extension
+            ("games.pgn", Some(ExportCompression::Zstd), ZSTD_MAGIC),
+        ];
+        for (name, compression, magic) in cases {
+            let path = dir.path().join(name);
+            ExportWriter::export(&path, compression, |writer| {
+                Ok(writer.write_all(TWO_GAMES_PGN.as_bytes())?)
+            })
+            .unwrap();
+            let bytes = std::fs::read(&path).unwrap();
+            assert!(bytes.starts_with(magic), "{name}");
+            let extension = path.extension().and_then(|e| e.to_str());
+            assert_eq!(import_bytes(&mut test_db(), bytes, extension), 2, "{name}");
+        }
+    }
+
+    #[test]
+    fn failed_exports_are_still_finished() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("games.pgn.zst");
+        let result: Result<(), Error> = ExportWriter::export(&path, None, |writer| {
+            writer.write_all(TWO_GAMES_PGN.as_bytes())?;
+            Err(Error::NoMatchingGames)
+        });
+        assert!(result.is_err());
+
+        let mut pgn = String::new();
+        open_pgn(&path).unwrap().read_to_string(&mut pgn).unwrap();
+        assert_eq!(pgn, TWO_GAMES_PGN);
+    }
+
+    #[test]
+    fn import_pgn_text_returns_ids() {
+        let mut db = test_db();
+        import_bytes(&mut db, TWO_GAMES_PGN.into(), None);
+
+        let ids = import_pgn_text(&mut db, TWO_GAMES_PGN, ImportOptions::default()).unwrap();
+        assert_eq!(ids, vec![3, 4]);
+        let players: i64 = players::table.count().get_result(&mut db).unwrap();
+        // Alice, Bob and the Unknown placeholder
+        assert_eq!(players, 3);
+
+        for pgn in ["", "1. e4 e5 2. Ke3 *"] {
+            assert!(matches!(
+                import_pgn_text(&mut db, pgn, ImportOptions::default()),
+                Err(Error::NoGamesInPgn)
+            ));
+        }
+    }
+
+    #[test]
+    fn backfilled_material_signatures_match_the_imported_ones() {
+        let pgn = [
+            "1. e4 d5 2. exd5 Qxd5 *",
+            "1. d4 d5 2. c4 dxc4 3. e3 *",
+            "1. e4 e5 2. Nf3 Nc6 *",
+        ]
+        .map(|moves| format!("[Event \"?\"]\n\n{moves}\n"))
+        .join("\n");
+        let mut db = test_db();
+        import_bytes(&mut db, pgn.into(), None);
+        let imported: Vec<Option<Vec<u8>>> = games::table
+            .select(games::material_signatures)
+            .order(games::id)
+            .load(&mut db)
+            .unwrap();
+        // The starting position, then one per capture
+        let sizes: Vec<usize> = imported
+            .iter()
+            .map(|s| s.as_ref().unwrap().len() / encoding::SIGNATURE_SIZE)
+            .collect();
+        assert_eq!(sizes, vec![3, 2, 1]);
+
+        // As if imported before they were stored
+        diesel::update(games::table)
+            .set(games::material_signatures.eq(None::<Vec<u8>>))
+            .execute(&mut db)
+            .unwrap();
+        assert_eq!(fill_missing_material_signatures(&mut db).unwrap(), 3);
+        assert_eq!(fill_missing_material_signatures(&mut db).unwrap(), 0);
+        let backfilled: Vec<Option<Vec<u8>>> = games::table
+            .select(games::material_signatures)
+            .order(games::id)
+            .load(&mut db)
+            .unwrap();
+        assert_eq!(backfilled, imported);
+    }
+
+    #[test]
+    fn position_searches_only_replay_the_filtered_games() {
+        let mut db = test_db();
+        import_bytes(&mut db, TWO_GAMES_PGN.into(), None);
+
+        let query = GameQuery {
+            position: Some(PositionQuery::partial_from_fen("8/8/8/8/8/8/8/8").unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(filtered_game_ids(&mut db, &query).unwrap(), None);
+
+        let query = GameQuery {
+            outcome: Some(OutcomeFilter::One(GameResult::Draw)),
+            ..query
+        };
+        let draws: Vec<i32> = games::table
+            .filter(games::result.eq(GameResult::Draw as i32))
+            .select(games::id)
+            .load(&mut db)
+            .unwrap();
+        assert_eq!(filtered_game_ids(&mut db, &query).unwrap(), Some(draws));
+
+        // Only the first game opens with 1. e4
+        let query = GameQuery {
+            outcome: None,
+            contains_move: Some(MoveFilter {
+                san: "e4".to_string(),
+                side: PlayerSide::White,
+                ply_range: Some((1, 1)),
+            }),
+            ..query
+        };
+        let first: i32 = games::table
+            .select(diesel::dsl::min(games::id).assume_not_null())
+            .first(&mut db)
+            .unwrap();
+        assert_eq!(
+            filtered_game_ids(&mut db, &query).unwrap(),
+            Some(vec![first])
+        );
     }
 }
@@ -2,7 +2,7 @@ use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
 use specta::Type;
 
-use crate::db::schema::*;
+use crate::db::{schema::*, GameResult};
 
 #[derive(Debug, Clone, Serialize, Deserialize, Queryable, Identifiable, Type)]
 #[diesel(table_name = puzzles)]
@@ -22,6 +22,11 @@ pub struct Player {
     pub id: i32,
     pub name: Option<String>,
     pub elo: Option<i32>,
+    pub title: Option<String>,
+    pub fide_id: Option<i32>,
+    /// Games of the player as white or black, `None` in databases imported
+    /// before it was counted.
+    pub game_count: Option<i32>,
 }
 
 #[derive(Insertable, Debug)]
@@ -29,6 +34,8 @@ pub struct Player {
 pub struct NewPlayer<'a> {
     pub name: &'a str,
     pub elo: Option<i32>,
+    pub fide_id: Option<i32>,
+    pub game_count: Option<i32>,
 }
 
 struct White(pub Player);
@@ -51,13 +58,40 @@ pub struct Game {
     pub black_elo: Option<i32>,
     pub white_material: i32,
     pub black_material: i32,
-    pub result: Option<String>,
+    pub result: Option<i32>,
     pub time_control: Option<String>,
     pub eco: Option<String>,
     pub ply_count: Option<i32>,
     pub fen: Option<String>,
     pub moves: Vec<u8>,
     pub pawn_home: i32,
+    pub speed: Option<i32>,
+    pub game_hash: Option<i64>,
+    pub white_title: Option<String>,
+    pub black_title: Option<String>,
+    pub variant: Option<String>,
+    pub clocks: Option<Vec<u8>>,
+    pub evals: Option<Vec<u8>>,
+    pub rated: Option<bool>,
+    pub termination: Option<String>,
+    pub max_rating: Option<i32>,
+    pub material_signatures: Option<Vec<u8>>,
+    /// The `White` header, when the name of the player is its normalized form.
+    pub white_original_name: Option<String>,
+    pub black_original_name: Option<String>,
+}
+
+/// A position of the first plies of a game, in the position index.
+#[derive(Insertable, Debug, PartialEq, Eq)]
+#[diesel(table_name = positions)]
+pub struct NewPosition {
+    /// The Polyglot Zobrist hash of the position.
+    pub hash: i64,
+    pub game_id: i32,
+    /// The first ply where the game reached the position.
+    pub ply: i32,
+    /// The encoded move played from the position, `None` if the game ended.
+    pub next_move: Option<i32>,
 }
 
 #[derive(Insertable, Debug)]
@@ -74,13 +108,30 @@ pub struct NewGame<'a> {
     pub black_elo: Option<i32>,
     pub white_material: i32,
     pub black_material: i32,
-    pub result: Option<&'a str>,
+    pub result: Option<i32>,
     pub time_control: Option<&'a str>,
     pub eco: Option<&'a str>,
     pub ply_count: i32,
     pub fen: Option<&'a str>,
     pub moves: &'a [u8],
     pub pawn_home: i32,
+    pub speed: Option<i32>,
+    pub game_hash: Option<i64>,
+    pub white_title: Option<&'a str>,
+    pub black_title: Option<&'a str>,
+    pub variant: Option<&'a str>,
+    pub clocks: Option<&'a [u8]>,
+    pub evals: Option<&'a [u8]>,
+    pub rated: Option<bool>,
+    pub termination: Option<&'a str>,
+    /// Higher rating of the two players, for sorting.
+    pub max_rating: Option<i32>,
+    /// Material of the positions of the mainline, to rule out games without
+    /// replaying them in position searches.
+    pub material_signatures: Option<&'a [u8]>,
+    /// The `White` header, when the name of the player is its normalized form.
+    pub white_original_name: Option<&'a str>,
+    pub black_original_name: Option<&'a str>,
 }
 
 #[derive(Default, Debug, Queryable, Serialize, Deserialize, Identifiable, Clone)]
@@ -107,6 +158,24 @@ pub struct NewEvent<'a> {
     pub name: &'a str,
 }
 
+#[derive(Debug, Queryable, Serialize, Deserialize, Identifiable, Clone)]
+pub struct Comment {
+    pub id: i32,
+    pub game_id: i32,
+    pub ply: Option<i32>,
+    pub comment: Option<String>,
+    pub nags: Option<String>,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = comments)]
+pub struct NewComment<'a> {
+    pub game_id: i32,
+    pub ply: i32,
+    pub comment: Option<&'a str>,
+    pub nags: Option<&'a str>,
+}
+
 #[derive(Queryable, Serialize, Deserialize)]
 pub struct Info {
     pub name: String,
@@ -130,11 +199,22 @@ pub struct NormalizedGame {
     pub black: String,
     pub black_id: i32,
     pub black_elo: Option<i32>,
-    pub result: Option<String>,
+    pub result: Option<GameResult>,
+    /// The `Termination` header, as written in the PGN.
+    pub termination: Option<String>,
     pub time_control: Option<String>,
     pub eco: Option<String>,
+    /// Name of the opening of the ECO code.
+    pub opening_name: Option<String>,
     pub ply_count: Option<i32>,
     pub white_material: i32,
     pub black_material: i32,
     pub moves: String,
+    pub comments: Vec<Comment>,
+    /// Remaining clock time in milliseconds after each mainline move, with
+    /// `NO_CLOCK` for moves without a clock annotation.
+    pub clocks: Option<Vec<u32>>,
+    /// Evaluation in centipawns after each mainline move, see `NO_EVAL` and
+    /// `MATE_EVAL`.
+    pub evals: Option<Vec<i16>>,
 }
@@ -1,26 +1,57 @@
 use crate::db::models::{Event, Game, NewEvent, NewGame, NewPlayer, NewSite, Player, Site};
 use diesel::prelude::*;
 
-/// Creates a new player in the database, and returns the player's ID.
-/// If the player already exists, returns the ID of the existing player.
+/// Creates a new player in the database, and returns it.
+/// If the player already exists, returns the existing player.
+///
+/// Players with a FIDE ID are told apart by it, so namesakes get their own
+/// rows. A player known only by name takes the FIDE ID the first time it's
+/// seen with one.
 pub fn create_player(
     conn: &mut SqliteConnection,
     name: &str,
+    fide_id: Option<i32>,
 ) -> Result<Player, diesel::result::Error> {
     use crate::db::schema::players;
 
-    let new_player = NewPlayer { name, elo: None };
+    let by_name = players::table.filter(players::name.eq(name));
+    if let Some(fide_id) = fide_id {
+        let player = players::table
+            .filter(players::fide_id.eq(fide_id))
+            .first::<Player>(conn)
+            .optional()?;
+        if let Some(player) = player {
+            return Ok(player);
+        }
+        let unidentified = by_name
+            .filter(players::fide_id.is_null())
+            .order(players::id)
+            .first::<Player>(conn)
+            .optional()?;
+        if let Some(player) = unidentified {
+            return diesel::update(players::table.find(player.id))
+                .set(players::fide_id.eq(fide_id))
+                .get_result(conn);
+        }
+    } else {
+        let player = by_name
+            .order(players::id)
+            .first::<Player>(conn)
+            .optional()?;
+        if let Some(player) = player {
+            return Ok(player);
+        }
+    }
 
-    let player = diesel::insert_or_ignore_into(players::table)
+    let new_player = NewPlayer {
+        name,
+        elo: None,
+        fide_id,
+        game_count: Some(0),
+    };
+    diesel::insert_into(players::table)
         .values(&new_player)
-        .execute(conn);
-
-    match player {
-        Ok(_) => players::table
-            .filter(players::name.eq(name))
-            .first::<Player>(conn),
-        Err(e) => Err(e),
-    }
+        .get_result(conn)
 }
 
 pub fn create_event(
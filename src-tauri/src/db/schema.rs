@@ -19,6 +19,12 @@ diesel::table! {
         name -> Nullable<Text>,
         #[sql_name = "Elo"]
         elo -> Nullable<Integer>,
+        #[sql_name = "Title"]
+        title -> Nullable<Text>,
+        #[sql_name = "FideId"]
+        fide_id -> Nullable<Integer>,
+        #[sql_name = "GameCount"]
+        game_count -> Nullable<Integer>,
     }
 }
 
@@ -50,7 +56,7 @@ diesel::table! {
         #[sql_name = "BlackMaterial"]
         black_material -> Integer,
         #[sql_name = "Result"]
-        result -> Nullable<Text>,
+        result -> Nullable<Integer>,
         #[sql_name = "TimeControl"]
         time_control -> Nullable<Text>,
         #[sql_name = "ECO"]
@@ -63,6 +69,32 @@ diesel::table! {
         moves -> Binary,
         #[sql_name = "PawnHome"]
         pawn_home -> Integer,
+        #[sql_name = "Speed"]
+        speed -> Nullable<Integer>,
+        #[sql_name = "GameHash"]
+        game_hash -> Nullable<BigInt>,
+        #[sql_name = "WhiteTitle"]
+        white_title -> Nullable<Text>,
+        #[sql_name = "BlackTitle"]
+        black_title -> Nullable<Text>,
+        #[sql_name = "Variant"]
+        variant -> Nullable<Text>,
+        #[sql_name = "Clocks"]
+        clocks -> Nullable<Binary>,
+        #[sql_name = "Evals"]
+        evals -> Nullable<Binary>,
+        #[sql_name = "Rated"]
+        rated -> Nullable<Bool>,
+        #[sql_name = "Termination"]
+        termination -> Nullable<Text>,
+        #[sql_name = "MaxRating"]
+        max_rating -> Nullable<Integer>,
+        #[sql_name = "MaterialSignatures"]
+        material_signatures -> Nullable<Binary>,
+        #[sql_name = "WhiteOriginalName"]
+        white_original_name -> Nullable<Text>,
+        #[sql_name = "BlackOriginalName"]
+        black_original_name -> Nullable<Text>,
     }
 }
 
@@ -77,6 +109,8 @@ diesel::table! {
         ply -> Nullable<Integer>,
         #[sql_name = "Comment"]
         comment -> Nullable<Text>,
+        #[sql_name = "Nags"]
+        nags -> Nullable<Text>,
     }
 }
 
@@ -110,7 +144,24 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    #[sql_name = "Positions"]
+    positions (hash, game_id) {
+        #[sql_name = "Hash"]
+        hash -> BigInt,
+        #[sql_name = "GameID"]
+        game_id -> Integer,
+        #[sql_name = "Ply"]
+        ply -> Integer,
+        #[sql_name = "NextMove"]
+        next_move -> Nullable<Integer>,
+    }
+}
+
+diesel::joinable!(comments -> games (game_id));
 diesel::joinable!(games -> events (event_id));
 diesel::joinable!(games -> sites (site_id));
 
-diesel::allow_tables_to_appear_in_same_query!(comments, events, games, info, players, sites,);
+diesel::allow_tables_to_appear_in_same_query!(
+    comments, events, games, info, players, positions, sites,
+);
@@ -1,35 +1,69 @@
-use dashmap::{mapref::entry::Entry, DashMap};
+use dashmap::DashMap;
 use diesel::prelude::*;
 use log::info;
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
-use shakmaty::{fen::Fen, san::SanPlus, Bitboard, ByColor, Chess, FromSetup, Position, Setup};
+use shakmaty::{
+    fen::Fen, san::SanPlus, uci::Uci, Bitboard, Board, ByColor, CastlingMode, Chess, Color,
+    EnPassantMode, FromSetup, Move, Piece, Position, Setup, Square,
+};
 use std::{
-    path::PathBuf,
+    collections::{HashMap, VecDeque},
+    path::{Path, PathBuf},
     sync::{
-        atomic::{AtomicUsize, Ordering},
-        Mutex,
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
     },
-    time::Instant,
+    time::{Duration, Instant},
 };
 use tauri::Manager;
+use tauri_specta::Event as _;
 
 use crate::{
     db::{
-        encoding::decode_move, get_db_or_create, get_material_count, get_pawn_home, models::*,
-        normalize_games, schema::*, ConnectionOptions, MaterialCount,
+        encoding::{
+            decode_move, encode_move, initial_position, mainline_moves, material_signature,
+            signature_counts, zobrist_key, SIGNATURE_SIZE,
+        },
+        fide_rating_difference, filtered_game_ids, get_db_or_create, get_material_count,
+        get_pawn_home,
+        models::*,
+        normalize_games,
+        schema::*,
+        set_info, write_games_by_id, ConnectionOptions, DatabaseProgress, ExportCompression,
+        ExportWriter, GameResult, MaterialCount, PlayerRef, RowCount, DEFAULT_IMPORT_BATCH_SIZE,
     },
     error::Error,
-    AppState,
+    AppState, GameData,
 };
 
 use super::GameQuery;
 
+/// Plies past the one given by the move counters of a position where a game
+/// may still reach it, for the transpositions.
+const MAX_PLY_MARGIN: u32 = 20;
+
+/// What an exact position search compares.
+#[derive(Debug, Default, Hash, PartialEq, Eq, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PositionMode {
+    /// The pieces, the side to move, the castling rights and the en passant
+    /// square.
+    #[default]
+    Exact,
+    /// Only the pieces, whatever the side to move or the rights.
+    Placement,
+}
+
 #[derive(Debug, Hash, PartialEq, Eq, Clone)]
 pub struct ExactData {
     pawn_home: u16,
     material: MaterialCount,
+    signature: [u8; SIGNATURE_SIZE],
     position: Chess,
+    mode: PositionMode,
+    /// Games are only replayed up to this ply.
+    max_ply: Option<u32>,
 }
 
 #[derive(Debug, Hash, PartialEq, Eq, Clone)]
@@ -37,6 +71,10 @@ pub struct PartialData {
     // piece_counts: Vec<(Piece, u8)>,
     piece_positions: Setup,
     material: MaterialCount,
+    /// The least of each piece a position needs to match.
+    counts: [u8; 2 * SIGNATURE_SIZE],
+    /// Games are only replayed up to this ply.
+    max_ply: Option<u32>,
 }
 
 #[derive(Debug, Hash, PartialEq, Eq, Clone)]
@@ -46,15 +84,19 @@ pub enum PositionQuery {
 }
 
 impl PositionQuery {
-    pub fn exact_from_fen(fen: &str) -> Result<PositionQuery, Error> {
+    pub fn exact_from_fen(fen: &str, mode: PositionMode) -> Result<PositionQuery, Error> {
         let position: Chess =
             Fen::from_ascii(fen.as_bytes())?.into_position(shakmaty::CastlingMode::Chess960)?;
         let pawn_home = get_pawn_home(position.board());
         let material = get_material_count(position.board());
+        let signature = material_signature(position.board());
         Ok(PositionQuery::Exact(ExactData {
             pawn_home,
             material,
+            signature,
             position,
+            mode,
+            max_ply: None,
         }))
     }
 
@@ -62,11 +104,50 @@ impl PositionQuery {
         let fen = Fen::from_ascii(fen.as_bytes())?;
         let setup = fen.into_setup();
         let material = get_material_count(&setup.board);
+        let counts = signature_counts(&material_signature(&setup.board));
         Ok(PositionQuery::Partial(PartialData {
             piece_positions: setup,
             material,
+            counts,
+            max_ply: None,
         }))
     }
+
+    /// Stops replaying the games past the ply given by the move counters of an
+    /// exact position, with a margin for the transpositions. Games reaching
+    /// the position later are missed, so it's only done on request.
+    pub fn with_counters_max_ply(self) -> PositionQuery {
+        let max_ply = match &self {
+            PositionQuery::Exact(data) => {
+                // Counters at the first move may well be the defaults of a
+                // FEN without them
+                let position = &data.position;
+                let ply =
+                    2 * (position.fullmoves().get() - 1) + u32::from(position.turn().is_black());
+                (ply > 0).then_some(ply + MAX_PLY_MARGIN)
+            }
+            PositionQuery::Partial(_) => None,
+        };
+        self.with_max_ply(max_ply)
+    }
+
+    /// Stops replaying the games past `max_ply`.
+    pub fn with_max_ply(mut self, max_ply: Option<u32>) -> PositionQuery {
+        if max_ply.is_some() {
+            match self {
+                PositionQuery::Exact(ref mut data) => data.max_ply = max_ply,
+                PositionQuery::Partial(ref mut data) => data.max_ply = max_ply,
+            }
+        }
+        self
+    }
+
+    fn max_ply(&self) -> Option<u32> {
+        match self {
+            PositionQuery::Exact(ref data) => data.max_ply,
+            PositionQuery::Partial(ref data) => data.max_ply,
+        }
+    }
 }
 
 impl<'de> Deserialize<'de> for PositionQuery {
@@ -83,17 +164,35 @@ impl<'de> Deserialize<'de> for PositionQuery {
                 let value = map
                     .get("fen")
                     .ok_or(serde::de::Error::custom("Missing fen"))?;
-                match type_.as_str().unwrap() {
+                // A number of plies, or "auto" for the one of the move counters
+                let max_ply = map.get("max_ply");
+                let query = match type_.as_str().unwrap() {
                     "exact" => {
                         let fen = value.as_str().unwrap();
-                        PositionQuery::exact_from_fen(fen).map_err(serde::de::Error::custom)
+                        let mode = match map.get("mode") {
+                            Some(mode) if !mode.is_null() => {
+                                PositionMode::deserialize(mode).map_err(serde::de::Error::custom)?
+                            }
+                            _ => PositionMode::default(),
+                        };
+                        PositionQuery::exact_from_fen(fen, mode).map_err(serde::de::Error::custom)
                     }
                     "partial" => {
                         let fen = value.as_str().unwrap();
                         PositionQuery::partial_from_fen(fen).map_err(serde::de::Error::custom)
                     }
                     _ => Err(serde::de::Error::custom("Invalid key")),
-                }
+                };
+                query.map(|query| match max_ply {
+                    Some(serde_json::Value::String(max_ply)) if max_ply == "auto" => {
+                        query.with_counters_max_ply()
+                    }
+                    max_ply => query.with_max_ply(
+                        max_ply
+                            .and_then(serde_json::Value::as_u64)
+                            .map(|ply| ply as u32),
+                    ),
+                })
             }
             _ => Err(serde::de::Error::custom("Invalid value")),
         }
@@ -103,9 +202,17 @@ impl<'de> Deserialize<'de> for PositionQuery {
 impl PositionQuery {
     fn matches(&self, position: &Chess) -> bool {
         match self {
-            PositionQuery::Exact(ref data) => {
-                data.position.board() == position.board() && data.position.turn() == position.turn()
-            }
+            PositionQuery::Exact(ref data) => match data.mode {
+                PositionMode::Exact => {
+                    data.position.board() == position.board()
+                        && data.position.turn() == position.turn()
+                        && data.position.castles().castling_rights()
+                            == position.castles().castling_rights()
+                        && data.position.ep_square(EnPassantMode::Legal)
+                            == position.ep_square(EnPassantMode::Legal)
+                }
+                PositionMode::Placement => data.position.board() == position.board(),
+            },
             PositionQuery::Partial(ref data) => {
                 let query_board = &data.piece_positions.board;
                 let tested_board = position.board();
@@ -143,6 +250,32 @@ impl PositionQuery {
     }
 }
 
+impl PositionQuery {
+    /// Whether a game with these material signatures can match, without
+    /// replaying it. Games whose signatures weren't computed can.
+    fn passes_signatures(&self, signatures: Option<&[u8]>) -> bool {
+        let Some(signatures) = signatures else {
+            return true;
+        };
+        match self {
+            PositionQuery::Exact(ref data) => signatures
+                .chunks_exact(SIGNATURE_SIZE)
+                .any(|s| s == data.signature),
+            PositionQuery::Partial(ref data) => has_counts(signatures, &data.counts),
+        }
+    }
+}
+
+/// Returns true if one of the signatures has at least `counts` of each piece
+fn has_counts(signatures: &[u8], counts: &[u8; 2 * SIGNATURE_SIZE]) -> bool {
+    signatures.chunks_exact(SIGNATURE_SIZE).any(|s| {
+        signature_counts(s)
+            .iter()
+            .zip(counts.iter())
+            .all(|(count, needed)| count >= needed)
+    })
+}
+
 /// Returns true if the end pawn structure is reachable
 fn is_end_reachable(end: u16, pos: u16) -> bool {
     end & !pos == 0
@@ -158,20 +291,156 @@ fn is_contained(container: Bitboard, subset: Bitboard) -> bool {
     container & subset == subset
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// The games where a move was played from a position, or that ended there
+/// with the move `*`.
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
 pub struct PositionStats {
     #[serde(rename = "move")]
     pub move_: String,
+    pub uci: String,
+    /// All the games, including those of unknown result.
+    pub games: i32,
     pub white: i32,
     pub draw: i32,
     pub black: i32,
+    /// The shares of the games of known result, in percent.
+    pub white_percent: f64,
+    pub draw_percent: f64,
+    pub black_percent: f64,
+    /// The average rating of the rated players of the games.
+    pub average_rating: Option<i32>,
+    /// The average rating of the rated players of the side to move.
+    pub mover_rating: Option<i32>,
+    /// The performance of the side to move in the games of known result
+    /// against a rated opponent, with the FIDE formula.
+    pub performance: Option<i32>,
+    #[serde(skip)]
+    rating_sum: i64,
+    #[serde(skip)]
+    ratings: i64,
+    #[serde(skip)]
+    mover_rating_sum: i64,
+    #[serde(skip)]
+    mover_ratings: i64,
+    #[serde(skip)]
+    opponent_rating_sum: i64,
+    #[serde(skip)]
+    rated_games: i64,
+    #[serde(skip)]
+    points: f64,
+}
+
+impl PositionStats {
+    /// Adds a game with its result, the ratings of White and Black, and the
+    /// side to move in the position.
+    fn record(&mut self, result: Option<i32>, ratings: [Option<i32>; 2], turn: Color) {
+        self.games += 1;
+        let result = GameResult::from_column(result);
+        match result {
+            GameResult::WhiteWins => self.white += 1,
+            GameResult::BlackWins => self.black += 1,
+            GameResult::Draw => self.draw += 1,
+            GameResult::Unknown => (),
+        }
+        let [white_elo, black_elo] = ratings.map(|r| r.filter(|&r| r > 0));
+        for rating in [white_elo, black_elo].into_iter().flatten() {
+            self.rating_sum += i64::from(rating);
+            self.ratings += 1;
+        }
+        let (mover, opponent) = if turn.is_white() {
+            (white_elo, black_elo)
+        } else {
+            (black_elo, white_elo)
+        };
+        if let Some(rating) = mover {
+            self.mover_rating_sum += i64::from(rating);
+            self.mover_ratings += 1;
+        }
+        if let (Some(points), Some(rating)) = (result.points(turn.is_white()), opponent) {
+            self.points += points;
+            self.opponent_rating_sum += i64::from(rating);
+            self.rated_games += 1;
+        }
+        self.summarize();
+    }
+
+    /// Adds the games of the same move in another search.
+    fn merge(&mut self, other: &PositionStats) {
+        self.games += other.games;
+        self.white += other.white;
+        self.draw += other.draw;
+        self.black += other.black;
+        self.rating_sum += other.rating_sum;
+        self.ratings += other.ratings;
+        self.mover_rating_sum += other.mover_rating_sum;
+        self.mover_ratings += other.mover_ratings;
+        self.opponent_rating_sum += other.opponent_rating_sum;
+        self.rated_games += other.rated_games;
+        self.points += other.points;
+        self.summarize();
+    }
+
+    /// Updates the shares and the averages from the sums.
+    fn summarize(&mut self) {
+        let decided = self.white + self.draw + self.black;
+        if decided > 0 {
+            let percent = |games: i32| f64::from(games) * 100.0 / f64::from(decided);
+            self.white_percent = percent(self.white);
+            self.draw_percent = percent(self.draw);
+            self.black_percent = percent(self.black);
+        }
+        if self.ratings > 0 {
+            self.average_rating = Some((self.rating_sum / self.ratings) as i32);
+        }
+        if self.mover_ratings > 0 {
+            self.mover_rating = Some((self.mover_rating_sum / self.mover_ratings) as i32);
+        }
+        if self.rated_games > 0 {
+            let games = self.rated_games as f64;
+            let average = self.opponent_rating_sum as f64 / games;
+            self.performance =
+                Some(average.round() as i32 + fide_rating_difference(self.points / games));
+        }
+    }
+
+    /// All the games of `openings`, without a move.
+    fn total<'a>(openings: impl Iterator<Item = &'a PositionStats>) -> PositionStats {
+        let mut total = PositionStats::default();
+        for stats in openings {
+            total.merge(stats);
+        }
+        total
+    }
+}
+
+/// The SAN and the UCI of the next move, both `*` if the game ended.
+type NextMove = (String, String);
+
+fn next_move(chess: Chess, m: &Move) -> NextMove {
+    let uci = m.to_uci(CastlingMode::Standard).to_string();
+    (SanPlus::from_move(chess, m).to_string(), uci)
+}
+
+fn game_end() -> NextMove {
+    ("*".to_string(), "*".to_string())
+}
+
+/// Where a game first reaches a position, and the move played from there.
+#[derive(Debug, PartialEq, Eq)]
+struct PositionMatch {
+    /// The plies played before, 0 for the first position of the game.
+    ply: u32,
+    /// The side to move in the position.
+    turn: Color,
+    next_move: NextMove,
 }
 
 fn get_move_after_match(
     move_blob: &Vec<u8>,
     fen: &Option<String>,
     query: &PositionQuery,
-) -> Result<Option<String>, Error> {
+) -> Result<Option<PositionMatch>, Error> {
+    let move_blob = mainline_moves(move_blob);
     let mut chess = if let Some(fen) = fen {
         let fen = Fen::from_ascii(fen.as_bytes())?;
         Chess::from_setup(fen.into_setup(), shakmaty::CastlingMode::Chess960)?
@@ -180,15 +449,26 @@ fn get_move_after_match(
     };
 
     if query.matches(&chess) {
-        if move_blob.is_empty() {
-            return Ok(Some("*".to_string()));
-        }
-        let next_move = decode_move(move_blob[0], &chess).unwrap();
-        let san = SanPlus::from_move(chess, &next_move);
-        return Ok(Some(san.to_string()));
+        let turn = chess.turn();
+        let next_move = match move_blob.first() {
+            Some(byte) => {
+                let m = decode_move(*byte, &chess).unwrap();
+                next_move(chess, &m)
+            }
+            None => game_end(),
+        };
+        return Ok(Some(PositionMatch {
+            ply: 0,
+            turn,
+            next_move,
+        }));
     }
 
+    let max_ply = query.max_ply();
     for (i, byte) in move_blob.iter().enumerate() {
+        if max_ply.is_some_and(|max_ply| i as u32 >= max_ply) {
+            return Ok(None);
+        }
         let m = decode_move(*byte, &chess).unwrap();
         chess.play_unchecked(&m);
         let board = chess.board();
@@ -196,47 +476,134 @@ fn get_move_after_match(
             return Ok(None);
         }
         if query.matches(&chess) {
-            if i == move_blob.len() - 1 {
-                return Ok(Some("*".to_string()));
-            }
-            let next_move = decode_move(move_blob[i + 1], &chess).unwrap();
-            let san = SanPlus::from_move(chess, &next_move);
-            return Ok(Some(san.to_string()));
+            let turn = chess.turn();
+            let next_move = match move_blob.get(i + 1) {
+                Some(byte) => {
+                    let m = decode_move(*byte, &chess).unwrap();
+                    next_move(chess, &m)
+                }
+                None => game_end(),
+            };
+            return Ok(Some(PositionMatch {
+                ply: i as u32 + 1,
+                turn,
+                next_move,
+            }));
         }
     }
     Ok(None)
 }
 
-#[derive(Clone, serde::Serialize)]
-pub struct ProgressPayload {
-    pub progress: f64,
-    pub id: String,
-    pub finished: bool,
+/// Megabytes of games kept by [`PositionCache`] unless set otherwise.
+const DEFAULT_POSITION_CACHE_MB: usize = 1024;
+
+/// The games loaded by the position searches, by database, so that the next
+/// searches skip loading them. The games of a database are loaded again once
+/// it's modified, and the least recently searched databases are dropped past
+/// the memory limit.
+pub struct PositionCache {
+    /// In bytes.
+    limit: AtomicUsize,
+    /// Most recently used first.
+    entries: Mutex<VecDeque<CachedGames>>,
 }
 
-#[tauri::command]
-pub async fn search_position(
-    file: PathBuf,
-    query: GameQuery,
-    app: tauri::AppHandle,
-    tab_id: String,
-    state: tauri::State<'_, AppState>,
-) -> Result<(Vec<PositionStats>, Vec<NormalizedGame>), Error> {
-    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+struct CachedGames {
+    file: String,
+    /// The generation of the database in the [`CountCache`](super::CountCache)
+    /// when its games were loaded.
+    generation: u64,
+    size: usize,
+    games: Arc<Vec<GameData>>,
+}
 
-    if let Some(pos) = state.line_cache.get(&(query.clone(), file.clone())) {
-        return Ok(pos.clone());
+impl Default for PositionCache {
+    fn default() -> Self {
+        PositionCache {
+            limit: AtomicUsize::new(DEFAULT_POSITION_CACHE_MB * 1024 * 1024),
+            entries: Mutex::default(),
+        }
     }
+}
 
-    // start counting the time
-    let start = Instant::now();
-    info!("start loading games");
+/// The memory taken by the games, roughly.
+fn games_size(games: &[GameData]) -> usize {
+    games
+        .iter()
+        .map(|game| {
+            std::mem::size_of::<GameData>()
+                + game.3.as_ref().map_or(0, String::len)
+                + game.5.len()
+                + game.6.as_ref().map_or(0, String::len)
+                + game.12.as_ref().map_or(0, Vec::len)
+        })
+        .sum()
+}
 
-    let permit = state.new_request.acquire().await.unwrap();
-    let mut games = state.db_cache.lock().unwrap();
+impl PositionCache {
+    fn get(&self, file: &str, generation: u64) -> Option<Arc<Vec<GameData>>> {
+        let mut entries = self.entries.lock().unwrap();
+        let index = entries.iter().position(|e| e.file == file)?;
+        let entry = entries.remove(index)?;
+        if entry.generation != generation {
+            return None;
+        }
+        let games = entry.games.clone();
+        entries.push_front(entry);
+        Some(games)
+    }
+
+    fn insert(&self, file: &str, generation: u64, games: Arc<Vec<GameData>>) {
+        let size = games_size(&games);
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|e| e.file != file);
+        entries.push_front(CachedGames {
+            file: file.to_string(),
+            generation,
+            size,
+            games,
+        });
+        self.evict(&mut entries);
+    }
+
+    /// Drops the least recently used games past the limit, including the
+    /// newest ones if they don't fit on their own.
+    fn evict(&self, entries: &mut VecDeque<CachedGames>) {
+        let limit = self.limit.load(Ordering::Relaxed);
+        let mut total: usize = entries.iter().map(|e| e.size).sum();
+        while total > limit {
+            let Some(entry) = entries.pop_back() else {
+                break;
+            };
+            total -= entry.size;
+        }
+    }
+
+    pub fn set_limit(&self, bytes: usize) {
+        self.limit.store(bytes, Ordering::Relaxed);
+        self.evict(&mut self.entries.lock().unwrap());
+    }
+
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
+
+/// The games of the database to search, from the cache unless the database
+/// was modified since they were loaded.
+fn load_position_games(
+    db: &mut SqliteConnection,
+    state: &AppState,
+    file: &str,
+) -> Result<Arc<Vec<GameData>>, Error> {
+    let generation = state.count_cache.generation(file);
+    if let Some(games) = state.position_cache.get(file, generation) {
+        return Ok(games);
+    }
 
-    if games.is_empty() {
-        *games = games::table
+    let start = Instant::now();
+    let games: Arc<Vec<GameData>> = Arc::new(
+        games::table
             .select((
                 games::id,
                 games::white_id,
@@ -248,207 +615,1135 @@ pub async fn search_position(
                 games::pawn_home,
                 games::white_material,
                 games::black_material,
+                games::white_elo,
+                games::black_elo,
+                games::material_signatures,
             ))
-            .load(db)?;
+            .load(db)?,
+    );
+    info!("got {} games: {:?}", games.len(), start.elapsed());
+    state.position_cache.insert(file, generation, games.clone());
+    Ok(games)
+}
+
+/// Results kept by [`LineCache`], across all databases.
+const LINE_CACHE_CAPACITY: usize = 64;
+
+/// The results of the recent position searches, so that going back to a
+/// position doesn't search it again. Like the [`PositionCache`], a result is
+/// only used in the generation of the database it was searched in.
+#[derive(Default)]
+pub struct LineCache {
+    /// Most recently used first.
+    entries: Mutex<VecDeque<CachedSearch>>,
+}
 
-        info!("got {} games: {:?}", games.len(), start.elapsed());
+struct CachedSearch {
+    file: PathBuf,
+    query: GameQuery,
+    generation: u64,
+    result: Arc<PositionSearchResult>,
+}
+
+impl LineCache {
+    fn get(
+        &self,
+        file: &Path,
+        query: &GameQuery,
+        generation: u64,
+    ) -> Option<Arc<PositionSearchResult>> {
+        let mut entries = self.entries.lock().unwrap();
+        let index = entries
+            .iter()
+            .position(|e| e.file == file && e.query == *query)?;
+        let entry = entries.remove(index)?;
+        if entry.generation != generation {
+            return None;
+        }
+        let result = entry.result.clone();
+        entries.push_front(entry);
+        Some(result)
     }
 
-    let openings: DashMap<String, PositionStats> = DashMap::new();
-    let sample_games: Mutex<Vec<i32>> = Mutex::new(Vec::new());
+    /// Stores a result searched in `generation`.
+    fn insert(
+        &self,
+        file: &Path,
+        query: &GameQuery,
+        generation: u64,
+        result: PositionSearchResult,
+    ) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|e| !(e.file == file && e.query == *query));
+        entries.push_front(CachedSearch {
+            file: file.to_path_buf(),
+            query: query.clone(),
+            generation,
+            result: Arc::new(result),
+        });
+        entries.truncate(LINE_CACHE_CAPACITY);
+    }
 
-    let processed = AtomicUsize::new(0);
+    fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
 
-    println!("start search on {tab_id}");
+/// Drops the games kept for the position searches, and their results.
+#[tauri::command]
+pub fn clear_position_cache(state: tauri::State<'_, AppState>) {
+    state.position_cache.clear();
+    state.line_cache.clear();
+}
 
-    games.par_iter().for_each(
-        |(
-            id,
-            white_id,
-            black_id,
-            date,
-            result,
-            game,
-            fen,
-            end_pawn_home,
-            white_material,
-            black_material,
-        )| {
-            if state.new_request.available_permits() == 0 {
-                return;
-            }
-            let end_material: MaterialCount = ByColor {
-                white: *white_material as u8,
-                black: *black_material as u8,
-            };
-            processed.fetch_add(1, Ordering::Relaxed);
-            let index = processed.load(Ordering::Relaxed);
-            if (index + 1) % 10000 == 0 {
-                info!("{} games processed: {:?}", index + 1, start.elapsed());
-                app.emit_all(
-                    "search_progress",
-                    ProgressPayload {
-                        progress: (index as f64 / games.len() as f64) * 100.0,
-                        id: tab_id.clone(),
-                        finished: false,
-                    },
-                )
-                .unwrap();
-            }
+/// Sets the memory kept for the games of the position searches.
+#[tauri::command]
+pub fn set_position_cache_limit(megabytes: usize, state: tauri::State<'_, AppState>) {
+    state
+        .position_cache
+        .set_limit(megabytes.saturating_mul(1024 * 1024));
+}
 
-            if let Some(start_date) = &query.start_date {
-                if let Some(date) = date {
-                    if date < start_date {
-                        return;
-                    }
-                }
-            }
+/// Games of a position search loaded along with the next moves.
+const SAMPLE_GAMES: usize = 10;
+
+/// The games reaching a position and the moves played from it.
+#[derive(Clone, Default, Serialize)]
+pub struct PositionSearchResult {
+    pub openings: Vec<PositionStats>,
+    /// All the matching games, with an empty move.
+    pub total: PositionStats,
+    /// The first few matching games.
+    pub games: Vec<NormalizedGame>,
+    /// The number of matching games, even past the `limit` of `game_ids`.
+    pub count: usize,
+    /// The IDs of the matching games, in order.
+    pub game_ids: Vec<i32>,
+    /// The ply where each game of `game_ids` first reached the position.
+    pub match_plies: Vec<u32>,
+    /// The games ruled out by their material, without replaying them.
+    pub skipped_games: usize,
+    /// The ply past which the games weren't replayed, `None` if they were
+    /// replayed to the end.
+    pub max_ply: Option<u32>,
+    pub elapsed_load_ms: u64,
+    pub elapsed_search_ms: u64,
+}
 
-            if let Some(end_date) = &query.end_date {
-                if let Some(date) = date {
-                    if date > end_date {
-                        return;
-                    }
-                }
-            }
+impl PositionSearchResult {
+    /// The result with at most `limit` IDs, unless it was cut to fewer.
+    fn limited(&self, limit: Option<usize>) -> Option<PositionSearchResult> {
+        let wanted = limit.map_or(self.count, |limit| limit.min(self.count));
+        if self.game_ids.len() < wanted {
+            return None;
+        }
+        let mut result = self.clone();
+        result.game_ids.truncate(wanted);
+        result.match_plies.truncate(wanted);
+        Some(result)
+    }
+}
+
+#[derive(Clone, serde::Serialize)]
+pub struct ProgressPayload {
+    pub progress: f64,
+    pub id: String,
+    pub finished: bool,
+}
 
-            if let Some(white) = query.player1 {
-                if white != *white_id {
-                    return;
+/// Searches the games of one database for [`search_position`] and
+/// [`search_position_multi`], reporting its progress in percent every 1% of
+/// the games.
+fn search_database(
+    file: &Path,
+    query: &GameQuery,
+    limit: Option<usize>,
+    state: &tauri::State<'_, AppState>,
+    cancelled: &AtomicBool,
+    report_progress: &(dyn Fn(f64) + Sync),
+) -> Result<PositionSearchResult, Error> {
+    // Read before searching, so that a result searched while the database
+    // is modified isn't taken as fresh
+    let generation = state.count_cache.generation(file.to_str().unwrap());
+    if let Some(result) = state
+        .line_cache
+        .get(file, query, generation)
+        .and_then(|cached| cached.limited(limit))
+    {
+        return Ok(result);
+    }
+
+    let db = &mut get_db_or_create(state, file.to_str().unwrap(), ConnectionOptions::default())?;
+
+    // start counting the time
+    let start = Instant::now();
+    if let Some(found) = indexed_matches(db, query)? {
+        info!("searched the position index in {:?}", start.elapsed());
+        return finish_search(
+            db,
+            state,
+            file,
+            query,
+            limit,
+            SearchMatches {
+                generation,
+                ..found
+            },
+            [Duration::ZERO, start.elapsed()],
+        );
+    }
+    info!("start loading games");
+
+    let games = load_position_games(db, state, file.to_str().unwrap())?;
+    let elapsed_load = start.elapsed();
+    // Progress is sent every 1% of the games
+    let progress_step = (games.len() / 100).max(1);
+
+    // Only the games matching the other filters of the query are replayed
+    let subset = filtered_game_ids(db, query)?;
+
+    let openings: DashMap<String, PositionStats> = DashMap::new();
+
+    let processed = AtomicUsize::new(0);
+    let skipped = AtomicUsize::new(0);
+
+    info!("start search on {}", file.display());
+
+    // Each thread collects the IDs of its matches, merged in order at the end
+    let matches: Vec<(i32, u32)> = games
+        .par_iter()
+        .filter_map(
+            |(
+                id,
+                _white_id,
+                _black_id,
+                _date,
+                result,
+                game,
+                fen,
+                end_pawn_home,
+                white_material,
+                black_material,
+                white_elo,
+                black_elo,
+                signatures,
+            )| {
+                if state.new_request.available_permits() == 0 || cancelled.load(Ordering::Relaxed) {
+                    return None;
+                }
+                let end_material: MaterialCount = ByColor {
+                    white: *white_material as u8,
+                    black: *black_material as u8,
+                };
+                let index = processed.fetch_add(1, Ordering::Relaxed);
+                if (index + 1) % progress_step == 0 {
+                    info!("{} games processed: {:?}", index + 1, start.elapsed());
+                    report_progress((index as f64 / games.len() as f64) * 100.0);
                 }
-            }
 
-            if let Some(black) = query.player2 {
-                if black != *black_id {
-                    return;
+                if subset
+                    .as_ref()
+                    .is_some_and(|subset| subset.binary_search(id).is_err())
+                {
+                    return None;
                 }
-            }
 
-            if let Some(position_query) = &query.position {
-                if position_query.can_reach(&end_material, *end_pawn_home as u16) {
-                    if let Ok(Some(m)) = get_move_after_match(game, fen, position_query) {
-                        if sample_games.lock().unwrap().len() < 10 {
-                            sample_games.lock().unwrap().push(*id);
-                        }
-                        let entry = openings.entry(m);
-                        match entry {
-                            Entry::Occupied(mut e) => {
-                                let opening = e.get_mut();
-                                match result.as_deref() {
-                                    Some("1-0") => opening.white += 1,
-                                    Some("0-1") => opening.black += 1,
-                                    Some("1/2-1/2") => opening.draw += 1,
-                                    _ => (),
-                                }
-                            }
-                            Entry::Vacant(e) => {
-                                let mut opening = PositionStats {
-                                    black: 0,
-                                    white: 0,
-                                    draw: 0,
-                                    move_: e.key().to_string(),
-                                };
-                                match result.as_deref() {
-                                    Some("1-0") => opening.white = 1,
-                                    Some("0-1") => opening.black = 1,
-                                    Some("1/2-1/2") => opening.draw = 1,
-                                    _ => (),
-                                }
-                                e.insert(opening);
-                            }
-                        }
+                if let Some(position_query) = &query.position {
+                    if !position_query.can_reach(&end_material, *end_pawn_home as u16)
+                        || !position_query.passes_signatures(signatures.as_deref())
+                    {
+                        skipped.fetch_add(1, Ordering::Relaxed);
+                        return None;
+                    }
+                    if let Ok(Some(PositionMatch {
+                        ply,
+                        turn,
+                        next_move: (san, uci),
+                    })) = get_move_after_match(game, fen, position_query)
+                    {
+                        openings
+                            .entry(san.clone())
+                            .or_insert_with(|| PositionStats {
+                                move_: san,
+                                uci,
+                                ..Default::default()
+                            })
+                            .record(*result, [*white_elo, *black_elo], turn);
+                        return Some((*id, ply));
                     }
                 }
-            }
-        },
-    );
-
+                None
+            },
+        )
+        .collect();
     let openings: Vec<PositionStats> = openings.into_iter().map(|(_, v)| v).collect();
-    let ids: Vec<i32> = sample_games.lock().unwrap().clone();
 
     info!("finished search in {:?}", start.elapsed());
+    let elapsed_search = start.elapsed() - elapsed_load;
 
+    if cancelled.load(Ordering::Relaxed) {
+        return Err(Error::SearchCancelled);
+    }
     if state.new_request.available_permits() == 0 {
-        drop(permit);
         return Err(Error::SearchStopped);
     }
 
+    let found = SearchMatches {
+        openings,
+        matches,
+        skipped_games: skipped.into_inner(),
+        generation,
+    };
+    finish_search(
+        db,
+        state,
+        file,
+        query,
+        limit,
+        found,
+        [elapsed_load, elapsed_search],
+    )
+}
+
+/// The games of a search reaching its position, from a scan or the index.
+struct SearchMatches {
+    openings: Vec<PositionStats>,
+    /// The IDs of the games and the ply of their first match.
+    matches: Vec<(i32, u32)>,
+    skipped_games: usize,
+    /// The generation of the database when the search started.
+    generation: u64,
+}
+
+/// Builds the result of [`search_database`] and caches it. `elapsed` is the
+/// time spent loading the games and searching them.
+fn finish_search(
+    db: &mut SqliteConnection,
+    state: &tauri::State<'_, AppState>,
+    file: &Path,
+    query: &GameQuery,
+    limit: Option<usize>,
+    found: SearchMatches,
+    elapsed: [Duration; 2],
+) -> Result<PositionSearchResult, Error> {
+    let SearchMatches {
+        mut openings,
+        mut matches,
+        skipped_games,
+        generation,
+    } = found;
+    matches.sort_unstable();
+    let (mut ids, mut plies): (Vec<i32>, Vec<u32>) = matches.into_iter().unzip();
+
+    sort_openings(&mut openings);
+
     let (white_players, black_players) = diesel::alias!(players as white, players as black);
     let games: Vec<(Game, Player, Player, Event, Site)> = games::table
         .inner_join(white_players.on(games::white_id.eq(white_players.field(players::id))))
         .inner_join(black_players.on(games::black_id.eq(black_players.field(players::id))))
         .inner_join(events::table.on(games::event_id.eq(events::id)))
         .inner_join(sites::table.on(games::site_id.eq(sites::id)))
-        .filter(games::id.eq_any(ids))
+        .filter(games::id.eq_any(&ids[..ids.len().min(SAMPLE_GAMES)]))
         .load(db)?;
     let normalized_games = normalize_games(games);
 
+    let count = ids.len();
+    if let Some(limit) = limit {
+        ids.truncate(limit);
+        plies.truncate(limit);
+    }
+    let result = PositionSearchResult {
+        total: PositionStats::total(openings.iter()),
+        openings,
+        games: normalized_games,
+        count,
+        game_ids: ids,
+        match_plies: plies,
+        skipped_games,
+        max_ply: query.position.as_ref().and_then(PositionQuery::max_ply),
+        elapsed_load_ms: elapsed[0].as_millis() as u64,
+        elapsed_search_ms: elapsed[1].as_millis() as u64,
+    };
     state
         .line_cache
-        .insert((query, file), (openings.clone(), normalized_games.clone()));
+        .insert(file, query, generation, result.clone());
 
-    Ok((openings, normalized_games))
+    Ok(result)
 }
 
-pub async fn is_position_in_db(
+/// Searches the games of `query` reaching its position, sending its progress
+/// as `search_progress` events. A search can be cancelled with
+/// [`cancel_search`] and its `tab_id`, or is stopped by the next one.
+#[tauri::command]
+pub async fn search_position(
     file: PathBuf,
     query: GameQuery,
+    app: tauri::AppHandle,
+    tab_id: String,
+    limit: Option<usize>,
     state: tauri::State<'_, AppState>,
-) -> Result<bool, Error> {
-    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+) -> Result<PositionSearchResult, Error> {
+    let cancelled = Arc::new(AtomicBool::new(false));
+    state
+        .search_cancellations
+        .insert(tab_id.clone(), cancelled.clone());
 
-    if let Some(pos) = state.line_cache.get(&(query.clone(), file.clone())) {
-        return Ok(!pos.0.is_empty());
-    }
+    let permit = state.new_request.acquire().await.unwrap();
+    let result = search_database(&file, &query, limit, &state, &cancelled, &|progress| {
+        app.emit_all(
+            "search_progress",
+            ProgressPayload {
+                progress,
+                id: tab_id.clone(),
+                finished: false,
+            },
+        )
+        .unwrap();
+    });
+    drop(permit);
 
-    // start counting the time
-    let start = Instant::now();
-    info!("start loading games");
+    state
+        .search_cancellations
+        .remove_if(&tab_id, |_, c| Arc::ptr_eq(c, &cancelled));
+    result
+}
 
-    let permit = state.new_request.acquire().await.unwrap();
-    let mut games = state.db_cache.lock().unwrap();
+/// The search of one database of [`search_position_multi`].
+#[derive(Clone, Serialize)]
+pub struct DatabaseSearchResult {
+    pub file: PathBuf,
+    pub result: Option<PositionSearchResult>,
+    /// Why the database couldn't be searched.
+    pub error: Option<String>,
+}
 
-    if games.is_empty() {
-        *games = games::table
-            .select((
-                games::id,
-                games::white_id,
-                games::black_id,
-                games::date,
-                games::result,
-                games::moves,
-                games::fen,
-                games::pawn_home,
-                games::white_material,
-                games::black_material,
-            ))
-            .load(db)?;
+/// The searches of [`search_position_multi`], and the moves played from the
+/// position in all of them.
+#[derive(Clone, Serialize)]
+pub struct MultiSearchResult {
+    pub databases: Vec<DatabaseSearchResult>,
+    pub openings: Vec<PositionStats>,
+    /// All the matching games of the databases, with an empty move.
+    pub total: PositionStats,
+    /// The matching games of all the databases.
+    pub count: usize,
+}
 
-        info!("got {} games: {:?}", games.len(), start.elapsed());
+/// The most played moves first.
+fn sort_openings(openings: &mut [PositionStats]) {
+    openings.sort_by(|a, b| b.games.cmp(&a.games).then_with(|| a.move_.cmp(&b.move_)));
+}
+
+/// The moves of several searches added up, the most played first.
+fn merge_openings<'a>(openings: impl Iterator<Item = &'a PositionStats>) -> Vec<PositionStats> {
+    let mut merged: Vec<PositionStats> = Vec::new();
+    for stats in openings {
+        match merged.iter_mut().find(|m| m.move_ == stats.move_) {
+            Some(m) => m.merge(stats),
+            None => merged.push(stats.clone()),
+        }
     }
+    sort_openings(&mut merged);
+    merged
+}
 
-    let exists = games.par_iter().any(
-        |(
-            _id,
-            _white_id,
-            _black_id,
-            _date,
-            _result,
-            game,
-            fen,
-            end_pawn_home,
-            white_material,
-            black_material,
-        )| {
-            if state.new_request.available_permits() == 0 {
-                return false;
-            }
-            let end_material: MaterialCount = ByColor {
-                white: *white_material as u8,
-                black: *black_material as u8,
-            };
-            if let Some(position_query) = &query.position {
+/// Searches the position of `query` in several databases at once, sending
+/// the progress of the whole batch as `search_progress` events. A database
+/// that can't be searched is reported with its error, while the batch as a
+/// whole can be cancelled or stopped like [`search_position`]. Players and
+/// events are given by name, as their IDs differ from a database to another.
+#[tauri::command]
+pub async fn search_position_multi(
+    files: Vec<PathBuf>,
+    query: GameQuery,
+    app: tauri::AppHandle,
+    tab_id: String,
+    limit: Option<usize>,
+    state: tauri::State<'_, AppState>,
+) -> Result<MultiSearchResult, Error> {
+    let by_id = query.player1.is_some()
+        || query.player2.is_some()
+        || query.tournament_id.is_some()
+        || query
+            .exclude_players
+            .iter()
+            .flatten()
+            .any(|player| matches!(player, PlayerRef::Id(_)));
+    if by_id {
+        return Err(Error::IdFilterAcrossDatabases);
+    }
+
+    let cancelled = Arc::new(AtomicBool::new(false));
+    state
+        .search_cancellations
+        .insert(tab_id.clone(), cancelled.clone());
+
+    let permit = state.new_request.acquire().await.unwrap();
+    // In percent, for each database
+    let progress: Vec<AtomicUsize> = files.iter().map(|_| AtomicUsize::new(0)).collect();
+    let results: Vec<Result<PositionSearchResult, Error>> = files
+        .par_iter()
+        .enumerate()
+        .map(|(i, file)| {
+            search_database(file, &query, limit, &state, &cancelled, &|percent| {
+                progress[i].store(percent as usize, Ordering::Relaxed);
+                let total: usize = progress.iter().map(|p| p.load(Ordering::Relaxed)).sum();
+                app.emit_all(
+                    "search_progress",
+                    ProgressPayload {
+                        progress: total as f64 / files.len() as f64,
+                        id: tab_id.clone(),
+                        finished: false,
+                    },
+                )
+                .unwrap();
+            })
+        })
+        .collect();
+    drop(permit);
+
+    state
+        .search_cancellations
+        .remove_if(&tab_id, |_, c| Arc::ptr_eq(c, &cancelled));
+    if cancelled.load(Ordering::Relaxed) {
+        return Err(Error::SearchCancelled);
+    }
+    if state.new_request.available_permits() == 0 {
+        return Err(Error::SearchStopped);
+    }
+
+    let databases: Vec<DatabaseSearchResult> = files
+        .into_iter()
+        .zip(results)
+        .map(|(file, result)| match result {
+            Ok(result) => DatabaseSearchResult {
+                file,
+                result: Some(result),
+                error: None,
+            },
+            Err(error) => DatabaseSearchResult {
+                file,
+                result: None,
+                error: Some(error.to_string()),
+            },
+        })
+        .collect();
+    let searched = databases.iter().filter_map(|d| d.result.as_ref());
+    Ok(MultiSearchResult {
+        openings: merge_openings(searched.clone().flat_map(|r| r.openings.iter())),
+        total: PositionStats::total(searched.clone().map(|r| &r.total)),
+        count: searched.map(|r| r.count).sum(),
+        databases,
+    })
+}
+
+/// Name of the state of the position index in the Info table.
+const POSITION_INDEX_KEY: &str = "PositionIndex";
+
+/// Plies of the games in the position index by default.
+const DEFAULT_INDEX_MAX_PLY: u32 = 40;
+
+/// Rows inserted at once in the position index, within the limit of bound
+/// parameters of SQLite.
+const INDEX_INSERT_ROWS: usize = 200;
+
+const CREATE_POSITIONS_SQL: &str = "CREATE TABLE Positions (
+    Hash INTEGER NOT NULL,
+    GameID INTEGER NOT NULL,
+    Ply INTEGER NOT NULL,
+    NextMove INTEGER,
+    PRIMARY KEY (Hash, GameID)
+) WITHOUT ROWID;";
+
+/// The games the position index was built from, to tell when it's stale.
+#[derive(Serialize, Deserialize)]
+struct PositionIndexState {
+    game_count: i64,
+    last_id: Option<i32>,
+    max_ply: u32,
+}
+
+fn game_count_and_last_id(db: &mut SqliteConnection) -> Result<(i64, Option<i32>), Error> {
+    Ok(games::table
+        .select((diesel::dsl::count_star(), diesel::dsl::max(games::id)))
+        .first(db)?)
+}
+
+/// The plies of the position index, unless there's none or games were added
+/// or deleted since it was built.
+fn position_index_max_ply(db: &mut SqliteConnection) -> Result<Option<u32>, Error> {
+    let stored: Option<Option<String>> = info::table
+        .filter(info::name.eq(POSITION_INDEX_KEY))
+        .select(info::value)
+        .first(db)
+        .optional()?;
+    let Some(index) = stored
+        .flatten()
+        .and_then(|s| serde_json::from_str::<PositionIndexState>(&s).ok())
+    else {
+        return Ok(None);
+    };
+    let (game_count, last_id) = game_count_and_last_id(db)?;
+    Ok((index.game_count == game_count && index.last_id == last_id).then_some(index.max_ply))
+}
+
+/// The positions of the first `max_ply` plies of a game, each at the first
+/// ply it was reached.
+fn game_positions(game_id: i32, moves: &[u8], fen: Option<&str>, max_ply: u32) -> Vec<NewPosition> {
+    let Some(mut chess) = initial_position(fen) else {
+        return Vec::new();
+    };
+    let moves = mainline_moves(moves);
+    let mut positions: Vec<NewPosition> = Vec::new();
+    let mut ply = 0;
+    loop {
+        let next_move = moves.get(ply).copied();
+        let hash = zobrist_key(&chess) as i64;
+        if !positions.iter().any(|p| p.hash == hash) {
+            positions.push(NewPosition {
+                hash,
+                game_id,
+                ply: ply as i32,
+                next_move: next_move.map(i32::from),
+            });
+        }
+        if ply as u32 >= max_ply {
+            break;
+        }
+        let Some(m) = next_move.and_then(|byte| decode_move(byte, &chess)) else {
+            break;
+        };
+        chess.play_unchecked(&m);
+        ply += 1;
+    }
+    positions
+}
+
+/// Replaces the position index with the positions of the first `max_ply`
+/// plies of every game, reporting the progress in percent. Returns the
+/// number of positions stored. Searches ignore the index until it's complete.
+fn build_index(
+    db: &mut SqliteConnection,
+    max_ply: u32,
+    mut on_progress: impl FnMut(f64),
+) -> Result<usize, Error> {
+    diesel::delete(info::table.filter(info::name.eq(POSITION_INDEX_KEY))).execute(db)?;
+    db.batch_execute("DROP TABLE IF EXISTS Positions;")?;
+    db.batch_execute(CREATE_POSITIONS_SQL)?;
+    let (game_count, last_id) = game_count_and_last_id(db)?;
+
+    let mut indexed = 0;
+    let mut done = 0;
+    let mut last_indexed = i32::MIN;
+    loop {
+        let games: Vec<(i32, Vec<u8>, Option<String>)> = games::table
+            .select((games::id, games::moves, games::fen))
+            .filter(games::id.gt(last_indexed))
+            .order(games::id)
+            .limit(DEFAULT_IMPORT_BATCH_SIZE as i64)
+            .load(db)?;
+        let Some((id, _, _)) = games.last() else {
+            break;
+        };
+        last_indexed = *id;
+
+        let rows: Vec<NewPosition> = games
+            .par_iter()
+            .flat_map_iter(|(id, moves, fen)| game_positions(*id, moves, fen.as_deref(), max_ply))
+            .collect();
+        db.transaction::<_, Error, _>(|db| {
+            for chunk in rows.chunks(INDEX_INSERT_ROWS) {
+                diesel::insert_into(positions::table)
+                    .values(chunk)
+                    .execute(db)?;
+            }
+            Ok(())
+        })?;
+        indexed += rows.len();
+        done += games.len();
+        on_progress(done as f64 / game_count.max(1) as f64 * 100.0);
+    }
+
+    let index = PositionIndexState {
+        game_count,
+        last_id,
+        max_ply,
+    };
+    set_info(db, POSITION_INDEX_KEY, &serde_json::to_string(&index)?)?;
+    Ok(indexed)
+}
+
+/// The moves played from the position of an exact search and its matching
+/// games, from the position index. `None` if the database has no up to date
+/// index covering the plies of the search, which then has to replay the
+/// games.
+fn indexed_matches(
+    db: &mut SqliteConnection,
+    query: &GameQuery,
+) -> Result<Option<SearchMatches>, Error> {
+    let Some(PositionQuery::Exact(data)) = &query.position else {
+        return Ok(None);
+    };
+    // The hashes tell apart the side to move and the rights, which
+    // placement searches ignore
+    if data.mode != PositionMode::Exact {
+        return Ok(None);
+    }
+    let Some(max_ply) = data.max_ply else {
+        return Ok(None);
+    };
+    match position_index_max_ply(db)? {
+        Some(indexed) if indexed >= max_ply => {}
+        _ => return Ok(None),
+    }
+
+    let subset = filtered_game_ids(db, query)?;
+    let rows: Vec<(i32, i32, Option<i32>, Option<i32>, Option<i32>, Option<i32>)> =
+        positions::table
+            .inner_join(games::table.on(games::id.eq(positions::game_id)))
+            .filter(positions::hash.eq(zobrist_key(&data.position) as i64))
+            .filter(positions::ply.le(max_ply as i32))
+            .select((
+                positions::game_id,
+                positions::ply,
+                positions::next_move,
+                games::result,
+                games::white_elo,
+                games::black_elo,
+            ))
+            .load(db)?;
+
+    let mut openings: HashMap<Option<i32>, PositionStats> = HashMap::new();
+    let mut matches = Vec::new();
+    for (id, ply, next, result, white_elo, black_elo) in rows {
+        if subset
+            .as_ref()
+            .is_some_and(|subset| subset.binary_search(&id).is_err())
+        {
+            continue;
+        }
+        openings
+            .entry(next)
+            .or_insert_with(|| {
+                let (san, uci) = next
+                    .and_then(|byte| u8::try_from(byte).ok())
+                    .and_then(|byte| decode_move(byte, &data.position))
+                    .map_or_else(game_end, |m| next_move(data.position.clone(), &m));
+                PositionStats {
+                    move_: san,
+                    uci,
+                    ..Default::default()
+                }
+            })
+            .record(result, [white_elo, black_elo], data.position.turn());
+        matches.push((id, ply as u32));
+    }
+    Ok(Some(SearchMatches {
+        openings: openings.into_values().collect(),
+        matches,
+        skipped_games: 0,
+        generation: 0,
+    }))
+}
+
+/// The size of the position index built by [`build_position_index`].
+#[derive(Debug, Clone, Serialize)]
+pub struct PositionIndexInfo {
+    /// The positions stored, once per game.
+    pub positions: usize,
+    pub max_ply: u32,
+    /// How much the database grew, in bytes.
+    pub size_increase: i64,
+}
+
+fn database_size(db: &mut SqliteConnection) -> Result<i64, Error> {
+    Ok(diesel::sql_query(
+        "SELECT page_count * page_size AS count FROM pragma_page_count(), pragma_page_size();",
+    )
+    .get_result::<RowCount>(db)?
+    .count)
+}
+
+/// Stores the positions of the first `max_ply` plies of every game, 40 by
+/// default, so that exact searches of positions within them don't replay
+/// the games. Progress is reported through `DatabaseProgress` events, with
+/// the database path as ID. The index is ignored once games are added or
+/// deleted, until it's built again.
+#[tauri::command]
+pub async fn build_position_index(
+    file: PathBuf,
+    max_ply: Option<u32>,
+    app: tauri::AppHandle,
+    state: tauri::State<'_, AppState>,
+) -> Result<PositionIndexInfo, Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    let id = file.to_string_lossy().into_owned();
+    let max_ply = max_ply.unwrap_or(DEFAULT_INDEX_MAX_PLY);
+
+    let size = database_size(db)?;
+    let positions = build_index(db, max_ply, |progress| {
+        let _ = DatabaseProgress {
+            id: id.clone(),
+            progress,
+        }
+        .emit_all(&app);
+    })?;
+
+    Ok(PositionIndexInfo {
+        positions,
+        max_ply,
+        size_increase: database_size(db)? - size,
+    })
+}
+
+/// Explorer sessions kept at once, the least recently used being closed.
+const EXPLORER_SESSIONS: usize = 8;
+
+/// What an explorer session keeps of a game reaching its root position.
+struct ExplorerGame {
+    result: Option<i32>,
+    ratings: [Option<i32>; 2],
+    /// The mainline moves played from the root position.
+    moves: Vec<u8>,
+}
+
+/// The games of `ids`, sorted, from the ply of `plies` where each one
+/// reaches the root position of an explorer session.
+fn explorer_games(
+    db: &mut SqliteConnection,
+    ids: &[i32],
+    plies: &[u32],
+) -> Result<Vec<ExplorerGame>, Error> {
+    let mut games = Vec::with_capacity(ids.len());
+    for (ids, plies) in ids
+        .chunks(DEFAULT_IMPORT_BATCH_SIZE)
+        .zip(plies.chunks(DEFAULT_IMPORT_BATCH_SIZE))
+    {
+        let rows: Vec<(i32, Option<i32>, Option<i32>, Option<i32>, Vec<u8>)> = games::table
+            .filter(games::id.eq_any(ids))
+            .order(games::id)
+            .select((
+                games::id,
+                games::result,
+                games::white_elo,
+                games::black_elo,
+                games::moves,
+            ))
+            .load(db)?;
+        for (id, result, white_elo, black_elo, moves) in rows {
+            let Ok(i) = ids.binary_search(&id) else {
+                continue;
+            };
+            let moves = mainline_moves(&moves);
+            games.push(ExplorerGame {
+                result,
+                ratings: [white_elo, black_elo],
+                moves: moves.get(plies[i] as usize..).unwrap_or_default().to_vec(),
+            });
+        }
+    }
+    Ok(games)
+}
+
+/// A position of the line of an explorer session, with its games.
+struct ExplorerNode {
+    position: Chess,
+    /// The games reaching the position, as their index in the games of the
+    /// session. They all played the moves of the line to get there.
+    matches: Vec<usize>,
+    openings: Vec<PositionStats>,
+    total: PositionStats,
+}
+
+impl ExplorerNode {
+    /// The node of `position`, reached after `depth` moves from the root.
+    fn new(
+        games: &[ExplorerGame],
+        position: Chess,
+        depth: usize,
+        matches: Vec<usize>,
+    ) -> ExplorerNode {
+        let turn = position.turn();
+        let mut openings: HashMap<Option<u8>, PositionStats> = HashMap::new();
+        for &index in &matches {
+            let game = &games[index];
+            let next = game.moves.get(depth).copied();
+            openings
+                .entry(next)
+                .or_insert_with(|| {
+                    let (san, uci) = next
+                        .and_then(|byte| decode_move(byte, &position))
+                        .map_or_else(game_end, |m| next_move(position.clone(), &m));
+                    PositionStats {
+                        move_: san,
+                        uci,
+                        ..Default::default()
+                    }
+                })
+                .record(game.result, game.ratings, turn);
+        }
+        let mut openings: Vec<PositionStats> = openings.into_values().collect();
+        sort_openings(&mut openings);
+        ExplorerNode {
+            total: PositionStats::total(openings.iter()),
+            position,
+            matches,
+            openings,
+        }
+    }
+}
+
+/// A line followed in the opening explorer. Every move narrows the games of
+/// the previous position to those that went on with it, so that they're not
+/// searched again.
+struct ExplorerSession {
+    /// The games reaching the root position when the session started.
+    games: Vec<ExplorerGame>,
+    /// The root position first.
+    line: Vec<ExplorerNode>,
+}
+
+impl ExplorerSession {
+    fn new(games: Vec<ExplorerGame>, position: Chess) -> ExplorerSession {
+        let root = ExplorerNode::new(&games, position, 0, (0..games.len()).collect());
+        ExplorerSession {
+            games,
+            line: vec![root],
+        }
+    }
+
+    fn current(&self) -> &ExplorerNode {
+        self.line.last().unwrap()
+    }
+
+    /// Plays a move given in UCI, keeping the games that played it.
+    fn push(&mut self, uci: &str) -> Result<&ExplorerNode, Error> {
+        let current = self.current();
+        let m = Uci::from_ascii(uci.as_bytes())?.to_move(&current.position)?;
+        // All the games are in the same position, so they encode the move
+        // alike
+        let byte = encode_move(&m, &current.position)?;
+        let games = &self.games;
+        let depth = self.line.len() - 1;
+        let matches = current
+            .matches
+            .par_iter()
+            .copied()
+            .filter(|&index| games[index].moves.get(depth) == Some(&byte))
+            .collect();
+        let mut position = current.position.clone();
+        position.play_unchecked(&m);
+        let node = ExplorerNode::new(games, position, depth + 1, matches);
+        self.line.push(node);
+        Ok(self.current())
+    }
+
+    /// Takes back the last move, if any.
+    fn pop(&mut self) -> &ExplorerNode {
+        if self.line.len() > 1 {
+            self.line.pop();
+        }
+        self.current()
+    }
+}
+
+/// The open explorer sessions, by token.
+#[derive(Default)]
+pub struct ExplorerSessions {
+    next_token: AtomicU64,
+    /// Most recently used first.
+    sessions: Mutex<VecDeque<(u64, Arc<Mutex<ExplorerSession>>)>>,
+}
+
+impl ExplorerSessions {
+    fn open(&self, session: ExplorerSession) -> u64 {
+        let token = self.next_token.fetch_add(1, Ordering::Relaxed);
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.push_front((token, Arc::new(Mutex::new(session))));
+        sessions.truncate(EXPLORER_SESSIONS);
+        token
+    }
+
+    fn get(&self, token: u64) -> Result<Arc<Mutex<ExplorerSession>>, Error> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let index = sessions
+            .iter()
+            .position(|(t, _)| *t == token)
+            .ok_or(Error::ExplorerSessionNotFound(token))?;
+        let entry = sessions.remove(index).unwrap();
+        let session = entry.1.clone();
+        sessions.push_front(entry);
+        Ok(session)
+    }
+
+    fn close(&self, token: u64) {
+        self.sessions.lock().unwrap().retain(|(t, _)| *t != token);
+    }
+}
+
+/// The moves played from a position of an explorer session.
+#[derive(Debug, Clone, Serialize)]
+pub struct ExplorerPosition {
+    pub token: u64,
+    /// The moves played since the root of the session.
+    pub depth: usize,
+    pub openings: Vec<PositionStats>,
+    /// All the games of the position, with an empty move.
+    pub total: PositionStats,
+    pub elapsed_ms: u64,
+}
+
+impl ExplorerPosition {
+    fn new(token: u64, session: &ExplorerSession, start: Instant) -> ExplorerPosition {
+        let node = session.current();
+        ExplorerPosition {
+            token,
+            depth: session.line.len() - 1,
+            openings: node.openings.clone(),
+            total: node.total.clone(),
+            elapsed_ms: start.elapsed().as_millis() as u64,
+        }
+    }
+}
+
+/// Starts following a line from the exact position of `query` in the
+/// opening explorer, returning the token of the session and the moves played
+/// from the position. The session keeps the games reaching the position as
+/// they are now. Only the most recent sessions are kept.
+#[tauri::command]
+pub async fn start_explorer_session(
+    file: PathBuf,
+    query: GameQuery,
+    state: tauri::State<'_, AppState>,
+) -> Result<ExplorerPosition, Error> {
+    let position = match &query.position {
+        Some(PositionQuery::Exact(data)) if data.mode == PositionMode::Exact => {
+            data.position.clone()
+        }
+        _ => return Err(Error::ExplorerNeedsExactPosition),
+    };
+    let start = Instant::now();
+    let permit = state.new_request.acquire().await.unwrap();
+    let result = search_database(
+        &file,
+        &query,
+        None,
+        &state,
+        &AtomicBool::new(false),
+        &|_| {},
+    );
+    drop(permit);
+    let result = result?;
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    let games = explorer_games(db, &result.game_ids, &result.match_plies)?;
+
+    let session = ExplorerSession::new(games, position);
+    let position = ExplorerPosition::new(0, &session, start);
+    let token = state.explorer_sessions.open(session);
+    Ok(ExplorerPosition { token, ..position })
+}
+
+/// Plays a move in UCI in an explorer session, keeping the games of the
+/// previous position that went on with it. Games transposing into the new
+/// position from elsewhere aren't added.
+#[tauri::command]
+pub async fn explorer_push_move(
+    token: u64,
+    uci: String,
+    state: tauri::State<'_, AppState>,
+) -> Result<ExplorerPosition, Error> {
+    let start = Instant::now();
+    let session = state.explorer_sessions.get(token)?;
+    let mut session = session.lock().unwrap();
+    session.push(&uci)?;
+    Ok(ExplorerPosition::new(token, &session, start))
+}
+
+/// Takes back the last move of an explorer session. The root position of
+/// the session stays as it is.
+#[tauri::command]
+pub async fn explorer_pop_move(
+    token: u64,
+    state: tauri::State<'_, AppState>,
+) -> Result<ExplorerPosition, Error> {
+    let start = Instant::now();
+    let session = state.explorer_sessions.get(token)?;
+    let mut session = session.lock().unwrap();
+    session.pop();
+    Ok(ExplorerPosition::new(token, &session, start))
+}
+
+#[tauri::command]
+pub fn close_explorer_session(token: u64, state: tauri::State<'_, AppState>) {
+    state.explorer_sessions.close(token);
+}
+
+/// Cancels the running [`search_position`] of `tab_id`, which then fails
+/// with [`Error::SearchCancelled`].
+#[tauri::command]
+pub async fn cancel_search(tab_id: String, state: tauri::State<'_, AppState>) -> Result<(), Error> {
+    if let Some(cancelled) = state.search_cancellations.get(&tab_id) {
+        cancelled.store(true, Ordering::Relaxed);
+    }
+    Ok(())
+}
+
+pub async fn is_position_in_db(
+    file: PathBuf,
+    query: GameQuery,
+    state: tauri::State<'_, AppState>,
+) -> Result<bool, Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+
+    let generation = state.count_cache.generation(file.to_str().unwrap());
+    if let Some(result) = state.line_cache.get(&file, &query, generation) {
+        return Ok(result.count > 0);
+    }
+
+    if let Some(found) = indexed_matches(db, &query)? {
+        return Ok(!found.matches.is_empty());
+    }
+
+    // start counting the time
+    let start = Instant::now();
+    info!("start loading games");
+
+    let permit = state.new_request.acquire().await.unwrap();
+    let games = load_position_games(db, &state, file.to_str().unwrap())?;
+    let subset = filtered_game_ids(db, &query)?;
+
+    let exists = games.par_iter().any(
+        |(
+            id,
+            _white_id,
+            _black_id,
+            _date,
+            _result,
+            game,
+            fen,
+            end_pawn_home,
+            white_material,
+            black_material,
+            _white_elo,
+            _black_elo,
+            signatures,
+        )| {
+            if state.new_request.available_permits() == 0 {
+                return false;
+            }
+            if subset
+                .as_ref()
+                .is_some_and(|subset| subset.binary_search(id).is_err())
+            {
+                return false;
+            }
+            let end_material: MaterialCount = ByColor {
+                white: *white_material as u8,
+                black: *black_material as u8,
+            };
+            if let Some(position_query) = &query.position {
                 position_query.can_reach(&end_material, *end_pawn_home as u16)
+                    && position_query.passes_signatures(signatures.as_deref())
                     && get_move_after_match(game, fen, position_query)
                         .unwrap_or(None)
                         .is_some()
@@ -464,17 +1759,741 @@ pub async fn is_position_in_db(
     }
 
     if !exists {
-        state.line_cache.insert((query, file), (vec![], vec![]));
+        state
+            .line_cache
+            .insert(&file, &query, generation, PositionSearchResult::default());
     }
 
     drop(permit);
     Ok(exists)
 }
 
+/// The columns of a game needed to tell if it reaches a position: ID, moves,
+/// FEN, pawn home, and white and black material.
+type MatchCandidate = (i32, Vec<u8>, Option<String>, i32, i32, i32);
+
+/// Returns the sorted IDs of the games reaching the position of `query`. Each
+/// game is only replayed up to its first match.
+fn matching_game_ids(games: &[MatchCandidate], query: &PositionQuery) -> Vec<i32> {
+    let mut ids: Vec<i32> = games
+        .par_iter()
+        .filter(
+            |(_, moves, fen, pawn_home, white_material, black_material)| {
+                let end_material: MaterialCount = ByColor {
+                    white: *white_material as u8,
+                    black: *black_material as u8,
+                };
+                query.can_reach(&end_material, *pawn_home as u16)
+                    && matches!(get_move_after_match(moves, fen, query), Ok(Some(_)))
+            },
+        )
+        .map(|(id, ..)| *id)
+        .collect();
+    ids.sort_unstable();
+    ids
+}
+
+/// A constraint of a [`PositionPattern`] on a square.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SquareConstraint {
+    /// The square, as in `"e4"`.
+    pub square: String,
+    /// The piece as in a FEN, `'P'` for a white pawn, or any piece if missing.
+    pub piece: Option<char>,
+}
+
+impl SquareConstraint {
+    fn parse(&self) -> Result<(Square, Option<Piece>), Error> {
+        let square = Square::from_ascii(self.square.as_bytes())
+            .map_err(|_| Error::InvalidPattern(self.square.clone()))?;
+        let piece = match self.piece {
+            Some(c) => Some(Piece::from_char(c).ok_or(Error::InvalidPattern(c.to_string()))?),
+            None => None,
+        };
+        Ok((square, piece))
+    }
+}
+
+/// A partial position: the pieces required on some squares and forbidden on
+/// others, any piece being allowed on the rest of the board.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PositionPattern {
+    #[serde(default)]
+    pub required: Vec<SquareConstraint>,
+    /// Without a piece, the square must be empty.
+    #[serde(default)]
+    pub forbidden: Vec<SquareConstraint>,
+    /// The side to move, `'w'` or `'b'`, or either if missing.
+    pub turn: Option<char>,
+}
+
+/// A [`PositionPattern`] as bitboards.
+#[derive(Debug, Default)]
+struct PatternMatcher {
+    required: Vec<(Piece, Bitboard)>,
+    /// Squares with any piece.
+    occupied: Bitboard,
+    forbidden: Vec<(Piece, Bitboard)>,
+    empty: Bitboard,
+    turn: Option<Color>,
+    /// The least of each piece a position needs to match.
+    counts: [u8; 2 * SIGNATURE_SIZE],
+}
+
+fn add_square(squares: &mut Vec<(Piece, Bitboard)>, piece: Piece, square: Square) {
+    match squares.iter_mut().find(|(p, _)| *p == piece) {
+        Some((_, bitboard)) => bitboard.add(square),
+        None => squares.push((piece, Bitboard::from_square(square))),
+    }
+}
+
+impl PatternMatcher {
+    fn new(pattern: &PositionPattern) -> Result<PatternMatcher, Error> {
+        let mut matcher = PatternMatcher::default();
+        let mut required = Board::empty();
+        for constraint in &pattern.required {
+            match constraint.parse()? {
+                (square, Some(piece)) => {
+                    add_square(&mut matcher.required, piece, square);
+                    required.set_piece_at(square, piece);
+                }
+                (square, None) => matcher.occupied.add(square),
+            }
+        }
+        for constraint in &pattern.forbidden {
+            match constraint.parse()? {
+                (square, Some(piece)) => add_square(&mut matcher.forbidden, piece, square),
+                (square, None) => matcher.empty.add(square),
+            }
+        }
+        if let Some(c) = pattern.turn {
+            matcher.turn = Some(Color::from_char(c).ok_or(Error::InvalidPattern(c.to_string()))?);
+        }
+        matcher.counts = signature_counts(&material_signature(&required));
+        Ok(matcher)
+    }
+
+    fn matches(&self, chess: &Chess) -> bool {
+        if self.turn.is_some_and(|turn| turn != chess.turn()) {
+            return false;
+        }
+        let board = chess.board();
+        let occupied = board.occupied();
+        occupied & self.occupied == self.occupied
+            && (occupied & self.empty).is_empty()
+            && self
+                .required
+                .iter()
+                .all(|(piece, squares)| board.by_piece(*piece) & *squares == *squares)
+            && self
+                .forbidden
+                .iter()
+                .all(|(piece, squares)| (board.by_piece(*piece) & *squares).is_empty())
+    }
+}
+
+/// The first ply where the mainline of a game reaches a position accepted by
+/// `matches`, 0 for the first position of the game.
+fn first_match_ply(
+    moves: &[u8],
+    fen: Option<&str>,
+    matches: impl Fn(&Chess) -> bool,
+) -> Option<u32> {
+    let mut chess = initial_position(fen)?;
+    if matches(&chess) {
+        return Some(0);
+    }
+    for (i, byte) in mainline_moves(moves).iter().enumerate() {
+        let m = decode_move(*byte, &chess)?;
+        chess.play_unchecked(&m);
+        if matches(&chess) {
+            return Some(i as u32 + 1);
+        }
+    }
+    None
+}
+
+/// The games reaching a [`PositionPattern`] or an endgame.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PatternSearchResult {
+    /// The number of matching games, even past the `limit` of `game_ids`.
+    pub count: usize,
+    /// The IDs of the matching games, in order.
+    pub game_ids: Vec<i32>,
+    /// The ply where each game of `game_ids` first reached the pattern, or
+    /// where its endgame started.
+    pub match_plies: Vec<u32>,
+    /// The games ruled out by their material, without replaying them.
+    pub skipped_games: usize,
+    pub elapsed_load_ms: u64,
+    pub elapsed_search_ms: u64,
+}
+
+/// The sorted IDs and first match plies of the games reaching the pattern,
+/// and the number of games ruled out by their material.
+fn pattern_matches(games: &[GameData], matcher: &PatternMatcher) -> (Vec<(i32, u32)>, usize) {
+    let skipped = AtomicUsize::new(0);
+    let mut matches: Vec<(i32, u32)> = games
+        .par_iter()
+        .filter_map(|game| {
+            let (id, moves, fen, signatures) = (game.0, &game.5, &game.6, &game.12);
+            if signatures
+                .as_deref()
+                .is_some_and(|s| !has_counts(s, &matcher.counts))
+            {
+                skipped.fetch_add(1, Ordering::Relaxed);
+                return None;
+            }
+            let ply = first_match_ply(moves, fen.as_deref(), |chess| matcher.matches(chess))?;
+            Some((id, ply))
+        })
+        .collect();
+    matches.sort_unstable();
+    (matches, skipped.into_inner())
+}
+
+/// Searches the games reaching a partial position given square by square,
+/// with up to `limit` of their IDs.
+#[tauri::command]
+pub async fn search_pattern(
+    file: PathBuf,
+    pattern: PositionPattern,
+    limit: Option<usize>,
+    state: tauri::State<'_, AppState>,
+) -> Result<PatternSearchResult, Error> {
+    let matcher = PatternMatcher::new(&pattern)?;
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+
+    let start = Instant::now();
+    let games = load_position_games(db, &state, file.to_str().unwrap())?;
+    let elapsed_load = start.elapsed();
+    let (matches, skipped_games) = pattern_matches(&games, &matcher);
+    let elapsed_search = start.elapsed() - elapsed_load;
+    info!("finished pattern search in {:?}", start.elapsed());
+
+    let count = matches.len();
+    let (game_ids, match_plies) = matches.into_iter().take(limit.unwrap_or(count)).unzip();
+    Ok(PatternSearchResult {
+        count,
+        game_ids,
+        match_plies,
+        skipped_games,
+        elapsed_load_ms: elapsed_load.as_millis() as u64,
+        elapsed_search_ms: elapsed_search.as_millis() as u64,
+    })
+}
+
+/// The material of an endgame, either exact with the kings, as in
+/// `"KRPP-KRP"`, or a class of endgames without them, as in `"R vs R"`. A
+/// class fixes the pieces but not the pawns, those given being the least
+/// each side has. Either side of the spec may be White.
+#[derive(Debug, PartialEq, Eq)]
+struct EndgameMaterial {
+    /// Pawns, knights, bishops, rooks and queens of each side.
+    sides: [[u8; SIGNATURE_SIZE]; 2],
+    exact: bool,
+}
+
+impl EndgameMaterial {
+    fn parse(spec: &str) -> Result<EndgameMaterial, Error> {
+        let invalid = || Error::InvalidMaterial(spec.to_string());
+        let upper = spec.to_ascii_uppercase();
+        let (first, second) = upper
+            .split_once(" VS ")
+            .or_else(|| upper.split_once('-'))
+            .ok_or_else(invalid)?;
+        let (first, second) = (first.trim(), second.trim());
+        let exact = first.starts_with('K');
+        if second.starts_with('K') != exact {
+            return Err(invalid());
+        }
+        let mut sides = [[0; SIGNATURE_SIZE]; 2];
+        for (side, text) in sides.iter_mut().zip([first, second]) {
+            let pieces = if exact { &text[1..] } else { text };
+            if !exact && pieces.is_empty() {
+                return Err(invalid());
+            }
+            for c in pieces.chars().filter(|c| !c.is_whitespace() && *c != '+') {
+                let index = "PNBRQ".find(c).ok_or_else(invalid)?;
+                side[index] += 1;
+            }
+        }
+        Ok(EndgameMaterial { sides, exact })
+    }
+
+    /// Whether the counts of a [`signature_counts`] match, with either side
+    /// of the spec as White.
+    fn matches(&self, counts: &[u8]) -> bool {
+        let (white, black) = counts.split_at(SIGNATURE_SIZE);
+        let side_matches = |side: &[u8; SIGNATURE_SIZE], counts: &[u8]| {
+            let pawns = if self.exact {
+                counts[0] == side[0]
+            } else {
+                counts[0] >= side[0]
+            };
+            pawns && counts[1..] == side[1..]
+        };
+        (side_matches(&self.sides[0], white) && side_matches(&self.sides[1], black))
+            || (side_matches(&self.sides[1], white) && side_matches(&self.sides[0], black))
+    }
+}
+
+/// The ply where the mainline of a game reaches the material of `material`
+/// to keep it for at least `min_plies`, or until the end of the game.
+fn endgame_start_ply(
+    moves: &[u8],
+    fen: Option<&str>,
+    material: &EndgameMaterial,
+    min_plies: u32,
+) -> Option<u32> {
+    let mut chess = initial_position(fen)?;
+    let matches =
+        |chess: &Chess| material.matches(&signature_counts(&material_signature(chess.board())));
+    let mut start = matches(&chess).then_some(0);
+    for (i, byte) in mainline_moves(moves).iter().enumerate() {
+        let ply = i as u32;
+        if start.is_some_and(|start| ply - start >= min_plies) {
+            return start;
+        }
+        let m = decode_move(*byte, &chess)?;
+        // Only captures and promotions change the material
+        let changes_material = m.is_capture() || m.is_promotion();
+        chess.play_unchecked(&m);
+        if changes_material {
+            // A class is kept through the captures of pawns
+            start = matches(&chess).then(|| start.unwrap_or(ply + 1));
+        }
+    }
+    start
+}
+
+/// The sorted IDs and endgame plies of the games reaching `material`, and
+/// the number of games ruled out by their material signatures.
+fn endgame_matches(
+    games: &[GameData],
+    material: &EndgameMaterial,
+    min_plies: u32,
+) -> (Vec<(i32, u32)>, usize) {
+    let skipped = AtomicUsize::new(0);
+    let mut matches: Vec<(i32, u32)> = games
+        .par_iter()
+        .filter_map(|game| {
+            let (id, moves, fen, signatures) = (game.0, &game.5, &game.6, &game.12);
+            if signatures.as_deref().is_some_and(|s| {
+                !s.chunks_exact(SIGNATURE_SIZE)
+                    .any(|s| material.matches(&signature_counts(s)))
+            }) {
+                skipped.fetch_add(1, Ordering::Relaxed);
+                return None;
+            }
+            let ply = endgame_start_ply(moves, fen.as_deref(), material, min_plies)?;
+            Some((id, ply))
+        })
+        .collect();
+    matches.sort_unstable();
+    (matches, skipped.into_inner())
+}
+
+/// Searches the games reaching an endgame given by its material, as in
+/// `"KRPP-KRP"` or `"R vs R"`, that lasts at least `min_plies_in_endgame`
+/// so that exchanges passing through it don't count. Returns up to `limit`
+/// of their IDs, with the ply where the endgame starts.
+#[tauri::command]
+pub async fn search_endgames(
+    file: PathBuf,
+    material: String,
+    min_plies_in_endgame: u32,
+    limit: Option<usize>,
+    state: tauri::State<'_, AppState>,
+) -> Result<PatternSearchResult, Error> {
+    let material = EndgameMaterial::parse(&material)?;
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+
+    let start = Instant::now();
+    let games = load_position_games(db, &state, file.to_str().unwrap())?;
+    let elapsed_load = start.elapsed();
+    let (matches, skipped_games) = endgame_matches(&games, &material, min_plies_in_endgame);
+    let elapsed_search = start.elapsed() - elapsed_load;
+    info!("finished endgame search in {:?}", start.elapsed());
+
+    let count = matches.len();
+    let (game_ids, match_plies) = matches.into_iter().take(limit.unwrap_or(count)).unzip();
+    Ok(PatternSearchResult {
+        count,
+        game_ids,
+        match_plies,
+        skipped_games,
+        elapsed_load_ms: elapsed_load.as_millis() as u64,
+        elapsed_search_ms: elapsed_search.as_millis() as u64,
+    })
+}
+
+/// Exports the games reaching the position of `fen` as PGN, ordered by ID.
+/// Returns the number of games written.
+#[tauri::command]
+pub async fn export_position_matches(
+    file: PathBuf,
+    fen: String,
+    dest_file: PathBuf,
+    compression: Option<ExportCompression>,
+    state: tauri::State<'_, AppState>,
+) -> Result<usize, Error> {
+    let query = PositionQuery::exact_from_fen(&fen, PositionMode::Exact)?;
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+
+    let games: Vec<MatchCandidate> = games::table
+        .select((
+            games::id,
+            games::moves,
+            games::fen,
+            games::pawn_home,
+            games::white_material,
+            games::black_material,
+        ))
+        .load(db)?;
+    let ids = matching_game_ids(&games, &query);
+    drop(games);
+    if ids.is_empty() {
+        return Err(Error::NoMatchingGames);
+    }
+
+    ExportWriter::export(&dest_file, compression, |writer| {
+        write_games_by_id(db, &ids, writer)
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use crate::db::encoding::encode_material_signatures;
+    use shakmaty::san::San;
+
+    fn candidate(id: i32, sans: &[&str]) -> MatchCandidate {
+        let mut chess = Chess::default();
+        let moves = sans
+            .iter()
+            .map(|san| {
+                let m = san.parse::<San>().unwrap().to_move(&chess).unwrap();
+                let byte = encode_move(&m, &chess).unwrap();
+                chess.play_unchecked(&m);
+                byte
+            })
+            .collect();
+        let material = get_material_count(chess.board());
+        (
+            id,
+            moves,
+            None,
+            get_pawn_home(chess.board()) as i32,
+            material.white as i32,
+            material.black as i32,
+        )
+    }
+
+    fn cached_games(n: usize) -> Arc<Vec<GameData>> {
+        Arc::new(vec![
+            (
+                0,
+                0,
+                0,
+                None,
+                None,
+                vec![0; 100],
+                None,
+                0,
+                0,
+                0,
+                None,
+                None,
+                None
+            );
+            n
+        ])
+    }
+
+    #[test]
+    fn position_cache_follows_the_generations_and_the_limit() {
+        let cache = PositionCache::default();
+        cache.insert("a.db3", 1, cached_games(2));
+        assert_eq!(cache.get("a.db3", 1).map(|g| g.len()), Some(2));
+        // Modified since
+        assert!(cache.get("a.db3", 2).is_none());
+        assert!(cache.get("a.db3", 1).is_none());
+
+        let size = games_size(&cached_games(1));
+        cache.set_limit(2 * size);
+        cache.insert("a.db3", 1, cached_games(1));
+        cache.insert("b.db3", 1, cached_games(1));
+        assert!(cache.get("a.db3", 1).is_some());
+        // Drops the least recently used
+        cache.insert("c.db3", 1, cached_games(1));
+        assert!(cache.get("b.db3", 1).is_none());
+        assert!(cache.get("a.db3", 1).is_some());
+        assert!(cache.get("c.db3", 1).is_some());
+
+        cache.insert("d.db3", 1, cached_games(3));
+        assert!(cache.get("d.db3", 1).is_none());
+        cache.clear();
+        assert!(cache.get("a.db3", 1).is_none());
+    }
+
+    #[test]
+    fn line_cache_follows_the_generations_and_the_capacity() {
+        let cache = LineCache::default();
+        let file = Path::new("a.db3");
+        let query = |id| GameQuery {
+            tournament_id: Some(id),
+            ..Default::default()
+        };
+        let result = PositionSearchResult {
+            count: 3,
+            ..Default::default()
+        };
+        cache.insert(file, &query(0), 1, result.clone());
+        assert_eq!(cache.get(file, &query(0), 1).map(|r| r.count), Some(3));
+        assert!(cache.get(Path::new("b.db3"), &query(0), 1).is_none());
+        // Searched before the database was modified
+        assert!(cache.get(file, &query(0), 2).is_none());
+        assert!(cache.get(file, &query(0), 1).is_none());
+
+        for id in 0..LINE_CACHE_CAPACITY as i32 {
+            cache.insert(file, &query(id), 1, result.clone());
+        }
+        assert!(cache.get(file, &query(0), 1).is_some());
+        cache.insert(file, &query(-1), 1, result);
+        assert!(cache.get(file, &query(0), 1).is_some());
+        assert!(cache.get(file, &query(1), 1).is_none());
+    }
+
+    #[test]
+    fn limited_search_results() {
+        let result = PositionSearchResult {
+            count: 5,
+            game_ids: vec![1, 2, 3],
+            ..Default::default()
+        };
+        assert_eq!(result.limited(Some(2)).unwrap().game_ids, vec![1, 2]);
+        assert_eq!(result.limited(Some(3)).unwrap().game_ids, vec![1, 2, 3]);
+        // The cached result was cut to fewer IDs
+        assert!(result.limited(Some(4)).is_none());
+        assert!(result.limited(None).is_none());
+
+        let result = PositionSearchResult {
+            count: 2,
+            game_ids: vec![1, 2],
+            ..Default::default()
+        };
+        assert_eq!(result.limited(None).unwrap().game_ids, vec![1, 2]);
+        assert_eq!(result.limited(Some(10)).unwrap().game_ids, vec![1, 2]);
+    }
+
+    fn pattern_game(id: i32, fen: Option<&str>, sans: &[&str]) -> GameData {
+        let mut chess = initial_position(fen).unwrap();
+        let moves: Vec<u8> = sans
+            .iter()
+            .map(|san| {
+                let m = san.parse::<San>().unwrap().to_move(&chess).unwrap();
+                let byte = encode_move(&m, &chess).unwrap();
+                chess.play_unchecked(&m);
+                byte
+            })
+            .collect();
+        let signatures = encode_material_signatures(&moves, fen);
+        let fen = fen.map(String::from);
+        (
+            id, 0, 0, None, None, moves, fen, 0, 0, 0, None, None, signatures,
+        )
+    }
+
+    #[test]
+    fn explorer_sessions_narrow_the_games_move_by_move() {
+        // The games the search found at the root
+        let lines: [&[&str]; 3] = [&["e4", "e5", "Nf3"], &["e4", "c5"], &["d4", "d5"]];
+        let games = lines
+            .iter()
+            .map(|sans| ExplorerGame {
+                result: None,
+                ratings: [None, None],
+                moves: pattern_game(0, None, sans).5,
+            })
+            .collect();
+        let moves = |node: &ExplorerNode| -> Vec<(String, i32)> {
+            node.openings
+                .iter()
+                .map(|o| (o.move_.clone(), o.games))
+                .collect()
+        };
+        let to_moves = |moves: &[(&str, i32)]| -> Vec<(String, i32)> {
+            moves.iter().map(|(m, n)| (m.to_string(), *n)).collect()
+        };
+
+        let mut session = ExplorerSession::new(games, Chess::default());
+        assert_eq!(moves(session.current()), to_moves(&[("e4", 2), ("d4", 1)]));
+        let node = session.push("e2e4").unwrap();
+        assert_eq!(moves(node), to_moves(&[("c5", 1), ("e5", 1)]));
+        assert_eq!(node.matches, vec![0, 1]);
+        let node = session.push("e7e5").unwrap();
+        assert_eq!(moves(node), to_moves(&[("Nf3", 1)]));
+        assert_eq!(node.total.games, 1);
+        assert!(session.push("e2e4").is_err());
+        assert_eq!(session.line.len(), 3);
+
+        assert_eq!(session.pop().total.games, 2);
+        assert_eq!(session.pop().total.games, 3);
+        assert_eq!(session.pop().total.games, 3);
+    }
+
+    #[test]
+    fn least_recently_used_explorer_sessions_are_closed() {
+        let sessions = ExplorerSessions::default();
+        let session = || ExplorerSession::new(Vec::new(), Chess::default());
+        let first = sessions.open(session());
+        for _ in 1..EXPLORER_SESSIONS {
+            sessions.open(session());
+        }
+        assert!(sessions.get(first).is_ok());
+        let second = first + 1;
+        sessions.open(session());
+        assert!(sessions.get(first).is_ok());
+        assert!(sessions.get(second).is_err());
+
+        sessions.close(first);
+        assert!(sessions.get(first).is_err());
+    }
+
+    #[test]
+    fn merged_openings_add_up_the_databases() {
+        let stats = |move_: &str, result: GameResult, rating| {
+            let mut stats = PositionStats {
+                move_: move_.to_string(),
+                ..Default::default()
+            };
+            stats.record(Some(result as i32), [Some(rating), None], Color::White);
+            stats
+        };
+        let first = [
+            stats("e4", GameResult::WhiteWins, 2000),
+            stats("d4", GameResult::Draw, 2200),
+        ];
+        let second = [stats("d4", GameResult::BlackWins, 2400)];
+
+        let merged = merge_openings(first.iter().chain(second.iter()));
+        let moves: Vec<(&str, i32)> = merged.iter().map(|m| (m.move_.as_str(), m.games)).collect();
+        assert_eq!(moves, vec![("d4", 2), ("e4", 1)]);
+        assert_eq!((merged[0].draw, merged[0].black), (1, 1));
+        assert_eq!(merged[0].average_rating, Some(2300));
+    }
+
+    #[test]
+    fn pattern_search() {
+        let games = [
+            pattern_game(1, None, &["d4", "d5", "e3", "Nf6"]),
+            pattern_game(2, None, &["e4", "e5"]),
+            // Not enough pawns to ever match
+            pattern_game(3, Some("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1"), &["e3"]),
+        ];
+        let constraint = |square: &str, piece| SquareConstraint {
+            square: square.to_string(),
+            piece,
+        };
+        let mut pattern = PositionPattern {
+            required: vec![
+                constraint("d4", Some('P')),
+                constraint("e3", Some('P')),
+                constraint("f2", Some('P')),
+                constraint("d5", Some('p')),
+            ],
+            ..Default::default()
+        };
+        let matcher = PatternMatcher::new(&pattern).unwrap();
+        assert_eq!(pattern_matches(&games, &matcher), (vec![(1, 3)], 1));
+
+        pattern.turn = Some('w');
+        let matcher = PatternMatcher::new(&pattern).unwrap();
+        assert_eq!(pattern_matches(&games, &matcher), (vec![(1, 4)], 1));
+
+        pattern.forbidden = vec![constraint("f6", Some('n'))];
+        let matcher = PatternMatcher::new(&pattern).unwrap();
+        assert_eq!(pattern_matches(&games, &matcher).0, vec![]);
+
+        // Any piece on e4, and an empty e5
+        let pattern = PositionPattern {
+            required: vec![constraint("e4", None)],
+            forbidden: vec![constraint("e5", None)],
+            ..Default::default()
+        };
+        let matcher = PatternMatcher::new(&pattern).unwrap();
+        assert_eq!(pattern_matches(&games, &matcher).0, vec![(2, 1)]);
+
+        let pattern = PositionPattern {
+            required: vec![constraint("e9", Some('P'))],
+            ..Default::default()
+        };
+        assert!(PatternMatcher::new(&pattern).is_err());
+    }
+
+    #[test]
+    fn endgame_material_specs() {
+        let material = EndgameMaterial::parse("KRPP-KRP").unwrap();
+        assert_eq!(material.sides, [[2, 0, 0, 1, 0], [1, 0, 0, 1, 0]]);
+        assert!(material.exact);
+        let material = EndgameMaterial::parse("R vs r").unwrap();
+        assert_eq!(material.sides, [[0, 0, 0, 1, 0]; 2]);
+        assert!(!material.exact);
+
+        // Rook and two pawns against rook, with White or Black ahead
+        let counts = [2, 0, 0, 1, 0, 0, 0, 0, 1, 0];
+        assert!(EndgameMaterial::parse("KRPP-KR").unwrap().matches(&counts));
+        assert!(EndgameMaterial::parse("KR-KRPP").unwrap().matches(&counts));
+        assert!(!EndgameMaterial::parse("KRP-KR").unwrap().matches(&counts));
+        assert!(EndgameMaterial::parse("RP vs R").unwrap().matches(&counts));
+        assert!(!EndgameMaterial::parse("RP vs RP").unwrap().matches(&counts));
+
+        for spec in ["KR", "KRX-KR", "KR vs R", " vs R"] {
+            assert!(EndgameMaterial::parse(spec).is_err(), "{spec}");
+        }
+    }
+
+    #[test]
+    fn endgames_have_to_last() {
+        let fen = Some("4k2r/8/8/8/8/n7/4P3/R3K3 w - - 0 1");
+        let games = [
+            // Rook and pawn against rook from ply 1, then rook against rook
+            pattern_game(1, fen, &["Rxa3", "Rh2", "Ra4", "Rxe2"]),
+            // Never leaves the knight
+            pattern_game(2, fen, &["Ra2", "Nb5"]),
+        ];
+        let search = |spec, min_plies| {
+            endgame_matches(&games, &EndgameMaterial::parse(spec).unwrap(), min_plies)
+        };
+        assert_eq!(search("KRP-KR", 0), (vec![(1, 1)], 1));
+        assert_eq!(search("KRP-KR", 2), (vec![(1, 1)], 1));
+        assert_eq!(search("KRP-KR", 3).0, vec![]);
+        // Until the end of the game
+        assert_eq!(search("KR-KR", 10).0, vec![(1, 4)]);
+        // The capture of the pawn stays in the class
+        assert_eq!(search("R vs R", 10).0, vec![(1, 1)]);
+        assert_eq!(search("RN vs R", 0), (vec![(1, 0), (2, 0)], 0));
+    }
+
+    #[test]
+    fn matching_game_ids_are_sorted() {
+        let games = vec![
+            candidate(3, &["e4", "e5", "Nf3"]),
+            candidate(1, &["d4", "d5"]),
+            candidate(2, &["e4", "e5"]),
+            // Transposes back to the position after 1. e4 e5
+            candidate(4, &["e4", "e5", "Nf3", "Nf6", "Ng1", "Ng8"]),
+        ];
+        let query = PositionQuery::exact_from_fen(
+            "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2",
+            PositionMode::Exact,
+        )
+        .unwrap();
+        assert_eq!(matching_game_ids(&games, &query), vec![2, 3, 4]);
+    }
+
     fn assert_partial_match(fen1: &str, fen2: &str) {
         let query = PositionQuery::partial_from_fen(fen1).unwrap();
         let fen = Fen::from_ascii(fen2.as_bytes()).unwrap();
@@ -486,6 +2505,7 @@ mod tests {
     fn exact_matches() {
         let query = PositionQuery::exact_from_fen(
             "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            PositionMode::Exact,
         )
         .unwrap();
         let chess = Chess::default();
@@ -523,9 +2543,11 @@ mod tests {
 
     #[test]
     fn correct_exact_is_reachable() {
-        let query =
-            PositionQuery::exact_from_fen("rnbqkb1r/pppp1ppp/5n2/4p3/4P3/2N5/PPPP1PPP/R1BQKBNR")
-                .unwrap();
+        let query = PositionQuery::exact_from_fen(
+            "rnbqkb1r/pppp1ppp/5n2/4p3/4P3/2N5/PPPP1PPP/R1BQKBNR",
+            PositionMode::Exact,
+        )
+        .unwrap();
         let chess = Chess::default();
         assert!(query.is_reachable_by(
             &get_material_count(chess.board()),
@@ -553,25 +2575,94 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn games_are_replayed_up_to_the_max_ply() {
+        let mut sans = ["Nf3", "Nf6", "Ng1", "Ng8"].repeat(6);
+        sans.extend(["e4", "e5"]);
+        let (_, game, ..) = candidate(1, &sans);
+        let fen = "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2";
+
+        // The whole game unless asked otherwise
+        let query = PositionQuery::exact_from_fen(fen, PositionMode::Exact).unwrap();
+        assert_eq!(query.max_ply(), None);
+        let result = get_move_after_match(&game, &None, &query).unwrap();
+        assert_eq!(result.map(|m| m.ply), Some(26));
+
+        // Only up to ply 22 from the move counters
+        let query = query.with_counters_max_ply();
+        assert_eq!(query.max_ply(), Some(22));
+        assert_eq!(get_move_after_match(&game, &None, &query).unwrap(), None);
+
+        let query = query.with_max_ply(Some(40));
+        let result = get_move_after_match(&game, &None, &query).unwrap();
+        assert_eq!(result.map(|m| m.ply), Some(26));
+
+        // No limit for a position at the first move
+        let query = PositionQuery::exact_from_fen(
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            PositionMode::Exact,
+        )
+        .unwrap()
+        .with_counters_max_ply();
+        assert_eq!(query.max_ply(), None);
+    }
+
     #[test]
     fn get_move_after_exact_match_test() {
         let game = vec![12, 12]; // 1. e4 e5
 
-        let query =
-            PositionQuery::exact_from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR").unwrap();
+        let query = PositionQuery::exact_from_fen(
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            PositionMode::Exact,
+        )
+        .unwrap();
+        let result = get_move_after_match(&game, &None, &query).unwrap();
+        assert_eq!(
+            result,
+            Some(PositionMatch {
+                ply: 0,
+                turn: Color::White,
+                next_move: ("e4".to_string(), "e2e4".to_string())
+            })
+        );
+
+        let query = PositionQuery::exact_from_fen(
+            "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1",
+            PositionMode::Exact,
+        )
+        .unwrap();
         let result = get_move_after_match(&game, &None, &query).unwrap();
-        assert_eq!(result, Some("e4".to_string()));
+        assert_eq!(
+            result,
+            Some(PositionMatch {
+                ply: 1,
+                turn: Color::Black,
+                next_move: ("e5".to_string(), "e7e5".to_string())
+            })
+        );
 
-        let query =
-            PositionQuery::exact_from_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR").unwrap();
+        // Without the castling rights, only the placement matches
+        let fen = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR w - - 0 1";
+        let query = PositionQuery::exact_from_fen(fen, PositionMode::Exact).unwrap();
+        assert_eq!(get_move_after_match(&game, &None, &query).unwrap(), None);
+        let query = PositionQuery::exact_from_fen(fen, PositionMode::Placement).unwrap();
         let result = get_move_after_match(&game, &None, &query).unwrap();
-        assert_eq!(result, Some("e5".to_string()));
+        assert_eq!(result.map(|m| m.ply), Some(1));
 
-        let query =
-            PositionQuery::exact_from_fen("rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR")
-                .unwrap();
+        let query = PositionQuery::exact_from_fen(
+            "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2",
+            PositionMode::Exact,
+        )
+        .unwrap();
         let result = get_move_after_match(&game, &None, &query).unwrap();
-        assert_eq!(result, Some("*".to_string()));
+        assert_eq!(
+            result,
+            Some(PositionMatch {
+                ply: 2,
+                turn: Color::White,
+                next_move: game_end()
+            })
+        );
     }
 
     #[test]
@@ -580,6 +2671,114 @@ mod tests {
 
         let query = PositionQuery::partial_from_fen("8/pppppppp/8/8/8/8/PPPPPPPP/8").unwrap();
         let result = get_move_after_match(&game, &None, &query).unwrap();
-        assert_eq!(result, Some("e4".to_string()));
+        assert_eq!(
+            result.map(|m| (m.ply, m.next_move.0)),
+            Some((0, "e4".to_string()))
+        );
+    }
+
+    #[test]
+    fn next_move_stats_average_the_ratings() {
+        let mut stats = PositionStats::default();
+        let turn = Color::White;
+        stats.record(
+            Some(GameResult::WhiteWins as i32),
+            [Some(2500), Some(2300)],
+            turn,
+        );
+        stats.record(Some(GameResult::Draw as i32), [Some(2600), None], turn);
+        stats.record(None, [None, None], turn);
+        assert_eq!(
+            (stats.games, stats.white, stats.draw, stats.black),
+            (3, 1, 1, 0)
+        );
+        assert_eq!(stats.average_rating, Some(2466));
+    }
+
+    #[test]
+    fn next_move_stats_score_the_side_to_move() {
+        let mut stats = PositionStats::default();
+        let turn = Color::Black;
+        stats.record(
+            Some(GameResult::BlackWins as i32),
+            [Some(2400), Some(2200)],
+            turn,
+        );
+        stats.record(
+            Some(GameResult::Draw as i32),
+            [Some(2600), Some(2300)],
+            turn,
+        );
+        stats.record(Some(GameResult::WhiteWins as i32), [None, Some(2100)], turn);
+        stats.record(
+            Some(GameResult::Unknown as i32),
+            [Some(2500), Some(2000)],
+            turn,
+        );
+        // The unknown result is counted but not as a draw
+        assert_eq!(
+            (stats.games, stats.white, stats.draw, stats.black),
+            (4, 1, 1, 1)
+        );
+        assert_eq!(
+            [stats.white_percent, stats.draw_percent, stats.black_percent].map(f64::round),
+            [33.0, 33.0, 33.0]
+        );
+        assert_eq!(stats.mover_rating, Some((2200 + 2300 + 2100 + 2000) / 4));
+        // 1.5 out of 2 against 2500 on average, without the unrated opponent
+        assert_eq!(stats.performance, Some(2500 + 193));
+
+        let total = PositionStats::total([stats.clone(), stats].iter());
+        assert_eq!((total.games, total.performance), (8, Some(2693)));
+    }
+
+    #[test]
+    fn exact_searches_use_an_up_to_date_position_index() {
+        let db = &mut SqliteConnection::establish(":memory:").unwrap();
+        db.batch_execute(crate::db::CREATE_TABLES_SQL).unwrap();
+        let insert = |db: &mut SqliteConnection, sans: &[&str], result: GameResult| {
+            let (_, moves, ..) = candidate(0, sans);
+            diesel::insert_into(games::table)
+                .values((games::moves.eq(moves), games::result.eq(result as i32)))
+                .execute(db)
+                .unwrap();
+        };
+        insert(db, &["e4", "e5", "Nf3", "Nc6"], GameResult::WhiteWins);
+        insert(db, &["Nf3", "Nc6", "e4", "e5", "Bc4"], GameResult::Draw);
+        insert(db, &["d4", "d5"], GameResult::BlackWins);
+
+        let fen = "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3";
+        let query = GameQuery {
+            position: Some(
+                PositionQuery::exact_from_fen(fen, PositionMode::Exact)
+                    .unwrap()
+                    .with_max_ply(Some(4)),
+            ),
+            ..Default::default()
+        };
+        assert!(indexed_matches(db, &query).unwrap().is_none());
+
+        // Each position once per game, none past the max ply
+        assert_eq!(build_index(db, 4, |_| ()).unwrap(), 5 + 5 + 3);
+        let mut found = indexed_matches(db, &query).unwrap().unwrap();
+        found.matches.sort();
+        assert_eq!(found.matches, vec![(1, 4), (2, 4)]);
+        let mut moves: Vec<_> = found
+            .openings
+            .iter()
+            .map(|o| (o.uci.as_str(), o.games))
+            .collect();
+        moves.sort();
+        assert_eq!(moves, vec![("*", 1), ("f1c4", 1)]);
+
+        // Past the plies of the index
+        let deeper = GameQuery {
+            position: query.position.clone().map(|p| p.with_max_ply(Some(10))),
+            ..Default::default()
+        };
+        assert!(indexed_matches(db, &deeper).unwrap().is_none());
+
+        insert(db, &["e4"], GameResult::Draw);
+        assert!(indexed_matches(db, &query).unwrap().is_none());
     }
 }
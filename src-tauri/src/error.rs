@@ -21,6 +21,9 @@ pub enum Error {
     #[error(transparent)]
     ParseInt(#[from] std::num::ParseIntError),
 
+    #[error(transparent)]
+    Csv(#[from] csv::Error),
+
     #[error(transparent)]
     Tauri(#[from] tauri::Error),
 
@@ -57,6 +60,12 @@ pub enum Error {
     #[error(transparent)]
     SystemTime(#[from] std::time::SystemTimeError),
 
+    #[error(transparent)]
+    Join(#[from] tokio::task::JoinError),
+
+    #[error(transparent)]
+    SerdeJson(#[from] serde_json::Error),
+
     #[error("No stdin")]
     NoStdin,
 
@@ -72,6 +81,9 @@ pub enum Error {
     #[error("Search stopped")]
     SearchStopped,
 
+    #[error("Search cancelled")]
+    SearchCancelled,
+
     #[error("Missing reference database")]
     MissingReferenceDatabase,
 
@@ -86,6 +98,75 @@ pub enum Error {
 
     #[error("Players aren't the same. They have played against each other")]
     NotDistinctPlayers,
+
+    #[error("Can't merge into itself")]
+    MergeIntoItself,
+
+    #[error("Import cancelled")]
+    ImportCancelled,
+
+    #[error("Database already exists: {0}")]
+    DatabaseAlreadyExists(String),
+
+    #[error("No interrupted import to resume")]
+    NoImportCheckpoint,
+
+    #[error("The source of the import changed since it was interrupted: {0}")]
+    ImportSourceChanged(String),
+
+    #[error("No valid games found in the PGN")]
+    NoGamesInPgn,
+
+    #[error("No games match the query")]
+    NoMatchingGames,
+
+    #[error("Game not found: {0}")]
+    GameNotFound(i32),
+
+    #[error("Player not found: {0}")]
+    PlayerNotFound(i32),
+
+    #[error("Event not found: {0}")]
+    EventNotFound(i32),
+
+    #[error("Site not found: {0}")]
+    SiteNotFound(i32),
+
+    #[error("Another event already has this name: {0}")]
+    EventNameTaken(i32),
+
+    #[error("Another site already has this name: {0}")]
+    SiteNameTaken(i32),
+
+    #[error("Another player already has this name: {0}")]
+    PlayerNameTaken(i32),
+
+    #[error("The player still has games: {0}")]
+    PlayerHasGames(i32),
+
+    #[error("The Unknown player can't be deleted")]
+    UnknownPlayerDeletion,
+
+    #[error("Invalid cursor for this sort")]
+    InvalidCursor,
+
+    #[error("Invalid square or piece in the pattern: {0}")]
+    InvalidPattern(String),
+
+    #[error("Invalid endgame material: {0}")]
+    InvalidMaterial(String),
+
+    #[error("The explorer needs an exact position")]
+    ExplorerNeedsExactPosition,
+
+    #[error("Explorer session not found: {0}")]
+    ExplorerSessionNotFound(u64),
+
+    #[error("Too many players match the pattern: {0}")]
+    TooManyPlayersMatched(String),
+
+    #[error("Players and events can only be filtered by name across databases")]
+    IdFilterAcrossDatabases,
 }
 
 impl serde::Serialize for Error {
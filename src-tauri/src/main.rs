@@ -14,13 +14,15 @@ mod opening;
 mod pgn;
 mod puzzle;
 
-use std::path::PathBuf;
-use std::sync::{Arc, Mutex};
+use std::sync::{atomic::AtomicBool, Arc};
 use std::{fs::create_dir_all, path::Path};
 
 use chess::{BestMovesPayload, EngineProcess, ReportProgress};
 use dashmap::DashMap;
-use db::{DatabaseProgress, GameQuery, NormalizedGame, PositionStats};
+use db::{
+    CountCache, DatabaseProgress, ExplorerSessions, GamesCounted, ImportProgress, LineCache,
+    PositionCache,
+};
 use derivative::Derivative;
 use fide::FidePlayer;
 use log::LevelFilter;
@@ -38,9 +40,17 @@ use crate::chess::{
     analyze_game, get_engine_config, get_engine_logs, kill_engine, kill_engines, stop_engine,
 };
 use crate::db::{
-    clear_games, convert_pgn, create_indexes, delete_database, delete_db_game, delete_empty_games,
-    delete_indexes, export_to_pgn, get_player, get_players_game_info, get_tournaments,
-    search_position,
+    autocomplete_players, build_indexes, build_position_index, cancel_import, cancel_search,
+    cancel_stream, clear_position_cache, close_explorer_session, convert_pgn, convert_pgn_from_url,
+    convert_pgns, create_database_from_query, create_indexes, create_opening_book, delete_database,
+    delete_db_game, delete_empty_games, delete_indexes, delete_phantom_games,
+    detect_event_editions, explorer_pop_move, explorer_push_move, export_csv, export_epd,
+    export_games, export_jsonl, export_player_games, export_position_matches, export_to_pgn,
+    get_event_standings, get_events, get_head_to_head, get_player, get_player_leaderboards,
+    get_player_openings, get_player_performance, get_player_rating_history, get_players_game_info,
+    get_sites, get_tournaments, import_pgn_string, resume_import, sample_games, search_endgames,
+    search_pattern, search_position, search_position_multi, set_position_cache_limit,
+    start_explorer_session, stream_games,
 };
 use crate::fide::{download_fide_db, find_fide_player};
 use crate::fs::{append_to_file, set_file_as_executable, DownloadProgress};
@@ -51,7 +61,12 @@ use crate::puzzle::{get_puzzle, get_puzzle_db_info};
 use crate::{
     chess::get_best_moves,
     db::{
-        delete_duplicated_games, edit_db_info, get_db_info, get_games, get_players, merge_players,
+        classify_openings, compute_material_signatures, compute_max_ratings,
+        compute_player_game_counts, compute_speeds, delete_duplicated_games, delete_player,
+        edit_db_info, find_similar_players, get_db_info, get_db_stats, get_game, get_games,
+        get_games_per_period, get_opening_stats, get_players, merge_events, merge_players,
+        merge_sites, normalize_dates, normalize_player_names, recompute_player_ratings,
+        remove_duplicate_games, update_event, update_player, update_site,
     },
     fs::{download_file, file_exists, get_file_metadata},
     opening::{get_opening_from_fen, get_opening_from_name, search_opening_name},
@@ -66,12 +81,15 @@ pub type GameData = (
     i32,
     i32,
     Option<String>,
-    Option<String>,
+    Option<i32>,
     Vec<u8>,
     Option<String>,
     i32,
     i32,
     i32,
+    Option<i32>,
+    Option<i32>,
+    Option<Vec<u8>>,
 );
 
 #[derive(Derivative)]
@@ -81,11 +99,16 @@ pub struct AppState {
         String,
         diesel::r2d2::Pool<diesel::r2d2::ConnectionManager<diesel::SqliteConnection>>,
     >,
-    line_cache: DashMap<(GameQuery, PathBuf), (Vec<PositionStats>, Vec<NormalizedGame>)>,
-    db_cache: Mutex<Vec<GameData>>,
+    line_cache: LineCache,
+    position_cache: PositionCache,
     #[derivative(Default(value = "Arc::new(Semaphore::new(2))"))]
     new_request: Arc<Semaphore>,
     pgn_offsets: DashMap<String, Vec<u64>>,
+    import_cancellations: DashMap<String, Arc<AtomicBool>>,
+    stream_cancellations: DashMap<String, Arc<AtomicBool>>,
+    search_cancellations: DashMap<String, Arc<AtomicBool>>,
+    count_cache: CountCache,
+    explorer_sessions: ExplorerSessions,
     fide_players: RwLock<Vec<FidePlayer>>,
     engine_processes: DashMap<(String, String), Arc<tokio::sync::Mutex<EngineProcess>>>,
     auth: AuthState,
@@ -150,12 +173,33 @@ fn main() {
                 get_file_metadata,
                 merge_players,
                 convert_pgn,
+                convert_pgns,
+                convert_pgn_from_url,
+                cancel_import,
+                resume_import,
+                import_pgn_string,
                 get_player,
+                autocomplete_players,
+                normalize_player_names,
+                find_similar_players,
+                update_player,
+                delete_player,
+                get_player_rating_history,
+                get_head_to_head,
+                get_player_leaderboards,
+                get_event_standings,
+                update_event,
+                merge_events,
+                update_site,
+                merge_sites,
+                detect_event_editions,
             ))
             .events(tauri_specta::collect_events!(
                 BestMovesPayload,
                 DatabaseProgress,
                 DownloadProgress,
+                GamesCounted,
+                ImportProgress,
                 ReportProgress
             ));
 
@@ -262,18 +306,45 @@ fn main() {
         .manage(AppState::default())
         .invoke_handler(tauri::generate_handler![
             download_file,
+            get_game,
             get_games,
+            get_games_per_period,
+            get_opening_stats,
+            get_player_openings,
+            get_player_performance,
+            stream_games,
+            cancel_stream,
+            sample_games,
             get_players,
             get_tournaments,
+            get_events,
+            get_sites,
             get_db_info,
+            get_db_stats,
             get_puzzle_db_info,
             edit_db_info,
             delete_duplicated_games,
+            remove_duplicate_games,
+            normalize_dates,
+            classify_openings,
+            compute_speeds,
+            compute_max_ratings,
+            compute_material_signatures,
+            compute_player_game_counts,
             authenticate,
             delete_database,
             search_position,
+            search_position_multi,
+            search_pattern,
+            search_endgames,
+            start_explorer_session,
+            explorer_push_move,
+            explorer_pop_move,
+            close_explorer_session,
+            cancel_search,
             is_bmi2_compatible,
-            clear_games,
+            clear_position_cache,
+            set_position_cache_limit,
             set_file_as_executable,
             count_pgn_games,
             read_games,
@@ -282,12 +353,24 @@ fn main() {
             write_game,
             delete_indexes,
             create_indexes,
+            build_indexes,
+            build_position_index,
             lex_pgn,
             download_fide_db,
             search_opening_name,
             delete_db_game,
             delete_empty_games,
-            export_to_pgn
+            delete_phantom_games,
+            recompute_player_ratings,
+            export_to_pgn,
+            export_games,
+            export_player_games,
+            export_csv,
+            create_opening_book,
+            export_epd,
+            create_database_from_query,
+            export_position_matches,
+            export_jsonl
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
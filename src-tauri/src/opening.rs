@@ -1,6 +1,12 @@
+use std::collections::HashMap;
+
 use log::info;
 use serde::{ser::SerializeStruct, Deserialize, Serialize};
-use shakmaty::{fen::Fen, san::San, Chess, EnPassantMode, Position, Setup};
+use shakmaty::{
+    fen::{Epd, Fen},
+    san::San,
+    Chess, EnPassantMode, Position, Setup,
+};
 
 use lazy_static::lazy_static;
 use strsim::{jaro_winkler, sorensen_dice};
@@ -74,6 +80,42 @@ pub fn get_opening_from_setup(setup: Setup) -> Result<String, Error> {
         .ok_or_else(|| Error::NoOpeningFound)
 }
 
+/// Returns the ECO code of a position of the openings table, if it's in it.
+/// The move counters are ignored, so transpositions are found too.
+pub fn get_eco_from_setup(setup: Setup) -> Option<&'static str> {
+    let epd = Epd::from_setup(setup).to_string();
+    ECO_BY_EPD.get(epd.as_str()).copied()
+}
+
+/// ECO codes of the openings whose name contains every word of the query,
+/// ignoring case, so "sicilian najdorf" finds "Sicilian Defense: Najdorf
+/// Variation". Names spread over several codes, like "King's Indian", get all
+/// of them.
+pub fn get_eco_codes_from_name(query: &str) -> Vec<&'static str> {
+    let query = query.to_lowercase();
+    let words: Vec<&str> = query.split_whitespace().collect();
+    if words.is_empty() {
+        return Vec::new();
+    }
+    let mut codes: Vec<&'static str> = ECO_OPENINGS
+        .iter()
+        .filter(|o| {
+            let name = o.name.to_lowercase();
+            words.iter().all(|word| name.contains(word))
+        })
+        .map(|o| o.eco.as_str())
+        .collect();
+    codes.sort_unstable();
+    codes.dedup();
+    codes
+}
+
+/// Name of the most general opening of an ECO code, the one with the fewest
+/// moves.
+pub fn get_opening_name_from_eco(eco: &str) -> Option<&'static str> {
+    NAME_BY_ECO.get(eco).copied()
+}
+
 #[tauri::command]
 pub async fn search_opening_name(query: String) -> Result<Vec<Opening>, Error> {
     let lower_query = query.to_lowercase();
@@ -154,6 +196,33 @@ lazy_static! {
         }
         positions
     };
+
+    /// The openings of the table that have an ECO code.
+    static ref ECO_OPENINGS: Vec<&'static Opening> = OPENINGS
+        .iter()
+        .filter(|o| o.eco != "Extra" && o.eco != "FRC")
+        .collect();
+
+    /// ECO codes of the openings table, keyed by EPD (the FEN without the move
+    /// counters) for fast lookups.
+    static ref ECO_BY_EPD: HashMap<String, &'static str> = ECO_OPENINGS
+        .iter()
+        .map(|o| (Epd::from_setup(o.setup.clone()).to_string(), o.eco.as_str()))
+        .collect();
+
+    static ref NAME_BY_ECO: HashMap<&'static str, &'static str> = {
+        let mut names: HashMap<&'static str, (&'static str, usize)> = HashMap::new();
+        for opening in ECO_OPENINGS.iter() {
+            let length = opening.pgn.as_deref().map_or(0, |pgn| pgn.split_whitespace().count());
+            let entry = names
+                .entry(opening.eco.as_str())
+                .or_insert((opening.name.as_str(), length));
+            if length < entry.1 {
+                *entry = (opening.name.as_str(), length);
+            }
+        }
+        names.into_iter().map(|(eco, (name, _))| (eco, name)).collect()
+    };
 }
 
 #[cfg(test)]
@@ -167,4 +236,37 @@ mod tests {
                 .unwrap();
         assert_eq!(opening, "Bongcloud Attack");
     }
+
+    #[test]
+    fn test_get_eco() {
+        let fen: Fen = "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPPKPPP/RNBQ1BNR b kq - 1 2"
+            .parse()
+            .unwrap();
+        assert_eq!(get_eco_from_setup(fen.into_setup()), Some("C20"));
+        let fen: Fen = "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPPKPPP/RNBQ1BNR b kq - 5 7"
+            .parse()
+            .unwrap();
+        assert_eq!(get_eco_from_setup(fen.into_setup()), Some("C20"));
+        assert_eq!(get_eco_from_setup(Setup::default()), None);
+    }
+
+    #[test]
+    fn test_eco_codes_from_name() {
+        let najdorf = get_eco_codes_from_name("Sicilian Najdorf");
+        assert!(najdorf.contains(&"B90") && najdorf.contains(&"B99"));
+        assert!(!najdorf.contains(&"B20"));
+
+        let kings_indian = get_eco_codes_from_name("king's indian");
+        assert!(kings_indian.contains(&"A05") && kings_indian.contains(&"E99"));
+        assert!(get_eco_codes_from_name("  ").is_empty());
+    }
+
+    #[test]
+    fn test_opening_name_from_eco() {
+        assert_eq!(
+            get_opening_name_from_eco("B90"),
+            Some("Sicilian Defense: Najdorf Variation")
+        );
+        assert_eq!(get_opening_name_from_eco("Z99"), None);
+    }
 }
@@ -98,9 +98,9 @@ try {
     else return { status: "error", error: e  as any };
 }
 },
-async getPlayersGameInfo(file: string, id: number) : Promise<__Result__<{ won: number; lost: number; draw: number; data_per_month: ([string, MonthData])[]; white_openings: ([string, Results])[]; black_openings: ([string, Results])[] }, string>> {
+async getPlayersGameInfo(file: string, id: number, includeUnknownResult: boolean | null, startDate: string | null, endDate: string | null, speed: Speed | null) : Promise<__Result__<{ won: number; lost: number; draw: number; unknown: number; as_white: Results; as_black: Results; data_per_month: ([string, MonthData])[]; white_openings: ([string, Results])[]; black_openings: ([string, Results])[] }, string>> {
 try {
-    return { status: "ok", data: await TAURI_INVOKE("plugin:tauri-specta|get_players_game_info", { file, id }) };
+    return { status: "ok", data: await TAURI_INVOKE("plugin:tauri-specta|get_players_game_info", { file, id, includeUnknownResult, startDate, endDate, speed }) };
 } catch (e) {
     if(e instanceof Error) throw e;
     else return { status: "error", error: e  as any };
@@ -130,9 +130,9 @@ try {
     else return { status: "error", error: e  as any };
 }
 },
-async mergePlayers(file: string, player1: number, player2: number) : Promise<__Result__<null, string>> {
+async mergePlayers(file: string, targetId: number, sourceIds: number[]) : Promise<__Result__<number, string>> {
 try {
-    return { status: "ok", data: await TAURI_INVOKE("plugin:tauri-specta|merge_players", { file, player1, player2 }) };
+    return { status: "ok", data: await TAURI_INVOKE("plugin:tauri-specta|merge_players", { file, targetId, sourceIds }) };
 } catch (e) {
     if(e instanceof Error) throw e;
     else return { status: "error", error: e  as any };
@@ -146,13 +146,125 @@ try {
     else return { status: "error", error: e  as any };
 }
 },
-async getPlayer(file: string, id: number) : Promise<__Result__<{ id: number; name: string | null; elo: number | null } | null, string>> {
+async autocompletePlayers(file: string, prefix: string, limit: number) : Promise<__Result__<Player[], string>> {
+try {
+    return { status: "ok", data: await TAURI_INVOKE("plugin:tauri-specta|autocomplete_players", { file, prefix, limit }) };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
+},
+async normalizePlayerNames(file: string) : Promise<__Result__<NameNormalization, string>> {
+try {
+    return { status: "ok", data: await TAURI_INVOKE("plugin:tauri-specta|normalize_player_names", { file }) };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
+},
+async getPlayer(file: string, id: number) : Promise<__Result__<PlayerDetails, string>> {
 try {
     return { status: "ok", data: await TAURI_INVOKE("plugin:tauri-specta|get_player", { file, id }) };
 } catch (e) {
     if(e instanceof Error) throw e;
     else return { status: "error", error: e  as any };
 }
+},
+async findSimilarPlayers(file: string) : Promise<__Result__<Player[][], string>> {
+try {
+    return { status: "ok", data: await TAURI_INVOKE("plugin:tauri-specta|find_similar_players", { file }) };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
+},
+async updatePlayer(file: string, id: number, name: string | null, elo: number | null) : Promise<__Result__<Player, string>> {
+try {
+    return { status: "ok", data: await TAURI_INVOKE("plugin:tauri-specta|update_player", { file, id, name, elo }) };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
+},
+async deletePlayer(file: string, id: number, mode: PlayerDeletion) : Promise<__Result__<number, string>> {
+try {
+    return { status: "ok", data: await TAURI_INVOKE("plugin:tauri-specta|delete_player", { file, id, mode }) };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
+},
+async getPlayerRatingHistory(file: string, playerId: number, maxPoints: number | null) : Promise<__Result__<RatingHistory, string>> {
+try {
+    return { status: "ok", data: await TAURI_INVOKE("plugin:tauri-specta|get_player_rating_history", { file, playerId, maxPoints }) };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
+},
+async getHeadToHead(file: string, player1Id: number, player2Id: number) : Promise<__Result__<HeadToHead, string>> {
+try {
+    return { status: "ok", data: await TAURI_INVOKE("plugin:tauri-specta|get_head_to_head", { file, player1Id, player2Id }) };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
+},
+async getPlayerLeaderboards(file: string, minGames: number) : Promise<__Result__<Leaderboards, string>> {
+try {
+    return { status: "ok", data: await TAURI_INVOKE("plugin:tauri-specta|get_player_leaderboards", { file, minGames }) };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
+},
+async getEventStandings(file: string, eventId: number) : Promise<__Result__<EventStandings, string>> {
+try {
+    return { status: "ok", data: await TAURI_INVOKE("plugin:tauri-specta|get_event_standings", { file, eventId }) };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
+},
+async updateEvent(file: string, id: number, name: string) : Promise<__Result__<null, string>> {
+try {
+    return { status: "ok", data: await TAURI_INVOKE("plugin:tauri-specta|update_event", { file, id, name }) };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
+},
+async mergeEvents(file: string, targetId: number, sourceIds: number[]) : Promise<__Result__<number, string>> {
+try {
+    return { status: "ok", data: await TAURI_INVOKE("plugin:tauri-specta|merge_events", { file, targetId, sourceIds }) };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
+},
+async updateSite(file: string, id: number, name: string) : Promise<__Result__<null, string>> {
+try {
+    return { status: "ok", data: await TAURI_INVOKE("plugin:tauri-specta|update_site", { file, id, name }) };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
+},
+async mergeSites(file: string, targetId: number, sourceIds: number[]) : Promise<__Result__<number, string>> {
+try {
+    return { status: "ok", data: await TAURI_INVOKE("plugin:tauri-specta|merge_sites", { file, targetId, sourceIds }) };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
+},
+async detectEventEditions(file: string, eventId: number, gapDays: number | null, apply: boolean) : Promise<__Result__<EventEdition[], string>> {
+try {
+    return { status: "ok", data: await TAURI_INVOKE("plugin:tauri-specta|detect_event_editions", { file, eventId, gapDays, apply }) };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
 }
 }
 
@@ -177,10 +289,48 @@ export type DatabaseProgress = { id: string; progress: number }
 export type DownloadProgress = { progress: number; id: string; finished: boolean }
 export type EngineOption = { name: string; value: string }
 export type EngineOptions = { fen: string; moves: string[]; extraOptions: EngineOption[] }
+export type EventEdition = { 
+/**
+ * The name of the event with the year of the edition, or with the
+ * month too if the event had several editions that year.
+ */
+name: string; first_date: string; last_date: string; game_count: number; 
+/**
+ * The event of the edition, once split.
+ */
+event_id: number | null }
+export type EventStandings = { 
+/**
+ * The best score first, then by name.
+ */
+standings: Standing[]; 
+/**
+ * For a round-robin, where every player met every other one, the points
+ * of each player of the standings against each of them, game by game,
+ * with `None` for unfinished games. Left out for other events, like
+ * Swiss ones.
+ */
+crosstable: ((number | null)[][])[] | null }
 export type GoMode = { t: "PlayersTime"; c: PlayersTime } | { t: "Depth"; c: number } | { t: "Time"; c: number } | { t: "Nodes"; c: number } | { t: "Infinite" }
+export type HeadToHead = { as_white: Results; as_black: Results; game_ids: number[]; common_openings: ([string, number])[] }
+export type Leaderboards = { most_games: Player[]; highest_rated: Player[]; 
+/**
+ * Among the players with at least `min_games` games of known result.
+ */
+best_score: PlayerScore[] }
 export type MonthData = { count: number; avg_elo: number }
+export type NameNormalization = { renamed: number; merged: number; skipped: number }
 export type PlayersTime = { white: number; black: number; winc: number; binc: number }
+export type RatingHistory = { points: ([string, number])[]; peak: [string, number] | null }
 export type ReportProgress = { progress: number; id: string; finished: boolean }
+export type Player = { id: number; name: string | null; elo: number | null; title: string | null; fide_id: number | null; game_count: number | null }
+export type PlayerDeletion = "Forbid" | "Anonymize" | "DeleteGames"
+export type PlayerDetails = ({ id: number; name: string | null; elo: number | null; title: string | null; fide_id: number | null; game_count: number | null }) & { first_game_date: string | null; last_game_date: string | null; peak_rating: number | null; favorite_openings: ([string, number])[] }
+export type PlayerScore = ({ id: number; name: string | null; elo: number | null; title: string | null; fide_id: number | null; game_count: number | null }) & { scored_games: number; 
+/**
+ * Points per game, in percent.
+ */
+score: number }
 export type Results = { won: number; lost: number; draw: number }
 export type Score = { value: ScoreValue; 
 /**
@@ -199,6 +349,12 @@ export type ScoreValue =
 /**
  * Represents a UCI option definition.
  */
+export type Speed = "bullet" | "blitz" | "rapid" | "classical" | "correspondence"
+export type Standing = { player_id: number; name: string | null; points: number; 
+/**
+ * The finished games.
+ */
+games: number; unfinished: number }
 export type UciOptionConfig = 
 /**
  * The option of type `check` (a boolean).
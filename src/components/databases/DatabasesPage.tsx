@@ -62,7 +62,7 @@ export default function DatabasesPage() {
   const [exportLoading, setExportLoading] = useState(false);
 
   function changeReferenceDatabase(file: string) {
-    invoke("clear_games");
+    invoke("clear_position_cache");
     if (file === referenceDatabase) {
       setReferenceDatabase(null);
     } else {
@@ -421,8 +421,8 @@ function PlayerMerger({
     setLoading(true);
     const res = await commands.mergePlayers(
       selectedDatabase.file,
-      player1,
       player2,
+      [player1],
     );
     setLoading(false);
     unwrap(res);
@@ -286,8 +286,8 @@ function GameTable({ database }: { database: DatabaseInfo }) {
                 render: ({ result }) => result?.replaceAll("1/2", "½"),
               },
               { accessor: "ply_count", title: "Plies", sortable: true },
-              { accessor: "event" },
-              { accessor: "site" },
+              { accessor: "event", sortable: true },
+              { accessor: "site", sortable: true },
             ]}
             rowClassName={(_, i) =>
               i === selectedGame ? classes.selected : ""
@@ -9,7 +9,14 @@ function PlayerCard({ player, file }: { player: Player; file: string }) {
   const { data: info, isLoading } = useSWRImmutable(
     ["player-game-info", file, player.id],
     async ([key, file, id]) => {
-      const games = await commands.getPlayersGameInfo(file, id);
+      const games = await commands.getPlayersGameInfo(
+        file,
+        id,
+        null,
+        null,
+        null,
+        null,
+      );
       return unwrap(games);
     },
   );
@@ -1,5 +1,4 @@
-import { commands } from "@/bindings";
-import { type Player, query_players } from "@/utils/db";
+import { type Player, commands } from "@/bindings";
 import { unwrap } from "@/utils/invoke";
 import { Autocomplete } from "@mantine/core";
 import { IconSearch } from "@tabler/icons-react";
@@ -44,20 +43,13 @@ export function PlayerSearchInput({
       setValue(player.id);
     }
 
-    const res = await query_players(file, {
-      page: 1,
-      pageSize: 5,
-      name: val,
-      skip_count: true,
-      sort: "elo",
-      direction: "asc",
-    });
-    setData(res.data);
+    const res = await commands.autocompletePlayers(file, val, 5);
+    setData(unwrap(res));
   }
   return (
     <Autocomplete
       value={tempValue}
-      data={data.map((player) => player.name)}
+      data={data.flatMap((player) => player.name ?? [])}
       onChange={handleChange}
       rightSection={rightSection}
       leftSection={<IconSearch size="1rem" />}
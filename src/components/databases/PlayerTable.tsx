@@ -142,6 +142,7 @@ function PlayerTable({ database }: { database: DatabaseInfo }) {
             { accessor: "id", sortable: true },
             { accessor: "name", sortable: true },
             { accessor: "elo", sortable: true },
+            { accessor: "game_count", title: "Games", sortable: true },
           ]}
           rowClassName={(_, i) =>
             i === selectedPlayer ? classes.selected : ""
@@ -96,11 +96,22 @@ function joinMonthData(data: [string, MonthData][][]) {
   );
 }
 
+function sumResults(results: Results[]): Results {
+  return {
+    won: results.reduce((acc, r) => acc + r.won, 0),
+    lost: results.reduce((acc, r) => acc + r.lost, 0),
+    draw: results.reduce((acc, r) => acc + r.draw, 0),
+  };
+}
+
 function combinePlayerInfo(playerInfos: PlayerGameInfo[]) {
   const combined: PlayerGameInfo = {
     won: playerInfos.reduce((acc, i) => acc + i.won, 0),
     lost: playerInfos.reduce((acc, i) => acc + i.lost, 0),
     draw: playerInfos.reduce((acc, i) => acc + i.draw, 0),
+    unknown: playerInfos.reduce((acc, i) => acc + i.unknown, 0),
+    as_white: sumResults(playerInfos.map((i) => i.as_white)),
+    as_black: sumResults(playerInfos.map((i) => i.as_black)),
     data_per_month: joinMonthData(playerInfos.map((i) => i.data_per_month)),
     white_openings: sumGamesPlayed(playerInfos.map((i) => i.white_openings)),
     black_openings: sumGamesPlayed(playerInfos.map((i) => i.black_openings)),
@@ -175,7 +186,14 @@ function Databases() {
               throw new Error("Player not found in database");
             }
             const info = unwrap(
-              await commands.getPlayersGameInfo(db.file, player.id),
+              await commands.getPlayersGameInfo(
+                db.file,
+                player.id,
+                null,
+                null,
+                null,
+                null,
+              ),
             );
             return { db, info };
           }),
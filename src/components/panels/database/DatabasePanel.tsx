@@ -52,6 +52,12 @@ export type LocalOptions = {
   path: string | null;
   fen: string;
   type: "exact" | "partial";
+  // Whether an exact search also compares the side to move and the rights
+  mode?: "exact" | "placement";
+  // Games are only replayed up to this ply, or with "auto" up to the one of
+  // the move counters of an exact position with a margin. Whole games by
+  // default, as a game may reach the position later than its counters say
+  max_ply?: number | "auto";
   player: number | null;
   color: "white" | "black";
   start_date?: string;
@@ -78,6 +84,7 @@ async function fetchOpening(db: DBType, tab: string) {
         games: await convertToNormalized(
           data.topGames || data.recentGames || [],
         ),
+        maxPly: null,
       };
     })
     .with({ type: "lch_master" }, async ({ fen, options }) => {
@@ -92,14 +99,16 @@ async function fetchOpening(db: DBType, tab: string) {
         games: await convertToNormalized(
           data.topGames || data.recentGames || [],
         ),
+        maxPly: null,
       };
     })
     .with({ type: "local" }, async ({ options }) => {
       if (!options.path) throw Error("Missing reference database");
       const positionData = await searchPosition(options, tab);
       return {
-        openings: sortOpenings(positionData[0]),
-        games: positionData[1],
+        openings: sortOpenings(positionData.openings),
+        games: positionData.games,
+        maxPly: positionData.max_ply,
       };
     })
     .exhaustive();
@@ -184,6 +193,8 @@ function DatabasePanel() {
                 Math.max(grandTotal || 0, openingData?.games.length || 0),
               ),
             })}
+            {openingData?.maxPly != null &&
+              ` (first ${openingData.maxPly} plies)`}
           </Text>
         )}
       </Group>
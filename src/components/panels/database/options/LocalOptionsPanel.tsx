@@ -5,7 +5,9 @@ import { currentLocalOptionsAtom } from "@/state/atoms";
 import {
   Box,
   Button,
+  Checkbox,
   Group,
+  NumberInput,
   SegmentedControl,
   Stack,
   Text,
@@ -109,6 +111,41 @@ function LocalOptionsPanel({ boardFen }: { boardFen: string }) {
             setOptions({ ...options, type: v as "exact" | "partial" })
           }
         />
+        {options.type === "exact" && (
+          <SegmentedControl
+            data={[
+              { value: "exact", label: "Same rights" },
+              { value: "placement", label: "Placement only" },
+            ]}
+            value={options.mode ?? "exact"}
+            onChange={(v) =>
+              setOptions({ ...options, mode: v as "exact" | "placement" })
+            }
+          />
+        )}
+        <NumberInput
+          label="Max ply"
+          placeholder="Whole game"
+          min={0}
+          disabled={options.max_ply === "auto"}
+          value={typeof options.max_ply === "number" ? options.max_ply : ""}
+          onChange={(v) =>
+            setOptions({
+              ...options,
+              max_ply: typeof v === "number" ? v : undefined,
+            })
+          }
+        />
+        <Checkbox
+          label="From the move counters"
+          checked={options.max_ply === "auto"}
+          onChange={(e) =>
+            setOptions({
+              ...options,
+              max_ply: e.currentTarget.checked ? "auto" : undefined,
+            })
+          }
+        />
       </Group>
 
       <Group>
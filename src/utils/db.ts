@@ -1,9 +1,9 @@
-import type { MonthData, Results } from "@/bindings";
+import { type MonthData, type Results, commands } from "@/bindings";
 import type { LocalOptions } from "@/components/panels/database/DatabasePanel";
 import { BaseDirectory, readDir } from "@tauri-apps/api/fs";
 import { fetch } from "@tauri-apps/api/http";
 import useSWR from "swr";
-import { invoke } from "./invoke";
+import { invoke, unwrap } from "./invoke";
 import type { PuzzleDatabase } from "./puzzles";
 
 export type Sides = "WhiteBlack" | "BlackWhite" | "Any";
@@ -33,11 +33,21 @@ interface Query {
   pageSize?: number;
   sort: string;
   direction: "asc" | "desc";
+  after?: Cursor;
+  approximate?: boolean;
+  request_id?: number;
+}
+
+export interface Cursor {
+  values: (number | string | null)[];
+  id: number;
 }
 
 interface QueryResponse<T> {
   data: T;
   count: number;
+  count_capped?: boolean;
+  next_cursor?: Cursor;
 }
 
 export type Speed =
@@ -55,11 +65,23 @@ export interface GameQuery extends Query {
   player1?: number;
   player2?: number;
   tournament_id?: number;
+  exclude_players?: (number | string)[];
   sides?: Sides;
   rangePlayer1?: [number, number];
   rangePlayer2?: [number, number];
+  min_both_elo?: number;
+  average_elo_range?: [number, number];
   speed?: Speed;
-  outcome?: Outcome;
+  outcome?: Outcome | Outcome[];
+  decisive_only?: boolean;
+  include_unknown_result?: boolean;
+  include_moves?: boolean;
+  opening?: string;
+  contains_move?: {
+    san: string;
+    side?: "White" | "Black" | "Both";
+    ply_range?: [number, number];
+  };
   start_date?: string;
   end_date?: string;
 }
@@ -90,36 +112,96 @@ function normalizeRange(
   return range;
 }
 
+function gameQueryArgs(query: GameQuery) {
+  return {
+    options: {
+      skip_count: query.skip_count ?? false,
+      page: query.page,
+      page_size: query.pageSize,
+      sort: query.sort,
+      direction: query.direction,
+      after: query.after,
+      approximate: query.approximate,
+    },
+    player1: query.player1,
+    range1: normalizeRange(query.rangePlayer1),
+    player2: query.player2,
+    range2: normalizeRange(query.rangePlayer2),
+    min_both_elo: query.min_both_elo,
+    average_elo_range: query.average_elo_range,
+    tournament_id: query.tournament_id,
+    exclude_players: query.exclude_players,
+    sides: query.sides,
+    speed: query.speed,
+    outcome: query.outcome,
+    decisive_only: query.decisive_only,
+    include_unknown_result: query.include_unknown_result,
+    include_moves: query.include_moves,
+    contains_move: query.contains_move,
+    opening: query.opening,
+    start_date: query.start_date,
+    end_date: query.end_date,
+  };
+}
+
 export async function query_games(
   db: string,
   query: GameQuery,
 ): Promise<QueryResponse<NormalizedGame[]>> {
   return invoke("get_games", {
     file: db,
-    query: {
-      options: {
-        skip_count: query.skip_count ?? false,
-        page: query.page,
-        page_size: query.pageSize,
-        sort: query.sort,
-        direction: query.direction,
-      },
-      player1: query.player1,
-      range1: normalizeRange(query.rangePlayer1),
-      player2: query.player2,
-      range2: normalizeRange(query.rangePlayer2),
-      tournament_id: query.tournament_id,
-      sides: query.sides,
-      speed: query.speed,
-      outcome: query.outcome,
-      start_date: query.start_date,
-      end_date: query.end_date,
-    },
+    query: gameQueryArgs(query),
   });
 }
 
+export type GameStreamMessage =
+  | { type: "batch"; index: number; games: NormalizedGame[] }
+  | {
+      type: "finished";
+      total: number;
+      matched: number;
+      cancelled: boolean;
+      error: string | null;
+    };
+
+// Sends all the games of the query as `GameStreamMessage`s on the event
+// named `channelId`.
+export async function streamGames(
+  file: string,
+  query: GameQuery,
+  channelId: string,
+) {
+  return invoke<void>("stream_games", {
+    file,
+    query: gameQueryArgs(query),
+    channelId,
+  });
+}
+
+export async function cancelStream(channelId: string) {
+  return invoke<void>("cancel_stream", { channelId });
+}
+
+// Up to `n` random games of the query, the same ones for the same `seed`.
+export async function sampleGames(
+  file: string,
+  query: GameQuery,
+  n: number,
+  seed?: number,
+) {
+  return invoke<NormalizedGame[]>("sample_games", {
+    file,
+    query: gameQueryArgs(query),
+    n,
+    seed,
+  });
+}
+
+export type NameMatch = "Exact" | "Contains" | "StartsWith" | "Pattern";
+
 interface PlayerQuery extends Query {
   name?: string;
+  match_mode?: NameMatch;
   range?: [number, number];
 }
 
@@ -128,6 +210,7 @@ export interface Player {
   name: string;
   elo?: number;
   image?: string;
+  game_count?: number | null;
 }
 
 export async function query_players(
@@ -145,6 +228,7 @@ export async function query_players(
         direction: query.direction,
       },
       name: query.name,
+      match_mode: query.match_mode,
       range: normalizeRange(query.range),
     },
   });
@@ -178,6 +262,48 @@ export async function query_tournaments(
   });
 }
 
+interface EventQuery extends Query {
+  name?: string;
+}
+
+export interface EventSummary {
+  id: number;
+  name: string;
+  game_count: number;
+  first_date: string | null;
+  last_date: string | null;
+}
+
+function eventQueryArgs(query: EventQuery) {
+  return {
+    options: {
+      skip_count: query.skip_count || false,
+      page: query.page,
+      page_size: query.pageSize,
+      sort: query.sort,
+      direction: query.direction,
+    },
+    name: query.name,
+  };
+}
+
+// The events of a database, with their number of games and dates. Sorted by
+// `name` or `game_count`.
+export async function getEvents(
+  file: string,
+  query: EventQuery,
+): Promise<QueryResponse<EventSummary[]>> {
+  return invoke("get_events", { file, query: eventQueryArgs(query) });
+}
+
+// The sites of a database, like `getEvents`.
+export async function getSites(
+  file: string,
+  query: EventQuery,
+): Promise<QueryResponse<EventSummary[]>> {
+  return invoke("get_sites", { file, query: eventQueryArgs(query) });
+}
+
 export async function getDatabases(): Promise<DatabaseInfo[]> {
   const files = await readDir("db", { dir: BaseDirectory.AppData });
   const dbs = files.filter((file) => file.name?.endsWith(".db3"));
@@ -246,9 +372,19 @@ export async function getDefaultPuzzleDatabases(): Promise<PuzzleDatabase[]> {
 
 export interface Opening {
   move: string;
+  uci?: string;
+  games?: number;
   white: number;
   black: number;
   draw: number;
+  // Shares of the games of known result, in percent
+  white_percent?: number;
+  draw_percent?: number;
+  black_percent?: number;
+  average_rating?: number | null;
+  // Average rating and performance of the side to move
+  mover_rating?: number | null;
+  performance?: number | null;
 }
 
 export type NormalizedGame = {
@@ -270,12 +406,120 @@ export type NormalizedGame = {
   result: Outcome;
   time_control?: string;
   eco?: string;
+  opening_name?: string | null;
   ply_count: number;
   white_material?: number;
   black_material?: number;
   moves: string;
 };
 
+// Games per year or month, like `2023` or `2023-05`, in chronological order.
+// The games of unknown date come last, as `Unknown`.
+export async function getGamesPerPeriod(
+  file: string,
+  granularity: "Year" | "Month",
+  query?: GameQuery,
+) {
+  return invoke<[string, number][]>("get_games_per_period", {
+    file,
+    granularity,
+    query: query ? gameQueryArgs(query) : undefined,
+  });
+}
+
+export interface DatabaseStats {
+  game_count: number;
+  white_wins: number;
+  draws: number;
+  black_wins: number;
+  unknown_results: number;
+  elo_histogram: [number, number][];
+  average_ply_count: number | null;
+  median_ply_count: number | null;
+  missing_ratings: number;
+  missing_dates: number;
+}
+
+export async function getDatabaseStats(file: string) {
+  return invoke<DatabaseStats>("get_db_stats", { file });
+}
+
+export interface OpeningStats {
+  group: string;
+  games: number;
+  white_wins: number;
+  draws: number;
+  black_wins: number;
+}
+
+// Games and results per opening, the most played first. Games without an ECO
+// code are grouped as `Unclassified`.
+export async function getOpeningStats(
+  file: string,
+  group: "EcoLetter" | "EcoCode" | "OpeningName",
+  query?: GameQuery,
+) {
+  return invoke<OpeningStats[]>("get_opening_stats", {
+    file,
+    query: query ? gameQueryArgs(query) : undefined,
+    group,
+  });
+}
+
+export interface RepertoireStats {
+  group: string;
+  games: number;
+  wins: number;
+  draws: number;
+  losses: number;
+}
+
+// The openings of a player with the given color, the most played first, with
+// the results from their side of the board.
+export async function getPlayerOpenings(
+  file: string,
+  playerId: number,
+  color: "White" | "Black" | "Both",
+  group: "EcoLetter" | "EcoCode" | "OpeningName",
+  startDate?: string,
+  endDate?: string,
+) {
+  return invoke<RepertoireStats[]>("get_player_openings", {
+    file,
+    playerId,
+    color,
+    group,
+    startDate,
+    endDate,
+  });
+}
+
+export interface Performance {
+  games: number;
+  points: number;
+  average_opponent_rating: number | null;
+  performance_rating: number | null;
+  excluded_games: number;
+}
+
+// The FIDE performance rating of a player over the games of the query. Games
+// against unrated opponents are left out and counted in `excluded_games`.
+export async function getPlayerPerformance(
+  file: string,
+  playerId: number,
+  query?: GameQuery,
+) {
+  return invoke<Performance>("get_player_performance", {
+    file,
+    playerId,
+    query: query ? gameQueryArgs(query) : undefined,
+  });
+}
+
+export async function getGame(file: string, id: number) {
+  return await invoke<NormalizedGame>("get_game", { file, id });
+}
+
 export async function getTournamentGames(file: string, id: number) {
   return await query_games(file, {
     direction: "asc",
@@ -289,13 +533,39 @@ export interface PlayerGameInfo {
   won: number;
   lost: number;
   draw: number;
+  unknown: number;
+  as_white: Results;
+  as_black: Results;
   data_per_month: [string, MonthData][];
   white_openings: [string, Results][];
   black_openings: [string, Results][];
 }
 
-export async function searchPosition(options: LocalOptions, tab: string) {
-  const openings: [Opening[], NormalizedGame[]] = await invoke(
+export interface PositionSearchResult {
+  openings: Opening[];
+  // All the matching games, with an empty move
+  total: Opening;
+  games: NormalizedGame[];
+  count: number;
+  game_ids: number[];
+  // The ply where each game of `game_ids` first reached the position
+  match_plies: number[];
+  // The games ruled out by their material without replaying them
+  skipped_games: number;
+  // The ply past which the games weren't replayed, null for whole games
+  max_ply: number | null;
+  elapsed_load_ms: number;
+  elapsed_search_ms: number;
+}
+
+// The moves played from a position and the games reaching it, with the IDs
+// of up to `limit` of them, to open or export them.
+export async function searchPosition(
+  options: LocalOptions,
+  tab: string,
+  limit = 1000,
+) {
+  return invoke<PositionSearchResult>(
     "search_position",
     {
       file: options.path,
@@ -307,10 +577,167 @@ export async function searchPosition(options: LocalOptions, tab: string) {
         end_date: options.end_date,
       },
       tabId: tab,
+      limit,
     },
-    (s) => s === "Search stopped",
+    (s) => s === "Search stopped" || s === "Search cancelled",
   );
-  return openings;
+}
+
+export interface DatabaseSearchResult {
+  file: string;
+  result: PositionSearchResult | null;
+  // Why the database couldn't be searched
+  error: string | null;
+}
+
+export interface MultiSearchResult {
+  databases: DatabaseSearchResult[];
+  // The moves of all the databases added up
+  openings: Opening[];
+  total: Opening;
+  count: number;
+}
+
+// Searches the position of the options in several databases at once
+export async function searchPositionMulti(
+  files: string[],
+  options: LocalOptions,
+  tab: string,
+  limit = 1000,
+) {
+  // Player IDs differ between databases, so the player is matched by name
+  const player =
+    options.player !== null && options.path
+      ? unwrap(await commands.getPlayer(options.path, options.player)).name
+      : null;
+  return invoke<MultiSearchResult>(
+    "search_position_multi",
+    {
+      files,
+      query: {
+        player1_name: options.color === "white" ? player : undefined,
+        player2_name: options.color === "black" ? player : undefined,
+        match_mode: "Exact",
+        position: options,
+        start_date: options.start_date,
+        end_date: options.end_date,
+      },
+      tabId: tab,
+      limit,
+    },
+    (s) => s === "Search stopped" || s === "Search cancelled",
+  );
+}
+
+// A square of a pattern, with a piece as in a FEN ("P" for a white pawn), or
+// any piece without one
+export interface SquareConstraint {
+  square: string;
+  piece?: string;
+}
+
+// A partial position: the squares not mentioned can hold anything. The
+// forbidden squares without a piece must be empty.
+export interface PositionPattern {
+  required?: SquareConstraint[];
+  forbidden?: SquareConstraint[];
+  turn?: "w" | "b";
+}
+
+export interface PatternSearchResult {
+  count: number;
+  game_ids: number[];
+  match_plies: number[];
+  skipped_games: number;
+  elapsed_load_ms: number;
+  elapsed_search_ms: number;
+}
+
+export async function searchPattern(
+  file: string,
+  pattern: PositionPattern,
+  limit = 1000,
+) {
+  return invoke<PatternSearchResult>("search_pattern", {
+    file,
+    pattern,
+    limit,
+  });
+}
+
+// The material of an endgame, exact with the kings ("KRPP-KRP") or a class
+// leaving the pawns free ("R vs R"). Either side may be White.
+export async function searchEndgames(
+  file: string,
+  material: string,
+  minPliesInEndgame = 0,
+  limit = 1000,
+) {
+  return invoke<PatternSearchResult>("search_endgames", {
+    file,
+    material,
+    minPliesInEndgame,
+    limit,
+  });
+}
+
+export interface ExplorerPosition {
+  token: number;
+  // The moves played since the root of the session
+  depth: number;
+  openings: Opening[];
+  total: Opening;
+  elapsed_ms: number;
+}
+
+// Follows a line from the exact position of the options, narrowing the games
+// of the previous position at every move instead of searching again
+export async function startExplorerSession(
+  file: string,
+  options: LocalOptions,
+) {
+  return invoke<ExplorerPosition>("start_explorer_session", {
+    file,
+    query: {
+      player1: options.color === "white" ? options.player : undefined,
+      player2: options.color === "black" ? options.player : undefined,
+      position: options,
+      start_date: options.start_date,
+      end_date: options.end_date,
+    },
+  });
+}
+
+export async function explorerPushMove(token: number, uci: string) {
+  return invoke<ExplorerPosition>("explorer_push_move", { token, uci });
+}
+
+export async function explorerPopMove(token: number) {
+  return invoke<ExplorerPosition>("explorer_pop_move", { token });
+}
+
+export async function closeExplorerSession(token: number) {
+  return invoke<void>("close_explorer_session", { token });
+}
+
+export interface PositionIndexInfo {
+  positions: number;
+  max_ply: number;
+  // Growth of the database file, in bytes
+  size_increase: number;
+}
+
+// Indexes the positions of the first plies of every game, which exact
+// searches within them then look up instead of replaying the games
+export async function buildPositionIndex(file: string, maxPly?: number) {
+  return invoke<PositionIndexInfo>("build_position_index", {
+    file,
+    maxPly,
+  });
+}
+
+export async function cancelSearch(tab: string) {
+  return invoke<void>("cancel_search", { tabId: tab });
 }
 
 export async function count_pgn_games(file: string) {
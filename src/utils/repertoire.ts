@@ -67,7 +67,7 @@ export async function openingReport({
     ) {
       continue;
     }
-    const [openings] = await searchPosition(
+    const { openings } = await searchPosition(
       {
         path: referenceDb,
         type: "exact",
@@ -76,6 +76,7 @@ export async function openingReport({
         player: null,
       },
       "opening",
+      0,
     );
     const total = openings.reduce(
       (acc, opening) => acc + opening.black + opening.white + opening.draw,